@@ -0,0 +1,363 @@
+//! Runs the binary against the fixtures in fixtures/demo, end to end from the
+//! API responses through scoring to each output format
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// (name, waste score) of every demo item, highest score first
+const DEMO_SCORES: [(&str, i64); 11] = [
+    ("Orbit Fall", 79),
+    ("Cannon Ridge", 75),
+    ("Iron Bell", 66),
+    ("Night Shift Diner", 40),
+    ("Hollow Creek Mysteries", 37),
+    ("Starlane Couriers", 36),
+    ("The Lighthouse Keeper's Son", 23),
+    ("Summer of the Fox", 22),
+    ("Paper Lanterns", 9),
+    ("Tidewater", 6),
+    ("The Quiet Harbor", 2),
+];
+
+/// wastearr on the demo fixtures with `home` as its home, so neither the
+/// user's config nor their state leaks into the run
+fn command(args: &[&str], home: &Path) -> Command {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/demo");
+    let (command, rest) = match args.first() {
+        Some(&command)
+            if !command.starts_with('-') && command != "sonarr" && command != "radarr" =>
+        {
+            (Some(command), &args[1..])
+        }
+        _ => (None, args),
+    };
+    let mut wastearr = Command::new(env!("CARGO_BIN_EXE_wastearr"));
+    wastearr
+        .args(command)
+        .arg("--mock")
+        .arg(&fixtures)
+        .args(["--no-pager", "--quiet"])
+        .args(rest)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("XDG_STATE_HOME", home.join("state"));
+    wastearr
+}
+
+fn wastearr(args: &[&str]) -> Output {
+    let home = tempfile::tempdir().unwrap();
+    command(args, home.path()).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn plain_rows(output: &Output) -> Vec<Vec<String>> {
+    stdout(output)
+        .lines()
+        .map(|line| line.split('\t').map(str::to_string).collect())
+        .collect()
+}
+
+#[test]
+fn plain_output_lists_every_item_by_waste_score() {
+    let output = wastearr(&["--plain"]);
+    assert_eq!(output.status.code(), Some(0));
+    let rows = plain_rows(&output);
+    let scores: Vec<(&str, i64)> = rows
+        .iter()
+        .map(|row| (row[0].as_str(), row[5].parse().unwrap()))
+        .collect();
+    assert_eq!(scores, DEMO_SCORES);
+    assert_eq!(
+        rows[0],
+        ["Orbit Fall", "movie", "2020", "5.5", "77309411328", "79"]
+    );
+}
+
+#[test]
+fn json_output_carries_the_same_scores() {
+    let output = wastearr(&["--output", "json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let items = report["items"].as_array().unwrap();
+    let scores: Vec<(&str, i64)> = items
+        .iter()
+        .map(|item| {
+            (
+                item["name"].as_str().unwrap(),
+                item["waste_score"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(scores, DEMO_SCORES);
+    assert_eq!(report["filters"], serde_json::json!([]));
+    assert_eq!(items[0]["instance"], "radarr");
+    assert_eq!(items[0]["size_bytes"], 77309411328_u64);
+}
+
+#[test]
+fn table_output_has_headers_totals_and_severity() {
+    let output = wastearr(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    let table = stdout(&output);
+    for expected in [
+        "Name",
+        "Waste Score",
+        "│ Orbit Fall ",
+        "│ Total (11) ",
+        "638.0 GB",
+        "Total items: 11 (5 series, 6 movies)",
+        "Critical   75-100     2 items    130.0 GB",
+    ] {
+        assert!(
+            table.contains(expected),
+            "{:?} missing from\n{}",
+            expected,
+            table
+        );
+    }
+}
+
+#[test]
+fn filters_narrow_the_report() {
+    let rows = plain_rows(&wastearr(&["radarr", "--plain", "--top-waste", "2"]));
+    let names: Vec<&str> = rows.iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(names, ["Orbit Fall", "Cannon Ridge"]);
+
+    let rows = plain_rows(&wastearr(&["--plain", "--waste-score", "40"]));
+    assert_eq!(rows.len(), 4);
+    assert!(rows.iter().all(|row| row[5].parse::<i32>().unwrap() >= 40));
+}
+
+#[test]
+fn groups_sum_their_items() {
+    let rows = plain_rows(&wastearr(&["--plain", "--group-by", "type"]));
+    let counts: Vec<(&str, &str)> = rows
+        .iter()
+        .map(|row| (row[0].as_str(), row[1].as_str()))
+        .collect();
+    assert!(counts.contains(&("Movie", "6")), "{:?}", counts);
+    assert!(counts.contains(&("Show", "5")), "{:?}", counts);
+}
+
+#[test]
+fn stats_summarizes_the_library() {
+    let output = wastearr(&["stats"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stats = stdout(&output);
+    assert!(stats.contains("Total items: 11"), "{}", stats);
+    assert!(stats.contains("Total size: 638.0 GB"), "{}", stats);
+    assert!(stats.contains("Waste score distribution"), "{}", stats);
+}
+
+#[test]
+fn fail_above_score_exits_3() {
+    assert_eq!(
+        wastearr(&["--fail-above-score", "70"]).status.code(),
+        Some(3)
+    );
+    assert_eq!(
+        wastearr(&["--fail-above-score", "79"]).status.code(),
+        Some(0)
+    );
+}
+
+#[test]
+fn fail_above_size_counts_flagged_items_only() {
+    // Orbit Fall, Cannon Ridge and Iron Bell: 161 GB
+    assert_eq!(
+        wastearr(&["--fail-above-size", "160GB"]).status.code(),
+        Some(3)
+    );
+    assert_eq!(
+        wastearr(&["--fail-above-size", "162GB"]).status.code(),
+        Some(0)
+    );
+}
+
+#[test]
+fn nagios_reports_flagged_size_against_thresholds() {
+    let output = wastearr(&["--output", "nagios", "-w", "100GB", "-c", "300GB"]);
+    assert_eq!(output.status.code(), Some(1));
+    let line = stdout(&output);
+    assert!(
+        line.starts_with("WASTEARR WARNING - 3 flagged items take 161.0 GB"),
+        "{}",
+        line
+    );
+    assert!(line.contains("items=11;;;0 flagged=3;;;0"), "{}", line);
+
+    let output = wastearr(&["--output", "nagios", "-w", "200GB", "-c", "300GB"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn missing_fixtures_fail_the_run() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wastearr"))
+        .args(["--mock", "fixtures/none", "--no-pager"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_clear()
+        .env("HOME", tempfile::tempdir().unwrap().path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("No fixtures found"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn thresholds_ignore_display_filters() {
+    for filters in [
+        &["--top-waste", "1"][..],
+        &["--waste-score", "101"],
+        &["--min-size", "1TB"],
+        &["radarr", "--ratings", "1"],
+    ] {
+        let with = |threshold: &[&str]| {
+            let args: Vec<&str> = filters.iter().chain(threshold).copied().collect();
+            wastearr(&args).status.code()
+        };
+        assert_eq!(
+            with(&["--fail-above-size", "100GB"]),
+            Some(3),
+            "{:?}",
+            filters
+        );
+        assert_eq!(
+            with(&["--fail-above-score", "10"]),
+            Some(3),
+            "{:?}",
+            filters
+        );
+    }
+    assert_eq!(
+        wastearr(&["stats", "--top-waste", "1", "--fail-above-score", "10"])
+            .status
+            .code(),
+        Some(3)
+    );
+}
+
+#[test]
+fn root_folders_flag_by_score_whatever_the_filters() {
+    for filters in [&[][..], &["--top-waste", "1"]] {
+        let args: Vec<&str> = ["--root-folders"].iter().chain(filters).copied().collect();
+        let table = stdout(&wastearr(&args));
+        let movies = table
+            .lines()
+            .find(|line| line.contains("/data/movies"))
+            .unwrap_or_else(|| panic!("no /data/movies row in\n{}", table));
+        let cells: Vec<&str> = movies.split(['│', '┆']).map(str::trim).collect();
+        assert_eq!(
+            cells[1..7],
+            ["/data/movies", "6", "188.0 GB", "3", "161.0 GB", "85.6%"],
+            "{:?}",
+            filters
+        );
+    }
+}
+
+#[test]
+fn watch_reports_cover_the_unfiltered_library() {
+    let home = tempfile::tempdir().unwrap();
+    let reports = home.path().join("reports");
+    let mut watch = command(&["watch", "--top-waste", "2"], home.path())
+        .env("WASTEARR_REPORT_DIR", &reports)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let latest = reports.join("latest.json");
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let report = loop {
+        // latest.json is swapped in once complete
+        if let Ok(report) = std::fs::read(&latest) {
+            break report;
+        }
+        assert!(Instant::now() < deadline, "no report written");
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+
+    let report: serde_json::Value = serde_json::from_slice(&report).unwrap();
+    assert_eq!(report["items"].as_array().unwrap().len(), DEMO_SCORES.len());
+}
+
+/// POST a JSON body with std alone, returning the status code and body
+fn post_json(port: u16, path: &str, token: Option<&str>, body: &str) -> (u16, String) {
+    use std::io::{Read, Write};
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {}\r\n", token))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        authorization,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    (status, body.to_string())
+}
+
+fn serve(token: Option<&str>, home: &Path) -> (std::process::Child, u16) {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let listen = format!("127.0.0.1:{}", port);
+    let mut serve = command(&["serve", "--listen", &listen], home);
+    if let Some(token) = token {
+        serve.env("WASTEARR_SERVE_TOKEN", token);
+    }
+    let child = serve
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "serve never listened");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    (child, port)
+}
+
+#[test]
+fn dashboard_actions_need_auth_and_live_servers() {
+    let delete = r#"{"action":"delete","items":[{"instance":"radarr","id":4}],"confirm":true}"#;
+    let home = tempfile::tempdir().unwrap();
+
+    let (mut open, port) = serve(None, home.path());
+    let (status, body) = post_json(port, "/api/items/action", None, delete);
+    open.kill().unwrap();
+    open.wait().unwrap();
+    assert_eq!(status, 403, "{}", body);
+
+    let (mut guarded, port) = serve(Some("secret"), home.path());
+    let unauthorized = post_json(port, "/api/items/action", None, delete);
+    let mocked = post_json(port, "/api/items/action", Some("secret"), delete);
+    guarded.kill().unwrap();
+    guarded.wait().unwrap();
+    assert_eq!(unauthorized.0, 401);
+    assert_eq!(mocked.0, 409, "{}", mocked.1);
+    assert!(mocked.1.contains("--mock"), "{}", mocked.1);
+}
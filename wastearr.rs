@@ -1,56 +1,632 @@
 use anyhow::{Context, Result};
+use axum::Json;
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::middleware::Next;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse};
+use axum::routing::{get, post};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::builder::PossibleValuesParser;
 use clap::{Arg, ArgAction, Command};
-use comfy_table::{Table, modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL};
-use dirs::{cache_dir, config_dir};
+use clap_complete::Shell;
+use comfy_table::{
+    Table,
+    modifiers::UTF8_ROUND_CORNERS,
+    presets::{ASCII_FULL, ASCII_MARKDOWN, NOTHING, UTF8_FULL, UTF8_FULL_CONDENSED},
+};
+use dirs::{cache_dir, config_dir, data_local_dir, state_dir};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use fs4::fs_std::FileExt;
+use futures::{Stream, StreamExt, future::join_all, stream};
+use indicatif::{MultiProgress, ProgressBar, ProgressFinish, ProgressStyle};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use nucleo_matcher::Utf32Str;
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
 use regex::Regex;
-use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, Response, ResponseBuilderExt, StatusCode};
+use roff::{Roff, bold, italic, line_break, roman};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::de::{DeserializeOwned, Deserializer as _, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fmt::Write as _;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::{self, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use terminal_size::{Height, Width, terminal_size};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace, warn};
+use tracing_subscriber::fmt::writer::EitherWriter;
+use unicode_width::UnicodeWidthChar;
+use wasmi::{Engine, Linker, Module, Store, TypedFunc};
 
 const CACHE_DURATION: u64 = 72 * 60 * 60; // 72 hours in seconds
+// Missing ratings often show up upstream soon after an item is added
+const NA_RATING_CACHE_DURATION: u64 = 6 * 60 * 60;
+// --refresh-ratings also replaces ratings cached longer ago than this
+const RATING_REFRESH_AGE: u64 = 24 * 60 * 60;
+// Library sizes change as downloads land, so whole libraries are reused for less long
+const DEFAULT_LIBRARY_CACHE_TTL: f64 = 60.0 * 60.0;
+const MIN_NAME_WIDTH: usize = 12;
+const WRAPPED_WINDOW: f64 = 365.0 * 24.0 * 3600.0;
+const WRAPPED_TOP_ACQUISITIONS: usize = 10;
+const HISTOGRAM_WIDTH: usize = 40;
+const NOTE_COLUMN_WIDTH: usize = 30;
+const BUNDLE_VERSION: u32 = 1;
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// How often `watch` and `serve` look for edits to the config files
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// How often `watch` looks for digests and quiet-hour notices that are due
+const HELD_NOTICE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const SCHEDULED_ACTIONS: [&str; 3] = ["scan", "stats", "wrapped"];
+const DEFAULT_LISTEN: &str = "127.0.0.1:8686";
+const FORECAST_WINDOW: f64 = 90.0 * 24.0 * 3600.0;
+const FORECAST_MIN_SPAN: f64 = 24.0 * 3600.0;
+const WEEK: f64 = 7.0 * 24.0 * 3600.0;
+// Per-item detail requests (episode/movie files) kept in flight at once,
+// unless overridden by --concurrency or WASTEARR_CONCURRENCY
+const DEFAULT_CONCURRENCY: usize = 8;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// Transient failures are retried with exponential backoff: 0.5s, 1s, ...
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_BACKOFF_MS: u64 = 500;
+const DEFAULT_RETRY_JITTER: f64 = 0.25;
+// Parsed records --stream lets pile up ahead of the scorer
+const STREAM_BUFFER: usize = 256;
+// Batches smaller than this finish too quickly to need progress lines
+const DETAIL_PROGRESS_MIN: usize = 50;
+// Oldest Sonarr/Radarr major version serving the v3 API
+const MIN_SERVER_VERSION: u32 = 3;
+// Results were produced, but --skip-unreachable left some instances out
+const PARTIAL_SCAN_EXIT_CODE: i32 = 2;
+// The scan went over a --fail-above-score or --fail-above-size threshold
+const THRESHOLD_EXIT_CODE: i32 = 3;
+// Nagios plugin states, in exit code order
+const NAGIOS_STATES: [&str; 4] = ["OK", "WARNING", "CRITICAL", "UNKNOWN"];
+// A movie folder counts as carrying extra files above both of these
+const EXTRA_FILES_MIN_BYTES: u64 = 100 * 1024 * 1024;
+const EXTRA_FILES_MIN_RATIO: f64 = 0.1;
+// Waste score bands as (label, lowest score), from least to most severe
+const SEVERITY_BANDS: [(&str, i32); 4] =
+    [("Low", 0), ("Moderate", 25), ("High", 50), ("Critical", 75)];
+// Scan history counts items of High severity and up as flagged
+const FLAGGED_MIN_SCORE: i32 = SEVERITY_BANDS[2].1;
 
-#[derive(Debug, Clone)]
+// Set while the tui owns the terminal, which log lines would scribble over
+static LOG_MUTED: AtomicBool = AtomicBool::new(false);
+// Phase progress bars, only drawn when stderr is a terminal and --quiet is off
+static PROGRESS_BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+static BARS_ENABLED: AtomicBool = AtomicBool::new(false);
+// Scan progress for `serve` clients; a slow browser skips what it missed
+const SCAN_EVENT_BUFFER: usize = 256;
+static SCAN_EVENTS: LazyLock<broadcast::Sender<ScanEvent>> =
+    LazyLock::new(|| broadcast::channel(SCAN_EVENT_BUFFER).0);
+
+/// A step of a running scan, streamed to the dashboard over /api/scan/events
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ScanEvent {
+    Started,
+    // A phase of PhaseTimer has completed
+    Phase {
+        phase: &'static str,
+        seconds: f64,
+    },
+    Progress {
+        message: String,
+    },
+    Count {
+        what: String,
+        done: usize,
+        total: usize,
+    },
+    Finished {
+        ok: bool,
+        items: usize,
+    },
+}
+
+/// Hand an event to any dashboard listening; without one it is dropped
+fn publish_scan_event(event: ScanEvent) {
+    let _ = SCAN_EVENTS.send(event);
+}
+
+macro_rules! progress {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        info!("{}", line);
+        publish_scan_event(ScanEvent::Progress {
+            message: line.trim().to_string(),
+        });
+    }};
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Item {
+    id: i32,
     name: String,
     year: i32,
     size_bytes: u64,
     rating: String,
     item_type: String, // 'show' or 'movie'
     waste_score: i32,
+    instance: String,
+    genres: Vec<String>,
+    path: String,
+    root_folder: String,
+    quality: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_info: Option<MediaInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    episodes: Option<EpisodeCounts>,
+    specials_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    primary_file_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tmdb_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tvdb_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    trend: Option<Trend>,
+    // Attached from the notes store on every run, never cached
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Trend {
+    New,
+    Seen { score_delta: i32, size_delta: i64 },
+}
+
+impl Trend {
+    fn label(&self) -> String {
+        match self {
+            Trend::New => "new".to_string(),
+            Trend::Seen {
+                score_delta: 0,
+                size_delta: 0,
+            } => "=".to_string(),
+            Trend::Seen {
+                score_delta,
+                size_delta,
+            } => {
+                let mut parts = Vec::new();
+                if *score_delta != 0 {
+                    let arrow = if *score_delta > 0 { "▲" } else { "▼" };
+                    parts.push(format!("{}{:+}", arrow, score_delta));
+                }
+                if *size_delta != 0 {
+                    let sign = if *size_delta > 0 { "+" } else { "-" };
+                    parts.push(format!(
+                        "{}{}",
+                        sign,
+                        format_file_size(size_delta.unsigned_abs())
+                    ));
+                }
+                parts.join(" ")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotItem {
+    instance: String,
+    id: i32,
+    name: String,
+    item_type: String,
+    size_bytes: u64,
+    waste_score: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanSnapshot {
+    timestamp: f64,
+    instances: Vec<String>,
+    // Empty when WASTEARR_HISTORY_ITEMS is off
+    items: Vec<SnapshotItem>,
+    #[serde(default)]
+    summaries: Vec<InstanceSummary>,
+    #[serde(default)]
+    folders: Vec<FolderSummary>,
+    #[serde(default)]
+    reclaimed: Reclaimed,
+}
+
+/// Space freed by deletions wastearr made from `tui` or the dashboard, summed
+/// from the deletion log rather than guessed from items going missing
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Reclaimed {
+    items: usize,
+    bytes: u64,
+}
+
+impl std::ops::AddAssign for Reclaimed {
+    fn add_assign(&mut self, other: Self) {
+        self.items += other.items;
+        self.bytes += other.bytes;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Instance {
+    name: String, // 'sonarr', 'radarr', or e.g. 'radarr-4k' for extra instances
+    kind: String, // 'sonarr' or 'radarr'
+    url: String,
+    api_key: Option<String>,
+    // Config key prefix, e.g. RADARR or RADARR_4K
+    env_prefix: String,
+    retry: RetryPolicy,
+    // Built per instance so connect timeouts, TLS and extra headers can differ
+    client: Client,
+    // Reverse-proxy basic auth, sent alongside the API key
+    basic_auth: Option<(String, Option<String>)>,
+    // None keeps the defaults: REQUEST_TIMEOUT, or STATUS_TIMEOUT for the health check
+    request_timeout: Option<Duration>,
+    // Shared by every instance on the same host
+    rate_limit: Option<Arc<RateLimiter>>,
+    // Server version from system/status, e.g. "4.0.1.929"; None until checked
+    version: Option<String>,
+    // Answers in place of the server for --mock and --replay
+    canned: Option<CannedResponses>,
+    // Where --record collects responses, shared by every instance
+    recorder: Option<Arc<Mutex<Vec<RecordedResponse>>>>,
+}
+
+/// Where an instance's responses come from when it isn't contacted live
+#[derive(Debug, Clone)]
+enum CannedResponses {
+    Fixtures(PathBuf),
+    Session(Arc<Session>),
+}
+
+/// Every API response of a run, written by --record and read by --replay
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Session {
+    recorded_at: f64,
+    instances: Vec<SessionInstance>,
+    responses: Vec<RecordedResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionInstance {
+    name: String,
+    kind: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    instance: String,
+    path: String,
+    status: u16,
+    // Kept as JSON so sessions stay readable; anything else is stored as a string
+    body: serde_json::Value,
+}
+
+/// Spaces requests evenly so a host sees at most `per_second` of them
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next| next.max(now));
+            *next_slot = Some(slot + self.interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    jitter: f64, // fraction of each delay to randomize by, in either direction
+}
+
+impl RetryPolicy {
+    /// Exponential backoff before the given retry (0-based)
+    fn delay(&self, retry: u32) -> Duration {
+        let base = self.backoff.as_secs_f64() * 2f64.powi(retry as i32);
+        // Clock noise is plenty to keep concurrent retries from lining up
+        let noise = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos() as f64
+            / 1e9;
+        Duration::from_secs_f64(base * (1.0 + self.jitter * (noise * 2.0 - 1.0)))
+    }
+}
+
+impl Instance {
+    fn service_name(&self) -> String {
+        let service = if self.kind == "sonarr" {
+            "Sonarr"
+        } else {
+            "Radarr"
+        };
+        match self.name.strip_prefix(&format!("{}-", self.kind)) {
+            Some(suffix) => format!("{} ({})", service, suffix),
+            None => service.to_string(),
+        }
+    }
+
+    fn major_version(&self) -> Option<u32> {
+        major_version(self.version.as_deref()?)
+    }
+
+    /// Sonarr v4 reshaped parts of the series schema
+    fn is_sonarr_v4(&self) -> bool {
+        self.kind == "sonarr" && self.major_version().is_some_and(|major| major >= 4)
+    }
+
+    fn require_api_key(&self) -> Result<&str> {
+        self.api_key
+            .as_deref()
+            .with_context(|| format!("{}_API_KEY environment variable not set", self.env_prefix))
+    }
 }
 
 #[derive(Debug)]
 struct Config {
-    sonarr_url: String,
-    sonarr_api_key: Option<String>,
-    radarr_url: String,
-    radarr_api_key: Option<String>,
+    instances: Vec<Instance>,
+    concurrency: usize,
+    scoring: Scoring,
+    protect: Vec<ProtectRule>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Config {
+    /// Whether a [[protect]] rule keeps the item's waste score at 0
+    fn is_protected(&self, item: &Item) -> bool {
+        self.protect.iter().any(|rule| rule.matches(item))
+    }
+
+    fn instances_for<'a>(&'a self, scan_types: &'a [String]) -> impl Iterator<Item = &'a Instance> {
+        self.instances
+            .iter()
+            .filter(move |instance| scan_types.contains(&instance.kind))
+    }
+
+    fn instance(&self, name: &str) -> Option<&Instance> {
+        self.instances.iter().find(|instance| instance.name == name)
+    }
+
+    fn require_instance(&self, name: &str) -> Result<&Instance> {
+        self.instance(name)
+            .with_context(|| format!("Unknown instance {}", name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MediaInfo {
+    resolution: String,
+    video_codec: String,
+    audio: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EpisodeCounts {
+    file_count: u64,
+    total_count: u64,
+    season_count: u64,
+    percent_complete: f64,
+}
+
+impl EpisodeCounts {
+    fn is_incomplete(&self) -> bool {
+        self.percent_complete < 100.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ExtraFiles {
+    name: String,
+    year: i32,
+    file_count: usize,
+    folder_bytes: u64,
+    primary_file_bytes: u64,
+}
+
+impl ExtraFiles {
+    fn reclaimable_bytes(&self) -> u64 {
+        self.folder_bytes.saturating_sub(self.primary_file_bytes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CrossInstanceDuplicate {
+    name: String,
+    item_type: String,
+    copies: Vec<(String, u64)>, // (instance, size_bytes)
+}
+
+impl CrossInstanceDuplicate {
+    fn combined_bytes(&self) -> u64 {
+        self.copies.iter().map(|(_, size)| size).sum()
+    }
+
+    /// Everything but the largest copy
+    fn reclaimable_bytes(&self) -> u64 {
+        self.combined_bytes() - self.copies.iter().map(|(_, size)| *size).max().unwrap_or(0)
+    }
+}
+
+/// Optional report sections gathered alongside the main scan
+#[derive(Debug, Default)]
+struct ReportExtras {
+    disks: Vec<DiskSpace>,
+    forecast: Option<GrowthForecast>,
+    extra_files: Vec<ExtraFiles>,
+    cross_instance: Vec<CrossInstanceDuplicate>,
+    metrics: ScanMetrics,
+}
+
+/// Library growth in bytes per week, fitted over recent scan history
+#[derive(Debug, Default)]
+struct GrowthForecast {
+    // (instance, root folder, growth) of every folder with enough history
+    folders: Vec<(String, String, f64)>,
+    // Keyed by (host, path); the growth of the root folders on each disk
+    disks: HashMap<(String, String), f64>,
+}
+
+#[derive(Debug, Clone)]
+struct DiskSpace {
+    host: String,
+    instances: Vec<String>,
+    path: String,
+    label: String,
+    free_bytes: u64,
+    total_bytes: u64,
+}
+
+#[derive(Debug, Default)]
 struct CacheData {
-    timestamp: f64,
     sonarr_ratings: HashMap<String, String>,
     radarr_ratings: HashMap<String, String>,
+    // (kind, key) of ratings read from the store, which saving can skip
+    loaded_ratings: HashSet<(String, String)>,
+    // Normalized library items per instance, so repeat runs can skip the list fetches
+    libraries: HashMap<String, CachedLibrary>,
+    // (hits, misses) of this run, kept for `cache stats`
+    run_stats: (usize, usize),
 }
 
 #[derive(Debug)]
+struct CachedLibrary {
+    fetched_at: f64,
+    items: Vec<Item>,
+    validators: Validators,
+}
+
+#[derive(Debug, Clone)]
 struct Args {
+    command: Option<String>,
+    // Scan numbers or "latest" for `compare`
+    compare_from: Option<String>,
+    compare_to: Option<String>,
+    // Scan to show for `history`
+    scan: Option<String>,
+    // Item and text for `note`
+    note_item: Option<String>,
+    note_text: Option<String>,
+    // File for `state export`/`state import`
+    bundle: Option<String>,
+    // Target of `completions`
+    shell: Option<Shell>,
+    // Time between scans for `watch` and `serve`
+    interval: Option<Duration>,
+    // Address for `serve`
+    listen: Option<String>,
     item_type: Option<String>,
     top_waste: Option<usize>,
     waste_score: Option<i32>,
     min_size: Option<String>,
     ratings: Option<f64>,
-    clear_cache: bool,
+    // "all", "sonarr", "radarr" or an instance name
+    clear_cache: Option<String>,
     no_cache: bool,
+    table_style: String,
+    plain: bool,
+    no_pager: bool,
+    // Only the result on stdout: no progress, warnings, pager, or scan footer
+    quiet: bool,
+    full_names: bool,
+    group_by: Option<String>,
+    output: String,
+    media_info: bool,
+    episodes: bool,
+    incomplete: bool,
+    specials: String,
+    resolution: Option<String>,
+    video_codec: Option<String>,
+    audio: Option<String>,
+    root_folders: bool,
+    disk_space: bool,
+    forecast: bool,
+    duplicates: bool,
+    cross_instance: bool,
+    trend: bool,
+    new_since_last: bool,
+    no_history: bool,
+    no_notify: bool,
+    concurrency: Option<usize>,
+    timeout: Option<f64>,
+    scorer: Option<String>,
+    stream: bool,
+    timing: bool,
+    // -v for debug logging, -vv for trace
+    verbose: u8,
+    wait: bool,
+    skip_unreachable: bool,
+    fail_above_score: Option<i32>,
+    fail_above_size: Option<u64>,
+    // Flagged size thresholds for --output nagios
+    warning: Option<u64>,
+    critical: Option<u64>,
+    skip_sonarr: bool,
+    skip_radarr: bool,
+    mock: Option<PathBuf>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    offline: bool,
+    refresh_ratings: bool,
 }
 
 fn load_file_vars(file_path: &Path) -> HashMap<String, String> {
+    if file_path.extension().is_some_and(|ext| ext == "toml") {
+        // Mistakes in it are reported by check_toml_config instead of on every lookup
+        let mut last_good = LAST_GOOD_TOML
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        return match read_toml_config(file_path)
+            .and_then(|table| flatten_toml(&table.unwrap_or_default()))
+        {
+            Ok(vars) => {
+                *last_good = Some(vars.clone());
+                vars
+            }
+            Err(_) => last_good.clone().unwrap_or_default(),
+        };
+    }
     fs::read_to_string(file_path).map_or_else(
         |_| HashMap::new(),
         |contents| {
@@ -82,576 +658,12822 @@ fn load_file_vars(file_path: &Path) -> HashMap<String, String> {
     )
 }
 
-fn get_config_value(key: &str) -> Option<String> {
-    env::var(key)
-        .ok()
-        .or_else(|| {
-            config_dir().and_then(|dir| {
-                load_file_vars(&dir.join("wastearr/config"))
-                    .get(key)
-                    .cloned()
-            })
-        })
-        .or_else(|| load_file_vars(&PathBuf::from(".env")).get(key).cloned())
-        .or_else(|| {
-            load_file_vars(&PathBuf::from("/etc/wastearr/config"))
-                .get(key)
-                .cloned()
-        })
+/// The settings read through get_config_value, for `wastearr man`. `SONARR` and
+/// `RADARR` stand for any instance prefix where per-instance keys are allowed.
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    ("SONARR_API_KEY", "Sonarr API key"),
+    ("RADARR_API_KEY", "Radarr API key"),
+    ("SONARR_URL", "Sonarr URL (default: http://localhost:8989)"),
+    ("RADARR_URL", "Radarr URL (default: http://localhost:7878)"),
+    (
+        "SONARR_INSTANCES / RADARR_INSTANCES",
+        "Comma-separated names of additional instances; NAME reads SONARR_NAME_URL and SONARR_NAME_API_KEY",
+    ),
+    (
+        "WASTEARR_RETRIES",
+        "Retries for timed-out, refused or HTTP 429/502/503/504 requests (default: 2)",
+    ),
+    (
+        "WASTEARR_RETRY_BACKOFF_MS",
+        "Delay before the first retry, doubling after that (default: 500)",
+    ),
+    (
+        "WASTEARR_RETRY_JITTER",
+        "Fraction by which retry delays are randomized (default: 0.25)",
+    ),
+    (
+        "WASTEARR_TIMEOUT",
+        "Seconds to wait for a response (default: 5 for the connectivity check, 10 otherwise)",
+    ),
+    (
+        "WASTEARR_CONNECT_TIMEOUT",
+        "Seconds to wait for a connection (default: 5)",
+    ),
+    (
+        "WASTEARR_RATE_LIMIT",
+        "Maximum requests per second per host (default: unlimited)",
+    ),
+    (
+        "WASTEARR_CA_BUNDLE",
+        "PEM file of extra CA certificates to trust",
+    ),
+    (
+        "WASTEARR_INSECURE",
+        "Set to true to accept invalid or self-signed certificates",
+    ),
+    (
+        "WASTEARR_USERNAME / WASTEARR_PASSWORD",
+        "Basic auth credentials for a reverse proxy",
+    ),
+    (
+        "WASTEARR_HEADERS",
+        "Extra headers for every request, as Name: value pairs separated by semicolons",
+    ),
+    (
+        "WASTEARR_CONCURRENCY",
+        "Maximum per-item detail requests in flight at once (default: 8)",
+    ),
+    (
+        "WASTEARR_LIBRARY_CACHE_TTL",
+        "Seconds a fetched library is reused from the cache (default: 3600)",
+    ),
+    (
+        "WASTEARR_CACHE_DIR",
+        "Directory for the rating and library cache (default: $XDG_CACHE_HOME/wastearr)",
+    ),
+    (
+        "WASTEARR_STATE_DIR",
+        "Directory for scan history and the run lock (default: $XDG_STATE_HOME/wastearr)",
+    ),
+    (
+        "WASTEARR_SCORE_TV_FACTOR",
+        "Waste score multiplier for shows (default: 0.6)",
+    ),
+    (
+        "WASTEARR_SCORE_UNRATED_RATING",
+        "Rating unrated items are scored as (default: 6.0)",
+    ),
+    (
+        "WASTEARR_HISTORY_ITEMS",
+        "Set to false to record only per-instance totals for each scan",
+    ),
+    ("WASTEARR_SERVE_TOKEN", "Bearer token required by serve"),
+    (
+        "WASTEARR_SERVE_USERNAME / WASTEARR_SERVE_PASSWORD",
+        "Basic auth credentials required by serve",
+    ),
+    (
+        "WASTEARR_SERVE_AUTH_HEADER",
+        "Header set by a trusted forward-auth proxy that lets a request in",
+    ),
+    (
+        "WASTEARR_SERVE_TRUSTED_PROXIES",
+        "Proxies whose auth header is trusted (default: 127.0.0.1,::1)",
+    ),
+    (
+        "WASTEARR_SERVE_WEBHOOK_SECRET",
+        "Turns on POST /api/webhook for Sonarr/Radarr webhook connections",
+    ),
+    (
+        "WASTEARR_DISCORD_WEBHOOK",
+        "Discord webhook URL for scan summaries",
+    ),
+    (
+        "WASTEARR_SLACK_WEBHOOK",
+        "Slack incoming webhook URL for scan summaries",
+    ),
+    (
+        "WASTEARR_TELEGRAM_TOKEN / WASTEARR_TELEGRAM_CHAT_ID",
+        "Telegram bot token and chat to message",
+    ),
+    (
+        "WASTEARR_TELEGRAM_API_URL",
+        "Self-hosted Telegram Bot API server",
+    ),
+    ("WASTEARR_NTFY_URL", "ntfy topic URL for scan summaries"),
+    (
+        "WASTEARR_NTFY_TOKEN",
+        "Access token for a protected ntfy topic",
+    ),
+    (
+        "WASTEARR_NTFY_PRIORITY",
+        "ntfy message priority (default: default)",
+    ),
+    ("WASTEARR_NTFY_TITLE", "Title template for ntfy messages"),
+    (
+        "WASTEARR_GOTIFY_URL / WASTEARR_GOTIFY_TOKEN",
+        "Gotify server and application token",
+    ),
+    (
+        "WASTEARR_GOTIFY_PRIORITY",
+        "Gotify message priority from 0 to 10 (default: 5)",
+    ),
+    (
+        "WASTEARR_SMTP_HOST",
+        "SMTP server to email scan reports through",
+    ),
+    (
+        "WASTEARR_SMTP_PORT",
+        "SMTP port (default: 587, 465 for tls, 25 for none)",
+    ),
+    ("WASTEARR_SMTP_TLS", "starttls (default), tls or none"),
+    (
+        "WASTEARR_SMTP_USERNAME / WASTEARR_SMTP_PASSWORD",
+        "SMTP login",
+    ),
+    (
+        "WASTEARR_EMAIL_FROM / WASTEARR_EMAIL_TO",
+        "Sender and comma-separated recipients",
+    ),
+    ("WASTEARR_EMAIL_FORMAT", "html (default) or markdown"),
+    ("WASTEARR_APPRISE_URL", "Apprise API notify endpoint"),
+    (
+        "WASTEARR_APPRISE_URLS",
+        "Apprise service URLs for the stateless endpoint",
+    ),
+    (
+        "WASTEARR_APPRISE_TAG",
+        "Only notify the Apprise services carrying this tag",
+    ),
+    (
+        "WASTEARR_WEBHOOK_URL",
+        "Comma-separated URLs to POST the scan summary to as JSON",
+    ),
+    (
+        "WASTEARR_WEBHOOK_HEADERS",
+        "Extra headers for the webhook requests",
+    ),
+    (
+        "WASTEARR_WEBHOOK_TEMPLATE",
+        "Request body to send instead of the JSON summary",
+    ),
+    (
+        "WASTEARR_<BACKEND>_EVENTS",
+        "Events a notification backend is sent: scan, flagged and low_space (default: all)",
+    ),
+    (
+        "WASTEARR_<BACKEND>_MIN_SEVERITY",
+        "Only notify when a top offender is of this severity or worse",
+    ),
+    (
+        "WASTEARR_NOTIFY_DEDUPE",
+        "Set to true to skip notifications that repeat the last one",
+    ),
+    (
+        "WASTEARR_NOTIFY_QUIET_HOURS",
+        "Local time range to hold notifications during, e.g. 22:00-07:00",
+    ),
+    (
+        "WASTEARR_NOTIFY_DIGEST",
+        "Cron expression to batch notifications into digests",
+    ),
+    (
+        "WASTEARR_HEALTHCHECK_URL",
+        "healthchecks.io ping URL for each scan",
+    ),
+    (
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OpenTelemetry collector to export each scan to over OTLP/HTTP",
+    ),
+    (
+        "OTEL_EXPORTER_OTLP_HEADERS",
+        "Headers for the OTLP requests, as key=value,key=value",
+    ),
+    (
+        "OTEL_SERVICE_NAME",
+        "Service name for OTLP exports (default: wastearr)",
+    ),
+    (
+        "WASTEARR_SCHEDULE_SCAN / WASTEARR_SCHEDULE_STATS / WASTEARR_SCHEDULE_WRAPPED",
+        "Cron expressions for watch to run a scan, stats or wrapped on",
+    ),
+    (
+        "WASTEARR_REPORT_DIR",
+        "Directory for watch to save each scan's HTML and JSON report to",
+    ),
+    (
+        "WASTEARR_REPORT_KEEP",
+        "Reports of each format to keep (default: 30)",
+    ),
+    (
+        "WASTEARR_LOW_SPACE",
+        "Free space threshold for watch to alert on, as a size or a percentage",
+    ),
+    (
+        "WASTEARR_LOW_SPACE_DISKS",
+        "Comma-separated disk paths to watch (default: all)",
+    ),
+    (
+        "WASTEARR_LOW_SPACE_INTERVAL",
+        "How often watch checks the disk space (default: 15m)",
+    ),
+];
+
+/// Config files in the order they're looked up, after the environment
+fn config_files() -> Vec<PathBuf> {
+    config_dir()
+        .map(|dir| dir.join("wastearr"))
+        .into_iter()
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .chain([PathBuf::from(".env"), PathBuf::from("/etc/wastearr/config")])
+        .collect()
 }
 
-fn fetch_api_data(
-    base_url: &str,
-    api_key: &str,
-    endpoint: &str,
-    service_name: &str,
-) -> Result<Vec<Value>> {
-    let url = format!("{}/api/v3/{}", base_url, endpoint);
-    let response = Client::new()
-        .get(&url)
-        .header("X-Api-Key", api_key)
-        .header("Content-Type", "application/json")
-        .timeout(std::time::Duration::from_secs(10))
-        .send()
-        .with_context(|| format!("Failed to connect to {} API", service_name))?;
+// The settings of the last config.toml that read cleanly, kept in effect
+// while a later edit of it is broken
+static LAST_GOOD_TOML: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
 
-    if response.status().is_success() {
-        let data: Vec<Value> = response
-            .json()
-            .with_context(|| format!("Failed to parse {} API response", service_name))?;
-        println!(
-            "Fetched {} {}s from {} API",
-            data.len(),
-            endpoint,
-            service_name
-        );
-        Ok(data)
-    } else {
-        anyhow::bail!(
-            "Failed to fetch {}s from {} API: HTTP {}",
-            endpoint,
-            service_name,
-            response.status()
-        )
+fn toml_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("wastearr/config.toml"))
+}
+
+/// How a config.toml value is written out as its flat key
+#[derive(Debug, Clone, Copy)]
+enum TomlKind {
+    Text,
+    Integer,
+    Number,
+    Bool,
+    // A string or an integer, such as an ntfy priority or a Telegram chat ID
+    Scalar,
+    // An array of strings (or one comma-separated string), joined with commas
+    List,
+    // Header names to values, as `Name: value; Name: value`
+    Headers,
+    // Keys to values, as `key=value,key=value`
+    Pairs,
+}
+
+impl TomlKind {
+    fn describe(self) -> &'static str {
+        match self {
+            TomlKind::Text => "a string",
+            TomlKind::Integer => "an integer",
+            TomlKind::Number => "a number",
+            TomlKind::Bool => "true or false",
+            TomlKind::Scalar => "a string or an integer",
+            TomlKind::List => "an array of strings",
+            TomlKind::Headers | TomlKind::Pairs => "a table of strings",
+        }
     }
 }
 
-fn scan_api_data(
-    base_url: &str,
-    api_key: Option<&String>,
-    endpoint: &str,
-    service_name: &str,
-    item_type: &str,
-    cache_stats: &mut (usize, usize),
-    cache: &mut Option<&mut HashMap<String, String>>,
-) -> Result<Vec<Item>> {
-    let api_key = api_key.with_context(|| {
-        format!(
-            "{}_API_KEY environment variable not set",
-            service_name.to_uppercase()
-        )
-    })?;
-    let data = fetch_api_data(base_url, api_key, endpoint, service_name)?;
+/// The config.toml settings and the flat keys they stand for. Per-connection
+/// settings ([network] and each instance) and the routing settings of each
+/// notifier are in TOML_CONNECTION_KEYS and TOML_ROUTE_KEYS
+const TOML_KEYS: &[(&str, &str, TomlKind)] = &[
+    ("concurrency", "WASTEARR_CONCURRENCY", TomlKind::Integer),
+    ("cache.dir", "WASTEARR_CACHE_DIR", TomlKind::Text),
+    (
+        "cache.library_ttl",
+        "WASTEARR_LIBRARY_CACHE_TTL",
+        TomlKind::Integer,
+    ),
+    ("state.dir", "WASTEARR_STATE_DIR", TomlKind::Text),
+    (
+        "state.history_items",
+        "WASTEARR_HISTORY_ITEMS",
+        TomlKind::Bool,
+    ),
+    (
+        "scoring.tv_factor",
+        "WASTEARR_SCORE_TV_FACTOR",
+        TomlKind::Number,
+    ),
+    (
+        "scoring.unrated_rating",
+        "WASTEARR_SCORE_UNRATED_RATING",
+        TomlKind::Number,
+    ),
+    ("serve.token", "WASTEARR_SERVE_TOKEN", TomlKind::Text),
+    ("serve.username", "WASTEARR_SERVE_USERNAME", TomlKind::Text),
+    ("serve.password", "WASTEARR_SERVE_PASSWORD", TomlKind::Text),
+    (
+        "serve.auth_header",
+        "WASTEARR_SERVE_AUTH_HEADER",
+        TomlKind::Text,
+    ),
+    (
+        "serve.trusted_proxies",
+        "WASTEARR_SERVE_TRUSTED_PROXIES",
+        TomlKind::List,
+    ),
+    (
+        "serve.webhook_secret",
+        "WASTEARR_SERVE_WEBHOOK_SECRET",
+        TomlKind::Text,
+    ),
+    ("notify.dedupe", "WASTEARR_NOTIFY_DEDUPE", TomlKind::Bool),
+    (
+        "notify.quiet_hours",
+        "WASTEARR_NOTIFY_QUIET_HOURS",
+        TomlKind::Text,
+    ),
+    ("notify.digest", "WASTEARR_NOTIFY_DIGEST", TomlKind::Text),
+    (
+        "notify.discord.webhook",
+        "WASTEARR_DISCORD_WEBHOOK",
+        TomlKind::Text,
+    ),
+    (
+        "notify.slack.webhook",
+        "WASTEARR_SLACK_WEBHOOK",
+        TomlKind::Text,
+    ),
+    (
+        "notify.telegram.token",
+        "WASTEARR_TELEGRAM_TOKEN",
+        TomlKind::Text,
+    ),
+    (
+        "notify.telegram.chat_id",
+        "WASTEARR_TELEGRAM_CHAT_ID",
+        TomlKind::Scalar,
+    ),
+    (
+        "notify.telegram.api_url",
+        "WASTEARR_TELEGRAM_API_URL",
+        TomlKind::Text,
+    ),
+    ("notify.ntfy.url", "WASTEARR_NTFY_URL", TomlKind::Text),
+    ("notify.ntfy.token", "WASTEARR_NTFY_TOKEN", TomlKind::Text),
+    (
+        "notify.ntfy.priority",
+        "WASTEARR_NTFY_PRIORITY",
+        TomlKind::Scalar,
+    ),
+    ("notify.ntfy.title", "WASTEARR_NTFY_TITLE", TomlKind::Text),
+    ("notify.gotify.url", "WASTEARR_GOTIFY_URL", TomlKind::Text),
+    (
+        "notify.gotify.token",
+        "WASTEARR_GOTIFY_TOKEN",
+        TomlKind::Text,
+    ),
+    (
+        "notify.gotify.priority",
+        "WASTEARR_GOTIFY_PRIORITY",
+        TomlKind::Integer,
+    ),
+    (
+        "notify.email.smtp_host",
+        "WASTEARR_SMTP_HOST",
+        TomlKind::Text,
+    ),
+    (
+        "notify.email.smtp_port",
+        "WASTEARR_SMTP_PORT",
+        TomlKind::Integer,
+    ),
+    ("notify.email.smtp_tls", "WASTEARR_SMTP_TLS", TomlKind::Text),
+    (
+        "notify.email.smtp_username",
+        "WASTEARR_SMTP_USERNAME",
+        TomlKind::Text,
+    ),
+    (
+        "notify.email.smtp_password",
+        "WASTEARR_SMTP_PASSWORD",
+        TomlKind::Text,
+    ),
+    ("notify.email.from", "WASTEARR_EMAIL_FROM", TomlKind::Text),
+    ("notify.email.to", "WASTEARR_EMAIL_TO", TomlKind::List),
+    (
+        "notify.email.format",
+        "WASTEARR_EMAIL_FORMAT",
+        TomlKind::Text,
+    ),
+    ("notify.apprise.url", "WASTEARR_APPRISE_URL", TomlKind::Text),
+    (
+        "notify.apprise.urls",
+        "WASTEARR_APPRISE_URLS",
+        TomlKind::List,
+    ),
+    ("notify.apprise.tag", "WASTEARR_APPRISE_TAG", TomlKind::Text),
+    ("notify.webhook.url", "WASTEARR_WEBHOOK_URL", TomlKind::List),
+    (
+        "notify.webhook.headers",
+        "WASTEARR_WEBHOOK_HEADERS",
+        TomlKind::Headers,
+    ),
+    (
+        "notify.webhook.template",
+        "WASTEARR_WEBHOOK_TEMPLATE",
+        TomlKind::Text,
+    ),
+    (
+        "healthcheck.url",
+        "WASTEARR_HEALTHCHECK_URL",
+        TomlKind::Text,
+    ),
+    (
+        "otel.endpoint",
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        TomlKind::Text,
+    ),
+    (
+        "otel.traces_endpoint",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+        TomlKind::Text,
+    ),
+    (
+        "otel.metrics_endpoint",
+        "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT",
+        TomlKind::Text,
+    ),
+    (
+        "otel.headers",
+        "OTEL_EXPORTER_OTLP_HEADERS",
+        TomlKind::Pairs,
+    ),
+    ("otel.service_name", "OTEL_SERVICE_NAME", TomlKind::Text),
+    ("schedule.scan", "WASTEARR_SCHEDULE_SCAN", TomlKind::Text),
+    ("schedule.stats", "WASTEARR_SCHEDULE_STATS", TomlKind::Text),
+    (
+        "schedule.wrapped",
+        "WASTEARR_SCHEDULE_WRAPPED",
+        TomlKind::Text,
+    ),
+    ("report.dir", "WASTEARR_REPORT_DIR", TomlKind::Text),
+    ("report.keep", "WASTEARR_REPORT_KEEP", TomlKind::Integer),
+    ("low_space.threshold", "WASTEARR_LOW_SPACE", TomlKind::Text),
+    (
+        "low_space.disks",
+        "WASTEARR_LOW_SPACE_DISKS",
+        TomlKind::List,
+    ),
+    (
+        "low_space.interval",
+        "WASTEARR_LOW_SPACE_INTERVAL",
+        TomlKind::Text,
+    ),
+];
 
-    Ok(data
-        .iter()
-        .filter_map(|item| {
-            let id = item.get("id")?.as_i64()? as i32;
-            let title = item.get("title")?.as_str()?.to_string();
-            let year = item.get("year")?.as_i64()? as i32;
+/// Settings of [network], as WASTEARR_*, and of each [[sonarr]]/[[radarr]]
+/// entry under its own prefix
+const TOML_CONNECTION_KEYS: &[(&str, &str, TomlKind)] = &[
+    ("retries", "RETRIES", TomlKind::Integer),
+    ("retry_backoff_ms", "RETRY_BACKOFF_MS", TomlKind::Integer),
+    ("retry_jitter", "RETRY_JITTER", TomlKind::Number),
+    ("timeout", "TIMEOUT", TomlKind::Number),
+    ("connect_timeout", "CONNECT_TIMEOUT", TomlKind::Number),
+    ("rate_limit", "RATE_LIMIT", TomlKind::Number),
+    ("ca_bundle", "CA_BUNDLE", TomlKind::Text),
+    ("insecure", "INSECURE", TomlKind::Bool),
+    ("username", "USERNAME", TomlKind::Text),
+    ("password", "PASSWORD", TomlKind::Text),
+    ("headers", "HEADERS", TomlKind::Headers),
+];
 
-            let size_bytes = if item_type == "show" {
-                item.get("statistics")?.get("sizeOnDisk")?.as_u64()?
-            } else {
-                item.get("sizeOnDisk")?.as_u64()?
-            };
+/// Routing settings every [notify.<backend>] table takes, as WASTEARR_<BACKEND>_*
+const TOML_ROUTE_KEYS: &[(&str, &str, TomlKind)] = &[
+    ("events", "EVENTS", TomlKind::List),
+    ("min_severity", "MIN_SEVERITY", TomlKind::Text),
+    ("dedupe", "DEDUPE", TomlKind::Bool),
+    ("quiet_hours", "QUIET_HOURS", TomlKind::Text),
+    ("digest", "DIGEST", TomlKind::Text),
+];
 
-            if size_bytes == 0 {
-                return None;
-            }
+fn read_toml_config(path: &Path) -> Result<Option<toml::Table>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    toml::from_str(&contents)
+        .map(Some)
+        .with_context(|| format!("Invalid {}", path.display()))
+}
 
-            let mut rating = item
-                .get("ratings")
-                .and_then(|r| {
-                    if item_type == "show" {
-                        r.get("value")
-                    } else {
-                        r.get("tmdb")?.get("value")
-                    }
-                })
-                .and_then(|v| v.as_f64())
-                .filter(|&r| r > 0.0)
-                .map(|r| format!("{:.1}", r))
-                .unwrap_or_else(|| "N/A".to_string());
-
-            let cache_key = id.to_string();
-            if let Some(cache_ref) = cache {
-                if let Some(cached_rating) = cache_ref.get(&cache_key) {
-                    cache_stats.0 += 1;
-                    rating = cached_rating.clone();
-                } else {
-                    cache_stats.1 += 1;
-                    cache_ref.insert(cache_key, rating.clone());
-                }
+/// Fail early on a config.toml that wouldn't be read as meant, rather than
+/// have its settings quietly missing
+fn check_toml_config() -> Result<()> {
+    let Some(path) = toml_config_path() else {
+        return Ok(());
+    };
+    if let Some(table) = read_toml_config(&path)? {
+        flatten_toml(&table).with_context(|| format!("Invalid {}", path.display()))?;
+    }
+    load_protect_rules()?;
+    Ok(())
+}
+
+/// Turn config.toml into the flat keys of the older config format, which is
+/// what get_config_value looks up
+fn flatten_toml(table: &toml::Table) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    for (key, value) in table {
+        match key.as_str() {
+            "sonarr" | "radarr" => flatten_toml_instances(key, value, &mut vars)?,
+            // Read by load_protect_rules, having no flat form
+            "protect" => {}
+            _ => flatten_toml_value(key, value, &mut vars)?,
+        }
+    }
+    Ok(vars)
+}
+
+fn flatten_toml_value(
+    path: &str,
+    value: &toml::Value,
+    vars: &mut HashMap<String, String>,
+) -> Result<()> {
+    if let Some((key, kind)) = toml_key(path) {
+        vars.insert(key, toml_flat_value(path, value, kind)?);
+        return Ok(());
+    }
+    match value {
+        toml::Value::Table(table) if is_toml_section(path) => {
+            for (key, value) in table {
+                flatten_toml_value(&format!("{}.{}", path, key), value, vars)?;
             }
+            Ok(())
+        }
+        _ => anyhow::bail!("unknown setting {}", path),
+    }
+}
 
-            Some(Item {
-                name: title,
-                year,
-                size_bytes,
-                rating,
-                item_type: item_type.to_string(),
-                waste_score: 0,
-            })
+/// The flat key and type of a setting given by its dotted path
+fn toml_key(path: &str) -> Option<(String, TomlKind)> {
+    if let Some(&(_, key, kind)) = TOML_KEYS.iter().find(|(name, ..)| *name == path) {
+        return Some((key.to_string(), kind));
+    }
+    if let Some(name) = path.strip_prefix("network.") {
+        return connection_key("WASTEARR", name);
+    }
+    let (backend, name) = path.strip_prefix("notify.")?.split_once('.')?;
+    if !is_toml_section(&format!("notify.{}", backend)) {
+        return None;
+    }
+    TOML_ROUTE_KEYS
+        .iter()
+        .find(|(route, ..)| *route == name)
+        .map(|&(_, suffix, kind)| {
+            (
+                format!("WASTEARR_{}_{}", backend.to_uppercase(), suffix),
+                kind,
+            )
         })
-        .collect())
 }
 
-fn validate_api_connectivity(config: &Config, scan_types: &[String]) -> Result<()> {
-    let client = Client::new();
-    let api_errors: Vec<String> = scan_types
+fn connection_key(prefix: &str, name: &str) -> Option<(String, TomlKind)> {
+    TOML_CONNECTION_KEYS
         .iter()
-        .filter_map(|scan_type| {
-            let (url, api_key, service_name) = match scan_type.as_str() {
-                "sonarr" => (&config.sonarr_url, config.sonarr_api_key.as_ref(), "Sonarr"),
-                "radarr" => (&config.radarr_url, config.radarr_api_key.as_ref(), "Radarr"),
-                _ => return None,
-            };
+        .find(|(setting, ..)| *setting == name)
+        .map(|&(_, suffix, kind)| (format!("{}_{}", prefix, suffix), kind))
+}
 
-            api_key.map_or(
-                Some(format!(
-                    "{}_API_KEY environment variable not set",
-                    service_name.to_uppercase()
-                )),
-                |key| match client
-                    .get(format!("{}/api/v3/system/status", url))
-                    .header("X-Api-Key", key)
-                    .timeout(std::time::Duration::from_secs(5))
-                    .send()
-                {
-                    Ok(resp) if resp.status().is_success() => None,
-                    Ok(resp) => Some(format!(
-                        "{} API unreachable at {} (HTTP {})",
-                        service_name,
-                        url,
-                        resp.status()
-                    )),
-                    Err(e) => Some(format!(
-                        "Cannot connect to {} at {}: {}",
-                        service_name, url, e
-                    )),
-                },
-            )
+fn is_toml_section(path: &str) -> bool {
+    path == "network"
+        || TOML_KEYS.iter().any(|(name, ..)| {
+            name.strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('.'))
         })
-        .collect();
+}
 
-    if !api_errors.is_empty() {
-        eprintln!("Error: API connectivity issues detected:");
-        api_errors
-            .iter()
-            .for_each(|error| eprintln!("  - {}", error));
-        eprintln!("\nPlease ensure:");
-        eprintln!("  - Sonarr/Radarr services are running");
-        eprintln!("  - API keys are correctly set via environment variables");
-        eprintln!("  - URLs are accessible");
-        anyhow::bail!("API connectivity validation failed");
+/// [[sonarr]] and [[radarr]] entries: the one without a name is the main
+/// instance, and the named ones are listed in SONARR_INSTANCES/RADARR_INSTANCES
+fn flatten_toml_instances(
+    kind: &str,
+    value: &toml::Value,
+    vars: &mut HashMap<String, String>,
+) -> Result<()> {
+    let entries = value.as_array().with_context(|| {
+        format!(
+            "{} should be an array of tables, written [[{}]]",
+            kind, kind
+        )
+    })?;
+    let mut names = Vec::new();
+    let mut has_main = false;
+    for entry in entries {
+        let entry = entry.as_table().with_context(|| {
+            format!(
+                "{} should be an array of tables, written [[{}]]",
+                kind, kind
+            )
+        })?;
+        let prefix = match entry.get("name") {
+            None if has_main => {
+                anyhow::bail!("only one [[{}]] entry can leave out its name", kind)
+            }
+            None => {
+                has_main = true;
+                kind.to_uppercase()
+            }
+            Some(toml::Value::String(name)) => {
+                let name = name.trim().to_lowercase();
+                if name.is_empty() || name.contains(',') {
+                    anyhow::bail!("{}.name must be a non-empty name without commas", kind);
+                }
+                let prefix = format!(
+                    "{}_{}",
+                    kind.to_uppercase(),
+                    name.to_uppercase().replace('-', "_")
+                );
+                names.push(name);
+                prefix
+            }
+            Some(_) => anyhow::bail!("{}.name should be a string", kind),
+        };
+        for (key, value) in entry {
+            let path = format!("{}.{}", kind, key);
+            let (flat, setting) = match key.as_str() {
+                "name" => continue,
+                "url" => (format!("{}_URL", prefix), TomlKind::Text),
+                "api_key" => (format!("{}_API_KEY", prefix), TomlKind::Text),
+                _ => connection_key(&prefix, key)
+                    .with_context(|| format!("unknown setting {}", path))?,
+            };
+            vars.insert(flat, toml_flat_value(&path, value, setting)?);
+        }
+    }
+    if !names.is_empty() {
+        vars.insert(
+            format!("{}_INSTANCES", kind.to_uppercase()),
+            names.join(","),
+        );
     }
-
     Ok(())
 }
 
-fn load_cache() -> (HashMap<String, String>, HashMap<String, String>) {
-    cache_dir()
-        .and_then(|dir| {
-            let cache_path = dir.join("wastearr/cache.json");
-            if !cache_path.exists() {
-                println!("No existing cache found");
-                return None;
-            }
-
-            fs::read_to_string(&cache_path).ok().and_then(|contents| {
-                serde_json::from_str::<CacheData>(&contents)
-                    .ok()
-                    .and_then(|cache_data| {
-                        let current_time = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs_f64();
-                        if current_time - cache_data.timestamp > CACHE_DURATION as f64 {
-                            println!("Cache expired, removing old cache file");
-                            let _ = fs::remove_file(&cache_path);
-                            None
-                        } else {
-                            println!("Loading cache from {}", cache_path.display());
-                            Some((cache_data.sonarr_ratings, cache_data.radarr_ratings))
-                        }
-                    })
-                    .or_else(|| {
-                        println!("Cache corrupted, starting fresh");
-                        let _ = fs::remove_file(&cache_path);
-                        None
-                    })
+fn toml_flat_value(path: &str, value: &toml::Value, kind: TomlKind) -> Result<String> {
+    use toml::Value as V;
+    let strings = |table: &toml::Table, separator: &str, joiner: &str| {
+        table
+            .iter()
+            .map(|(key, value)| {
+                value
+                    .as_str()
+                    .map(|value| format!("{}{}{}", key, separator, value))
             })
-        })
-        .unwrap_or_else(|| {
-            if cache_dir().is_none() {
-                println!("No cache directory available");
-            }
-            (HashMap::new(), HashMap::new())
-        })
+            .collect::<Option<Vec<_>>>()
+            .map(|pairs| pairs.join(joiner))
+    };
+    let flat = match (kind, value) {
+        (TomlKind::Text | TomlKind::Scalar | TomlKind::List, V::String(text)) => Some(text.clone()),
+        (TomlKind::Integer | TomlKind::Number | TomlKind::Scalar, V::Integer(n)) => {
+            Some(n.to_string())
+        }
+        (TomlKind::Number, V::Float(n)) => Some(n.to_string()),
+        (TomlKind::Bool, V::Boolean(b)) => Some(b.to_string()),
+        (TomlKind::List, V::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        (TomlKind::Headers, V::Table(table)) => strings(table, ": ", "; "),
+        (TomlKind::Pairs, V::Table(table)) => strings(table, "=", ","),
+        _ => None,
+    };
+    flat.with_context(|| format!("{} should be {}", path, kind.describe()))
 }
 
-fn save_cache(sonarr_cache: &HashMap<String, String>, radarr_cache: &HashMap<String, String>) {
-    if let Some(cache_path) = cache_dir().map(|d| d.join("wastearr/cache.json")) {
-        let cache_data = CacheData {
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs_f64(),
-            sonarr_ratings: sonarr_cache.clone(),
-            radarr_ratings: radarr_cache.clone(),
-        };
-        println!(
-            "Saving cache with {} ratings",
-            sonarr_cache.len() + radarr_cache.len()
-        );
-        if let Some(parent) = cache_path.parent() {
-            let _ = fs::create_dir_all(parent);
+/// A [[protect]] rule of config.toml. Items matching every condition it sets
+/// keep a waste score of 0, so they're never flagged
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct ProtectRule {
+    // Part of the title, in any case
+    name: Option<String>,
+    instance: Option<String>,
+    // "show" or "movie"
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    genre: Option<String>,
+    // Folder the item lives under
+    path: Option<String>,
+    // Items rated at least this
+    min_rating: Option<f64>,
+}
+
+impl ProtectRule {
+    fn matches(&self, item: &Item) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| item.name.to_lowercase().contains(&name.to_lowercase()))
+            && self
+                .instance
+                .as_ref()
+                .is_none_or(|instance| *instance == item.instance)
+            && self
+                .item_type
+                .as_ref()
+                .is_none_or(|kind| *kind == item.item_type)
+            && self
+                .genre
+                .as_ref()
+                .is_none_or(|genre| item.genres.iter().any(|g| g.eq_ignore_ascii_case(genre)))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| Path::new(&item.path).starts_with(path))
+            && self
+                .min_rating
+                .is_none_or(|min| item.rating.parse::<f64>().is_ok_and(|rating| rating >= min))
+    }
+}
+
+fn load_protect_rules() -> Result<Vec<ProtectRule>> {
+    let Some(path) = toml_config_path() else {
+        return Ok(Vec::new());
+    };
+    let Some(rules) = read_toml_config(&path)?.and_then(|mut table| table.remove("protect")) else {
+        return Ok(Vec::new());
+    };
+    let rules: Vec<ProtectRule> = rules
+        .try_into()
+        .with_context(|| format!("Invalid [[protect]] rule in {}", path.display()))?;
+    for (number, rule) in rules.iter().enumerate().map(|(i, rule)| (i + 1, rule)) {
+        if *rule == ProtectRule::default() {
+            anyhow::bail!(
+                "[[protect]] rule {} in {} has no conditions and would protect everything",
+                number,
+                path.display()
+            );
         }
-        if let Ok(json) = serde_json::to_string(&cache_data) {
-            let _ = fs::write(&cache_path, json);
+        if rule
+            .item_type
+            .as_deref()
+            .is_some_and(|kind| !matches!(kind, "show" | "movie"))
+        {
+            anyhow::bail!(
+                "[[protect]] rule {} in {}: type must be \"show\" or \"movie\"",
+                number,
+                path.display()
+            );
         }
     }
+    Ok(rules)
 }
 
-fn calculate_size_score(size_bytes: u64) -> f64 {
-    let size_gb = size_bytes as f64 / (1024.0_f64.powi(3));
+fn get_config_value(key: &str) -> Option<String> {
+    env::var(key).ok().or_else(|| {
+        config_files()
+            .iter()
+            .find_map(|path| load_file_vars(path).get(key).cloned())
+    })
+}
 
-    if size_gb <= 1.0 {
-        size_gb * 10.0
-    } else {
-        10.0 + (size_gb.log10() * 30.0)
+/// The settings from the config files, each key from the first file that has it
+fn read_config_files() -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for path in config_files() {
+        for (key, value) in load_file_vars(&path) {
+            vars.entry(key).or_insert(value);
+        }
     }
-    .min(80.0)
+    vars
 }
 
-fn get_rating_multiplier(rating: f64, is_tv: bool) -> f64 {
-    let multipliers = if is_tv {
-        [0.05, 0.15, 0.35, 0.55, 0.75, 1.1] // TV: more forgiving
-    } else {
-        [0.1, 0.2, 0.4, 0.6, 0.8, 1.2] // Movies: stricter
-    };
+/// Polls the config files of a long-running process for edits. Scans read
+/// the config afresh, so instances, notifiers and thresholds follow an edit
+/// on their own; this is for logging the change and for what `watch` and
+/// `serve` set up once at start
+struct ConfigWatch {
+    vars: HashMap<String, String>,
+}
 
-    let thresholds = [8.0, 7.5, 7.0, 6.5, 6.0];
-    thresholds
-        .iter()
-        .position(|&threshold| rating >= threshold)
-        .map(|i| multipliers[i])
-        .unwrap_or(multipliers[5])
+impl ConfigWatch {
+    fn new() -> Self {
+        Self {
+            vars: read_config_files(),
+        }
+    }
+
+    /// Keys added, changed or removed since the last poll. Keys set in the
+    /// environment are left out, since the environment wins over the files
+    fn changes(&mut self) -> Vec<String> {
+        if let Err(e) = check_toml_config() {
+            warn!("{:#}", e);
+        }
+        let vars = read_config_files();
+        let mut changes: Vec<String> = vars
+            .iter()
+            .filter_map(|(key, value)| match self.vars.get(key) {
+                None => Some(format!("{} added", key)),
+                Some(old) if old != value => Some(format!("{} changed", key)),
+                _ => None,
+            })
+            .chain(
+                self.vars
+                    .keys()
+                    .filter(|key| !vars.contains_key(*key))
+                    .map(|key| format!("{} removed", key)),
+            )
+            .filter(|change| {
+                let key = change.split(' ').next().unwrap_or_default();
+                env::var_os(key).is_none()
+            })
+            .collect();
+        changes.sort();
+        self.vars = vars;
+        changes
+    }
 }
 
-fn calculate_normalized_waste_score(item: &mut Item) {
-    let rating = item.rating.parse::<f64>().unwrap_or(6.0);
-    let base_size_score = calculate_size_score(item.size_bytes);
-    let is_tv = item.item_type == "show";
+fn config_changed(changes: &[String], prefix: &str) -> bool {
+    changes.iter().any(|change| change.starts_with(prefix))
+}
 
-    let normalized_size = if is_tv {
-        base_size_score * 0.6
-    } else {
-        base_size_score
-    };
-    let waste_score = normalized_size * get_rating_multiplier(rating, is_tv);
-    item.waste_score = (waste_score.round() as i32).clamp(0, 100);
+/// A per-instance setting such as RADARR_4K_RETRIES, falling back to the
+/// WASTEARR_-prefixed default for all instances
+fn instance_setting<T: FromStr>(env_prefix: &str, key: &str) -> Option<T> {
+    get_config_value(&format!("{}_{}", env_prefix, key))
+        .or_else(|| get_config_value(&format!("WASTEARR_{}", key)))
+        .and_then(|value| value.trim().parse().ok())
 }
 
-fn format_file_size(size_bytes: u64) -> String {
-    let units = ["B", "KB", "MB", "GB", "TB"];
-    let mut size = size_bytes as f64;
-    let mut unit_index = 0;
+fn seconds_setting(env_prefix: &str, key: &str) -> Option<Duration> {
+    instance_setting::<f64>(env_prefix, key)
+        .filter(|&secs| secs > 0.0)
+        .map(Duration::from_secs_f64)
+}
 
-    while size >= 1024.0 && unit_index < units.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+/// Parse "Name: value; Other-Name: value" into headers sent with every request
+fn parse_extra_headers(spec: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, value) = entry
+            .split_once(':')
+            .with_context(|| format!("expected 'Name: value', got '{}'", entry))?;
+        headers.insert(
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .with_context(|| format!("invalid header name '{}'", name.trim()))?,
+            HeaderValue::from_str(value.trim())
+                .with_context(|| format!("invalid value for header '{}'", name.trim()))?,
+        );
     }
+    Ok(headers)
+}
 
-    format!("{:.1} {}", size, units[unit_index])
+fn flag_setting(env_prefix: &str, key: &str) -> bool {
+    instance_setting::<String>(env_prefix, key)
+        .is_some_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
 }
 
-fn parse_size_string(size_str: &str) -> Result<u64> {
-    let re = Regex::new(r"^(\d+(?:\.\d+)?)\s*([KMGTB]?B?)?$").unwrap();
-    let size_upper = size_str.to_uppercase();
+fn new_instance(name: String, kind: &str, url: String, env_prefix: String) -> Result<Instance> {
+    let mut builder = Client::builder().connect_timeout(
+        seconds_setting(&env_prefix, "CONNECT_TIMEOUT").unwrap_or(CONNECT_TIMEOUT),
+    );
+    // Home-lab instances often sit behind a private CA or a self-signed cert
+    if let Some(bundle) = instance_setting::<String>(&env_prefix, "CA_BUNDLE") {
+        let pem = fs::read(&bundle)
+            .with_context(|| format!("Failed to read CA bundle {} for {}", bundle, name))?;
+        for cert in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA bundle {} for {}", bundle, name))?
+        {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if flag_setting(&env_prefix, "INSECURE") {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(headers) = instance_setting::<String>(&env_prefix, "HEADERS") {
+        builder = builder.default_headers(
+            parse_extra_headers(&headers)
+                .with_context(|| format!("Invalid extra headers configured for {}", name))?,
+        );
+    }
+    let client = builder
+        .build()
+        .with_context(|| format!("Failed to set up HTTP client for {}", name))?;
+    Ok(Instance {
+        name,
+        kind: kind.to_string(),
+        url: normalize_url(&url),
+        api_key: get_config_value(&format!("{}_API_KEY", env_prefix)),
+        retry: RetryPolicy {
+            retries: instance_setting(&env_prefix, "RETRIES").unwrap_or(DEFAULT_RETRIES),
+            backoff: Duration::from_millis(
+                instance_setting(&env_prefix, "RETRY_BACKOFF_MS").unwrap_or(DEFAULT_BACKOFF_MS),
+            ),
+            jitter: instance_setting(&env_prefix, "RETRY_JITTER")
+                .unwrap_or(DEFAULT_RETRY_JITTER)
+                .clamp(0.0, 1.0),
+        },
+        client,
+        basic_auth: instance_setting::<String>(&env_prefix, "USERNAME")
+            .map(|username| (username, instance_setting(&env_prefix, "PASSWORD"))),
+        request_timeout: seconds_setting(&env_prefix, "TIMEOUT"),
+        rate_limit: None,
+        version: None,
+        canned: None,
+        recorder: None,
+        env_prefix,
+    })
+}
 
-    let captures = re
-        .captures(&size_upper)
-        .context(format!("Invalid size format: {}", size_str))?;
+fn major_version(version: &str) -> Option<u32> {
+    version.split('.').next()?.parse().ok()
+}
 
-    let number: f64 = captures
-        .get(1)
-        .unwrap()
-        .as_str()
-        .parse()
-        .context("Invalid number in size string")?;
+/// Give each host one limiter, at the strictest rate any of its instances asks for
+fn assign_rate_limits(instances: &mut [Instance]) {
+    let mut by_host: HashMap<String, f64> = HashMap::new();
+    for instance in instances.iter() {
+        if let Some(rate) =
+            instance_setting::<f64>(&instance.env_prefix, "RATE_LIMIT").filter(|&r| r > 0.0)
+        {
+            let entry = by_host.entry(url_host(&instance.url)).or_insert(rate);
+            *entry = entry.min(rate);
+        }
+    }
+    let limiters: HashMap<String, Arc<RateLimiter>> = by_host
+        .into_iter()
+        .map(|(host, rate)| (host, Arc::new(RateLimiter::new(rate))))
+        .collect();
+    for instance in instances {
+        instance.rate_limit = limiters.get(&url_host(&instance.url)).cloned();
+    }
+}
 
-    let unit = captures.get(2).map(|m| m.as_str()).unwrap_or("B");
+/// The primary SONARR_*/RADARR_* instances plus any extras named in
+/// SONARR_INSTANCES/RADARR_INSTANCES (e.g. RADARR_INSTANCES=4k reads RADARR_4K_URL)
+fn load_config() -> Result<Config> {
+    let mut instances = Vec::new();
+    for (kind, default_url) in [
+        ("sonarr", "http://localhost:8989"),
+        ("radarr", "http://localhost:7878"),
+    ] {
+        let prefix = kind.to_uppercase();
+        instances.push(new_instance(
+            kind.to_string(),
+            kind,
+            get_config_value(&format!("{}_URL", prefix)).unwrap_or_else(|| default_url.to_string()),
+            prefix.clone(),
+        )?);
 
-    let multiplier = match unit {
-        "B" => 1,
-        "K" | "KB" => 1024,
-        "M" | "MB" => 1024_u64.pow(2),
-        "G" | "GB" => 1024_u64.pow(3),
-        "T" | "TB" => 1024_u64.pow(4),
-        _ => anyhow::bail!("Unknown unit: {}", unit),
-    };
+        let extra_names = get_config_value(&format!("{}_INSTANCES", prefix)).unwrap_or_default();
+        for extra in extra_names
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+        {
+            let env_prefix = format!("{}_{}", prefix, extra.to_uppercase().replace('-', "_"));
+            let Some(url) = get_config_value(&format!("{}_URL", env_prefix)) else {
+                warn!(
+                    "{}_URL not set, skipping {} instance '{}'",
+                    env_prefix, kind, extra
+                );
+                continue;
+            };
+            instances.push(new_instance(
+                format!("{}-{}", kind, extra),
+                kind,
+                url,
+                env_prefix,
+            )?);
+        }
+    }
+    assign_rate_limits(&mut instances);
+    Ok(Config {
+        instances,
+        concurrency: get_config_value("WASTEARR_CONCURRENCY")
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_CONCURRENCY),
+        scoring: Scoring::load()?,
+        protect: load_protect_rules()?,
+    })
+}
 
-    Ok((number * multiplier as f64) as u64)
+// API models hold only the fields wastearr reads; serde skips the rest of each
+// object without building a Value tree for it. Missing or null fields fall back
+// to their defaults, and records without an id, title or year are dropped later,
+// so one odd entry can't fail the whole library.
+
+/// A series from Sonarr's /series endpoint
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct SeriesRecord {
+    id: Option<i32>,
+    title: Option<String>,
+    year: Option<i32>,
+    first_aired: Option<String>,
+    statistics: Option<SeriesStatistics>,
+    ratings: SeriesRatings,
+    genres: Vec<String>,
+    path: String,
+    root_folder_path: String,
+    title_slug: Option<String>,
+    quality_profile_id: Option<i64>,
+    seasons: Vec<SeasonRecord>,
+    tvdb_id: Option<i64>,
 }
 
-fn median(mut values: Vec<f64>) -> f64 {
-    if values.is_empty() {
-        return 0.0;
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+struct SeriesStatistics {
+    size_on_disk: u64,
+    episode_file_count: u64,
+    total_episode_count: u64,
+    season_count: u64,
+    percent_of_episodes: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SeriesRatings {
+    value: Option<f64>,
+    votes: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct SeasonRecord {
+    season_number: Option<i64>,
+    statistics: SeriesStatistics,
+}
+
+/// A movie from Radarr's /movie endpoint
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct MovieRecord {
+    id: Option<i32>,
+    title: Option<String>,
+    year: Option<i32>,
+    size_on_disk: u64,
+    ratings: MovieRatings,
+    genres: Vec<String>,
+    path: String,
+    root_folder_path: String,
+    title_slug: Option<String>,
+    quality_profile_id: Option<i64>,
+    movie_file: Option<MediaFile>,
+    collection: Option<CollectionRecord>,
+    tmdb_id: Option<i64>,
+}
+
+/// Radarr v4+ nests scores per source, of which wastearr uses TMDB's;
+/// v3 has a single flat TMDB score
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MovieRatings {
+    tmdb: Option<SeriesRatings>,
+    value: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CollectionRecord {
+    // Radarr v3 calls the collection title "name", later versions "title"
+    title: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct QualityProfileRecord {
+    id: Option<i64>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct DiskSpaceRecord {
+    path: Option<String>,
+    label: String,
+    free_space: u64,
+    total_space: u64,
+}
+
+/// An episode or movie file as returned by /episodefile and /moviefile
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct MediaFile {
+    size: Option<u64>,
+    media_info: Option<MediaInfoRecord>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct MediaInfoRecord {
+    resolution: Option<String>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+    audio_channels: Option<f64>,
+}
+
+async fn fetch_api_data<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+) -> Result<Vec<T>> {
+    let data = request_api_data(instance, endpoint).await?;
+    progress!(
+        "Fetched {} {}s from {} API",
+        data.len(),
+        endpoint,
+        instance.service_name()
+    );
+    Ok(data)
+}
+
+/// Validators from a library response, sent back on the next fetch so an
+/// unchanged list isn't downloaded and parsed again
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Validators {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
     }
-    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let mid = values.len() / 2;
-    if values.len() % 2 == 0 {
-        (values[mid - 1] + values[mid]) / 2.0
-    } else {
-        values[mid]
+
+    fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let pairs = [
+            (reqwest::header::IF_NONE_MATCH, &self.etag),
+            (reqwest::header::IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in pairs {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
     }
 }
 
-fn mode(values: &[f64]) -> f64 {
-    if values.is_empty() {
-        return 0.0;
+/// Fetch a library list, or None when the server confirms it hasn't changed
+/// since the response `previous` was taken from
+async fn fetch_library<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+    previous: &Validators,
+) -> Result<Option<(Vec<T>, Validators)>> {
+    let service_name = instance.service_name();
+    let api_key = instance.require_api_key()?;
+    let timeout = instance.request_timeout.unwrap_or(REQUEST_TIMEOUT);
+    let response = send_request(
+        instance,
+        api_key,
+        endpoint,
+        timeout,
+        &previous.conditional_headers(),
+    )
+    .await
+    .with_context(|| format!("Failed to connect to {} API", service_name))?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        progress!(
+            "{} {} list unchanged since the last fetch",
+            service_name,
+            endpoint
+        );
+        return Ok(None);
     }
-    let mut counts = HashMap::new();
-    for &v in values {
-        *counts.entry((v * 10.0).round() as i32).or_insert(0) += 1;
+
+    let validators = Validators::from_response(&response);
+    let data = parse_body(
+        instance,
+        endpoint,
+        checked_body(instance, endpoint, response)?,
+    )
+    .await?;
+    progress!(
+        "Fetched {} {}s from {} API",
+        data.len(),
+        endpoint,
+        service_name
+    );
+    Ok(Some((data, validators)))
+}
+
+/// One API request attempt, timed until the response headers arrived
+#[derive(Debug, Clone)]
+struct RequestSample {
+    instance: String,
+    endpoint: String,
+    started_at: f64,
+    duration: Duration,
+    ok: bool,
+}
+
+static REQUEST_LOG: Mutex<Vec<RequestSample>> = Mutex::new(Vec::new());
+
+fn record_request(instance: &Instance, path: &str, duration: Duration, ok: bool) {
+    let endpoint = path.split('?').next().unwrap_or(path).to_string();
+    REQUEST_LOG.lock().unwrap().push(RequestSample {
+        instance: instance.name.clone(),
+        endpoint,
+        started_at: current_timestamp() - duration.as_secs_f64(),
+        duration,
+        ok,
+    });
+}
+
+/// Scan metadata reported alongside the results
+#[derive(Debug, Default, Serialize)]
+struct ScanMetrics {
+    duration_secs: f64,
+    items_per_instance: BTreeMap<String, usize>,
+    requests: usize,
+    failed_requests: usize,
+    latency_p50_ms: Option<f64>,
+    latency_p90_ms: Option<f64>,
+    latency_p99_ms: Option<f64>,
+    cache_hits: usize,
+    cache_misses: usize,
+    cache_hit_ratio: Option<f64>,
+    skipped: Vec<SkippedInstance>,
+    // When --offline, the time the oldest cached library was fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_as_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since_last_scan: Option<ScanTrend>,
+    // Lifetime total, including this scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reclaimed: Option<Reclaimed>,
+}
+
+fn scan_metrics(
+    items: &[Item],
+    duration: Duration,
+    cache_stats: (usize, usize),
+    skipped: &[SkippedInstance],
+) -> ScanMetrics {
+    let mut items_per_instance = BTreeMap::new();
+    for item in items {
+        *items_per_instance.entry(item.instance.clone()).or_insert(0) += 1;
     }
-    counts
+
+    let requests = REQUEST_LOG.lock().unwrap();
+    let mut latencies: Vec<f64> = requests
         .iter()
-        .max_by_key(|(_, count)| *count)
-        .map(|(&val, _)| val as f64 / 10.0)
-        .unwrap_or(0.0)
+        .map(|s| s.duration.as_secs_f64() * 1000.0)
+        .collect();
+    latencies.sort_by(f64::total_cmp);
+    // Nearest-rank percentile
+    let percentile = |p: f64| {
+        let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied()
+    };
+
+    let (cache_hits, cache_misses) = cache_stats;
+    ScanMetrics {
+        duration_secs: duration.as_secs_f64(),
+        items_per_instance,
+        requests: requests.len(),
+        failed_requests: requests.iter().filter(|s| !s.ok).count(),
+        latency_p50_ms: percentile(50.0),
+        latency_p90_ms: percentile(90.0),
+        latency_p99_ms: percentile(99.0),
+        cache_hits,
+        cache_misses,
+        cache_hit_ratio: (cache_hits + cache_misses > 0)
+            .then(|| cache_hits as f64 / (cache_hits + cache_misses) as f64),
+        skipped: skipped.to_vec(),
+        data_as_of: None,
+        since_last_scan: None,
+        reclaimed: None,
+    }
 }
 
-fn format_unified_table(items: &[Item], show_type_column: bool) -> String {
-    let mut table = Table::new();
-    table
-        .load_preset(UTF8_FULL)
-        .apply_modifier(UTF8_ROUND_CORNERS);
+fn format_local_time(timestamp: f64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
 
-    let mut headers = vec!["Name", "Year", "TMDB Score", "Size", "Waste Score"];
-    if show_type_column {
-        headers.insert(1, "Type");
-    }
-    table.set_header(&headers);
+fn offline_banner(metrics: &ScanMetrics) -> String {
+    metrics
+        .data_as_of
+        .as_ref()
+        .map(|as_of| format!("Offline: data as of {}\n\n", as_of))
+        .unwrap_or_default()
+}
 
-    let (total_size, total_waste) = items.iter().fold((0u64, 0i32), |acc, item| {
-        let mut row = vec![
-            item.name.clone(),
-            item.year.to_string(),
-            item.rating.clone(),
-            format_file_size(item.size_bytes),
-            item.waste_score.to_string(),
-        ];
-        if show_type_column {
-            row.insert(
-                1,
-                if item.item_type == "show" {
-                    "Show"
-                } else {
-                    "Movie"
-                }
-                .to_string(),
-            );
+fn format_scan_metrics(metrics: &ScanMetrics) -> String {
+    let per_instance: Vec<String> = metrics
+        .items_per_instance
+        .iter()
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    let mut line = format!(
+        "\nScanned in {:.1}s ({})",
+        metrics.duration_secs,
+        per_instance.join(", ")
+    );
+    if let (Some(p50), Some(p90), Some(p99)) = (
+        metrics.latency_p50_ms,
+        metrics.latency_p90_ms,
+        metrics.latency_p99_ms,
+    ) {
+        let _ = write!(
+            line,
+            " · {} requests, p50 {:.0}ms, p90 {:.0}ms, p99 {:.0}ms",
+            metrics.requests, p50, p90, p99
+        );
+        if metrics.failed_requests > 0 {
+            let _ = write!(line, " ({} failed)", metrics.failed_requests);
         }
-        table.add_row(row);
-        (acc.0 + item.size_bytes, acc.1 + item.waste_score)
-    });
+    }
+    if let Some(ratio) = metrics.cache_hit_ratio {
+        let _ = write!(line, " · cache {:.0}% hits", ratio * 100.0);
+    }
+    if let Some(trend) = &metrics.since_last_scan {
+        let _ = write!(line, "\n{}", format_scan_trend(trend));
+    }
+    if let Some(reclaimed) = &metrics.reclaimed {
+        let _ = write!(
+            line,
+            "\nSpace reclaimed so far: {} across {} deleted items",
+            format_file_size(reclaimed.bytes),
+            reclaimed.items
+        );
+    }
+    for skipped in &metrics.skipped {
+        let _ = write!(
+            line,
+            "\nSkipped unreachable {}: {}",
+            skipped.instance, skipped.reason
+        );
+    }
+    line
+}
 
-    if !items.is_empty() {
-        let numeric_ratings: Vec<f64> = items
-            .iter()
-            .filter_map(|item| item.rating.parse().ok())
-            .collect();
-        let rating_display = if numeric_ratings.is_empty() {
-            "N/A".to_string()
-        } else {
-            let avg = numeric_ratings.iter().sum::<f64>() / numeric_ratings.len() as f64;
-            format!(
-                "{:.1} ({:.1}/{:.1})",
-                avg,
-                mode(&numeric_ratings),
-                median(numeric_ratings.clone())
-            )
-        };
+/// Wall-clock time per phase of a run, for --timing
+struct PhaseTimer {
+    started: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
 
-        let mut total_row = vec![
-            format!("Total ({})", items.len()),
-            "".to_string(),
-            rating_display,
-            format_file_size(total_size),
-            (total_waste / items.len() as i32).to_string(),
-        ];
-        if show_type_column {
-            let types: std::collections::HashSet<_> = items.iter().map(|i| &i.item_type).collect();
-            total_row.insert(
-                1,
-                format!(
-                    "{} type{}",
-                    types.len(),
-                    if types.len() != 1 { "s" } else { "" }
-                ),
-            );
+impl PhaseTimer {
+    fn new() -> Self {
+        let now = Instant::now();
+        PhaseTimer {
+            started: now,
+            last: now,
+            phases: Vec::new(),
         }
-        table.add_row(total_row);
     }
 
-    table.to_string()
+    /// Close the phase that has been running since the previous lap
+    fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        debug!("{} took {:.2}s", phase, (now - self.last).as_secs_f64());
+        self.phases.push((phase, now - self.last));
+        publish_scan_event(ScanEvent::Phase {
+            phase,
+            seconds: (now - self.last).as_secs_f64(),
+        });
+        self.last = now;
+    }
 }
 
-fn parse_args() -> Args {
-    let matches = Command::new("wastearr")
-        .about("Analyze Sonarr/Radarr collections with ratings and waste scores")
-        .arg(Arg::new("item_type").value_parser(["sonarr", "radarr"]))
-        .arg(
-            Arg::new("top-waste")
-                .short('t')
-                .long("top-waste")
-                .value_parser(clap::value_parser!(usize)),
-        )
-        .arg(
-            Arg::new("waste-score")
-                .short('s')
-                .long("waste-score")
-                .value_parser(clap::value_parser!(i32)),
-        )
-        .arg(Arg::new("min-size").short('m').long("min-size"))
-        .arg(
-            Arg::new("ratings")
-                .short('r')
-                .long("ratings")
-                .value_parser(clap::value_parser!(f64)),
-        )
-        .arg(
-            Arg::new("clear-cache")
-                .long("clear-cache")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("no-cache")
-                .long("no-cache")
-                .action(ArgAction::SetTrue),
-        )
-        .get_matches();
+fn show_progress_bars() -> bool {
+    BARS_ENABLED.load(Ordering::Relaxed) && !LOG_MUTED.load(Ordering::Relaxed)
+}
 
-    Args {
-        item_type: matches.get_one::<String>("item_type").cloned(),
-        top_waste: matches.get_one::<usize>("top-waste").copied(),
-        waste_score: matches.get_one::<i32>("waste-score").copied(),
-        min_size: matches.get_one::<String>("min-size").cloned(),
-        ratings: matches.get_one::<f64>("ratings").copied(),
-        clear_cache: matches.get_flag("clear-cache"),
-        no_cache: matches.get_flag("no-cache"),
+/// A bar counting the `len` steps of a phase; it clears itself once dropped
+fn phase_bar(len: usize, what: &str) -> ProgressBar {
+    if !show_progress_bars() {
+        return ProgressBar::hidden();
     }
+    let style =
+        ProgressStyle::with_template("{spinner} {msg:<20} [{bar:30}] {pos}/{len} ({eta} left)")
+            .expect("static progress template is valid")
+            .progress_chars("=> ");
+    let bar = ProgressBar::new(len as u64)
+        .with_style(style)
+        .with_message(what.to_string())
+        .with_finish(ProgressFinish::AndClear);
+    PROGRESS_BARS.add(bar)
 }
 
-fn print_results(
-    items: &mut Vec<Item>,
-    requested_types: &[String],
-    args: &Args,
-    min_size_bytes: Option<u64>,
-) {
-    items.retain(|item| {
-        args.waste_score.is_none_or(|min| item.waste_score >= min)
-            && min_size_bytes.is_none_or(|min| item.size_bytes >= min)
-            && args.ratings.is_none_or(|max| {
-                item.rating == "N/A" || item.rating.parse::<f64>().unwrap_or(0.0) <= max
-            })
-    });
+/// A spinner for a step of unknown length, such as one library request
+fn phase_spinner(what: String) -> ProgressBar {
+    if !show_progress_bars() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{spinner} {msg} ({elapsed})")
+        .expect("static progress template is valid");
+    let spinner = PROGRESS_BARS.add(
+        ProgressBar::new_spinner()
+            .with_style(style)
+            .with_message(what)
+            .with_finish(ProgressFinish::AndClear),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
 
-    items.sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+/// Stderr for log lines, lifting the progress bars out of the way while writing
+struct LogStderr;
 
-    let mut filters = Vec::new();
-    if let Some(score) = args.waste_score {
-        filters.push(format!("Waste Score >= {}", score));
+impl Write for LogStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        PROGRESS_BARS.suspend(|| io::stderr().write(buf))
     }
-    if let Some(size) = min_size_bytes {
-        filters.push(format!("Size >= {}", format_file_size(size)));
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
     }
-    if let Some(rating) = args.ratings {
-        filters.push(format!("Rating <= {}", rating));
+}
+
+fn format_timing_report(timer: &PhaseTimer) -> String {
+    let mut report = String::from("\nTiming:\n");
+    for (phase, duration) in &timer.phases {
+        let _ = writeln!(report, "  {:<22} {:>8.2}s", phase, duration.as_secs_f64());
+    }
+    let _ = writeln!(
+        report,
+        "  {:<22} {:>8.2}s",
+        "Total",
+        timer.started.elapsed().as_secs_f64()
+    );
+
+    let requests = REQUEST_LOG.lock().unwrap();
+    if requests.is_empty() {
+        return report;
+    }
+    let mut by_target: Vec<((&str, &str), Vec<&RequestSample>)> = Vec::new();
+    for sample in requests.iter() {
+        let key = (sample.instance.as_str(), sample.endpoint.as_str());
+        match by_target.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, samples)) => samples.push(sample),
+            None => by_target.push((key, vec![sample])),
+        }
+    }
+    by_target.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let _ = writeln!(report, "\nRequests:");
+    for ((instance, endpoint), samples) in &by_target {
+        let total: Duration = samples.iter().map(|s| s.duration).sum();
+        let slowest = samples.iter().map(|s| s.duration).max().unwrap_or_default();
+        let failed = samples.iter().filter(|s| !s.ok).count();
+        let _ = writeln!(
+            report,
+            "  {:<12} {:<16} {:>5} requests  avg {:>6.0}ms  max {:>6.0}ms{}",
+            instance,
+            endpoint,
+            samples.len(),
+            total.as_secs_f64() * 1000.0 / samples.len() as f64,
+            slowest.as_secs_f64() * 1000.0,
+            if failed > 0 {
+                format!("  ({} failed)", failed)
+            } else {
+                String::new()
+            }
+        );
+    }
+    report
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// GET an API path with extra request headers, e.g. conditional ones: from the
+/// --mock fixtures or --replay session when there is one, else from the
+/// instance, saving the response when --record is on
+async fn send_request(
+    instance: &Instance,
+    api_key: &str,
+    path: &str,
+    timeout: Duration,
+    headers: &HeaderMap,
+) -> reqwest::Result<Response> {
+    // A leading slash addresses the server root instead of the v3 API
+    let url = match path.strip_prefix('/') {
+        Some(path) => format!("{}/{}", instance.url, path),
+        None => format!("{}/api/v3/{}", instance.url, path),
+    };
+    if let Some(canned) = &instance.canned {
+        return Ok(canned_response(instance, canned, path, &url));
+    }
+    let result = send_live(instance, api_key, path, &url, timeout, headers).await;
+    match (&instance.recorder, result) {
+        (Some(recorder), Ok(response)) => record_response(instance, recorder, path, response).await,
+        (_, result) => result,
+    }
+}
+
+/// GET a URL from the instance, retrying timeouts, connection failures and
+/// gateway errors per its retry policy. Anything else is returned as-is.
+async fn send_live(
+    instance: &Instance,
+    api_key: &str,
+    path: &str,
+    url: &str,
+    timeout: Duration,
+    headers: &HeaderMap,
+) -> reqwest::Result<Response> {
+    let mut retry = 0;
+    loop {
+        if let Some(limiter) = &instance.rate_limit {
+            limiter.acquire().await;
+        }
+        let mut request = instance
+            .client
+            .get(url)
+            .header("X-Api-Key", api_key)
+            .header("Content-Type", "application/json")
+            .headers(headers.clone())
+            .timeout(timeout);
+        if let Some((username, password)) = &instance.basic_auth {
+            request = request.basic_auth(username, password.as_ref());
+        }
+        let sent = Instant::now();
+        let result = request.send().await;
+        match &result {
+            Ok(response) => debug!(
+                "GET {} -> {} in {:.0}ms",
+                url,
+                response.status(),
+                sent.elapsed().as_secs_f64() * 1000.0
+            ),
+            Err(e) => debug!(
+                "GET {} failed after {:.0}ms: {}",
+                url,
+                sent.elapsed().as_secs_f64() * 1000.0,
+                e
+            ),
+        }
+        trace!("GET {} sent headers: {:?}", url, headers);
+        record_request(
+            instance,
+            path,
+            sent.elapsed(),
+            result
+                .as_ref()
+                .is_ok_and(|r| r.status().is_success() || r.status() == StatusCode::NOT_MODIFIED),
+        );
+        let reason = match &result {
+            Ok(response) if is_transient_status(response.status()) => {
+                format!("HTTP {}", response.status())
+            }
+            Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => e.to_string(),
+            _ => return result,
+        };
+        if retry >= instance.retry.retries {
+            return result;
+        }
+
+        let delay = instance.retry.delay(retry);
+        retry += 1;
+        warn!(
+            "Retrying {} {} in {:.1}s ({}/{}): {}",
+            instance.service_name(),
+            path,
+            delay.as_secs_f64(),
+            retry,
+            instance.retry.retries,
+            reason
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Answer an API path without the server. Fixture directories are laid out as
+/// `<dir>/<instance>/<path>.json` with any query string as a subdirectory
+/// (`episodefile?seriesId=3` reads `episodefile/seriesId=3.json`).
+fn canned_response(
+    instance: &Instance,
+    canned: &CannedResponses,
+    path: &str,
+    url: &str,
+) -> Response {
+    let (status, body) = match canned {
+        CannedResponses::Fixtures(dir) => {
+            let relative = path.trim_start_matches('/').replace('?', "/");
+            match fs::read(dir.join(&instance.name).join(format!("{}.json", relative))) {
+                Ok(body) => (StatusCode::OK, body),
+                // Fixtures only need the library files; every mocked instance is up
+                Err(_) if path == "system/status" => (StatusCode::OK, b"{}".to_vec()),
+                Err(_) => (StatusCode::NOT_FOUND, Vec::new()),
+            }
+        }
+        CannedResponses::Session(session) => session
+            .responses
+            .iter()
+            .find(|r| r.instance == instance.name && r.path == path)
+            .map_or((StatusCode::NOT_FOUND, Vec::new()), |recorded| {
+                let body = match &recorded.body {
+                    serde_json::Value::String(text) => text.clone().into_bytes(),
+                    json => serde_json::to_vec(json).unwrap_or_default(),
+                };
+                (
+                    StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK),
+                    body,
+                )
+            }),
+    };
+    trace!(
+        "{} {} answered {} from {:?}",
+        instance.name, path, status, canned
+    );
+    build_response(status, url, body)
+}
+
+/// Keep a copy of a live response for --record, handing back an identical one
+async fn record_response(
+    instance: &Instance,
+    recorder: &Mutex<Vec<RecordedResponse>>,
+    path: &str,
+    response: Response,
+) -> reqwest::Result<Response> {
+    let (status, url) = (response.status(), response.url().to_string());
+    let body = response.bytes().await?;
+    recorder.lock().unwrap().push(RecordedResponse {
+        instance: instance.name.clone(),
+        path: path.to_string(),
+        status: status.as_u16(),
+        body: serde_json::from_slice(&body)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into())),
+    });
+    Ok(build_response(status, &url, body.to_vec()))
+}
+
+fn build_response(status: StatusCode, url: &str, body: Vec<u8>) -> Response {
+    let mut response = http::Response::builder().status(status);
+    if let Ok(url) = reqwest::Url::parse(url) {
+        response = response.url(url);
+    }
+    Response::from(
+        response
+            .body(body)
+            .expect("static response parts are valid"),
+    )
+}
+
+/// Libraries can run to tens of megabytes of JSON, so response bodies are read
+/// as they stream in rather than buffered whole. The reader blocks, so parse it
+/// on a blocking thread.
+async fn open_api_body(
+    instance: &Instance,
+    endpoint: &str,
+) -> Result<impl io::Read + Send + 'static> {
+    let service_name = instance.service_name();
+    let api_key = instance.require_api_key()?;
+    let timeout = instance.request_timeout.unwrap_or(REQUEST_TIMEOUT);
+    let response = send_request(instance, api_key, endpoint, timeout, &HeaderMap::new())
+        .await
+        .with_context(|| format!("Failed to connect to {} API", service_name))?;
+    checked_body(instance, endpoint, response)
+}
+
+fn checked_body(
+    instance: &Instance,
+    endpoint: &str,
+    response: Response,
+) -> Result<impl io::Read + Send + 'static> {
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Failed to fetch {}s from {} API: HTTP {}",
+            endpoint,
+            instance.service_name(),
+            response.status()
+        )
+    }
+    let body = StreamReader::new(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(io::Error::other)),
+    );
+    Ok(io::BufReader::new(SyncIoBridge::new(body)))
+}
+
+fn parse_error(
+    instance: &Instance,
+    endpoint: &str,
+    path: &str,
+    e: serde_json::Error,
+) -> anyhow::Error {
+    anyhow::Error::new(e).context(format!(
+        "Failed to parse {} {} response at {}",
+        instance.service_name(),
+        endpoint,
+        path
+    ))
+}
+
+/// Make a change through an instance's API. Changes are never retried, so one
+/// that timed out after landing isn't applied twice, and never canned or recorded
+async fn send_api_change(
+    instance: &Instance,
+    method: reqwest::Method,
+    endpoint: &str,
+    body: &serde_json::Value,
+) -> Result<serde_json::Value> {
+    let api_key = instance.require_api_key()?;
+    if let Some(limiter) = &instance.rate_limit {
+        limiter.acquire().await;
+    }
+    let mut request = instance
+        .client
+        .request(
+            method.clone(),
+            format!("{}/api/v3/{}", instance.url, endpoint),
+        )
+        .header("X-Api-Key", api_key)
+        .json(body)
+        .timeout(instance.request_timeout.unwrap_or(REQUEST_TIMEOUT));
+    if let Some((username, password)) = &instance.basic_auth {
+        request = request.basic_auth(username, password.as_ref());
+    }
+    let sent = Instant::now();
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to connect to {} API", instance.service_name()))?;
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    debug!(
+        "{} {}/api/v3/{} -> {} in {:.0}ms",
+        method,
+        instance.url,
+        endpoint,
+        status,
+        sent.elapsed().as_secs_f64() * 1000.0
+    );
+    trace!("{} {} sent {}, got {}", method, endpoint, body, text);
+    if !status.is_success() {
+        anyhow::bail!(
+            "{} {} on {} API failed: HTTP {} {}",
+            method,
+            endpoint,
+            instance.service_name(),
+            status,
+            truncate_to_width(text.trim(), 200)
+        );
+    }
+    Ok(serde_json::from_str(&text).unwrap_or_default())
+}
+
+async fn request_api_data<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+) -> Result<Vec<T>> {
+    let reader = open_api_body(instance, endpoint).await?;
+    parse_body(instance, endpoint, reader).await
+}
+
+async fn parse_body<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+    reader: impl io::Read + Send + 'static,
+) -> Result<Vec<T>> {
+    // Report where in the document parsing failed, e.g. "[12].ratings.value"
+    tokio::task::spawn_blocking(move || {
+        serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_reader(reader))
+    })
+    .await?
+    .map_err(|e| {
+        let path = e.path().to_string();
+        parse_error(instance, endpoint, &path, e.into_inner())
+    })
+}
+
+/// Sends each element of a JSON array down a channel as soon as it's parsed
+struct SendEach<T>(mpsc::Sender<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SendEach<T> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element()? {
+            // The receiver only hangs up when the run is being abandoned
+            if self.0.blocking_send(record).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Like request_api_data, but hands records over one at a time so the whole
+/// array never has to be held in memory. The parser's result arrives once the
+/// channel has been drained.
+async fn stream_api_data<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+) -> Result<(
+    mpsc::Receiver<T>,
+    tokio::task::JoinHandle<Result<(), (String, serde_json::Error)>>,
+)> {
+    let reader = open_api_body(instance, endpoint).await?;
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let parser = tokio::task::spawn_blocking(move || {
+        let mut json = serde_json::Deserializer::from_reader(reader);
+        let mut track = serde_path_to_error::Track::new();
+        serde_path_to_error::Deserializer::new(&mut json, &mut track)
+            .deserialize_seq(SendEach(sender))
+            .map_err(|e| (track.path().to_string(), e))
+    });
+    Ok((receiver, parser))
+}
+
+async fn fetch_quality_profiles(instance: &Instance) -> Result<HashMap<i64, String>> {
+    Ok(
+        fetch_api_data::<QualityProfileRecord>(instance, "qualityprofile")
+            .await?
+            .into_iter()
+            .filter_map(|profile| Some((profile.id?, profile.name?)))
+            .collect(),
+    )
+}
+
+/// The /system/status response, read to learn the server version
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SystemStatus {
+    version: Option<String>,
+}
+
+fn season_size(series: &SeriesRecord, season_number: i64) -> u64 {
+    series
+        .seasons
+        .iter()
+        .find(|s| s.season_number == Some(season_number))
+        .map_or(0, |season| season.statistics.size_on_disk)
+}
+
+/// Apply --specials: keep Season 0 in show totals, drop it, or list it separately
+fn apply_specials_mode(items: &mut Vec<Item>, mode: &str) {
+    if mode == "include" {
+        return;
+    }
+
+    let mut specials = Vec::new();
+    for item in items.iter_mut().filter(|i| i.specials_bytes > 0) {
+        item.size_bytes = item.size_bytes.saturating_sub(item.specials_bytes);
+        if mode == "separate" {
+            let mut special = item.clone();
+            special.name = format!("{} (Specials)", item.name);
+            special.size_bytes = item.specials_bytes;
+            special.episodes = None;
+            specials.push(special);
+        }
+        item.specials_bytes = 0;
+    }
+    items.retain(|item| item.size_bytes > 0);
+    items.extend(specials);
+}
+
+fn root_folder_of(root_folder_path: &str, path: &str) -> String {
+    Some(root_folder_path)
+        .filter(|p| !p.is_empty())
+        .map(|p| p.trim_end_matches('/').to_string())
+        .or_else(|| {
+            Path::new(path)
+                .parent()
+                .map(|p| p.to_string_lossy().into_owned())
+        })
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+enum Library {
+    Series(Vec<SeriesRecord>),
+    Movies(Vec<MovieRecord>),
+    // The server answered 304 to a conditional request
+    Unchanged,
+}
+
+/// Library and quality profiles for one instance, requested together
+struct InstanceData {
+    library: Library,
+    validators: Validators,
+    quality_profiles: HashMap<i64, String>,
+}
+
+async fn fetch_instance_data(instance: &Instance, previous: Validators) -> Result<InstanceData> {
+    progress!("Fetching {} data from API", instance.name);
+    let _spinner = phase_spinner(format!("Fetching {}", instance.name));
+    let (fetched, quality_profiles) = if instance.kind == "sonarr" {
+        let (series, profiles) = tokio::join!(
+            fetch_library(instance, "series", &previous),
+            fetch_quality_profiles(instance),
+        );
+        (
+            series?.map(|(series, validators)| (Library::Series(series), validators)),
+            profiles,
+        )
+    } else {
+        let (movies, profiles) = tokio::join!(
+            fetch_library(instance, "movie", &previous),
+            fetch_quality_profiles(instance),
+        );
+        (
+            movies?.map(|(movies, validators)| (Library::Movies(movies), validators)),
+            profiles,
+        )
+    };
+    let (library, validators) = fetched.unwrap_or((Library::Unchanged, previous));
+    Ok(InstanceData {
+        library,
+        validators,
+        // Profile names are only cosmetic, so a failed lookup shouldn't sink the scan
+        quality_profiles: quality_profiles.unwrap_or_default(),
+    })
+}
+
+/// The fields every library item shares, filled in the same way for both services
+#[allow(clippy::too_many_arguments)]
+fn library_item(
+    instance: &Instance,
+    quality_profiles: &HashMap<i64, String>,
+    id: i32,
+    name: &str,
+    year: i32,
+    size_bytes: u64,
+    rating: Option<f64>,
+    genres: &[String],
+    path: &str,
+    root_folder_path: &str,
+    title_slug: Option<&str>,
+    quality_profile_id: Option<i64>,
+) -> Item {
+    let (item_type, ui_section) = if instance.kind == "sonarr" {
+        ("show", "series")
+    } else {
+        ("movie", "movie")
+    };
+    Item {
+        id,
+        name: name.to_string(),
+        year,
+        size_bytes,
+        rating: rating
+            .filter(|&r| r > 0.0)
+            .map(|r| format!("{:.1}", r))
+            .unwrap_or_else(|| "N/A".to_string()),
+        item_type: item_type.to_string(),
+        waste_score: 0,
+        instance: instance.name.clone(),
+        genres: genres.to_vec(),
+        path: path.to_string(),
+        root_folder: root_folder_of(root_folder_path, path),
+        quality: quality_profile_id
+            .and_then(|id| quality_profiles.get(&id).cloned())
+            .unwrap_or_else(|| "Unknown".to_string()),
+        url: title_slug.map(|slug| format!("{}/{}/{}", instance.url, ui_section, slug)),
+        media_info: None,
+        episodes: None,
+        specials_bytes: 0,
+        collection: None,
+        primary_file_bytes: None,
+        tmdb_id: None,
+        tvdb_id: None,
+        trend: None,
+        note: None,
+    }
+}
+
+/// Series-level statistics, totalled from the seasons when Sonarr v4 leaves
+/// them off the series itself
+fn series_statistics(instance: &Instance, series: &SeriesRecord) -> SeriesStatistics {
+    match &series.statistics {
+        Some(stats) => stats.clone(),
+        None if instance.is_sonarr_v4() => {
+            let mut totals = SeriesStatistics::default();
+            for season in &series.seasons {
+                totals.size_on_disk += season.statistics.size_on_disk;
+                // Like Sonarr's own series totals, episode counts leave specials out
+                if season.season_number.is_some_and(|n| n > 0) {
+                    totals.episode_file_count += season.statistics.episode_file_count;
+                    totals.total_episode_count += season.statistics.total_episode_count;
+                    totals.season_count += 1;
+                }
+            }
+            totals.percent_of_episodes = (totals.total_episode_count > 0).then(|| {
+                totals.episode_file_count as f64 / totals.total_episode_count as f64 * 100.0
+            });
+            totals
+        }
+        None => SeriesStatistics::default(),
+    }
+}
+
+fn series_item(
+    instance: &Instance,
+    quality_profiles: &HashMap<i64, String>,
+    series: &SeriesRecord,
+) -> Option<Item> {
+    let stats = series_statistics(instance, series);
+    let sonarr_v4 = instance.is_sonarr_v4();
+    // v4 sends 0 for series that haven't aired yet instead of leaving the year out
+    let year = match series.year {
+        Some(0) | None if sonarr_v4 => series
+            .first_aired
+            .as_deref()
+            .and_then(|date| date.get(..4)?.parse().ok())
+            .or(series.year),
+        year => year,
+    };
+    // v4 reports unrated series as a 0 rating with no votes
+    let rating = series
+        .ratings
+        .value
+        .filter(|&value| !(sonarr_v4 && value == 0.0 && series.ratings.votes == 0));
+    let mut item = library_item(
+        instance,
+        quality_profiles,
+        series.id?,
+        series.title.as_deref()?,
+        year?,
+        stats.size_on_disk,
+        rating,
+        &series.genres,
+        &series.path,
+        &series.root_folder_path,
+        series.title_slug.as_deref(),
+        series.quality_profile_id,
+    );
+    item.episodes = Some(EpisodeCounts {
+        file_count: stats.episode_file_count,
+        total_count: stats.total_episode_count,
+        season_count: stats.season_count,
+        percent_complete: stats.percent_of_episodes.unwrap_or(100.0),
+    });
+    item.specials_bytes = season_size(series, 0);
+    item.tvdb_id = series.tvdb_id.filter(|&v| v > 0);
+    Some(item)
+}
+
+fn movie_item(
+    instance: &Instance,
+    quality_profiles: &HashMap<i64, String>,
+    movie: &MovieRecord,
+) -> Option<Item> {
+    let mut item = library_item(
+        instance,
+        quality_profiles,
+        movie.id?,
+        movie.title.as_deref()?,
+        movie.year?,
+        movie.size_on_disk,
+        if instance.major_version().is_some_and(|major| major < 4) {
+            movie.ratings.value
+        } else {
+            movie.ratings.tmdb.as_ref().and_then(|r| r.value)
+        },
+        &movie.genres,
+        &movie.path,
+        &movie.root_folder_path,
+        movie.title_slug.as_deref(),
+        movie.quality_profile_id,
+    );
+    item.media_info = movie
+        .movie_file
+        .as_ref()
+        .and_then(|file| file.media_info.as_ref())
+        .map(parse_media_info);
+    item.collection = movie
+        .collection
+        .as_ref()
+        .and_then(|c| c.title.clone().or_else(|| c.name.clone()))
+        .filter(|t| !t.is_empty());
+    item.primary_file_bytes = movie.movie_file.as_ref().and_then(|file| file.size);
+    item.tmdb_id = movie.tmdb_id.filter(|&v| v > 0);
+    Some(item)
+}
+
+fn scan_api_data(
+    instance: &Instance,
+    data: &InstanceData,
+    cache_stats: &mut (usize, usize),
+    cache: &mut Option<&mut HashMap<String, String>>,
+) -> Vec<Item> {
+    let profiles = &data.quality_profiles;
+    let items: Vec<Item> = match &data.library {
+        Library::Series(series) => series
+            .iter()
+            .filter_map(|s| series_item(instance, profiles, s))
+            .collect(),
+        Library::Movies(movies) => movies
+            .iter()
+            .filter_map(|m| movie_item(instance, profiles, m))
+            .collect(),
+        Library::Unchanged => Vec::new(),
+    };
+
+    items
+        .into_iter()
+        .filter(|item| item.size_bytes > 0)
+        .map(|mut item| {
+            cache_rating(instance, &mut item, cache_stats, cache);
+            item
+        })
+        .collect()
+}
+
+/// Where --stream sends each item: scored, filtered, then written straight out,
+/// or held back in a bounded top-N list when --top-waste is set
+struct StreamSink<'a> {
+    args: &'a Args,
+    config: &'a Config,
+    min_size_bytes: Option<u64>,
+    scorer: Option<&'a mut ScorerPlugin>,
+    out: io::BufWriter<io::Stdout>,
+    top: Vec<Item>,
+    emitted: usize,
+}
+
+impl StreamSink<'_> {
+    fn accept(&mut self, item: Item) -> Result<()> {
+        let mut batch = vec![item];
+        apply_specials_mode(&mut batch, &self.args.specials);
+        for mut item in batch {
+            calculate_normalized_waste_score(&mut item, &self.config.scoring);
+            if let Some(plugin) = self.scorer.as_deref_mut() {
+                item.waste_score = plugin.score(&item)?;
+            }
+            if self.config.is_protected(&item) {
+                item.waste_score = 0;
+            }
+            if !item_matches(&item, self.args, self.min_size_bytes) {
+                continue;
+            }
+            match self.args.top_waste {
+                Some(limit) => keep_top(&mut self.top, item, limit),
+                None => self.write(&item)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, item: &Item) -> Result<()> {
+        if self.args.output == "json" {
+            serde_json::to_writer(&mut self.out, item)?;
+            writeln!(self.out)?;
+        } else {
+            writeln!(self.out, "{}", format_plain_row(item))?;
+        }
+        self.emitted += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<usize> {
+        let mut top = std::mem::take(&mut self.top);
+        top.sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+        for item in &top {
+            self.write(item)?;
+        }
+        self.out.flush()?;
+        Ok(self.emitted)
+    }
+}
+
+/// Keep the `limit` highest-scoring items seen so far
+fn keep_top(top: &mut Vec<Item>, item: Item, limit: usize) {
+    if top.len() < limit {
+        top.push(item);
+    } else if let Some((lowest, _)) = top
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, kept)| kept.waste_score)
+        .filter(|(_, kept)| kept.waste_score < item.waste_score)
+    {
+        top[lowest] = item;
+    }
+}
+
+async fn stream_instance<T: DeserializeOwned + Send + 'static>(
+    instance: &Instance,
+    endpoint: &str,
+    to_item: impl Fn(&T) -> Option<Item>,
+    cache_stats: &mut (usize, usize),
+    cache: &mut Option<&mut HashMap<String, String>>,
+    sink: &mut StreamSink<'_>,
+) -> Result<usize> {
+    let (mut records, parser) = stream_api_data::<T>(instance, endpoint).await?;
+    let mut count = 0;
+    while let Some(record) = records.recv().await {
+        count += 1;
+        let Some(mut item) = to_item(&record).filter(|item| item.size_bytes > 0) else {
+            continue;
+        };
+        cache_rating(instance, &mut item, cache_stats, cache);
+        sink.accept(item)?;
+    }
+    parser
+        .await?
+        .map_err(|(path, e)| parse_error(instance, endpoint, &path, e))?;
+    Ok(count)
+}
+
+/// --stream: score, filter and print each record as it's parsed instead of
+/// collecting the library first, so memory stays flat however large it is.
+/// Anything that needs every item at once (sorting, grouping, summaries) is
+/// unavailable; --top-waste only holds its N items.
+#[allow(clippy::too_many_arguments)]
+async fn run_stream_pipeline(
+    config: &Config,
+    scan_types: &[String],
+    args: &Args,
+    min_size_bytes: Option<u64>,
+    scorer: Option<&mut ScorerPlugin>,
+    sonarr_cache: &mut HashMap<String, String>,
+    radarr_cache: &mut HashMap<String, String>,
+    cache_stats: &mut (usize, usize),
+) -> Result<usize> {
+    let mut sink = StreamSink {
+        args,
+        config,
+        min_size_bytes,
+        scorer,
+        out: io::BufWriter::new(io::stdout()),
+        top: Vec::new(),
+        emitted: 0,
+    };
+
+    for instance in config.instances_for(scan_types) {
+        progress!("Streaming {} data from API", instance.name);
+        let quality_profiles = fetch_quality_profiles(instance).await.unwrap_or_default();
+        let mut cache_ref = match (args.no_cache, instance.kind.as_str()) {
+            (true, _) => None,
+            (false, "sonarr") => Some(&mut *sonarr_cache),
+            (false, _) => Some(&mut *radarr_cache),
+        };
+        let count = if instance.kind == "sonarr" {
+            stream_instance(
+                instance,
+                "series",
+                |series: &SeriesRecord| series_item(instance, &quality_profiles, series),
+                cache_stats,
+                &mut cache_ref,
+                &mut sink,
+            )
+            .await?
+        } else {
+            stream_instance(
+                instance,
+                "movie",
+                |movie: &MovieRecord| movie_item(instance, &quality_profiles, movie),
+                cache_stats,
+                &mut cache_ref,
+                &mut sink,
+            )
+            .await?
+        };
+        progress!(
+            "Processed {} records from {} API",
+            count,
+            instance.service_name()
+        );
+    }
+
+    sink.finish()
+}
+
+fn cache_rating(
+    instance: &Instance,
+    item: &mut Item,
+    cache_stats: &mut (usize, usize),
+    cache: &mut Option<&mut HashMap<String, String>>,
+) {
+    // Instances of a service share its cache, so keep their IDs apart
+    let cache_key = format!("{}:{}", instance.name, item.id);
+    if let Some(cache_ref) = cache {
+        if let Some(cached_rating) = cache_ref.get(&cache_key) {
+            trace!("Rating of {} cached as {}", cache_key, cached_rating);
+            cache_stats.0 += 1;
+            item.rating = cached_rating.clone();
+        } else {
+            cache_stats.1 += 1;
+            cache_ref.insert(cache_key, item.rating.clone());
+        }
+    }
+}
+
+async fn fetch_disk_space(config: &Config, scan_types: &[String]) -> Vec<DiskSpace> {
+    let instances: Vec<&Instance> = config
+        .instances_for(scan_types)
+        .filter(|instance| instance.api_key.is_some())
+        .collect();
+    let responses = join_all(
+        instances
+            .iter()
+            .map(|instance| fetch_api_data::<DiskSpaceRecord>(instance, "diskspace")),
+    )
+    .await;
+
+    let mut disks: Vec<DiskSpace> = Vec::new();
+    for (instance, response) in instances.into_iter().zip(responses) {
+        match response {
+            Ok(data) => {
+                let host = url_host(&instance.url);
+                for disk in data {
+                    let Some(path) = disk.path else {
+                        continue;
+                    };
+                    // Instances on the same host usually see the same mounts
+                    if let Some(existing) =
+                        disks.iter_mut().find(|d| d.path == path && d.host == host)
+                    {
+                        existing.instances.push(instance.name.clone());
+                        continue;
+                    }
+                    disks.push(DiskSpace {
+                        host: host.clone(),
+                        instances: vec![instance.name.clone()],
+                        path,
+                        label: disk.label,
+                        free_bytes: disk.free_space,
+                        total_bytes: disk.total_space,
+                    });
+                }
+            }
+            Err(e) => warn!("{:#}", e),
+        }
+    }
+    disks
+}
+
+/// Accept URLs the way people paste them: without a scheme, with trailing
+/// slashes, or with the API path already on the end
+fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let url = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    };
+    let url = url
+        .strip_suffix("/api/v3")
+        .or_else(|| url.strip_suffix("/api"))
+        .unwrap_or(&url);
+    url.trim_end_matches('/').to_string()
+}
+
+/// The URL base part of an instance URL, e.g. "/sonarr", or "" when there is none
+fn url_path(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    without_scheme
+        .find('/')
+        .map_or("", |start| &without_scheme[start..])
+}
+
+fn url_host(url: &str) -> String {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    without_scheme
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+fn normalize_resolution(resolution: &str) -> String {
+    // Use the width so letterboxed encodes (e.g. 1920x800) land in the right class
+    match resolution
+        .split_once('x')
+        .and_then(|(width, _)| width.trim().parse::<u32>().ok())
+    {
+        Some(width) if width >= 3200 => "2160p".to_string(),
+        Some(width) if width >= 1800 => "1080p".to_string(),
+        Some(width) if width >= 1200 => "720p".to_string(),
+        Some(width) if width > 0 => "SD".to_string(),
+        _ => "Unknown".to_string(),
+    }
+}
+
+fn parse_media_info(media_info: &MediaInfoRecord) -> MediaInfo {
+    let field = |value: &Option<String>| {
+        value
+            .as_deref()
+            .filter(|v| !v.is_empty())
+            .unwrap_or("Unknown")
+            .to_string()
+    };
+    let channels = media_info
+        .audio_channels
+        .filter(|&c| c > 0.0)
+        .map(|c| format!(" {:.1}", c))
+        .unwrap_or_default();
+
+    MediaInfo {
+        resolution: normalize_resolution(&field(&media_info.resolution)),
+        video_codec: field(&media_info.video_codec),
+        audio: format!("{}{}", field(&media_info.audio_codec), channels),
+    }
+}
+
+/// Summarize a series by the media info that accounts for most of its bytes
+async fn fetch_series_media_info(instance: &Instance, series_id: i32) -> Result<Option<MediaInfo>> {
+    let files =
+        request_api_data::<MediaFile>(instance, &format!("episodefile?seriesId={}", series_id))
+            .await?;
+
+    let mut bytes_by_media: HashMap<(String, String, String), u64> = HashMap::new();
+    for file in &files {
+        let Some(media_info) = &file.media_info else {
+            continue;
+        };
+        let media = parse_media_info(media_info);
+        *bytes_by_media
+            .entry((media.resolution, media.video_codec, media.audio))
+            .or_insert(0) += file.size.unwrap_or(0);
+    }
+
+    Ok(bytes_by_media
+        .into_iter()
+        .max_by_key(|(_, bytes)| *bytes)
+        .map(|((resolution, video_codec, audio), _)| MediaInfo {
+            resolution,
+            video_codec,
+            audio,
+        }))
+}
+
+/// Make one detail request per entry with at most `concurrency` in flight,
+/// reporting progress on long runs. Failed requests are tallied in a single
+/// warning and handed back alongside their entry rather than aborting the batch.
+async fn fetch_details<I, T, Fut>(
+    entries: Vec<I>,
+    what: &str,
+    concurrency: usize,
+    fetch: impl Fn(&I) -> Fut,
+) -> Vec<(I, Result<T>)>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let total = entries.len();
+    let step = (total / 10).max(1);
+    let bar = phase_bar(total, what);
+    let mut done = 0;
+    let results: Vec<(I, Result<T>)> = stream::iter(entries)
+        .map(|entry| {
+            let request = fetch(&entry);
+            async move { (entry, request.await) }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .inspect(|_| {
+            done += 1;
+            bar.inc(1);
+            publish_scan_event(ScanEvent::Count {
+                what: what.to_string(),
+                done,
+                total,
+            });
+            // The bar already shows the count when there is one
+            if bar.is_hidden()
+                && total >= DETAIL_PROGRESS_MIN
+                && (done % step == 0 || done == total)
+            {
+                progress!("  {}/{} {}", done, total, what);
+            }
+        })
+        .collect()
+        .await;
+
+    let failures: Vec<&anyhow::Error> = results
+        .iter()
+        .filter_map(|(_, r)| r.as_ref().err())
+        .collect();
+    if let Some(first) = failures.first() {
+        warn!(
+            "{} of {} {} requests failed ({:#})",
+            failures.len(),
+            total,
+            what,
+            first
+        );
+    }
+    results
+}
+
+async fn enrich_series_media_info(items: &mut [Item], config: &Config) {
+    let shows: Vec<&mut Item> = items
+        .iter_mut()
+        .filter(|item| item.item_type == "show")
+        .collect();
+    if shows.is_empty() {
+        return;
+    }
+
+    progress!("Fetching episode file details for {} series", shows.len());
+    let results = fetch_details(shows, "episode file", config.concurrency, |item| {
+        let instance = config.require_instance(&item.instance);
+        let series_id = item.id;
+        async move { fetch_series_media_info(instance?, series_id).await }
+    })
+    .await;
+
+    for (item, result) in results {
+        if let Ok(media_info) = result {
+            item.media_info = media_info;
+        }
+    }
+}
+
+/// Find movies whose on-disk size exceeds their primary file, i.e. leftover
+/// old versions, extra editions, or extras Radarr still tracks
+async fn find_extra_movie_files(items: &[Item], config: &Config) -> Vec<ExtraFiles> {
+    let suspects: Vec<&Item> = items
+        .iter()
+        .filter(|item| item.item_type == "movie")
+        .filter(|item| {
+            item.primary_file_bytes.is_none_or(|primary| {
+                let delta = item.size_bytes.saturating_sub(primary);
+                delta >= EXTRA_FILES_MIN_BYTES
+                    && delta as f64 >= primary as f64 * EXTRA_FILES_MIN_RATIO
+            })
+        })
+        .collect();
+    if suspects.is_empty() {
+        return Vec::new();
+    }
+
+    progress!("Checking {} movies for extra files", suspects.len());
+    let results = fetch_details(suspects, "movie file", config.concurrency, |item| {
+        let instance = config.require_instance(&item.instance);
+        let endpoint = format!("moviefile?movieId={}", item.id);
+        async move { request_api_data::<MediaFile>(instance?, &endpoint).await }
+    })
+    .await;
+    let mut extra_files: Vec<ExtraFiles> = results
+        .into_iter()
+        .filter_map(|(item, files)| {
+            let sizes: Vec<u64> = files
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|file| file.size)
+                .collect();
+            let primary_file_bytes = sizes
+                .iter()
+                .copied()
+                .max()
+                .or(item.primary_file_bytes)
+                .unwrap_or(0);
+            let entry = ExtraFiles {
+                name: item.name.clone(),
+                year: item.year,
+                file_count: sizes.len().max(1),
+                folder_bytes: item.size_bytes.max(sizes.iter().sum()),
+                primary_file_bytes,
+            };
+            (entry.file_count > 1 || entry.reclaimable_bytes() >= EXTRA_FILES_MIN_BYTES)
+                .then_some(entry)
+        })
+        .collect();
+
+    extra_files.sort_by_key(|e| std::cmp::Reverse(e.reclaimable_bytes()));
+    extra_files
+}
+
+/// Items held by more than one instance of the same service, matched by TVDB/TMDB ID
+fn find_cross_instance_duplicates(items: &[Item]) -> Vec<CrossInstanceDuplicate> {
+    let mut by_external_id: HashMap<(String, i64), Vec<&Item>> = HashMap::new();
+    for item in items {
+        let external_id = if item.item_type == "show" {
+            item.tvdb_id
+        } else {
+            item.tmdb_id
+        };
+        if let Some(id) = external_id {
+            by_external_id
+                .entry((item.item_type.clone(), id))
+                .or_default()
+                .push(item);
+        }
+    }
+
+    let mut duplicates: Vec<CrossInstanceDuplicate> = by_external_id
+        .into_values()
+        .filter(|copies| {
+            copies
+                .iter()
+                .any(|copy| copy.instance != copies[0].instance)
+        })
+        .map(|copies| CrossInstanceDuplicate {
+            name: copies[0].name.clone(),
+            item_type: copies[0].item_type.clone(),
+            copies: copies
+                .iter()
+                .map(|copy| (copy.instance.clone(), copy.size_bytes))
+                .collect(),
+        })
+        .collect();
+
+    duplicates.sort_by_key(|d| std::cmp::Reverse(d.reclaimable_bytes()));
+    duplicates
+}
+
+fn format_cross_instance_table(duplicates: &[CrossInstanceDuplicate], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Name",
+        "Type",
+        "Copies",
+        "Combined Size",
+        "Reclaimable",
+    ]);
+
+    for duplicate in duplicates {
+        let copies = duplicate
+            .copies
+            .iter()
+            .map(|(instance, size)| format!("{} ({})", instance, format_file_size(*size)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        table.add_row(vec![
+            duplicate.name.clone(),
+            if duplicate.item_type == "show" {
+                "Show"
+            } else {
+                "Movie"
+            }
+            .to_string(),
+            copies,
+            format_file_size(duplicate.combined_bytes()),
+            format_file_size(duplicate.reclaimable_bytes()),
+        ]);
+    }
+
+    let total: u64 = duplicates
+        .iter()
+        .map(CrossInstanceDuplicate::reclaimable_bytes)
+        .sum();
+    table.add_row(vec![
+        format!("Total ({})", duplicates.len()),
+        String::new(),
+        String::new(),
+        String::new(),
+        format_file_size(total),
+    ]);
+
+    table.to_string()
+}
+
+fn format_extra_files_table(extra_files: &[ExtraFiles], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Name",
+        "Year",
+        "Files",
+        "Folder Size",
+        "Primary File",
+        "Reclaimable",
+    ]);
+
+    for entry in extra_files {
+        table.add_row(vec![
+            entry.name.clone(),
+            entry.year.to_string(),
+            entry.file_count.to_string(),
+            format_file_size(entry.folder_bytes),
+            format_file_size(entry.primary_file_bytes),
+            format_file_size(entry.reclaimable_bytes()),
+        ]);
+    }
+
+    let total: u64 = extra_files.iter().map(ExtraFiles::reclaimable_bytes).sum();
+    table.add_row(vec![
+        format!("Total ({})", extra_files.len()),
+        String::new(),
+        String::new(),
+        String::new(),
+        String::new(),
+        format_file_size(total),
+    ]);
+
+    table.to_string()
+}
+
+async fn read_status_version(resp: Response) -> Option<String> {
+    let body = resp.bytes().await.ok()?;
+    serde_json::from_slice::<SystemStatus>(&body).ok()?.version
+}
+
+fn unsupported_version_error(instance: &Instance, version: Option<&str>) -> String {
+    let found = match version {
+        Some(version) => format!("is version {}", version),
+        None => "only offers the legacy API".to_string(),
+    };
+    format!(
+        "{} at {} {}, which wastearr doesn't support; it needs v{} or newer (API v3)",
+        instance.service_name(),
+        instance.url,
+        found,
+        MIN_SERVER_VERSION
+    )
+}
+
+/// Reach the instance's status endpoint, returning the server version it reports
+async fn check_instance_status(instance: &Instance) -> Result<Option<String>, String> {
+    let (url, service_name) = (&instance.url, instance.service_name());
+    let Some(key) = instance.api_key.as_ref() else {
+        return Err(format!(
+            "{}_API_KEY environment variable not set",
+            instance.env_prefix
+        ));
+    };
+
+    let timeout = instance.request_timeout.unwrap_or(STATUS_TIMEOUT);
+    match send_request(instance, key, "system/status", timeout, &HeaderMap::new()).await {
+        // An unreadable status body only costs the version-specific handling
+        Ok(resp) if resp.status().is_success() => {
+            // Redirects are followed, but each request then pays for the extra hop
+            if let Some(base) = resp
+                .url()
+                .as_str()
+                .strip_suffix("/api/v3/system/status")
+                .filter(|base| base != url)
+            {
+                info!(
+                    "{} redirects to {}; set {}_URL={} to skip the redirect",
+                    url, base, instance.env_prefix, base
+                );
+            }
+            let version = read_status_version(resp).await;
+            match version.as_deref().and_then(major_version) {
+                Some(major) if major < MIN_SERVER_VERSION => {
+                    Err(unsupported_version_error(instance, version.as_deref()))
+                }
+                _ => Ok(version),
+            }
+        }
+        // Servers from before the v3 API only answer on the legacy /api root
+        Ok(resp) if resp.status() == StatusCode::NOT_FOUND => {
+            match send_request(
+                instance,
+                key,
+                "/api/system/status",
+                timeout,
+                &HeaderMap::new(),
+            )
+            .await
+            {
+                Ok(legacy) if legacy.status().is_success() => {
+                    let version = read_status_version(legacy).await;
+                    Err(unsupported_version_error(instance, version.as_deref()))
+                }
+                _ => Err(unreachable_error(instance, &resp)),
+            }
+        }
+        Ok(resp) => Err(unreachable_error(instance, &resp)),
+        // Include the cause chain so certificate problems are visible
+        Err(e) => {
+            let error = format!("{:#}", anyhow::Error::from(e));
+            let mut message = format!("Cannot connect to {} at {}: {}", service_name, url, error);
+            // OpenSSL's complaints when an https:// URL points at a plain HTTP port
+            if url.starts_with("https://")
+                && (error.contains("wrong version number") || error.contains("packet length"))
+            {
+                let _ = write!(
+                    message,
+                    "\n    Hint: the server doesn't seem to speak HTTPS on this port; try {}_URL={}",
+                    instance.env_prefix,
+                    url.replacen("https://", "http://", 1)
+                );
+            }
+            Err(message)
+        }
+    }
+}
+
+/// Describe a failed status check, with the likely fix for common URL mistakes
+fn unreachable_error(instance: &Instance, resp: &Response) -> String {
+    let (url, status) = (&instance.url, resp.status());
+    let mut message = format!(
+        "{} API unreachable at {} (HTTP {})",
+        instance.service_name(),
+        url,
+        status
+    );
+    let final_url = resp.url().as_str();
+    if !final_url.starts_with(url.as_str()) {
+        let _ = write!(message, ", redirected to {}", final_url);
+    }
+
+    let url_key = format!("{}_URL", instance.env_prefix);
+    let hint = match status {
+        StatusCode::NOT_FOUND if url_path(url).is_empty() => Some(format!(
+            "if {} has a URL base set (Settings → General), add it to {}, e.g. {}/{}",
+            instance.service_name(),
+            url_key,
+            url,
+            instance.kind
+        )),
+        StatusCode::NOT_FOUND => Some(format!(
+            "check that {} matches the URL base in {}'s Settings → General",
+            url_path(url),
+            instance.service_name()
+        )),
+        StatusCode::UNAUTHORIZED => Some(format!(
+            "check {}_API_KEY against Settings → General → Security",
+            instance.env_prefix
+        )),
+        // What nginx and friends answer when plain HTTP reaches a TLS port
+        StatusCode::BAD_REQUEST if url.starts_with("http://") => Some(format!(
+            "if this port serves HTTPS, try {}={}",
+            url_key,
+            url.replacen("http://", "https://", 1)
+        )),
+        _ => None,
+    };
+    if let Some(hint) = hint {
+        let _ = write!(message, "\n    Hint: {}", hint);
+    }
+    message
+}
+
+/// An instance left out of a --skip-unreachable scan
+#[derive(Debug, Clone, Serialize)]
+struct SkippedInstance {
+    instance: String,
+    reason: String,
+}
+
+/// Drop an unreachable instance from the scan, warning about it
+fn skip_instance(skipped: &mut Vec<SkippedInstance>, name: &str, reason: String) {
+    warn!("skipping {}: {}", name, reason);
+    skipped.push(SkippedInstance {
+        instance: name.to_string(),
+        reason,
+    });
+}
+
+/// Check every instance up front; with `skip_unreachable` the failing ones
+/// are dropped from `config` and returned, as long as one instance is left
+async fn validate_api_connectivity(
+    config: &mut Config,
+    scan_types: &[String],
+    skip_unreachable: bool,
+) -> Result<Vec<SkippedInstance>> {
+    let instances: Vec<&Instance> = config.instances_for(scan_types).collect();
+    let statuses = join_all(
+        instances
+            .iter()
+            .map(|instance| check_instance_status(instance)),
+    )
+    .await;
+    let checked = instances.len();
+    let statuses: Vec<(String, Result<Option<String>, String>)> = instances
+        .iter()
+        .map(|instance| instance.name.clone())
+        .zip(statuses)
+        .collect();
+
+    let mut failures = Vec::new();
+    for (name, status) in statuses {
+        match status {
+            Ok(version) => {
+                if let Some(instance) = config.instances.iter_mut().find(|i| i.name == name) {
+                    instance.version = version;
+                }
+            }
+            Err(error) => failures.push((name, error)),
+        }
+    }
+
+    if skip_unreachable && failures.len() < checked {
+        let mut skipped = Vec::new();
+        for (name, error) in failures {
+            skip_instance(&mut skipped, &name, error);
+        }
+        config
+            .instances
+            .retain(|instance| !skipped.iter().any(|s| s.instance == instance.name));
+        return Ok(skipped);
+    }
+
+    let api_errors: Vec<String> = failures.into_iter().map(|(_, error)| error).collect();
+    if !api_errors.is_empty() {
+        let mut report = String::from("API connectivity issues detected:");
+        for error in &api_errors {
+            let _ = write!(report, "\n  - {}", error);
+        }
+        report.push_str(
+            "\n\nPlease ensure:\n  - Sonarr/Radarr services are running\n  \
+             - API keys are correctly set via environment variables\n  - URLs are accessible",
+        );
+        tracing::error!("{}", report);
+        anyhow::bail!("API connectivity validation failed");
+    }
+
+    Ok(Vec::new())
+}
+
+/// wastearr's own cache directory: WASTEARR_CACHE_DIR, or `wastearr` under
+/// the platform cache dir
+fn wastearr_cache_dir() -> Option<PathBuf> {
+    get_config_value("WASTEARR_CACHE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| cache_dir().map(|d| d.join("wastearr")))
+}
+
+// Ratings and libraries are disposable, so they live in the cache dir; scan
+// history is durable and lives in the state dir, where clearing caches can't touch it
+fn cache_store_path() -> Option<PathBuf> {
+    wastearr_cache_dir().map(|d| d.join("wastearr.db"))
+}
+
+fn history_store_path() -> Option<PathBuf> {
+    wastearr_state_dir().map(|d| d.join("history.db"))
+}
+
+/// wastearr's own state directory: WASTEARR_STATE_DIR, or `wastearr` under the
+/// platform state dir (the local data dir where there is none, e.g. macOS)
+fn wastearr_state_dir() -> Option<PathBuf> {
+    get_config_value("WASTEARR_STATE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            state_dir()
+                .or_else(data_local_dir)
+                .map(|d| d.join("wastearr"))
+        })
+}
+
+const CACHE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS ratings (
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        rating TEXT NOT NULL,
+        cached_at REAL NOT NULL,
+        PRIMARY KEY (kind, key)
+    );
+    CREATE TABLE IF NOT EXISTS libraries (
+        instance TEXT PRIMARY KEY,
+        fetched_at REAL NOT NULL,
+        validators TEXT NOT NULL,
+        items BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sources (
+        instance TEXT PRIMARY KEY,
+        url TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS last_run (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        finished_at REAL NOT NULL,
+        hits INTEGER NOT NULL,
+        misses INTEGER NOT NULL
+    );
+";
+
+const HISTORY_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY,
+        timestamp REAL NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS scan_instances (
+        scan_id INTEGER NOT NULL REFERENCES scans (id),
+        instance TEXT NOT NULL,
+        PRIMARY KEY (scan_id, instance)
+    );
+    CREATE INDEX IF NOT EXISTS scan_instances_by_instance ON scan_instances (instance, scan_id);
+    CREATE TABLE IF NOT EXISTS scan_items (
+        scan_id INTEGER NOT NULL REFERENCES scans (id),
+        instance TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        item_type TEXT NOT NULL,
+        size_bytes INTEGER NOT NULL,
+        waste_score INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS scan_items_by_scan ON scan_items (scan_id, instance);
+    CREATE TABLE IF NOT EXISTS scan_summaries (
+        scan_id INTEGER NOT NULL REFERENCES scans (id),
+        instance TEXT NOT NULL,
+        items INTEGER NOT NULL,
+        total_bytes INTEGER NOT NULL,
+        average_waste REAL NOT NULL,
+        flagged INTEGER NOT NULL,
+        PRIMARY KEY (scan_id, instance)
+    );
+    CREATE TABLE IF NOT EXISTS scan_folders (
+        scan_id INTEGER NOT NULL REFERENCES scans (id),
+        instance TEXT NOT NULL,
+        root_folder TEXT NOT NULL,
+        total_bytes INTEGER NOT NULL,
+        PRIMARY KEY (scan_id, instance, root_folder)
+    );
+    CREATE TABLE IF NOT EXISTS scan_reclaimed (
+        scan_id INTEGER PRIMARY KEY REFERENCES scans (id),
+        items INTEGER NOT NULL,
+        bytes INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS notes (
+        instance TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        note TEXT NOT NULL,
+        updated_at REAL NOT NULL,
+        PRIMARY KEY (instance, id)
+    );
+    CREATE TABLE IF NOT EXISTS deletions (
+        instance TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        item_type TEXT NOT NULL,
+        size_bytes INTEGER NOT NULL,
+        deleted_at REAL NOT NULL,
+        PRIMARY KEY (instance, id, deleted_at)
+    );
+    CREATE TABLE IF NOT EXISTS notifications (
+        backend TEXT PRIMARY KEY,
+        fingerprint TEXT,
+        pending TEXT,
+        release_at REAL
+    );
+";
+
+// How long a run waits for another one writing to a store
+const STORE_BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn open_database(path: &Path, schema: &str) -> Result<Connection> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let conn =
+        Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    conn.busy_timeout(STORE_BUSY_TIMEOUT)?;
+    // WAL lets reports read while another run is writing
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.execute_batch(schema)
+        .with_context(|| format!("Failed to set up {}", path.display()))?;
+    Ok(conn)
+}
+
+/// Forget an instance's cached library so the next scan fetches it in full
+fn drop_cached_library(instance: &str) -> Result<()> {
+    if let Some(conn) = open_cache_store()? {
+        conn.execute("DELETE FROM libraries WHERE instance = ?1", [instance])?;
+    }
+    Ok(())
+}
+
+/// Open the rating and library cache, creating it on first use; None without a
+/// cache directory
+fn open_cache_store() -> Result<Option<Connection>> {
+    let Some(path) = cache_store_path() else {
+        return Ok(None);
+    };
+    let conn = open_database(&path, CACHE_SCHEMA)?;
+    // Ratings of primary instances used to be keyed by bare item ID
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version < 1 {
+        conn.execute_batch(
+            "BEGIN;
+             UPDATE OR REPLACE ratings SET key = kind || ':' || key WHERE instr(key, ':') = 0;
+             PRAGMA user_version = 1;
+             COMMIT;",
+        )?;
+    }
+    // The JSON cache of older versions is rebuilt on the next scan anyway
+    if let Some(dir) = wastearr_cache_dir() {
+        for name in ["cache.json", "cache.json.gz", "cache.lock"] {
+            if fs::remove_file(dir.join(name)).is_ok() {
+                progress!("Removed old cache file {}", name);
+            }
+        }
+    }
+    Ok(Some(conn))
+}
+
+/// Open the scan history, creating it on first use and moving in history
+/// that older versions kept in the cache dir; None without a state directory
+fn open_history_store() -> Result<Option<Connection>> {
+    let Some(path) = history_store_path() else {
+        return Ok(None);
+    };
+    let mut conn = open_database(&path, HISTORY_SCHEMA)?;
+    if let Some(old) = cache_store_path().filter(|path| path.exists()) {
+        migrate_cached_history(&mut conn, &old)?;
+    }
+    import_history_jsonl(&mut conn)?;
+    rebuild_reclaimed(&mut conn)?;
+    Ok(Some(conn))
+}
+
+const HISTORY_TABLES: [&str; 6] = [
+    "scans",
+    "scan_instances",
+    "scan_items",
+    "scan_summaries",
+    "scan_folders",
+    "scan_reclaimed",
+];
+
+/// Move scan tables out of the cache database at `old`, where they used to live
+fn migrate_cached_history(conn: &mut Connection, old: &Path) -> Result<()> {
+    let migrated = attached(conn, old, |conn| {
+        let mut tables = Vec::new();
+        for table in HISTORY_TABLES {
+            if imported_table_exists(conn, table)? {
+                tables.push(table);
+            }
+        }
+        if tables.is_empty() {
+            return Ok(0);
+        }
+        let empty: bool =
+            conn.query_row("SELECT COUNT(*) = 0 FROM main.scans", [], |row| row.get(0))?;
+        let moved = if !tables.contains(&"scans") {
+            0
+        } else if empty {
+            // Scan ids carry over as they are into an empty history
+            let tx = conn.transaction()?;
+            let mut moved = 0;
+            for table in &tables {
+                let rows = tx.execute(
+                    &format!("INSERT INTO main.{0} SELECT * FROM imported.{0}", table),
+                    [],
+                )?;
+                if *table == "scans" {
+                    moved = rows;
+                }
+            }
+            tx.commit()?;
+            moved
+        } else {
+            // e.g. left behind by a downgrade; merged like an imported bundle
+            merge_history(conn)?.0
+        };
+        let tx = conn.transaction()?;
+        // Children first, or dropping scans trips their foreign keys
+        for table in tables.iter().rev() {
+            tx.execute(&format!("DROP TABLE imported.{}", table), [])?;
+        }
+        tx.commit()?;
+        Ok(moved)
+    });
+    let migrated = migrated
+        .with_context(|| format!("Failed to move scan history out of {}", old.display()))?;
+    if migrated > 0 {
+        progress!(
+            "Moved {} scans from {} to the state directory",
+            migrated,
+            old.display()
+        );
+    }
+    Ok(())
+}
+
+/// Recount the space each scan reclaimed from the deletion log: once for
+/// histories that guessed it from flagged items going missing, and again after
+/// an import renumbers scans or brings in deletions
+fn rebuild_reclaimed(conn: &mut Connection) -> Result<()> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version >= 2 {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM scan_reclaimed", [])?;
+    // Each scan gets the deletions since the scan before it
+    tx.execute(
+        "INSERT INTO scan_reclaimed (scan_id, items, bytes)
+         SELECT scans.id, COUNT(*), SUM(deletions.size_bytes) FROM scans
+         JOIN deletions ON deletions.deleted_at <= scans.timestamp
+             AND deletions.deleted_at > COALESCE(
+                 (SELECT MAX(earlier.timestamp) FROM scans AS earlier
+                  WHERE earlier.timestamp < scans.timestamp),
+                 0)
+         GROUP BY scans.id",
+        [],
+    )?;
+    tx.pragma_update(None, "user_version", 2)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Import the history.jsonl that older versions kept in the cache dir
+fn import_history_jsonl(conn: &mut Connection) -> Result<()> {
+    let Some(dir) = wastearr_cache_dir() else {
+        return Ok(());
+    };
+    let path = dir.join("history.jsonl");
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    let snapshots: Vec<ScanSnapshot> = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let tx = conn.transaction()?;
+    for snapshot in &snapshots {
+        insert_snapshot(&tx, snapshot)?;
+    }
+    tx.commit()?;
+    fs::rename(&path, dir.join("history.jsonl.imported"))?;
+    progress!("Imported {} scans from {}", snapshots.len(), path.display());
+    Ok(())
+}
+
+fn compress_items(items: &[Item]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, items)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress_items(bytes: &[u8]) -> Result<Vec<Item>> {
+    Ok(serde_json::from_reader(GzDecoder::new(bytes))?)
+}
+
+fn library_cache_ttl() -> f64 {
+    get_config_value("WASTEARR_LIBRARY_CACHE_TTL")
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_LIBRARY_CACHE_TTL)
+}
+
+/// Read the cache; `keep_expired` serves it however old it is (for --offline)
+fn load_cache(keep_expired: bool, refresh_ratings: bool) -> CacheData {
+    try_load_cache(keep_expired, refresh_ratings).unwrap_or_else(|e| {
+        warn!("failed to read cache, starting fresh: {:#}", e);
+        CacheData::default()
+    })
+}
+
+fn rating_expired(rating: &str, age: f64) -> bool {
+    let ttl = if rating == "N/A" {
+        NA_RATING_CACHE_DURATION
+    } else {
+        CACHE_DURATION
+    };
+    age > ttl as f64
+}
+
+fn try_load_cache(keep_expired: bool, refresh_ratings: bool) -> Result<CacheData> {
+    let Some(conn) = open_cache_store()? else {
+        progress!("No cache directory available");
+        return Ok(CacheData::default());
+    };
+    let mut cache_data = CacheData::default();
+    let now = current_timestamp();
+    let mut ratings = conn.prepare("SELECT kind, key, rating, cached_at FROM ratings")?;
+    let rows = ratings.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, f64>(3)?,
+        ))
+    })?;
+    let mut refreshing = 0;
+    for row in rows {
+        let (kind, key, rating, cached_at) = row?;
+        let age = now - cached_at;
+        if !keep_expired && rating_expired(&rating, age) {
+            continue;
+        }
+        if refresh_ratings && (rating == "N/A" || age > RATING_REFRESH_AGE as f64) {
+            refreshing += 1;
+            continue;
+        }
+        cache_data
+            .loaded_ratings
+            .insert((kind.clone(), key.clone()));
+        match kind.as_str() {
+            "sonarr" => cache_data.sonarr_ratings.insert(key, rating),
+            _ => cache_data.radarr_ratings.insert(key, rating),
+        };
+    }
+    if refresh_ratings {
+        // Cached library items carry the ratings they were scanned with, so the
+        // lists have to be fetched again too
+        progress!("Refreshing {} N/A or stale cached ratings", refreshing);
+        return Ok(cache_data);
+    }
+
+    let mut libraries =
+        conn.prepare("SELECT instance, fetched_at, validators, items FROM libraries")?;
+    let rows = libraries.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, f64>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (instance, fetched_at, validators, items) = row?;
+        // An unreadable library is simply fetched again
+        let Ok(items) = decompress_items(&items) else {
+            progress!("Cached {} library unreadable, refetching", instance);
+            continue;
+        };
+        cache_data.libraries.insert(
+            instance,
+            CachedLibrary {
+                fetched_at,
+                items,
+                validators: serde_json::from_str(&validators).unwrap_or_default(),
+            },
+        );
+    }
+    progress!(
+        "Loaded {} cached ratings and {} libraries",
+        cache_data.sonarr_ratings.len() + cache_data.radarr_ratings.len(),
+        cache_data.libraries.len()
+    );
+    Ok(cache_data)
+}
+
+fn save_cache(cache_data: &CacheData) {
+    if let Err(e) = try_save_cache(cache_data) {
+        warn!("failed to save cache: {:#}", e);
+    }
+}
+
+/// Write new and refreshed ratings and libraries in one transaction and drop
+/// expired rows. Rows another run saved meanwhile are kept; the newest library wins.
+fn try_save_cache(cache_data: &CacheData) -> Result<()> {
+    let Some(mut conn) = open_cache_store()? else {
+        return Ok(());
+    };
+    let now = current_timestamp();
+    progress!(
+        "Saving cache with {} ratings",
+        cache_data.sonarr_ratings.len() + cache_data.radarr_ratings.len()
+    );
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM ratings WHERE cached_at < ?1 OR (rating = 'N/A' AND cached_at < ?2)",
+        [
+            now - CACHE_DURATION as f64,
+            now - NA_RATING_CACHE_DURATION as f64,
+        ],
+    )?;
+    {
+        // Ratings loaded from the store are already there; the rest are new or refreshed
+        let mut upsert = tx.prepare(
+            "INSERT INTO ratings (kind, key, rating, cached_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (kind, key) DO UPDATE SET
+                 rating = excluded.rating,
+                 cached_at = excluded.cached_at",
+        )?;
+        for (kind, ratings) in [
+            ("sonarr", &cache_data.sonarr_ratings),
+            ("radarr", &cache_data.radarr_ratings),
+        ] {
+            for (key, rating) in ratings {
+                if !cache_data
+                    .loaded_ratings
+                    .contains(&(kind.to_string(), key.clone()))
+                {
+                    upsert.execute(params![kind, key, rating, now])?;
+                }
+            }
+        }
+        let mut upsert = tx.prepare(
+            "INSERT INTO libraries (instance, fetched_at, validators, items) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (instance) DO UPDATE SET
+                 fetched_at = excluded.fetched_at,
+                 validators = excluded.validators,
+                 items = excluded.items
+             WHERE excluded.fetched_at > libraries.fetched_at",
+        )?;
+        for (instance, library) in &cache_data.libraries {
+            upsert.execute(params![
+                instance,
+                library.fetched_at,
+                serde_json::to_string(&library.validators)?,
+                compress_items(&library.items)?,
+            ])?;
+        }
+    }
+    // Libraries past their TTL are kept a while longer for revalidation and --offline
+    tx.execute(
+        "DELETE FROM libraries WHERE fetched_at < ?1",
+        [now - library_cache_ttl().max(CACHE_DURATION as f64)],
+    )?;
+    let (hits, misses) = cache_data.run_stats;
+    tx.execute(
+        "INSERT OR REPLACE INTO last_run (id, finished_at, hits, misses) VALUES (1, ?1, ?2, ?3)",
+        params![now, hits as i64, misses as i64],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct RatingCacheStats {
+    service: String,
+    entries: usize,
+    not_available: usize,
+    expired: usize,
+    oldest_age: Option<f64>,
+    // Seconds until the next rating expires
+    next_expiry: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LibraryCacheStats {
+    instance: String,
+    // None when the stored items don't parse
+    items: Option<usize>,
+    compressed_bytes: u64,
+    age: f64,
+    ttl_remaining: f64,
+    revalidates: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct LastRun {
+    finished_at: f64,
+    hits: usize,
+    misses: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct CacheReport {
+    path: String,
+    size_bytes: u64,
+    ratings: Vec<RatingCacheStats>,
+    libraries: Vec<LibraryCacheStats>,
+    last_run: Option<LastRun>,
+    problems: Vec<String>,
+}
+
+fn read_cache_report(conn: &Connection, path: &Path) -> Result<CacheReport> {
+    let now = current_timestamp();
+    let mut problems: Vec<String> = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|result| result != "ok")
+        .collect();
+
+    let mut ratings = Vec::new();
+    let mut query = conn.prepare("SELECT rating, cached_at FROM ratings WHERE kind = ?1")?;
+    for service in ["sonarr", "radarr"] {
+        let rows = query
+            .query_map([service], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let ages = || rows.iter().map(|(_, cached_at)| now - cached_at);
+        ratings.push(RatingCacheStats {
+            service: service.to_string(),
+            entries: rows.len(),
+            not_available: rows.iter().filter(|(rating, _)| rating == "N/A").count(),
+            expired: rows
+                .iter()
+                .filter(|(rating, cached_at)| rating_expired(rating, now - cached_at))
+                .count(),
+            oldest_age: ages().reduce(f64::max),
+            next_expiry: rows
+                .iter()
+                .map(|(rating, cached_at)| {
+                    let ttl = if rating == "N/A" {
+                        NA_RATING_CACHE_DURATION
+                    } else {
+                        CACHE_DURATION
+                    };
+                    ttl as f64 - (now - cached_at)
+                })
+                .filter(|remaining| *remaining > 0.0)
+                .reduce(f64::min),
+        });
+    }
+
+    let mut libraries = Vec::new();
+    let mut query = conn.prepare(
+        "SELECT instance, fetched_at, validators, items FROM libraries ORDER BY instance",
+    )?;
+    let rows = query.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, f64>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (instance, fetched_at, validators, items) = row?;
+        let items = match decompress_items(&items) {
+            Ok(parsed) => Some((parsed.len(), items.len())),
+            Err(e) => {
+                problems.push(format!("{} library doesn't parse: {:#}", instance, e));
+                None
+            }
+        };
+        let validators = match serde_json::from_str::<Validators>(&validators) {
+            Ok(validators) => validators,
+            Err(e) => {
+                problems.push(format!("{} validators don't parse: {:#}", instance, e));
+                Validators::default()
+            }
+        };
+        let age = now - fetched_at;
+        libraries.push(LibraryCacheStats {
+            instance,
+            items: items.map(|(count, _)| count),
+            compressed_bytes: items.map_or(0, |(_, bytes)| bytes as u64),
+            age,
+            ttl_remaining: (library_cache_ttl() - age).max(0.0),
+            revalidates: validators.etag.is_some() || validators.last_modified.is_some(),
+        });
+    }
+
+    let last_run = conn
+        .query_row(
+            "SELECT finished_at, hits, misses FROM last_run WHERE id = 1",
+            [],
+            |row| {
+                Ok(LastRun {
+                    finished_at: row.get(0)?,
+                    hits: row.get::<_, i64>(1)? as usize,
+                    misses: row.get::<_, i64>(2)? as usize,
+                })
+            },
+        )
+        .optional()?;
+
+    // The WAL holds recent writes not yet merged into the main file
+    let size_bytes = ["", "-wal"]
+        .iter()
+        .filter_map(|suffix| {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            fs::metadata(file).ok()
+        })
+        .map(|metadata| metadata.len())
+        .sum();
+    Ok(CacheReport {
+        path: path.display().to_string(),
+        size_bytes,
+        ratings,
+        libraries,
+        last_run,
+        problems,
+    })
+}
+
+fn format_cache_report(report: &CacheReport, style: &str) -> String {
+    let mut output = format!(
+        "Cache: {} ({})\n",
+        report.path,
+        format_file_size(report.size_bytes)
+    );
+
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Ratings",
+        "Entries",
+        "N/A",
+        "Expired",
+        "Oldest",
+        "Next Expiry",
+    ]);
+    for ratings in &report.ratings {
+        table.add_row(vec![
+            ratings.service.clone(),
+            ratings.entries.to_string(),
+            ratings.not_available.to_string(),
+            ratings.expired.to_string(),
+            ratings.oldest_age.map_or("-".to_string(), format_age),
+            ratings
+                .next_expiry
+                .map_or("-".to_string(), |secs| format!("in {}", format_age(secs))),
+        ]);
+    }
+    let _ = writeln!(output, "\n{}", table);
+
+    if report.libraries.is_empty() {
+        let _ = writeln!(output, "\nNo cached libraries");
+    } else {
+        let mut table = Table::new();
+        apply_table_style(&mut table, style);
+        table.set_header(vec![
+            "Library",
+            "Items",
+            "Stored",
+            "Fetched",
+            "TTL Remaining",
+            "Revalidates",
+        ]);
+        for library in &report.libraries {
+            table.add_row(vec![
+                library.instance.clone(),
+                library
+                    .items
+                    .map_or("unreadable".to_string(), |n| n.to_string()),
+                format_file_size(library.compressed_bytes),
+                format!("{} ago", format_age(library.age)),
+                if library.ttl_remaining > 0.0 {
+                    format_age(library.ttl_remaining)
+                } else {
+                    "expired".to_string()
+                },
+                if library.revalidates { "yes" } else { "no" }.to_string(),
+            ]);
+        }
+        let _ = writeln!(output, "\n{}", table);
+    }
+
+    match &report.last_run {
+        Some(run) => {
+            let lookups = run.hits + run.misses;
+            let _ = writeln!(
+                output,
+                "\nLast run {} ago: {} hits, {} misses ({:.0}% hit rate)",
+                format_age(current_timestamp() - run.finished_at),
+                run.hits,
+                run.misses,
+                run.hits as f64 / lookups.max(1) as f64 * 100.0
+            );
+        }
+        None => {
+            let _ = writeln!(output, "\nNo run has saved the cache yet");
+        }
+    }
+    if report.problems.is_empty() {
+        let _ = writeln!(output, "Validation: ok");
+    } else {
+        let _ = writeln!(output, "Validation failed:");
+        for problem in &report.problems {
+            let _ = writeln!(output, "  {}", problem);
+        }
+    }
+    output
+}
+
+fn print_cache_stats(args: &Args) -> Result<()> {
+    let Some(path) = cache_store_path() else {
+        anyhow::bail!("No cache directory available");
+    };
+    if !path.exists() {
+        progress!("No cache yet at {}; run a scan first", path.display());
+        return Ok(());
+    }
+    let report = match open_cache_store() {
+        Ok(Some(conn)) => read_cache_report(&conn, &path),
+        Ok(None) => anyhow::bail!("No cache directory available"),
+        Err(e) => Err(e),
+    }
+    .with_context(|| format!("Cache at {} is unreadable", path.display()))?;
+    if args.output == "json" {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", format_cache_report(&report, &args.table_style));
+    }
+    if !report.problems.is_empty() {
+        anyhow::bail!("Cache failed validation; clear it with --clear-cache");
+    }
+    Ok(())
+}
+
+/// Drop the cached ratings and library of instances whose URL changed since
+/// they were cached, so a repointed instance doesn't inherit another server's data
+fn check_cache_sources(config: &Config) {
+    if let Err(e) = try_check_cache_sources(config) {
+        warn!("failed to check cache sources: {:#}", e);
+    }
+}
+
+fn try_check_cache_sources(config: &Config) -> Result<()> {
+    let Some(mut conn) = open_cache_store()? else {
+        return Ok(());
+    };
+    let tx = conn.transaction()?;
+    for instance in &config.instances {
+        let cached_url: Option<String> = tx
+            .query_row(
+                "SELECT url FROM sources WHERE instance = ?1",
+                [&instance.name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if cached_url.as_deref() == Some(instance.url.as_str()) {
+            continue;
+        }
+        // Caches from before URLs were recorded are assumed to match
+        if let Some(cached_url) = cached_url {
+            let ratings = tx.execute(
+                "DELETE FROM ratings WHERE kind = ?1 AND key LIKE ?2",
+                params![instance.kind, format!("{}:%", instance.name)],
+            )?;
+            let libraries = tx.execute(
+                "DELETE FROM libraries WHERE instance = ?1",
+                [&instance.name],
+            )?;
+            progress!(
+                "{} moved from {} to {}, discarded {} cached ratings and {} libraries",
+                instance.name,
+                cached_url,
+                instance.url,
+                ratings,
+                libraries
+            );
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO sources (instance, url) VALUES (?1, ?2)",
+            params![instance.name, instance.url],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Empty the rating and library cache, keeping scan history. `target` is
+/// "all", a service ("sonarr"/"radarr"), or one instance's name.
+fn clear_cache(target: &str, config: &Config) -> Result<()> {
+    let instance = match target {
+        "all" | "sonarr" | "radarr" => None,
+        name => Some(
+            config
+                .instances
+                .iter()
+                .find(|instance| instance.name == name)
+                .with_context(|| {
+                    format!(
+                        "Unknown cache '{}' to clear: expected sonarr, radarr, or an instance name",
+                        name
+                    )
+                })?,
+        ),
+    };
+    let Some(conn) = open_cache_store()? else {
+        progress!("No cache to clear");
+        return Ok(());
+    };
+    let (ratings, libraries) = match instance {
+        _ if target == "all" => (
+            conn.execute("DELETE FROM ratings", [])?,
+            conn.execute("DELETE FROM libraries", [])?,
+        ),
+        // Extra instances are named after their service, e.g. radarr-4k
+        None => (
+            conn.execute("DELETE FROM ratings WHERE kind = ?1", [target])?,
+            conn.execute(
+                "DELETE FROM libraries WHERE instance = ?1 OR instance LIKE ?1 || '-%'",
+                [target],
+            )?,
+        ),
+        // Instances share their service's ratings under "name:id" keys
+        Some(instance) => (
+            conn.execute(
+                "DELETE FROM ratings WHERE kind = ?1 AND key LIKE ?2",
+                params![instance.kind, format!("{}:%", instance.name)],
+            )?,
+            conn.execute("DELETE FROM libraries WHERE instance = ?1", [target])?,
+        ),
+    };
+    progress!(
+        "Cleared {} cached ratings and {} libraries ({})",
+        ratings,
+        libraries,
+        target
+    );
+    Ok(())
+}
+
+fn current_timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Hold an advisory lock for the rest of the run so overlapping runs don't
+/// race on the cache or double up on API requests
+fn acquire_run_lock(wait: bool) -> Result<Option<fs::File>> {
+    let Some(path) = wastearr_state_dir()
+        .or_else(wastearr_cache_dir)
+        .map(|d| d.join("wastearr.lock"))
+    else {
+        return Ok(None);
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+    if !file.try_lock_exclusive()? {
+        if !wait {
+            anyhow::bail!(
+                "Another wastearr instance is running (lock held on {}); use --wait to wait for it",
+                path.display()
+            );
+        }
+        progress!("Waiting for another wastearr instance to finish...");
+        file.lock_exclusive()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+    }
+    Ok(Some(file))
+}
+
+/// Point instances at --mock fixtures or a --replay session, or start
+/// collecting responses for --record
+fn set_up_response_source(
+    config: &mut Config,
+    args: &Args,
+) -> Result<Option<Arc<Mutex<Vec<RecordedResponse>>>>> {
+    if let Some(dir) = &args.mock {
+        config
+            .instances
+            .retain(|instance| dir.join(&instance.name).is_dir());
+        if config.instances.is_empty() {
+            anyhow::bail!(
+                "No fixtures found in {}: expected a directory per instance, e.g. sonarr/series.json and radarr/movie.json",
+                dir.display()
+            );
+        }
+        for instance in &mut config.instances {
+            instance.canned = Some(CannedResponses::Fixtures(dir.clone()));
+            instance.api_key.get_or_insert_with(|| "mock".to_string());
+        }
+    }
+
+    if let Some(path) = &args.replay {
+        let session: Session = serde_json::from_str(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Failed to read session {}", path.display()))?,
+        )
+        .with_context(|| format!("Invalid session file {}", path.display()))?;
+        let session = Arc::new(session);
+        // Replay the recorded instances, so a shared session works without its author's config
+        config.instances = session
+            .instances
+            .iter()
+            .map(|recorded| {
+                let env_prefix = recorded.name.to_uppercase().replace('-', "_");
+                let mut instance = new_instance(
+                    recorded.name.clone(),
+                    &recorded.kind,
+                    recorded.url.clone(),
+                    env_prefix,
+                )?;
+                instance.canned = Some(CannedResponses::Session(Arc::clone(&session)));
+                instance.api_key = Some("replay".to_string());
+                Ok(instance)
+            })
+            .collect::<Result<_>>()?;
+        progress!(
+            "Replaying {} responses recorded {} ago",
+            session.responses.len(),
+            format_age(current_timestamp() - session.recorded_at)
+        );
+    }
+
+    if args.record.is_none() {
+        return Ok(None);
+    }
+    let recorder = Arc::new(Mutex::new(Vec::new()));
+    for instance in &mut config.instances {
+        instance.recorder = Some(Arc::clone(&recorder));
+    }
+    Ok(Some(recorder))
+}
+
+fn save_session(
+    path: &Path,
+    instances: &[Instance],
+    recorder: &Mutex<Vec<RecordedResponse>>,
+) -> Result<()> {
+    let session = Session {
+        recorded_at: current_timestamp(),
+        instances: instances
+            .iter()
+            .map(|instance| SessionInstance {
+                name: instance.name.clone(),
+                kind: instance.kind.clone(),
+                url: instance.url.clone(),
+            })
+            .collect(),
+        responses: std::mem::take(&mut *recorder.lock().unwrap()),
+    };
+    fs::write(path, serde_json::to_string(&session)?)
+        .with_context(|| format!("Failed to write session {}", path.display()))?;
+    progress!(
+        "Recorded {} responses to {}",
+        session.responses.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Every stored scan, oldest first
+fn load_history() -> Vec<ScanSnapshot> {
+    let history = open_history_store().and_then(|conn| match conn {
+        Some(conn) => read_history(&conn),
+        None => Ok(Vec::new()),
+    });
+    history.unwrap_or_else(|e| {
+        warn!("failed to read scan history: {:#}", e);
+        Vec::new()
+    })
+}
+
+fn read_history(conn: &Connection) -> Result<Vec<ScanSnapshot>> {
+    let mut scans = conn.prepare("SELECT id, timestamp FROM scans ORDER BY id")?;
+    let mut history = Vec::new();
+    let mut positions = HashMap::new();
+    for row in scans.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)))? {
+        let (id, timestamp) = row?;
+        positions.insert(id, history.len());
+        history.push(ScanSnapshot {
+            timestamp,
+            instances: Vec::new(),
+            items: Vec::new(),
+            summaries: Vec::new(),
+            folders: Vec::new(),
+            reclaimed: Reclaimed::default(),
+        });
+    }
+
+    let mut instances = conn.prepare("SELECT scan_id, instance FROM scan_instances")?;
+    for row in instances.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))? {
+        let (scan_id, instance) = row?;
+        if let Some(&position) = positions.get(&scan_id) {
+            history[position].instances.push(instance);
+        }
+    }
+
+    let mut items = conn.prepare(
+        "SELECT scan_id, instance, id, name, item_type, size_bytes, waste_score FROM scan_items",
+    )?;
+    for row in items.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, snapshot_item(row, 1)?))
+    })? {
+        let (scan_id, item) = row?;
+        if let Some(&position) = positions.get(&scan_id) {
+            history[position].items.push(item);
+        }
+    }
+    Ok(history)
+}
+
+/// (scan time, size, waste score) of one item in every stored scan that has it, oldest first
+fn read_item_history(conn: &Connection, instance: &str, id: i32) -> Result<Vec<(f64, u64, i32)>> {
+    let mut statement = conn.prepare(
+        "SELECT scans.timestamp, scan_items.size_bytes, scan_items.waste_score
+         FROM scan_items JOIN scans ON scans.id = scan_items.scan_id
+         WHERE scan_items.instance = ?1 AND scan_items.id = ?2
+         ORDER BY scans.id",
+    )?;
+    let rows = statement.query_map(params![instance, id], |row| {
+        Ok((row.get(0)?, row.get::<_, i64>(1)? as u64, row.get(2)?))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// A SnapshotItem from the scan_items columns starting at `first`
+fn snapshot_item(row: &rusqlite::Row, first: usize) -> rusqlite::Result<SnapshotItem> {
+    Ok(SnapshotItem {
+        instance: row.get(first)?,
+        id: row.get(first + 1)?,
+        name: row.get(first + 2)?,
+        item_type: row.get(first + 3)?,
+        size_bytes: row.get::<_, i64>(first + 4)? as u64,
+        waste_score: row.get(first + 5)?,
+    })
+}
+
+fn insert_snapshot(conn: &Connection, snapshot: &ScanSnapshot) -> Result<()> {
+    conn.execute(
+        "INSERT INTO scans (timestamp) VALUES (?1)",
+        [snapshot.timestamp],
+    )?;
+    let scan_id = conn.last_insert_rowid();
+    let mut instances = conn.prepare_cached(
+        "INSERT OR IGNORE INTO scan_instances (scan_id, instance) VALUES (?1, ?2)",
+    )?;
+    for instance in &snapshot.instances {
+        instances.execute(params![scan_id, instance])?;
+    }
+    let mut items = conn.prepare_cached(
+        "INSERT INTO scan_items (scan_id, instance, id, name, item_type, size_bytes, waste_score)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for item in &snapshot.items {
+        items.execute(params![
+            scan_id,
+            item.instance,
+            item.id,
+            item.name,
+            item.item_type,
+            item.size_bytes as i64,
+            item.waste_score,
+        ])?;
+    }
+    if snapshot.reclaimed.items > 0 {
+        conn.execute(
+            "INSERT INTO scan_reclaimed (scan_id, items, bytes) VALUES (?1, ?2, ?3)",
+            params![
+                scan_id,
+                snapshot.reclaimed.items as i64,
+                snapshot.reclaimed.bytes as i64
+            ],
+        )?;
+    }
+    let mut summaries = conn.prepare_cached(
+        "INSERT OR REPLACE INTO scan_summaries (scan_id, instance, items, total_bytes, average_waste, flagged)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for summary in &snapshot.summaries {
+        summaries.execute(params![
+            scan_id,
+            summary.instance,
+            summary.items as i64,
+            summary.total_bytes as i64,
+            summary.average_waste,
+            summary.flagged as i64,
+        ])?;
+    }
+    let mut folders = conn.prepare_cached(
+        "INSERT OR REPLACE INTO scan_folders (scan_id, instance, root_folder, total_bytes)
+         VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for folder in &snapshot.folders {
+        folders.execute(params![
+            scan_id,
+            folder.instance,
+            folder.root_folder,
+            folder.total_bytes as i64,
+        ])?;
+    }
+    Ok(())
+}
+
+fn append_history(snapshot: &ScanSnapshot) {
+    let written = open_history_store().and_then(|conn| {
+        let Some(mut conn) = conn else {
+            return Ok(());
+        };
+        let tx = conn.transaction()?;
+        insert_snapshot(&tx, snapshot)?;
+        tx.commit()?;
+        Ok(())
+    });
+    if let Err(e) = written {
+        warn!("failed to record scan history: {:#}", e);
+    }
+}
+
+fn snapshot_items(items: &[Item]) -> Vec<SnapshotItem> {
+    items
+        .iter()
+        .map(|item| SnapshotItem {
+            instance: item.instance.clone(),
+            id: item.id,
+            name: item.name.clone(),
+            item_type: item.item_type.clone(),
+            size_bytes: item.size_bytes,
+            waste_score: item.waste_score,
+        })
+        .collect()
+}
+
+/// The most recent earlier scan of each instance being scanned now
+#[derive(Debug, Default)]
+struct PreviousScan {
+    items: HashMap<(String, i32), SnapshotItem>,
+    summaries: Vec<InstanceSummary>,
+    // The oldest of those scans, so the report can say what it's comparing against
+    timestamp: Option<f64>,
+    // False when a scan was recorded without its item list
+    items_recorded: bool,
+}
+
+fn previous_scan(instances: &[String]) -> PreviousScan {
+    let previous = open_history_store().and_then(|conn| match conn {
+        Some(conn) => read_previous_scan(&conn, instances),
+        None => Ok(PreviousScan::default()),
+    });
+    previous.unwrap_or_else(|e| {
+        warn!("failed to read scan history: {:#}", e);
+        PreviousScan::default()
+    })
+}
+
+// Only the latest scan per instance is read, however long the history is
+fn read_previous_scan(conn: &Connection, instances: &[String]) -> Result<PreviousScan> {
+    let mut latest = conn.prepare(
+        "SELECT scans.id, scans.timestamp FROM scans
+         JOIN scan_instances ON scan_instances.scan_id = scans.id
+         WHERE scan_instances.instance = ?1
+         ORDER BY scans.id DESC LIMIT 1",
+    )?;
+    let mut items = conn.prepare(
+        "SELECT instance, id, name, item_type, size_bytes, waste_score FROM scan_items
+         WHERE scan_id = ?1 AND instance = ?2",
+    )?;
+    let mut summary = conn.prepare(
+        "SELECT items, total_bytes, average_waste, flagged FROM scan_summaries
+         WHERE scan_id = ?1 AND instance = ?2",
+    )?;
+    let mut previous = PreviousScan {
+        items_recorded: true,
+        ..Default::default()
+    };
+    for instance in instances {
+        let Some((scan_id, timestamp)) = latest
+            .query_row([instance], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?))
+            })
+            .optional()?
+        else {
+            continue;
+        };
+        previous.timestamp = Some(previous.timestamp.map_or(timestamp, |t| t.min(timestamp)));
+        let scan_items = items
+            .query_map(params![scan_id, instance], |row| snapshot_item(row, 0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        // Scans imported from history.jsonl have no summary; derive it from their items
+        let instance_summary = summary
+            .query_row(params![scan_id, instance], |row| {
+                Ok(InstanceSummary {
+                    instance: instance.clone(),
+                    items: row.get::<_, i64>(0)? as usize,
+                    total_bytes: row.get::<_, i64>(1)? as u64,
+                    average_waste: row.get(2)?,
+                    flagged: row.get::<_, i64>(3)? as usize,
+                })
+            })
+            .optional()?
+            .unwrap_or_else(|| {
+                summarize_instance(
+                    instance,
+                    scan_items
+                        .iter()
+                        .map(|item| (item.size_bytes, item.waste_score)),
+                )
+            });
+        if scan_items.len() < instance_summary.items {
+            previous.items_recorded = false;
+        }
+        previous.summaries.push(instance_summary);
+        previous.items.extend(
+            scan_items
+                .into_iter()
+                .map(|item| ((item.instance.clone(), item.id), item)),
+        );
+    }
+    Ok(previous)
+}
+
+/// Per-instance totals stored with every scan, so library growth and score
+/// drift can be reported even when item lists aren't kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstanceSummary {
+    instance: String,
+    items: usize,
+    total_bytes: u64,
+    average_waste: f64,
+    flagged: usize,
+}
+
+/// Summarize one instance from (size, waste score) pairs
+fn summarize_instance(instance: &str, items: impl Iterator<Item = (u64, i32)>) -> InstanceSummary {
+    let mut summary = InstanceSummary {
+        instance: instance.to_string(),
+        items: 0,
+        total_bytes: 0,
+        average_waste: 0.0,
+        flagged: 0,
+    };
+    let mut total_waste = 0.0;
+    for (size_bytes, waste_score) in items {
+        summary.items += 1;
+        summary.total_bytes += size_bytes;
+        total_waste += waste_score as f64;
+        if waste_score >= FLAGGED_MIN_SCORE {
+            summary.flagged += 1;
+        }
+    }
+    if summary.items > 0 {
+        summary.average_waste = total_waste / summary.items as f64;
+    }
+    summary
+}
+
+fn summarize_scan(items: &[Item], instances: &[String]) -> Vec<InstanceSummary> {
+    instances
+        .iter()
+        .map(|instance| {
+            summarize_instance(
+                instance,
+                items
+                    .iter()
+                    .filter(|item| &item.instance == instance)
+                    .map(|item| (item.size_bytes, item.waste_score)),
+            )
+        })
+        .collect()
+}
+
+/// Size of one root folder of an instance, stored with every scan so growth can
+/// be fitted per folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FolderSummary {
+    instance: String,
+    root_folder: String,
+    total_bytes: u64,
+}
+
+fn summarize_folders(items: &[Item]) -> Vec<FolderSummary> {
+    let mut totals: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    for item in items {
+        *totals
+            .entry((item.instance.as_str(), item.root_folder.as_str()))
+            .or_insert(0) += item.size_bytes;
+    }
+    totals
+        .into_iter()
+        .map(|((instance, root_folder), total_bytes)| FolderSummary {
+            instance: instance.to_string(),
+            root_folder: root_folder.to_string(),
+            total_bytes,
+        })
+        .collect()
+}
+
+/// How the library changed since the previous scan, for the report footer
+#[derive(Debug, Serialize)]
+struct ScanTrend {
+    previous_scan_at: f64,
+    item_delta: i64,
+    size_delta_bytes: i64,
+    average_waste_drift: f64,
+    // Items now at or above FLAGGED_MIN_SCORE that weren't before; None when the
+    // previous scan didn't keep its item list
+    newly_flagged: Option<usize>,
+}
+
+fn scan_trend(
+    current: &[InstanceSummary],
+    previous: &PreviousScan,
+    items: &[Item],
+) -> Option<ScanTrend> {
+    let previous_scan_at = previous.timestamp?;
+    let mut compared = Vec::new();
+    let (mut items_now, mut items_before) = (0usize, 0usize);
+    let (mut waste_now, mut waste_before) = (0.0, 0.0);
+    let mut size_delta_bytes = 0i64;
+    for now in current {
+        let Some(before) = previous
+            .summaries
+            .iter()
+            .find(|before| before.instance == now.instance)
+        else {
+            continue;
+        };
+        compared.push(&now.instance);
+        items_now += now.items;
+        items_before += before.items;
+        waste_now += now.average_waste * now.items as f64;
+        waste_before += before.average_waste * before.items as f64;
+        size_delta_bytes += now.total_bytes as i64 - before.total_bytes as i64;
+    }
+    if compared.is_empty() {
+        return None;
+    }
+    let average = |total: f64, count: usize| {
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f64
+        }
+    };
+
+    let newly_flagged = previous.items_recorded.then(|| {
+        items
+            .iter()
+            .filter(|item| {
+                item.waste_score >= FLAGGED_MIN_SCORE && compared.contains(&&item.instance)
+            })
+            .filter(|item| {
+                previous
+                    .items
+                    .get(&(item.instance.clone(), item.id))
+                    .is_none_or(|before| before.waste_score < FLAGGED_MIN_SCORE)
+            })
+            .count()
+    });
+    Some(ScanTrend {
+        previous_scan_at,
+        item_delta: items_now as i64 - items_before as i64,
+        size_delta_bytes,
+        average_waste_drift: average(waste_now, items_now) - average(waste_before, items_before),
+        newly_flagged,
+    })
+}
+
+fn format_scan_trend(trend: &ScanTrend) -> String {
+    let sign = if trend.size_delta_bytes < 0 { "-" } else { "+" };
+    let mut line = format!(
+        "Since the last scan {} ago: {:+} items, {}{}, average waste {:+.1}",
+        format_age(current_timestamp() - trend.previous_scan_at),
+        trend.item_delta,
+        sign,
+        format_file_size(trend.size_delta_bytes.unsigned_abs()),
+        trend.average_waste_drift
+    );
+    if let Some(newly_flagged) = trend.newly_flagged {
+        let _ = write!(line, ", {} newly flagged", newly_flagged);
+    }
+    line
+}
+
+/// A deletion wastearr made, as kept in the deletion log
+#[derive(Debug, Clone)]
+struct Deletion {
+    item_type: String,
+    size_bytes: u64,
+    deleted_at: f64,
+}
+
+fn read_deletions() -> Result<Vec<Deletion>> {
+    let Some(conn) = open_history_store()? else {
+        return Ok(Vec::new());
+    };
+    let deletions = conn
+        .prepare("SELECT item_type, size_bytes, deleted_at FROM deletions ORDER BY deleted_at")?
+        .query_map([], |row| {
+            Ok(Deletion {
+                item_type: row.get(0)?,
+                size_bytes: row.get::<_, i64>(1)? as u64,
+                deleted_at: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(deletions)
+}
+
+/// Total up the deletions made after `since`, or all of them
+fn sum_deletions(deletions: &[Deletion], since: Option<f64>) -> Reclaimed {
+    deletions
+        .iter()
+        .filter(|deletion| since.is_none_or(|since| deletion.deleted_at > since))
+        .fold(Reclaimed::default(), |mut total, deletion| {
+            total += Reclaimed {
+                items: 1,
+                bytes: deletion.size_bytes,
+            };
+            total
+        })
+}
+
+/// Space reclaimed all time, and since the latest recorded scan of any instance
+fn reclaimed_totals() -> (Reclaimed, Reclaimed) {
+    let totals = read_deletions().and_then(|deletions| {
+        let latest_scan = match open_history_store()? {
+            Some(conn) => {
+                conn.query_row("SELECT MAX(timestamp) FROM scans", [], |row| row.get(0))?
+            }
+            None => None,
+        };
+        Ok((
+            sum_deletions(&deletions, None),
+            sum_deletions(&deletions, Some(latest_scan.unwrap_or(0.0))),
+        ))
+    });
+    totals.unwrap_or_else(|e| {
+        warn!("failed to read the deletion log: {:#}", e);
+        Default::default()
+    })
+}
+
+fn apply_trends(items: &mut [Item], previous: &HashMap<(String, i32), SnapshotItem>) {
+    for item in items {
+        item.trend = Some(match previous.get(&(item.instance.clone(), item.id)) {
+            Some(before) => Trend::Seen {
+                score_delta: item.waste_score - before.waste_score,
+                size_delta: item.size_bytes as i64 - before.size_bytes as i64,
+            },
+            None => Trend::New,
+        });
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Acquisition {
+    name: String,
+    item_type: String,
+    instance: String,
+    size_bytes: u64,
+    waste_score: i32,
+    first_seen: f64,
+}
+
+/// How one instance's average waste score and size moved over the window
+#[derive(Debug, Serialize)]
+struct InstanceChange {
+    instance: String,
+    average_waste_before: f64,
+    average_waste_after: f64,
+    size_before: u64,
+    size_after: u64,
+}
+
+impl InstanceChange {
+    fn improvement(&self) -> f64 {
+        self.average_waste_before - self.average_waste_after
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct WrappedSummary {
+    since: f64,
+    scans: usize,
+    added_bytes: u64,
+    added_items: usize,
+    freed_bytes: u64,
+    removed_items: usize,
+    reclaimed: Reclaimed,
+    lifetime_reclaimed: Reclaimed,
+    acquisitions: Vec<Acquisition>,
+    instances: Vec<InstanceChange>,
+}
+
+fn average_waste(items: &[&SnapshotItem]) -> f64 {
+    if items.is_empty() {
+        return 0.0;
+    }
+    items
+        .iter()
+        .map(|item| item.waste_score as f64)
+        .sum::<f64>()
+        / items.len() as f64
+}
+
+/// Walk each instance's scans within the window and total up what came and went.
+/// Each instance is compared against its last scan before the window, if any, so
+/// the first scan of the year isn't counted as one big acquisition.
+fn build_wrapped(
+    history: &[ScanSnapshot],
+    deletions: &[Deletion],
+    since: f64,
+    item_type: Option<&str>,
+) -> WrappedSummary {
+    let mut summary = WrappedSummary {
+        since,
+        scans: history.iter().filter(|s| s.timestamp >= since).count(),
+        ..Default::default()
+    };
+
+    let mut instances: Vec<&String> = history
+        .iter()
+        .filter(|s| s.timestamp >= since)
+        .flat_map(|s| &s.instances)
+        .collect();
+    instances.sort();
+    instances.dedup();
+
+    for instance in instances {
+        let runs: Vec<&ScanSnapshot> = history
+            .iter()
+            .filter(|s| s.instances.contains(instance))
+            .collect();
+        let baseline = runs.iter().rposition(|s| s.timestamp < since).unwrap_or(0);
+        let runs = &runs[baseline..];
+        let scans: Vec<HashMap<i32, &SnapshotItem>> = runs
+            .iter()
+            .map(|s| {
+                s.items
+                    .iter()
+                    .filter(|item| &item.instance == instance)
+                    .filter(|item| item_type.is_none_or(|t| item.item_type == t))
+                    .map(|item| (item.id, item))
+                    .collect()
+            })
+            .collect();
+        let (Some(first), Some(last)) = (scans.first(), scans.last()) else {
+            continue;
+        };
+
+        let mut acquired: HashMap<i32, f64> = HashMap::new();
+        for (pair, run) in scans.windows(2).zip(runs.iter().skip(1)) {
+            let (before, after) = (&pair[0], &pair[1]);
+            for (id, item) in after {
+                match before.get(id) {
+                    Some(prev) if item.size_bytes >= prev.size_bytes => {
+                        summary.added_bytes += item.size_bytes - prev.size_bytes;
+                    }
+                    Some(prev) => summary.freed_bytes += prev.size_bytes - item.size_bytes,
+                    None => {
+                        summary.added_bytes += item.size_bytes;
+                        summary.added_items += 1;
+                        acquired.entry(*id).or_insert(run.timestamp);
+                    }
+                }
+            }
+            for (id, item) in before {
+                if !after.contains_key(id) {
+                    summary.freed_bytes += item.size_bytes;
+                    summary.removed_items += 1;
+                }
+            }
+        }
+
+        summary
+            .acquisitions
+            .extend(acquired.into_iter().filter_map(|(id, first_seen)| {
+                last.get(&id).map(|item| Acquisition {
+                    name: item.name.clone(),
+                    item_type: item.item_type.clone(),
+                    instance: item.instance.clone(),
+                    size_bytes: item.size_bytes,
+                    waste_score: item.waste_score,
+                    first_seen,
+                })
+            }));
+
+        let before: Vec<&SnapshotItem> = first.values().copied().collect();
+        let after: Vec<&SnapshotItem> = last.values().copied().collect();
+        summary.instances.push(InstanceChange {
+            instance: instance.clone(),
+            average_waste_before: average_waste(&before),
+            average_waste_after: average_waste(&after),
+            size_before: before.iter().map(|item| item.size_bytes).sum(),
+            size_after: after.iter().map(|item| item.size_bytes).sum(),
+        });
+    }
+
+    summary.acquisitions.sort_by(|a, b| {
+        b.size_bytes
+            .cmp(&a.size_bytes)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    summary.acquisitions.truncate(WRAPPED_TOP_ACQUISITIONS);
+    let deletions: Vec<Deletion> = deletions
+        .iter()
+        .filter(|deletion| item_type.is_none_or(|t| deletion.item_type == t))
+        .cloned()
+        .collect();
+    summary.reclaimed = sum_deletions(&deletions, Some(since));
+    summary.lifetime_reclaimed = sum_deletions(&deletions, None);
+    summary
+        .instances
+        .sort_by(|a, b| b.improvement().total_cmp(&a.improvement()));
+    summary
+}
+
+fn format_wrapped(summary: &WrappedSummary, style: &str, now: f64) -> String {
+    let mut output = String::new();
+    let _ = writeln!(output, "Your Year in Waste");
+    let _ = writeln!(output, "{}", "=".repeat(60));
+    let _ = writeln!(
+        output,
+        "{} scans over the last {}",
+        summary.scans,
+        format_age(now - summary.since)
+    );
+    let _ = writeln!(
+        output,
+        "Added: {} across {} new items",
+        format_file_size(summary.added_bytes),
+        summary.added_items
+    );
+    let _ = writeln!(
+        output,
+        "Freed: {} across {} removed items",
+        format_file_size(summary.freed_bytes),
+        summary.removed_items
+    );
+    let net = summary.added_bytes as i64 - summary.freed_bytes as i64;
+    let _ = writeln!(
+        output,
+        "Net change: {}{}",
+        if net < 0 { "-" } else { "+" },
+        format_file_size(net.unsigned_abs())
+    );
+    if summary.lifetime_reclaimed.items > 0 {
+        let _ = writeln!(
+            output,
+            "Reclaimed by wastearr's deletions: {} across {} items ({} all time)",
+            format_file_size(summary.reclaimed.bytes),
+            summary.reclaimed.items,
+            format_file_size(summary.lifetime_reclaimed.bytes)
+        );
+    }
+
+    if !summary.acquisitions.is_empty() {
+        let mut table = Table::new();
+        apply_table_style(&mut table, style);
+        table.set_header(vec!["Name", "Type", "Size", "Waste Score", "Added"]);
+        for acquisition in &summary.acquisitions {
+            table.add_row(vec![
+                acquisition.name.clone(),
+                if acquisition.item_type == "show" {
+                    "Show"
+                } else {
+                    "Movie"
+                }
+                .to_string(),
+                format_file_size(acquisition.size_bytes),
+                acquisition.waste_score.to_string(),
+                format!("{} ago", format_age(now - acquisition.first_seen)),
+            ]);
+        }
+        let _ = writeln!(
+            output,
+            "\nBiggest acquisitions still in the library:\n{}",
+            table
+        );
+    }
+
+    if !summary.instances.is_empty() {
+        let mut table = Table::new();
+        apply_table_style(&mut table, style);
+        table.set_header(vec![
+            "Instance",
+            "Avg Waste Before",
+            "Avg Waste Now",
+            "Change",
+            "Size Before",
+            "Size Now",
+        ]);
+        for change in &summary.instances {
+            table.add_row(vec![
+                change.instance.clone(),
+                format!("{:.1}", change.average_waste_before),
+                format!("{:.1}", change.average_waste_after),
+                format!("{:+.1}", -change.improvement()),
+                format_file_size(change.size_before),
+                format_file_size(change.size_after),
+            ]);
+        }
+        let _ = writeln!(output, "\nMost improved instances:\n{}", table);
+    }
+
+    output
+}
+
+fn print_wrapped(args: &Args) {
+    let now = current_timestamp();
+    let history = load_history();
+    if history.is_empty() {
+        progress!("No scan history recorded yet; run a scan first");
+        return;
+    }
+    let item_type = args.item_type.as_deref().map(|t| match t {
+        "sonarr" => "show",
+        _ => "movie",
+    });
+    let deletions = read_deletions().unwrap_or_else(|e| {
+        warn!("failed to read the deletion log: {:#}", e);
+        Vec::new()
+    });
+    let summary = build_wrapped(&history, &deletions, now - WRAPPED_WINDOW, item_type);
+
+    if args.output == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&summary).unwrap_or_default()
+        );
+        return;
+    }
+    page_output(
+        &format_wrapped(&summary, &args.table_style, now),
+        args.no_pager,
+    );
+}
+
+/// A stored scan picked by `compare`: its number in the history, or `latest`
+fn load_snapshot(reference: &str) -> Result<(i64, ScanSnapshot)> {
+    let conn = open_history_store()?.context("No state directory available for scan history")?;
+    let id = if reference == "latest" {
+        conn.query_row("SELECT MAX(id) FROM scans", [], |row| {
+            row.get::<_, Option<i64>>(0)
+        })?
+        .context("No scan history recorded yet; run a scan first")?
+    } else {
+        reference.trim_start_matches('#').parse().with_context(|| {
+            format!(
+                "Invalid scan '{}': expected a scan number or 'latest'",
+                reference
+            )
+        })?
+    };
+    let timestamp = conn
+        .query_row("SELECT timestamp FROM scans WHERE id = ?1", [id], |row| {
+            row.get::<_, f64>(0)
+        })
+        .optional()?
+        .with_context(|| format!("No scan #{} in the history", id))?;
+
+    let mut instances = conn.prepare("SELECT instance FROM scan_instances WHERE scan_id = ?1")?;
+    let instances = instances
+        .query_map([id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let mut items = conn.prepare(
+        "SELECT instance, id, name, item_type, size_bytes, waste_score FROM scan_items
+         WHERE scan_id = ?1",
+    )?;
+    let items = items
+        .query_map([id], |row| snapshot_item(row, 0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if items.is_empty() {
+        anyhow::bail!("Scan #{} was recorded without its item list", id);
+    }
+    Ok((
+        id,
+        ScanSnapshot {
+            timestamp,
+            instances,
+            items,
+            summaries: Vec::new(),
+            folders: Vec::new(),
+            reclaimed: Reclaimed::default(),
+        },
+    ))
+}
+
+/// Per-instance summaries of one stored scan, derived from its items for scans
+/// recorded before summaries were kept
+fn read_scan_summaries(conn: &Connection, scan_id: i64) -> Result<Vec<InstanceSummary>> {
+    let summary = |row: &rusqlite::Row| {
+        Ok(InstanceSummary {
+            instance: row.get(0)?,
+            items: row.get::<_, i64>(1)? as usize,
+            total_bytes: row.get::<_, i64>(2)? as u64,
+            average_waste: row.get(3)?,
+            flagged: row.get::<_, i64>(4)? as usize,
+        })
+    };
+    let mut stored = conn.prepare_cached(
+        "SELECT instance, items, total_bytes, average_waste, flagged FROM scan_summaries
+         WHERE scan_id = ?1 ORDER BY instance",
+    )?;
+    let summaries = stored
+        .query_map([scan_id], summary)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !summaries.is_empty() {
+        return Ok(summaries);
+    }
+    let mut derived = conn.prepare_cached(
+        "SELECT instance, COUNT(*), SUM(size_bytes), AVG(waste_score), SUM(waste_score >= ?2)
+         FROM scan_items WHERE scan_id = ?1 GROUP BY instance ORDER BY instance",
+    )?;
+    Ok(derived
+        .query_map(params![scan_id, FLAGGED_MIN_SCORE], summary)?
+        .collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+#[derive(Debug, Serialize)]
+struct Note {
+    instance: String,
+    id: i32,
+    // From the latest scan that had the item
+    name: Option<String>,
+    note: String,
+    updated_at: f64,
+}
+
+fn read_notes(conn: &Connection) -> Result<Vec<Note>> {
+    let mut notes = conn.prepare(
+        "SELECT n.instance, n.id, n.note, n.updated_at,
+             (SELECT name FROM scan_items i WHERE i.instance = n.instance AND i.id = n.id
+              ORDER BY i.scan_id DESC LIMIT 1)
+         FROM notes n ORDER BY n.instance, n.id",
+    )?;
+    Ok(notes
+        .query_map([], |row| {
+            Ok(Note {
+                instance: row.get(0)?,
+                id: row.get(1)?,
+                note: row.get(2)?,
+                updated_at: row.get(3)?,
+                name: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn apply_notes(items: &mut [Item]) {
+    let notes = open_history_store().and_then(|conn| match conn {
+        Some(conn) => read_notes(&conn),
+        None => Ok(Vec::new()),
+    });
+    let notes: HashMap<(String, i32), String> = match notes {
+        Ok(notes) => notes
+            .into_iter()
+            .map(|note| ((note.instance, note.id), note.note))
+            .collect(),
+        Err(e) => {
+            warn!("failed to read notes: {:#}", e);
+            return;
+        }
+    };
+    for item in items {
+        item.note = notes.get(&(item.instance.clone(), item.id)).cloned();
+    }
+}
+
+/// Find the instance an item belongs to: `INSTANCE:ID` as given, or a bare ID
+/// looked up in the latest scan of each instance
+fn resolve_note_item(conn: &Connection, reference: &str) -> Result<(String, i32)> {
+    if let Some((instance, id)) = reference.rsplit_once(':') {
+        let id = id
+            .parse()
+            .with_context(|| format!("'{}' is not an item ID", id))?;
+        return Ok((instance.to_string(), id));
+    }
+    let id: i32 = reference
+        .parse()
+        .with_context(|| format!("'{}' is not an item ID or INSTANCE:ID", reference))?;
+    let mut latest = conn.prepare(
+        "SELECT i.instance, i.name FROM scan_items i
+         WHERE i.id = ?1 AND i.scan_id =
+             (SELECT MAX(scan_id) FROM scan_items l WHERE l.instance = i.instance)
+         ORDER BY i.instance",
+    )?;
+    let matches = latest
+        .query_map([id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    match matches.as_slice() {
+        [] => anyhow::bail!(
+            "No item {} in the latest scans; give it as INSTANCE:{} instead",
+            id,
+            id
+        ),
+        [(instance, _)] => Ok((instance.clone(), id)),
+        _ => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .map(|(instance, name)| format!("{}:{} ({})", instance, id, name))
+                .collect();
+            anyhow::bail!(
+                "Item {} exists on several instances, pick one: {}",
+                id,
+                candidates.join(", ")
+            )
+        }
+    }
+}
+
+fn format_note_list(notes: &[Note], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec!["Item", "Name", "Note", "Updated"]);
+    for note in notes {
+        table.add_row(vec![
+            format!("{}:{}", note.instance, note.id),
+            note.name.clone().unwrap_or_else(|| "-".to_string()),
+            note.note.clone(),
+            format!("{} ago", format_age(current_timestamp() - note.updated_at)),
+        ]);
+    }
+    table.to_string()
+}
+
+/// `note` lists all notes, `note ITEM` shows one, `note ITEM TEXT` sets it and
+/// an empty TEXT removes it
+fn run_note(args: &Args) -> Result<()> {
+    let Some(conn) = open_history_store()? else {
+        anyhow::bail!("No state directory available for notes");
+    };
+    let Some(reference) = &args.note_item else {
+        let notes = read_notes(&conn)?;
+        if args.output == "json" {
+            println!("{}", serde_json::to_string_pretty(&notes)?);
+        } else if notes.is_empty() {
+            progress!("No notes yet; add one with `wastearr note ITEM \"TEXT\"`");
+        } else {
+            println!("{}", format_note_list(&notes, &args.table_style));
+        }
+        return Ok(());
+    };
+
+    let (instance, id) = resolve_note_item(&conn, reference)?;
+    match args.note_text.as_deref().map(str::trim) {
+        None => {
+            let note = read_notes(&conn)?
+                .into_iter()
+                .find(|note| note.instance == instance && note.id == id);
+            match note {
+                Some(note) if args.output == "json" => {
+                    println!("{}", serde_json::to_string_pretty(&note)?)
+                }
+                Some(note) => println!("{}", note.note),
+                None => progress!("No note on {}:{}", instance, id),
+            }
+        }
+        Some("") => {
+            let removed = conn.execute(
+                "DELETE FROM notes WHERE instance = ?1 AND id = ?2",
+                params![instance, id],
+            )?;
+            if removed > 0 {
+                progress!("Removed the note on {}:{}", instance, id);
+            } else {
+                progress!("No note on {}:{}", instance, id);
+            }
+        }
+        Some(text) => {
+            conn.execute(
+                "INSERT OR REPLACE INTO notes (instance, id, note, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![instance, id, text, current_timestamp()],
+            )?;
+            progress!("Saved the note on {}:{}", instance, id);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleManifest {
+    version: u32,
+    wastearr_version: String,
+    exported_at: f64,
+}
+
+/// Scratch copy of a database for a bundle, removed when dropped
+struct ScratchFile(PathBuf);
+
+impl ScratchFile {
+    fn new(name: &str) -> Self {
+        ScratchFile(env::temp_dir().join(format!("wastearr-{}-{}", process::id(), name)))
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Write scan history, notes and the cache to a zstd-compressed tarball. The
+/// config isn't included, since it holds API keys.
+fn export_state(path: &Path) -> Result<()> {
+    let mut databases = Vec::new();
+    for (name, conn) in [
+        ("history.db", open_history_store()?),
+        ("cache.db", open_cache_store()?),
+    ] {
+        let Some(conn) = conn else {
+            continue;
+        };
+        // A consistent copy even while a scan is writing
+        let scratch = ScratchFile::new(name);
+        conn.execute("VACUUM INTO ?1", [scratch.0.to_string_lossy()])?;
+        databases.push((name, scratch));
+    }
+    if databases.is_empty() {
+        anyhow::bail!("No state or cache directory available to export");
+    }
+
+    let file =
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut bundle = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    let manifest = serde_json::to_vec_pretty(&BundleManifest {
+        version: BUNDLE_VERSION,
+        wastearr_version: env!("CARGO_PKG_VERSION").to_string(),
+        exported_at: current_timestamp(),
+    })?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(current_timestamp() as u64);
+    header.set_cksum();
+    bundle.append_data(&mut header, "manifest.json", manifest.as_slice())?;
+    for (name, scratch) in &databases {
+        bundle.append_path_with_name(&scratch.0, name)?;
+    }
+    bundle.into_inner()?.finish()?;
+    progress!(
+        "Exported {} to {}",
+        databases
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(" and "),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Merge a bundle from `export_state` into this machine's history and cache.
+/// Scans already present are skipped, so importing twice is harmless.
+fn import_state(path: &Path) -> Result<()> {
+    let file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut bundle = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut manifest = None;
+    let mut databases = Vec::new();
+    for entry in bundle
+        .entries()
+        .with_context(|| format!("{} is not a wastearr bundle", path.display()))?
+    {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        match name.as_str() {
+            "manifest.json" => {
+                manifest = Some(serde_json::from_reader::<_, BundleManifest>(&mut entry)?);
+            }
+            "history.db" | "cache.db" => {
+                let scratch = ScratchFile::new(&format!("import-{}", name));
+                entry.unpack(&scratch.0)?;
+                databases.push((name, scratch));
+            }
+            _ => {}
+        }
+    }
+    let manifest =
+        manifest.with_context(|| format!("{} is not a wastearr bundle", path.display()))?;
+    if manifest.version > BUNDLE_VERSION {
+        anyhow::bail!(
+            "{} was exported by wastearr {}; upgrade to import it",
+            path.display(),
+            manifest.wastearr_version
+        );
+    }
+
+    for (name, scratch) in &databases {
+        if name == "history.db" {
+            let Some(mut conn) = open_history_store()? else {
+                anyhow::bail!("No state directory available to import scan history into");
+            };
+            let (scans, notes) = attached(&mut conn, &scratch.0, merge_history)?;
+            rebuild_reclaimed(&mut conn)?;
+            progress!("Imported {} scans and {} notes", scans, notes);
+        } else if let Some(mut conn) = open_cache_store()? {
+            let (ratings, libraries) = attached(&mut conn, &scratch.0, merge_cache)?;
+            progress!(
+                "Imported {} cached ratings and {} libraries",
+                ratings,
+                libraries
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Run `merge` with the database at `path` attached as "imported"
+fn attached<T>(
+    conn: &mut Connection,
+    path: &Path,
+    merge: fn(&mut Connection) -> Result<T>,
+) -> Result<T> {
+    conn.execute("ATTACH DATABASE ?1 AS imported", [path.to_string_lossy()])?;
+    let merged = merge(conn);
+    conn.execute("DETACH DATABASE imported", [])?;
+    merged
+}
+
+fn merge_history(conn: &mut Connection) -> Result<(usize, usize)> {
+    let tx = conn.transaction()?;
+    let latest: Option<f64> = tx.query_row("SELECT MAX(timestamp) FROM main.scans", [], |row| {
+        row.get(0)
+    })?;
+    let scans = tx
+        .prepare(
+            "SELECT id, timestamp FROM imported.scans
+             WHERE timestamp NOT IN (SELECT timestamp FROM main.scans) ORDER BY timestamp",
+        )?
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut children = Vec::new();
+    for table in &HISTORY_TABLES[1..] {
+        let columns = tx
+            .prepare("SELECT name FROM pragma_table_info(?1, 'imported') WHERE name != 'scan_id'")?
+            .query_map([table], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .join(", ");
+        if !columns.is_empty() {
+            children.push(format!(
+                "INSERT INTO main.{0} (scan_id, {1}) SELECT ?1, {1} FROM imported.{0} WHERE scan_id = ?2",
+                table, columns
+            ));
+        }
+    }
+    for (id, timestamp) in &scans {
+        tx.execute(
+            "INSERT INTO main.scans (timestamp) VALUES (?1)",
+            [timestamp],
+        )?;
+        let new_id = tx.last_insert_rowid();
+        for insert in &children {
+            tx.execute(insert, [new_id, *id])?;
+        }
+    }
+    // Scans are read in id order, so older imported scans need renumbering
+    if latest.is_some_and(|latest| scans.first().is_some_and(|(_, t)| *t < latest)) {
+        renumber_scans(&tx)?;
+    }
+    if !scans.is_empty() {
+        // Recount what each scan reclaimed against the merged deletion log
+        tx.pragma_update(Some("main"), "user_version", 0)?;
+    }
+    // The cache database of older versions has no notes or deletion log
+    let notes = if imported_table_exists(&tx, "notes")? {
+        tx.execute(
+            "INSERT INTO main.notes SELECT * FROM imported.notes WHERE true
+             ON CONFLICT (instance, id) DO UPDATE SET
+                 note = excluded.note,
+                 updated_at = excluded.updated_at
+             WHERE excluded.updated_at > notes.updated_at",
+            [],
+        )?
+    } else {
+        0
+    };
+    if imported_table_exists(&tx, "deletions")? {
+        let deletions = tx.execute(
+            "INSERT OR IGNORE INTO main.deletions SELECT * FROM imported.deletions",
+            [],
+        )?;
+        if deletions > 0 {
+            tx.pragma_update(Some("main"), "user_version", 0)?;
+        }
+    }
+    tx.commit()?;
+    Ok((scans.len(), notes))
+}
+
+fn imported_table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) > 0 FROM imported.sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )?)
+}
+
+/// Log items deleted from `tui` or the dashboard, so what wastearr removed can
+/// be told apart from what went missing some other way
+fn record_deletions(items: &[Item], deleted_at: f64) -> Result<()> {
+    let Some(mut conn) = open_history_store()? else {
+        return Ok(());
+    };
+    let tx = conn.transaction()?;
+    let mut insert = tx.prepare_cached(
+        "INSERT OR IGNORE INTO deletions (instance, id, name, item_type, size_bytes, deleted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for item in items {
+        insert.execute(params![
+            item.instance,
+            item.id,
+            item.name,
+            item.item_type,
+            item.size_bytes as i64,
+            deleted_at,
+        ])?;
+    }
+    drop(insert);
+    tx.commit()?;
+    Ok(())
+}
+
+/// Give scans ids in timestamp order again
+fn renumber_scans(tx: &rusqlite::Transaction) -> Result<()> {
+    // Flipped negative first so new ids never collide with old ones
+    tx.execute_batch(
+        "PRAGMA defer_foreign_keys = ON;
+         CREATE TEMP TABLE scan_order AS
+             SELECT id AS old_id, ROW_NUMBER() OVER (ORDER BY timestamp, id) AS new_id
+             FROM main.scans;",
+    )?;
+    for table in &HISTORY_TABLES[1..] {
+        tx.execute(
+            &format!(
+                "UPDATE main.{} SET scan_id = -(SELECT new_id FROM scan_order WHERE old_id = scan_id)",
+                table
+            ),
+            [],
+        )?;
+    }
+    tx.execute(
+        "UPDATE main.scans SET id = -(SELECT new_id FROM scan_order WHERE old_id = id)",
+        [],
+    )?;
+    for table in &HISTORY_TABLES[1..] {
+        tx.execute(&format!("UPDATE main.{} SET scan_id = -scan_id", table), [])?;
+    }
+    tx.execute("UPDATE main.scans SET id = -id", [])?;
+    tx.execute("DROP TABLE scan_order", [])?;
+    Ok(())
+}
+
+/// Imported rows win where they're newer. Instance URLs aren't carried over:
+/// the new machine may well reach the same servers by another address.
+fn merge_cache(conn: &mut Connection) -> Result<(usize, usize)> {
+    let tx = conn.transaction()?;
+    let ratings = tx.execute(
+        "INSERT INTO main.ratings SELECT kind, key, rating, cached_at FROM imported.ratings WHERE true
+         ON CONFLICT (kind, key) DO UPDATE SET
+             rating = excluded.rating,
+             cached_at = excluded.cached_at
+         WHERE excluded.cached_at > ratings.cached_at",
+        [],
+    )?;
+    let libraries = tx.execute(
+        "INSERT INTO main.libraries SELECT instance, fetched_at, validators, items
+         FROM imported.libraries WHERE true
+         ON CONFLICT (instance) DO UPDATE SET
+             fetched_at = excluded.fetched_at,
+             validators = excluded.validators,
+             items = excluded.items
+         WHERE excluded.fetched_at > libraries.fetched_at",
+        [],
+    )?;
+    tx.commit()?;
+    Ok((ratings, libraries))
+}
+
+#[derive(Debug, Serialize)]
+struct ScanListing {
+    id: i64,
+    timestamp: f64,
+    instances: Vec<String>,
+    items: usize,
+    total_bytes: u64,
+    average_waste: f64,
+    flagged: usize,
+}
+
+fn list_scans() -> Result<Vec<ScanListing>> {
+    let Some(conn) = open_history_store()? else {
+        return Ok(Vec::new());
+    };
+    let mut scans = conn.prepare("SELECT id, timestamp FROM scans ORDER BY id DESC")?;
+    let scans = scans
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut instances =
+        conn.prepare("SELECT instance FROM scan_instances WHERE scan_id = ?1 ORDER BY instance")?;
+    scans
+        .into_iter()
+        .map(|(id, timestamp)| {
+            let summaries = read_scan_summaries(&conn, id)?;
+            let items: usize = summaries.iter().map(|s| s.items).sum();
+            let total_waste: f64 = summaries
+                .iter()
+                .map(|s| s.average_waste * s.items as f64)
+                .sum();
+            Ok(ScanListing {
+                id,
+                timestamp,
+                instances: instances
+                    .query_map([id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?,
+                items,
+                total_bytes: summaries.iter().map(|s| s.total_bytes).sum(),
+                average_waste: if items > 0 {
+                    total_waste / items as f64
+                } else {
+                    0.0
+                },
+                flagged: summaries.iter().map(|s| s.flagged).sum(),
+            })
+        })
+        .collect()
+}
+
+/// One scan's totals as a flat row, for charting history in Grafana
+#[derive(Debug, Serialize)]
+struct TimeseriesPoint {
+    // Milliseconds since the epoch
+    time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    items: usize,
+    total_bytes: u64,
+    average_waste: f64,
+    flagged: usize,
+    // Only tracked for the whole scan, so absent from per-instance rows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reclaimed_bytes: Option<u64>,
+}
+
+/// Scan totals oldest first, optionally one row per instance, between `from`
+/// and `to` (epoch milliseconds)
+fn read_timeseries(
+    per_instance: bool,
+    instance: Option<&str>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<TimeseriesPoint>> {
+    let Some(conn) = open_history_store()? else {
+        return Ok(Vec::new());
+    };
+    let mut scans = conn.prepare(
+        "SELECT scans.id, scans.timestamp, scan_reclaimed.bytes FROM scans
+         LEFT JOIN scan_reclaimed ON scan_reclaimed.scan_id = scans.id
+         WHERE scans.timestamp * 1000 >= ?1 AND scans.timestamp * 1000 <= ?2
+         ORDER BY scans.timestamp",
+    )?;
+    let scans = scans
+        .query_map(
+            params![from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                ))
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut points = Vec::new();
+    for (id, timestamp, reclaimed_bytes) in scans {
+        let time = (timestamp * 1000.0) as i64;
+        let summaries = read_scan_summaries(&conn, id)?;
+        if per_instance || instance.is_some() {
+            points.extend(
+                summaries
+                    .into_iter()
+                    .filter(|summary| instance.is_none_or(|i| summary.instance == i))
+                    .map(|summary| TimeseriesPoint {
+                        time,
+                        instance: Some(summary.instance),
+                        items: summary.items,
+                        total_bytes: summary.total_bytes,
+                        average_waste: summary.average_waste,
+                        flagged: summary.flagged,
+                        reclaimed_bytes: None,
+                    }),
+            );
+            continue;
+        }
+        let items: usize = summaries.iter().map(|s| s.items).sum();
+        let total_waste: f64 = summaries
+            .iter()
+            .map(|s| s.average_waste * s.items as f64)
+            .sum();
+        points.push(TimeseriesPoint {
+            time,
+            instance: None,
+            items,
+            total_bytes: summaries.iter().map(|s| s.total_bytes).sum(),
+            average_waste: if items > 0 {
+                total_waste / items as f64
+            } else {
+                0.0
+            },
+            flagged: summaries.iter().map(|s| s.flagged).sum(),
+            reclaimed_bytes: Some(reclaimed_bytes.unwrap_or(0) as u64),
+        });
+    }
+    Ok(points)
+}
+
+/// Items per severity band in one scan; the fields follow SEVERITY_BANDS
+#[derive(Debug, Serialize)]
+struct SeverityPoint {
+    time: i64,
+    low: usize,
+    moderate: usize,
+    high: usize,
+    critical: usize,
+}
+
+/// Severity band counts of every scan that stored its items, oldest first
+fn read_severity_history() -> Result<Vec<SeverityPoint>> {
+    let Some(conn) = open_history_store()? else {
+        return Ok(Vec::new());
+    };
+    let bands: Vec<String> = SEVERITY_BANDS
+        .iter()
+        .enumerate()
+        .map(|(i, (_, low))| match SEVERITY_BANDS.get(i + 1) {
+            Some((_, high)) => format!(
+                "SUM(scan_items.waste_score >= {} AND scan_items.waste_score < {})",
+                low, high
+            ),
+            None => format!("SUM(scan_items.waste_score >= {})", low),
+        })
+        .collect();
+    let mut scans = conn.prepare(&format!(
+        "SELECT scans.timestamp, {} FROM scans
+         JOIN scan_items ON scan_items.scan_id = scans.id
+         GROUP BY scans.id ORDER BY scans.timestamp",
+        bands.join(", ")
+    ))?;
+    let points = scans
+        .query_map([], |row| {
+            Ok(SeverityPoint {
+                time: (row.get::<_, f64>(0)? * 1000.0) as i64,
+                low: row.get(1)?,
+                moderate: row.get(2)?,
+                high: row.get(3)?,
+                critical: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(points)
+}
+
+/// Space freed by wastearr's deletions in one calendar month
+#[derive(Debug, Serialize)]
+struct ReclaimedMonth {
+    // "2025-01", in local time
+    month: String,
+    items: usize,
+    bytes: u64,
+}
+
+fn read_reclaimed_by_month() -> Result<Vec<ReclaimedMonth>> {
+    let Some(conn) = open_history_store()? else {
+        return Ok(Vec::new());
+    };
+    let mut months = conn.prepare(
+        "SELECT strftime('%Y-%m', deleted_at, 'unixepoch', 'localtime') AS month,
+                COUNT(*), SUM(size_bytes)
+         FROM deletions GROUP BY month ORDER BY month",
+    )?;
+    let months = months
+        .query_map([], |row| {
+            Ok(ReclaimedMonth {
+                month: row.get(0)?,
+                items: row.get(1)?,
+                bytes: row.get::<_, i64>(2)? as u64,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(months)
+}
+
+fn format_scan_list(scans: &[ScanListing], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Scan",
+        "Date",
+        "Instances",
+        "Items",
+        "Size",
+        "Avg Waste",
+        "Flagged",
+    ]);
+    for scan in scans {
+        table.add_row(vec![
+            format!("#{}", scan.id),
+            format_local_time(scan.timestamp),
+            scan.instances.join(", "),
+            scan.items.to_string(),
+            format_file_size(scan.total_bytes),
+            format!("{:.1}", scan.average_waste),
+            scan.flagged.to_string(),
+        ]);
+    }
+    format!(
+        "{}\n\nShow a scan's items with `wastearr history SCAN`, or diff two with `wastearr compare FROM TO`\n",
+        table
+    )
+}
+
+fn format_past_scan(
+    id: i64,
+    snapshot: &ScanSnapshot,
+    items: &[&SnapshotItem],
+    style: &str,
+) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec!["Name", "Type", "Instance", "Size", "Waste Score"]);
+    for item in items {
+        table.add_row(vec![
+            item.name.clone(),
+            if item.item_type == "show" {
+                "Show"
+            } else {
+                "Movie"
+            }
+            .to_string(),
+            item.instance.clone(),
+            format_file_size(item.size_bytes),
+            item.waste_score.to_string(),
+        ]);
+    }
+    format!(
+        "Scan #{} from {} ({})\n{}\n\nTotal items shown: {} ({})\n",
+        id,
+        format_local_time(snapshot.timestamp),
+        snapshot.instances.join(", "),
+        table,
+        items.len(),
+        format_file_size(items.iter().map(|item| item.size_bytes).sum())
+    )
+}
+
+/// `wastearr history`: list stored scans, or show the items of one of them
+fn print_history(args: &Args) -> Result<()> {
+    let Some(reference) = &args.scan else {
+        let scans = list_scans()?;
+        if args.output == "json" {
+            println!("{}", serde_json::to_string_pretty(&scans)?);
+        } else if scans.is_empty() {
+            progress!("No scan history recorded yet; run a scan first");
+        } else {
+            page_output(&format_scan_list(&scans, &args.table_style), args.no_pager);
+        }
+        return Ok(());
+    };
+
+    let (id, snapshot) = load_snapshot(reference)?;
+    let min_size_bytes = args
+        .min_size
+        .as_deref()
+        .map(parse_size_string)
+        .transpose()?;
+    let item_type = args.item_type.as_deref().map(|t| match t {
+        "sonarr" => "show",
+        _ => "movie",
+    });
+    let mut items: Vec<&SnapshotItem> = snapshot
+        .items
+        .iter()
+        .filter(|item| item_type.is_none_or(|wanted| item.item_type == wanted))
+        .filter(|item| args.waste_score.is_none_or(|min| item.waste_score >= min))
+        .filter(|item| min_size_bytes.is_none_or(|min| item.size_bytes >= min))
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+    if let Some(top) = args.top_waste {
+        items.truncate(top);
+    }
+    if args.output == "json" {
+        println!("{}", serde_json::to_string_pretty(&items)?);
+    } else {
+        page_output(
+            &format_past_scan(id, &snapshot, &items, &args.table_style),
+            args.no_pager,
+        );
+    }
+    Ok(())
+}
+
+fn snapshot_label(id: Option<i64>, snapshot: &ScanSnapshot) -> String {
+    match id {
+        Some(id) => format!("scan #{} ({})", id, format_local_time(snapshot.timestamp)),
+        None => "the current scan".to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChangedItem {
+    #[serde(flatten)]
+    item: SnapshotItem,
+    size_delta_bytes: i64,
+    score_delta: i32,
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotDiff {
+    from: String,
+    to: String,
+    added: Vec<SnapshotItem>,
+    removed: Vec<SnapshotItem>,
+    changed: Vec<ChangedItem>,
+    size_delta_bytes: i64,
+}
+
+/// Items added, removed and changed between two scans, over the instances
+/// both of them covered
+fn diff_snapshots(
+    before: &ScanSnapshot,
+    after: &ScanSnapshot,
+    item_type: Option<&str>,
+) -> SnapshotDiff {
+    let covered = |item: &SnapshotItem| {
+        before.instances.contains(&item.instance)
+            && after.instances.contains(&item.instance)
+            && item_type.is_none_or(|wanted| item.item_type == wanted)
+    };
+    let index = |snapshot: &ScanSnapshot| -> HashMap<(String, i32), SnapshotItem> {
+        snapshot
+            .items
+            .iter()
+            .filter(|item| covered(item))
+            .map(|item| ((item.instance.clone(), item.id), item.clone()))
+            .collect()
+    };
+    let (old, new) = (index(before), index(after));
+
+    let mut diff = SnapshotDiff {
+        from: String::new(),
+        to: String::new(),
+        added: new
+            .iter()
+            .filter(|(key, _)| !old.contains_key(key))
+            .map(|(_, item)| item.clone())
+            .collect(),
+        removed: old
+            .iter()
+            .filter(|(key, _)| !new.contains_key(key))
+            .map(|(_, item)| item.clone())
+            .collect(),
+        changed: new
+            .iter()
+            .filter_map(|(key, item)| {
+                let before = old.get(key)?;
+                let change = ChangedItem {
+                    item: item.clone(),
+                    size_delta_bytes: item.size_bytes as i64 - before.size_bytes as i64,
+                    score_delta: item.waste_score - before.waste_score,
+                };
+                (change.size_delta_bytes != 0 || change.score_delta != 0).then_some(change)
+            })
+            .collect(),
+        size_delta_bytes: new.values().map(|item| item.size_bytes as i64).sum::<i64>()
+            - old.values().map(|item| item.size_bytes as i64).sum::<i64>(),
+    };
+    diff.added
+        .sort_by_key(|item| std::cmp::Reverse(item.size_bytes));
+    diff.removed
+        .sort_by_key(|item| std::cmp::Reverse(item.size_bytes));
+    diff.changed.sort_by_key(|change| {
+        (
+            std::cmp::Reverse(change.score_delta.abs()),
+            std::cmp::Reverse(change.size_delta_bytes.abs()),
+        )
+    });
+    diff
+}
+
+fn format_signed_size(bytes: i64) -> String {
+    format!(
+        "{}{}",
+        if bytes < 0 { "-" } else { "+" },
+        format_file_size(bytes.unsigned_abs())
+    )
+}
+
+fn format_snapshot_diff(diff: &SnapshotDiff, style: &str) -> String {
+    let mut output = String::new();
+    let _ = writeln!(output, "Comparing {} with {}", diff.from, diff.to);
+    let _ = writeln!(output, "{}", "=".repeat(60));
+    let total = |items: &[SnapshotItem]| items.iter().map(|item| item.size_bytes).sum::<u64>();
+    let _ = writeln!(
+        output,
+        "Added: {} across {} items",
+        format_file_size(total(&diff.added)),
+        diff.added.len()
+    );
+    let _ = writeln!(
+        output,
+        "Removed: {} across {} items",
+        format_file_size(total(&diff.removed)),
+        diff.removed.len()
+    );
+    let _ = writeln!(output, "Changed: {} items", diff.changed.len());
+    let _ = writeln!(
+        output,
+        "Net change: {}",
+        format_signed_size(diff.size_delta_bytes)
+    );
+
+    let type_label = |item: &SnapshotItem| {
+        if item.item_type == "show" {
+            "Show"
+        } else {
+            "Movie"
+        }
+        .to_string()
+    };
+    for (title, items) in [("Added", &diff.added), ("Removed", &diff.removed)] {
+        if items.is_empty() {
+            continue;
+        }
+        let mut table = Table::new();
+        apply_table_style(&mut table, style);
+        table.set_header(vec!["Name", "Type", "Instance", "Size", "Waste Score"]);
+        for item in items {
+            table.add_row(vec![
+                item.name.clone(),
+                type_label(item),
+                item.instance.clone(),
+                format_file_size(item.size_bytes),
+                item.waste_score.to_string(),
+            ]);
+        }
+        let _ = writeln!(output, "\n{}:\n{}", title, table);
+    }
+
+    if !diff.changed.is_empty() {
+        let mut table = Table::new();
+        apply_table_style(&mut table, style);
+        table.set_header(vec![
+            "Name",
+            "Type",
+            "Instance",
+            "Size",
+            "Size Change",
+            "Waste Score",
+            "Score Change",
+        ]);
+        for change in &diff.changed {
+            table.add_row(vec![
+                change.item.name.clone(),
+                type_label(&change.item),
+                change.item.instance.clone(),
+                format_file_size(change.item.size_bytes),
+                format_signed_size(change.size_delta_bytes),
+                change.item.waste_score.to_string(),
+                format!("{:+}", change.score_delta),
+            ]);
+        }
+        let _ = writeln!(output, "\nChanged:\n{}", table);
+    }
+    output
+}
+
+/// Print the diff from `before` (a stored scan) to `after`, which is either
+/// another stored scan or, with `after_id` None, the scan just run
+fn print_comparison(
+    before: &(i64, ScanSnapshot),
+    after: &ScanSnapshot,
+    after_id: Option<i64>,
+    args: &Args,
+) {
+    let item_type = args.item_type.as_deref().map(|t| match t {
+        "sonarr" => "show",
+        _ => "movie",
+    });
+    let diff = SnapshotDiff {
+        from: snapshot_label(Some(before.0), &before.1),
+        to: snapshot_label(after_id, after),
+        ..diff_snapshots(&before.1, after, item_type)
+    };
+    if args.output == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).unwrap_or_default()
+        );
+        return;
+    }
+    page_output(
+        &format_snapshot_diff(&diff, &args.table_style),
+        args.no_pager,
+    );
+}
+
+fn format_age(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    let (count, unit) = match seconds {
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 2 * 86400 => (s / 3600, "hour"),
+        s => (s / 86400, "day"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+fn calculate_size_score(size_bytes: u64) -> f64 {
+    let size_gb = size_bytes as f64 / (1024.0_f64.powi(3));
+
+    if size_gb <= 1.0 {
+        size_gb * 10.0
+    } else {
+        10.0 + (size_gb.log10() * 30.0)
+    }
+    .min(80.0)
+}
+
+fn get_rating_multiplier(rating: f64, is_tv: bool) -> f64 {
+    let multipliers = if is_tv {
+        [0.05, 0.15, 0.35, 0.55, 0.75, 1.1] // TV: more forgiving
+    } else {
+        [0.1, 0.2, 0.4, 0.6, 0.8, 1.2] // Movies: stricter
+    };
+
+    let thresholds = [8.0, 7.5, 7.0, 6.5, 6.0];
+    thresholds
+        .iter()
+        .position(|&threshold| rating >= threshold)
+        .map(|i| multipliers[i])
+        .unwrap_or(multipliers[5])
+}
+
+type ScoreFn = TypedFunc<(f64, f64, i32, i32, f64), f64>;
+
+/// A user-supplied scorer: a WebAssembly module (binary .wasm or text .wat)
+/// exporting `score(size_gb: f64, rating: f64, year: i32, is_show: i32,
+/// builtin: f64) -> f64`. Rating is -1 when unknown and `builtin` is
+/// wastearr's own score, so a plugin can adjust it rather than start over.
+struct ScorerPlugin {
+    name: String,
+    store: Store<()>,
+    score: ScoreFn,
+}
+
+impl ScorerPlugin {
+    fn plugin_dir() -> Option<PathBuf> {
+        config_dir().map(|d| d.join("wastearr/plugins"))
+    }
+
+    /// Names of the plugins in the plugins directory, as --scorer takes them
+    fn available() -> Vec<String> {
+        let Some(entries) = Self::plugin_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == "wasm" || ext == "wat")
+            })
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Load by name from the plugins directory, or from an explicit path
+    fn load(spec: &str) -> Result<Self> {
+        let path = if spec.contains('/') || spec.ends_with(".wasm") || spec.ends_with(".wat") {
+            PathBuf::from(spec)
+        } else {
+            let dir = Self::plugin_dir().context("Could not determine the config directory")?;
+            ["wasm", "wat"]
+                .iter()
+                .map(|ext| dir.join(format!("{}.{}", spec, ext)))
+                .find(|path| path.exists())
+                .with_context(|| format!("No scorer plugin '{}' in {}", spec, dir.display()))?
+        };
+        let bytes = fs::read(&path)
+            .with_context(|| format!("Failed to read scorer plugin {}", path.display()))?;
+
+        // parse_bytes passes binary modules through and assembles text ones
+        let wasm = wat::parse_bytes(&bytes)
+            .with_context(|| format!("Invalid scorer plugin {}", path.display()))?;
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm[..])
+            .with_context(|| format!("Invalid scorer plugin {}", path.display()))?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .with_context(|| format!("Failed to start scorer plugin {}", path.display()))?;
+        let score = instance.get_typed_func(&store, "score").with_context(|| {
+            format!(
+                "Scorer plugin {} must export score(f64, f64, i32, i32, f64) -> f64",
+                path.display()
+            )
+        })?;
+
+        Ok(ScorerPlugin {
+            name: path
+                .file_stem()
+                .map_or_else(|| spec.to_string(), |s| s.to_string_lossy().into_owned()),
+            store,
+            score,
+        })
+    }
+
+    fn score(&mut self, item: &Item) -> Result<i32> {
+        let args = (
+            item.size_bytes as f64 / 1024.0_f64.powi(3),
+            item.rating.parse::<f64>().unwrap_or(-1.0),
+            item.year,
+            (item.item_type == "show") as i32,
+            item.waste_score as f64,
+        );
+        let score = self
+            .score
+            .call(&mut self.store, args)
+            .with_context(|| format!("Scorer plugin {} failed on '{}'", self.name, item.name))?;
+        Ok(if score.is_finite() {
+            (score.round() as i32).clamp(0, 100)
+        } else {
+            0
+        })
+    }
+}
+
+/// Knobs of the built-in waste score, from [scoring] or WASTEARR_SCORE_*
+#[derive(Debug, Clone, Copy)]
+struct Scoring {
+    // Shows are discounted, having more files for the same watch time
+    tv_factor: f64,
+    // The rating unrated items are scored as
+    unrated_rating: f64,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring {
+            tv_factor: 0.6,
+            unrated_rating: 6.0,
+        }
+    }
+}
+
+impl Scoring {
+    fn load() -> Result<Self> {
+        let setting = |key: &str| -> Result<Option<f64>> {
+            get_config_value(key)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|n| n.is_finite() && *n >= 0.0)
+                        .with_context(|| format!("{} must be a number of 0 or more", key))
+                })
+                .transpose()
+        };
+        let defaults = Scoring::default();
+        let unrated_rating =
+            setting("WASTEARR_SCORE_UNRATED_RATING")?.unwrap_or(defaults.unrated_rating);
+        if unrated_rating > 10.0 {
+            anyhow::bail!("WASTEARR_SCORE_UNRATED_RATING must be a rating from 0 to 10");
+        }
+        Ok(Scoring {
+            tv_factor: setting("WASTEARR_SCORE_TV_FACTOR")?.unwrap_or(defaults.tv_factor),
+            unrated_rating,
+        })
+    }
+}
+
+/// The factors of the built-in waste score, kept apart so the score can be explained
+struct ScoreFactors {
+    size_score: f64,
+    tv_factor: f64,
+    // None when unrated, which scores as Scoring::unrated_rating
+    rating: Option<f64>,
+    rating_multiplier: f64,
+}
+
+impl ScoreFactors {
+    fn of(item: &Item, scoring: &Scoring) -> Self {
+        let rating = item.rating.parse::<f64>().ok();
+        let is_tv = item.item_type == "show";
+        ScoreFactors {
+            size_score: calculate_size_score(item.size_bytes),
+            tv_factor: if is_tv { scoring.tv_factor } else { 1.0 },
+            rating,
+            rating_multiplier: get_rating_multiplier(
+                rating.unwrap_or(scoring.unrated_rating),
+                is_tv,
+            ),
+        }
+    }
+
+    fn score(&self) -> i32 {
+        let waste_score = self.size_score * self.tv_factor * self.rating_multiplier;
+        (waste_score.round() as i32).clamp(0, 100)
+    }
+}
+
+fn calculate_normalized_waste_score(item: &mut Item, scoring: &Scoring) {
+    item.waste_score = ScoreFactors::of(item, scoring).score();
+}
+
+fn format_file_size(size_bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = size_bytes as f64;
+    let mut unit_index = 0;
+
+    while size >= 1024.0 && unit_index < units.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+
+    format!("{:.1} {}", size, units[unit_index])
+}
+
+fn parse_size_string(size_str: &str) -> Result<u64> {
+    let re = Regex::new(r"^(\d+(?:\.\d+)?)\s*([KMGTB]?B?)?$").unwrap();
+    let size_upper = size_str.to_uppercase();
+
+    let captures = re
+        .captures(&size_upper)
+        .context(format!("Invalid size format: {}", size_str))?;
+
+    let number: f64 = captures
+        .get(1)
+        .unwrap()
+        .as_str()
+        .parse()
+        .context("Invalid number in size string")?;
+
+    let unit = captures.get(2).map(|m| m.as_str()).unwrap_or("B");
+
+    let multiplier = match unit {
+        "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024_u64.pow(2),
+        "G" | "GB" => 1024_u64.pow(3),
+        "T" | "TB" => 1024_u64.pow(4),
+        _ => anyhow::bail!("Unknown unit: {}", unit),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+fn mode(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut counts = HashMap::new();
+    for &v in values {
+        *counts.entry((v * 10.0).round() as i32).or_insert(0) += 1;
+    }
+    counts
+        .iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(&val, _)| val as f64 / 10.0)
+        .unwrap_or(0.0)
+}
+
+/// Mean rating with mode/median in parentheses, ignoring unrated items
+fn rating_summary<'a>(items: impl IntoIterator<Item = &'a Item>) -> String {
+    let numeric_ratings: Vec<f64> = items
+        .into_iter()
+        .filter_map(|item| item.rating.parse().ok())
+        .collect();
+    if numeric_ratings.is_empty() {
+        return "N/A".to_string();
+    }
+    let avg = numeric_ratings.iter().sum::<f64>() / numeric_ratings.len() as f64;
+    format!(
+        "{:.1} ({:.1}/{:.1})",
+        avg,
+        mode(&numeric_ratings),
+        median(numeric_ratings.clone())
+    )
+}
+
+fn format_type_breakdown(items: &[Item], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Type",
+        "Items",
+        "Rating (Mode/Median)",
+        "Total Size",
+        "Median Size",
+        "Avg Waste",
+    ]);
+
+    for (item_type, label) in [("show", "Shows"), ("movie", "Movies")] {
+        let typed: Vec<&Item> = items.iter().filter(|i| i.item_type == item_type).collect();
+        if typed.is_empty() {
+            continue;
+        }
+        let total_size: u64 = typed.iter().map(|i| i.size_bytes).sum();
+        let total_waste: i64 = typed.iter().map(|i| i.waste_score as i64).sum();
+        table.add_row(vec![
+            label.to_string(),
+            typed.len().to_string(),
+            rating_summary(typed.iter().copied()),
+            format_file_size(total_size),
+            format_file_size(median(typed.iter().map(|i| i.size_bytes as f64).collect()) as u64),
+            (total_waste / typed.len() as i64).to_string(),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn apply_table_style(table: &mut Table, style: &str) {
+    match style {
+        "ascii" => table.load_preset(ASCII_FULL),
+        "markdown" => table.load_preset(ASCII_MARKDOWN),
+        "borderless" => table.load_preset(NOTHING),
+        "compact" => table.load_preset(UTF8_FULL_CONDENSED),
+        _ => table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS),
+    };
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().filter_map(UnicodeWidthChar::width).sum()
+}
+
+fn truncate_to_width(text: &str, max_width: usize) -> String {
+    if display_width(text) <= max_width {
+        return text.to_string();
+    }
+
+    // Reserve one column for the ellipsis
+    let mut width = 0;
+    let mut truncated: String = text
+        .chars()
+        .take_while(|c| {
+            width += c.width().unwrap_or(0);
+            width < max_width
+        })
+        .collect();
+    truncated.push('…');
+    truncated
+}
+
+fn terminal_width() -> Option<usize> {
+    io::stdout()
+        .is_terminal()
+        .then(terminal_size)
+        .flatten()
+        .map(|(Width(cols), _)| cols as usize)
+        .filter(|&cols| cols > 0)
+}
+
+fn hyperlink(text: &str, url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+struct TableOptions<'a> {
+    style: &'a str,
+    show_trend_column: bool,
+    show_type_column: bool,
+    show_media_columns: bool,
+    show_episode_columns: bool,
+    show_note_column: bool,
+    max_width: Option<usize>,
+    links: bool,
+}
+
+/// Optional columns, inserted between Size and Waste Score
+fn extra_headers(options: &TableOptions) -> Vec<&'static str> {
+    let mut headers = Vec::new();
+    if options.show_trend_column {
+        headers.push("Trend");
+    }
+    if options.show_episode_columns {
+        headers.extend(["Episodes", "Seasons", "Complete"]);
+    }
+    if options.show_media_columns {
+        headers.extend(["Resolution", "Video Codec", "Audio"]);
+    }
+    if options.show_note_column {
+        headers.push("Note");
+    }
+    headers
+}
+
+fn extra_cells(item: &Item, options: &TableOptions) -> Vec<String> {
+    let mut cells = Vec::new();
+    if options.show_trend_column {
+        cells.push(item.trend.as_ref().map_or("-".to_string(), Trend::label));
+    }
+    if options.show_episode_columns {
+        match &item.episodes {
+            Some(episodes) => cells.extend([
+                format!("{}/{}", episodes.file_count, episodes.total_count),
+                episodes.season_count.to_string(),
+                format!("{:.0}%", episodes.percent_complete),
+            ]),
+            None => cells.extend(["-".to_string(), "-".to_string(), "-".to_string()]),
+        }
+    }
+    if options.show_media_columns {
+        let media = item.media_info.as_ref();
+        cells.extend([
+            media.map_or("-", |m| &m.resolution).to_string(),
+            media.map_or("-", |m| &m.video_codec).to_string(),
+            media.map_or("-", |m| &m.audio).to_string(),
+        ]);
+    }
+    if options.show_note_column {
+        cells.push(item.note.as_deref().map_or(String::new(), |note| {
+            truncate_to_width(note, NOTE_COLUMN_WIDTH)
+        }));
+    }
+    cells
+}
+
+fn format_unified_table(items: &[Item], options: &TableOptions) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, options.style);
+
+    let mut headers = vec!["Name", "Year", "TMDB Score", "Size"];
+    if options.show_type_column {
+        headers.insert(1, "Type");
+    }
+    headers.extend(extra_headers(options));
+    headers.push("Waste Score");
+    table.set_header(&headers);
+
+    let mut rows = Vec::with_capacity(items.len() + 1);
+    let (total_size, total_waste) = items.iter().fold((0u64, 0i32), |acc, item| {
+        let mut row = vec![
+            item.name.clone(),
+            item.year.to_string(),
+            item.rating.clone(),
+            format_file_size(item.size_bytes),
+        ];
+        if options.show_type_column {
+            row.insert(
+                1,
+                if item.item_type == "show" {
+                    "Show"
+                } else {
+                    "Movie"
+                }
+                .to_string(),
+            );
+        }
+        row.extend(extra_cells(item, options));
+        row.push(item.waste_score.to_string());
+        rows.push(row);
+        (acc.0 + item.size_bytes, acc.1 + item.waste_score)
+    });
+
+    if !items.is_empty() {
+        let mut total_row = vec![
+            format!("Total ({})", items.len()),
+            "".to_string(),
+            rating_summary(items),
+            format_file_size(total_size),
+        ];
+        if options.show_type_column {
+            let types: std::collections::HashSet<_> = items.iter().map(|i| &i.item_type).collect();
+            total_row.insert(
+                1,
+                format!(
+                    "{} type{}",
+                    types.len(),
+                    if types.len() != 1 { "s" } else { "" }
+                ),
+            );
+        }
+        total_row.extend(vec![String::new(); extra_headers(options).len()]);
+        total_row.push((total_waste / items.len() as i32).to_string());
+        rows.push(total_row);
+    }
+
+    if let Some(max_width) = options.max_width {
+        // Each column costs its content plus one padding space per side and a separator
+        let other_columns: usize = (1..headers.len())
+            .map(|col| {
+                rows.iter()
+                    .map(|row: &Vec<String>| display_width(&row[col]))
+                    .chain(std::iter::once(display_width(headers[col])))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .sum();
+        let name_width = max_width
+            .saturating_sub(other_columns + 3 * headers.len() + 1)
+            .max(MIN_NAME_WIDTH);
+        rows.iter_mut()
+            .for_each(|row| row[0] = truncate_to_width(&row[0], name_width));
+    }
+
+    if options.links {
+        rows.iter_mut()
+            .zip(items)
+            .filter_map(|(row, item)| item.url.as_ref().map(|url| (row, url)))
+            .for_each(|(row, url)| row[0] = hyperlink(&row[0], url));
+    }
+
+    table.add_rows(rows);
+    table.to_string()
+}
+
+#[derive(Debug)]
+struct GroupSummary {
+    key: String,
+    count: usize,
+    size_bytes: u64,
+    ratings: Vec<f64>,
+    total_waste: i64,
+}
+
+impl GroupSummary {
+    fn average_rating(&self) -> Option<f64> {
+        (!self.ratings.is_empty())
+            .then(|| self.ratings.iter().sum::<f64>() / self.ratings.len() as f64)
+    }
+
+    fn average_waste(&self) -> i64 {
+        self.total_waste / self.count.max(1) as i64
+    }
+}
+
+/// The groups an item counts toward: one for every key but genre, where an
+/// item with several genres lands in each of them
+fn group_keys(item: &Item, group_by: &str) -> Vec<String> {
+    if group_by == "genre" {
+        return if item.genres.is_empty() {
+            vec!["Unknown".to_string()]
+        } else {
+            item.genres.clone()
+        };
+    }
+    let key = match group_by {
+        "year" => item.year.to_string(),
+        "decade" => format!("{}s", item.year / 10 * 10),
+        "root-folder" => item.root_folder.clone(),
+        "quality" => item.quality.clone(),
+        "instance" => item.instance.clone(),
+        "collection" => item
+            .collection
+            .clone()
+            .unwrap_or_else(|| "No Collection".to_string()),
+        _ => if item.item_type == "show" {
+            "Show"
+        } else {
+            "Movie"
+        }
+        .to_string(),
+    };
+    vec![key]
+}
+
+fn group_items(items: &[Item], group_by: &str) -> Vec<GroupSummary> {
+    let mut groups: HashMap<String, GroupSummary> = HashMap::new();
+    for item in items {
+        for key in group_keys(item, group_by) {
+            let group = groups.entry(key.clone()).or_insert_with(|| GroupSummary {
+                key,
+                count: 0,
+                size_bytes: 0,
+                ratings: Vec::new(),
+                total_waste: 0,
+            });
+            group.count += 1;
+            group.size_bytes += item.size_bytes;
+            group.total_waste += item.waste_score as i64;
+            if let Ok(rating) = item.rating.parse() {
+                group.ratings.push(rating);
+            }
+        }
+    }
+
+    let mut groups: Vec<_> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.size_bytes
+            .cmp(&a.size_bytes)
+            .then_with(|| a.key.cmp(&b.key))
+    });
+    groups
+}
+
+fn format_group_table(groups: &[GroupSummary], group_by: &str, style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+
+    let label = match group_by {
+        "genre" => "Genre",
+        "year" => "Year",
+        "decade" => "Decade",
+        "root-folder" => "Root Folder",
+        "quality" => "Quality Profile",
+        "instance" => "Instance",
+        "collection" => "Collection",
+        _ => "Type",
+    };
+    table.set_header(vec![
+        label,
+        "Items",
+        "Total Size",
+        "Avg Rating",
+        "Avg Waste",
+    ]);
+
+    for group in groups {
+        table.add_row(vec![
+            group.key.clone(),
+            group.count.to_string(),
+            format_file_size(group.size_bytes),
+            group
+                .average_rating()
+                .map_or_else(|| "N/A".to_string(), |r| format!("{:.1}", r)),
+            group.average_waste().to_string(),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn format_plain_groups(groups: &[GroupSummary]) -> String {
+    groups
+        .iter()
+        .map(|group| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                group.key.replace(['\t', '\n', '\r'], " "),
+                group.count,
+                group.size_bytes,
+                group
+                    .average_rating()
+                    .map_or_else(|| "N/A".to_string(), |r| format!("{:.1}", r)),
+                group.average_waste()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn root_folder_totals<'a>(
+    items: impl IntoIterator<Item = &'a Item>,
+) -> HashMap<String, (usize, u64)> {
+    items.into_iter().fold(HashMap::new(), |mut totals, item| {
+        let entry = totals.entry(item.root_folder.clone()).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += item.size_bytes;
+        totals
+    })
+}
+
+fn format_root_folder_table(
+    library: &HashMap<String, (usize, u64)>,
+    flagged: &HashMap<String, (usize, u64)>,
+    style: &str,
+) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Root Folder",
+        "Items",
+        "Library Size",
+        "Flagged",
+        "Flagged Size",
+        "Flagged %",
+    ]);
+
+    let mut folders: Vec<_> = library.iter().collect();
+    folders.sort_by(|a, b| b.1.1.cmp(&a.1.1).then_with(|| a.0.cmp(b.0)));
+
+    for (folder, &(count, size)) in folders {
+        let (flagged_count, flagged_size) = flagged.get(folder).copied().unwrap_or((0, 0));
+        table.add_row(vec![
+            folder.clone(),
+            count.to_string(),
+            format_file_size(size),
+            flagged_count.to_string(),
+            format_file_size(flagged_size),
+            format!("{:.1}%", flagged_size as f64 / size.max(1) as f64 * 100.0),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn disk_for_item<'a>(disks: &'a [DiskSpace], item: &Item) -> Option<&'a DiskSpace> {
+    disk_for_path(disks, &item.instance, &item.path)
+}
+
+/// The most specific disk an instance reports for a path
+fn disk_for_path<'a>(disks: &'a [DiskSpace], instance: &str, path: &str) -> Option<&'a DiskSpace> {
+    disks
+        .iter()
+        .filter(|disk| disk.instances.iter().any(|name| name == instance))
+        .filter(|disk| Path::new(path).starts_with(&disk.path))
+        .max_by_key(|disk| disk.path.len())
+}
+
+/// Bytes of the flagged items on each disk, keyed by (host, path) since
+/// separate hosts can report the same mount path
+fn flagged_bytes_by_disk<'a>(
+    disks: &'a [DiskSpace],
+    items: &[Item],
+) -> HashMap<(&'a str, &'a str), u64> {
+    let mut totals = HashMap::new();
+    for item in items
+        .iter()
+        .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+    {
+        if let Some(disk) = disk_for_item(disks, item) {
+            *totals
+                .entry((disk.host.as_str(), disk.path.as_str()))
+                .or_insert(0u64) += item.size_bytes;
+        }
+    }
+    totals
+}
+
+fn format_disk_space_table(disks: &[DiskSpace], items: &[Item], style: &str) -> String {
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Disk",
+        "Free",
+        "Total",
+        "Free %",
+        "Flagged",
+        "Free % After",
+    ]);
+
+    let flagged_by_disk = flagged_bytes_by_disk(disks, items);
+
+    for disk in disks.iter().filter(|d| d.total_bytes > 0) {
+        let flagged_bytes = flagged_by_disk
+            .get(&(disk.host.as_str(), disk.path.as_str()))
+            .copied()
+            .unwrap_or(0);
+        table.add_row(vec![
+            disk_name(disk),
+            format_file_size(disk.free_bytes),
+            format_file_size(disk.total_bytes),
+            format!("{:.1}%", percent_free(disk.free_bytes, disk.total_bytes)),
+            format_file_size(flagged_bytes),
+            format!(
+                "{:.1}%",
+                percent_free(disk.free_bytes + flagged_bytes, disk.total_bytes)
+            ),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn disk_name(disk: &DiskSpace) -> String {
+    if disk.label.is_empty() {
+        disk.path.clone()
+    } else {
+        format!("{} ({})", disk.path, disk.label)
+    }
+}
+
+fn percent_free(free_bytes: u64, total_bytes: u64) -> f64 {
+    (free_bytes as f64 / total_bytes.max(1) as f64 * 100.0).min(100.0)
+}
+
+fn disk_space_projection(disks: &[DiskSpace], items: &[Item]) -> Option<String> {
+    let (free, total) = disks.iter().fold((0u64, 0u64), |acc, d| {
+        (acc.0 + d.free_bytes, acc.1 + d.total_bytes)
+    });
+    if total == 0 {
+        return None;
+    }
+    let flagged_bytes: u64 = flagged_bytes_by_disk(disks, items).values().sum();
+    Some(format!(
+        "Flagged waste ({}) could raise free space from {:.1}% to {:.1}%",
+        format_file_size(flagged_bytes),
+        percent_free(free, total),
+        percent_free(free + flagged_bytes, total)
+    ))
+}
+
+/// Least-squares slope of (timestamp, bytes) points, in bytes per week
+fn growth_per_week(points: &[(f64, f64)]) -> Option<f64> {
+    let first = points.first()?.0;
+    let last = points.last()?.0;
+    if points.len() < 2 || last - first < FORECAST_MIN_SPAN {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_b = points.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |acc, (t, b)| {
+        (
+            acc.0 + (t - mean_t) * (b - mean_b),
+            acc.1 + (t - mean_t).powi(2),
+        )
+    });
+    Some(covariance / variance * WEEK)
+}
+
+/// Growth of each root folder of `instances`, fitted over the recent scans
+/// that recorded folder sizes
+fn read_folder_growth(
+    conn: &Connection,
+    instances: &[String],
+) -> Result<Vec<(String, String, f64)>> {
+    let mut sizes = conn.prepare(
+        "SELECT scan_folders.instance, scan_folders.root_folder, scans.timestamp,
+                scan_folders.total_bytes
+         FROM scan_folders JOIN scans ON scans.id = scan_folders.scan_id
+         WHERE scans.timestamp >= ?1 ORDER BY scans.timestamp",
+    )?;
+    let mut points: BTreeMap<(String, String), Vec<(f64, f64)>> = BTreeMap::new();
+    for row in sizes.query_map([current_timestamp() - FORECAST_WINDOW], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, f64>(2)?,
+            row.get::<_, i64>(3)?,
+        ))
+    })? {
+        let (instance, root_folder, timestamp, bytes) = row?;
+        points
+            .entry((instance, root_folder))
+            .or_default()
+            .push((timestamp, bytes as f64));
+    }
+    Ok(points
+        .into_iter()
+        .filter(|((instance, _), _)| instances.contains(instance))
+        .filter_map(|((instance, root_folder), points)| {
+            Some((instance, root_folder, growth_per_week(&points)?))
+        })
+        .collect())
+}
+
+fn growth_forecast(instances: &[String], disks: &[DiskSpace]) -> GrowthForecast {
+    let growth = open_history_store().and_then(|conn| match conn {
+        Some(conn) => read_folder_growth(&conn, instances),
+        None => Ok(Vec::new()),
+    });
+    let folders = growth.unwrap_or_else(|e| {
+        warn!("failed to read scan history: {:#}", e);
+        Vec::new()
+    });
+    GrowthForecast {
+        disks: growth_by_disk(&folders, disks),
+        folders,
+    }
+}
+
+/// Sum the growth of the root folders on each disk
+fn growth_by_disk(
+    folders: &[(String, String, f64)],
+    disks: &[DiskSpace],
+) -> HashMap<(String, String), f64> {
+    let mut by_disk = HashMap::new();
+    for (instance, root_folder, growth) in folders {
+        if let Some(disk) = disk_for_path(disks, instance, root_folder) {
+            *by_disk
+                .entry((disk.host.clone(), disk.path.clone()))
+                .or_insert(0.0) += growth;
+        }
+    }
+    by_disk
+}
+
+fn format_runway(free_bytes: u64, growth: f64) -> String {
+    if growth <= 0.0 {
+        return "Not growing".to_string();
+    }
+    let weeks = free_bytes as f64 / growth;
+    if weeks >= 104.0 {
+        format!("~{:.0} years", weeks / 52.0)
+    } else {
+        format!("~{:.0} weeks", weeks)
+    }
+}
+
+fn format_growth_forecast(
+    forecast: &GrowthForecast,
+    disks: &[DiskSpace],
+    items: &[Item],
+    style: &str,
+) -> String {
+    if forecast.folders.is_empty() {
+        return "Not enough scan history for a growth forecast yet; it needs scans spanning at least a day".to_string();
+    }
+    let rates: Vec<String> = forecast
+        .folders
+        .iter()
+        .map(|(instance, root_folder, growth)| {
+            format!(
+                "{} ({}) {}{}/week",
+                root_folder,
+                instance,
+                if *growth < 0.0 { "-" } else { "+" },
+                format_file_size(growth.abs() as u64)
+            )
+        })
+        .collect();
+    let mut output = format!(
+        "Growth forecast (last {} days of scans): {}",
+        FORECAST_WINDOW / 86400.0,
+        rates.join(", ")
+    );
+
+    let mut table = Table::new();
+    apply_table_style(&mut table, style);
+    table.set_header(vec![
+        "Disk",
+        "Free",
+        "Growth/Week",
+        "Full In",
+        "Flagged",
+        "Full In After",
+    ]);
+    let flagged_by_disk = flagged_bytes_by_disk(disks, items);
+    let mut rows = 0;
+    for disk in disks.iter().filter(|d| d.total_bytes > 0) {
+        let Some(&growth) = forecast.disks.get(&(disk.host.clone(), disk.path.clone())) else {
+            continue;
+        };
+        let flagged_bytes = flagged_by_disk
+            .get(&(disk.host.as_str(), disk.path.as_str()))
+            .copied()
+            .unwrap_or(0);
+        table.add_row(vec![
+            disk_name(disk),
+            format_file_size(disk.free_bytes),
+            format!(
+                "{}{}",
+                if growth < 0.0 { "-" } else { "+" },
+                format_file_size(growth.abs() as u64)
+            ),
+            format_runway(disk.free_bytes, growth),
+            format_file_size(flagged_bytes),
+            format_runway(disk.free_bytes + flagged_bytes, growth),
+        ]);
+        rows += 1;
+    }
+    if rows > 0 {
+        let _ = write!(output, "\n{}", table);
+    }
+    output
+}
+
+fn severity_of(waste_score: i32) -> &'static str {
+    SEVERITY_BANDS
+        .iter()
+        .rev()
+        .find(|(_, low)| waste_score >= *low)
+        .map_or(SEVERITY_BANDS[0].0, |(label, _)| label)
+}
+
+fn format_severity_summary(items: &[Item]) -> String {
+    let bands: Vec<String> = SEVERITY_BANDS
+        .iter()
+        .enumerate()
+        .map(|(i, (label, low))| {
+            let high = SEVERITY_BANDS.get(i + 1).map_or(100, |(_, next)| *next);
+            let (count, size) = items
+                .iter()
+                .filter(|item| severity_of(item.waste_score) == *label)
+                .fold((0, 0u64), |acc, item| (acc.0 + 1, acc.1 + item.size_bytes));
+            format!(
+                "  {:<9} {:>3}-{:<3} {:>5} items  {:>10}",
+                label,
+                low,
+                high,
+                count,
+                format_file_size(size)
+            )
+        })
+        .collect();
+    format!("\nSeverity:\n{}", bands.join("\n"))
+}
+
+fn format_plain_row(item: &Item) -> String {
+    let name = item.name.replace(['\t', '\n', '\r'], " ");
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        name, item.item_type, item.year, item.rating, item.size_bytes, item.waste_score
+    )
+}
+
+fn format_plain_rows(items: &[Item]) -> String {
+    items
+        .iter()
+        .map(format_plain_row)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A duration such as "90s", "30m", "6h" or "1d"; bare numbers are seconds
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .map_or((value, "s"), |at| value.split_at(at));
+    let multiplier = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("'{}' is not a duration like 30m, 6h or 1d", value)),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(Duration::from_secs_f64(n * multiplier)),
+        _ => Err(format!("'{}' is not a duration like 30m, 6h or 1d", value)),
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(secs),
+        _ => Err(format!("'{}' is not a positive number of seconds", value)),
+    }
+}
+
+fn analysis_args() -> Vec<Arg> {
+    vec![
+        Arg::new("item_type")
+            .help("Analyze only TV series (sonarr) or only movies (radarr)")
+            .value_name("TYPE")
+            .value_parser(["sonarr", "radarr"]),
+        Arg::new("skip-sonarr")
+            .help("Leave Sonarr out of this run")
+            .long("skip-sonarr")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("skip-radarr"),
+        Arg::new("skip-radarr")
+            .help("Leave Radarr out of this run")
+            .long("skip-radarr")
+            .action(ArgAction::SetTrue),
+        Arg::new("top-waste")
+            .help("Show the N highest waste scores")
+            .value_name("N")
+            .short('t')
+            .long("top-waste")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("waste-score")
+            .help("Show items with a waste score of at least N")
+            .value_name("N")
+            .short('s')
+            .long("waste-score")
+            .value_parser(clap::value_parser!(i32)),
+        Arg::new("min-size")
+            .help("Show items of at least SIZE, e.g. 5GB or 500MB")
+            .value_name("SIZE")
+            .short('m')
+            .long("min-size"),
+        Arg::new("ratings")
+            .help("Show items rated N or lower")
+            .value_name("N")
+            .short('r')
+            .long("ratings")
+            .value_parser(clap::value_parser!(f64)),
+        Arg::new("clear-cache")
+            .help("Clear the rating and library cache, or only that of a service or instance")
+            .long("clear-cache")
+            .value_name("TARGET")
+            .num_args(0..=1)
+            .default_missing_value("all"),
+        Arg::new("no-cache")
+            .help("Bypass the cache entirely")
+            .long("no-cache")
+            .action(ArgAction::SetTrue),
+        Arg::new("table-style")
+            .help("Table layout")
+            .value_name("STYLE")
+            .long("table-style")
+            .value_parser(["rounded", "ascii", "markdown", "borderless", "compact"])
+            .default_value("rounded"),
+        Arg::new("plain")
+            .help("Print tab-separated rows instead of a table")
+            .long("plain")
+            .action(ArgAction::SetTrue),
+        Arg::new("no-pager")
+            .help("Don't pipe long results through $PAGER")
+            .long("no-pager")
+            .action(ArgAction::SetTrue),
+        Arg::new("quiet")
+            .help("Print only the result: no progress, warnings, scan footer or pager")
+            .short('q')
+            .long("quiet")
+            .visible_alias("porcelain")
+            .conflicts_with("verbose")
+            .action(ArgAction::SetTrue),
+        Arg::new("full-names")
+            .help("Don't truncate long titles to fit the terminal")
+            .long("full-names")
+            .action(ArgAction::SetTrue),
+        Arg::new("group-by")
+            .help("Collapse items into aggregate rows by KEY; by genre, an item counts toward each of its genres, so totals overlap")
+            .value_name("KEY")
+            .short('g')
+            .long("group-by")
+            .value_parser([
+                "genre",
+                "year",
+                "decade",
+                "root-folder",
+                "quality",
+                "instance",
+                "collection",
+                "type",
+            ]),
+        Arg::new("output")
+            .help("Output format")
+            .value_name("FORMAT")
+            .short('o')
+            .long("output")
+            .value_parser(["table", "json", "nagios"])
+            .default_value("table"),
+        Arg::new("warning")
+            .help("Flagged size above which --output nagios reports WARNING")
+            .value_name("SIZE")
+            .short('w')
+            .long("warning")
+            .value_parser(|size: &str| parse_size_string(size).map_err(|e| e.to_string())),
+        Arg::new("critical")
+            .help("Flagged size above which --output nagios reports CRITICAL")
+            .value_name("SIZE")
+            .short('c')
+            .long("critical")
+            .value_parser(|size: &str| parse_size_string(size).map_err(|e| e.to_string())),
+        Arg::new("media-info")
+            .help("Add resolution, video codec and audio columns")
+            .long("media-info")
+            .action(ArgAction::SetTrue),
+        Arg::new("episodes")
+            .help("Add episode and season counts and completion for series")
+            .long("episodes")
+            .action(ArgAction::SetTrue),
+        Arg::new("incomplete")
+            .help("Show only partially downloaded series")
+            .long("incomplete")
+            .action(ArgAction::SetTrue),
+        Arg::new("specials")
+            .help("How to count Season 0 specials in series sizes")
+            .value_name("MODE")
+            .long("specials")
+            .value_parser(["include", "exclude", "separate"])
+            .default_value("include"),
+        Arg::new("resolution")
+            .help("Show only items whose resolution contains the text")
+            .value_name("RES")
+            .long("resolution"),
+        Arg::new("video-codec")
+            .help("Show only items whose video codec contains the text")
+            .value_name("CODEC")
+            .long("video-codec"),
+        Arg::new("audio")
+            .help("Show only items whose audio contains the text")
+            .value_name("AUDIO")
+            .long("audio"),
+        Arg::new("root-folders")
+            .help("Append library and flagged bytes per root folder")
+            .long("root-folders")
+            .action(ArgAction::SetTrue),
+        Arg::new("disk-space")
+            .help("Show free and total space per disk, and what the flagged items would free")
+            .long("disk-space")
+            .action(ArgAction::SetTrue),
+        Arg::new("forecast")
+            .help("Project when each disk fills up from the library growth in scan history")
+            .long("forecast")
+            .action(ArgAction::SetTrue),
+        Arg::new("duplicates")
+            .help("List movies with multiple files or leftover data")
+            .long("duplicates")
+            .action(ArgAction::SetTrue),
+        Arg::new("cross-instance")
+            .help("List series and movies present on more than one instance")
+            .long("cross-instance")
+            .action(ArgAction::SetTrue),
+        Arg::new("trend")
+            .help("Compare each item's waste score and size with the previous scan")
+            .long("trend")
+            .action(ArgAction::SetTrue),
+        Arg::new("new-since-last")
+            .help("Show only items that weren't in the previous scan")
+            .long("new-since-last")
+            .action(ArgAction::SetTrue),
+        Arg::new("no-history")
+            .help("Don't record this run in the scan history")
+            .long("no-history")
+            .action(ArgAction::SetTrue),
+        Arg::new("no-notify")
+            .help("Don't send notifications for this run")
+            .long("no-notify")
+            .action(ArgAction::SetTrue),
+        Arg::new("concurrency")
+            .help("Maximum per-item detail requests in flight at once")
+            .value_name("N")
+            .long("concurrency")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("timeout")
+            .help("Seconds to wait for each API response")
+            .value_name("SECS")
+            .long("timeout")
+            .value_parser(parse_timeout),
+        Arg::new("scorer")
+            .help("Replace waste scores with a scorer plugin, by name or path")
+            .value_name("NAME")
+            .long("scorer"),
+        Arg::new("timing")
+            .help("Print how long each phase took and per-endpoint latencies")
+            .long("timing")
+            .action(ArgAction::SetTrue),
+        Arg::new("verbose")
+            .help("Log API requests and phase durations; repeat for more detail")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count),
+        Arg::new("wait")
+            .help("Wait for another running wastearr instead of exiting")
+            .long("wait")
+            .action(ArgAction::SetTrue),
+        Arg::new("mock")
+            .help("Read canned API responses from DIR instead of contacting the instances")
+            .value_name("DIR")
+            .long("mock")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("record")
+            .help("Save every API response of this run to a session file")
+            .value_name("FILE")
+            .long("record")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["mock", "replay"]),
+        Arg::new("replay")
+            .help("Re-run the analysis against a recorded session")
+            .value_name("FILE")
+            .long("replay")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with("mock"),
+        Arg::new("refresh-ratings")
+            .help("Re-fetch ratings cached as N/A or older than a day")
+            .long("refresh-ratings")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["no-cache", "offline"]),
+        Arg::new("offline")
+            .help("Build the report from cached libraries without contacting the instances")
+            .long("offline")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all([
+                "no-cache",
+                "mock",
+                "record",
+                "replay",
+                "stream",
+                "media-info",
+                "disk-space",
+                "forecast",
+                "duplicates",
+            ]),
+        Arg::new("skip-unreachable")
+            .help("Scan the instances that respond instead of failing when one is down")
+            .long("skip-unreachable")
+            .action(ArgAction::SetTrue),
+        Arg::new("stream")
+            .help("Score and print each item as it arrives, for very large libraries")
+            .long("stream")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all([
+                "group-by",
+                "trend",
+                "new-since-last",
+                "root-folders",
+                "disk-space",
+                "forecast",
+                "duplicates",
+                "cross-instance",
+                "media-info",
+                "fail-above-score",
+                "fail-above-size",
+            ]),
+        Arg::new("fail-above-score")
+            .help("Exit with status 3 when any item's waste score is above N")
+            .value_name("N")
+            .long("fail-above-score")
+            .value_parser(clap::value_parser!(i32)),
+        Arg::new("fail-above-size")
+            .help("Exit with status 3 when the flagged items take more than SIZE")
+            .value_name("SIZE")
+            .long("fail-above-size")
+            .value_parser(|size: &str| parse_size_string(size).map_err(|e| e.to_string())),
+    ]
+}
+
+fn build_cli() -> Command {
+    Command::new("wastearr")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Analyze Sonarr/Radarr collections with ratings and waste scores")
+        .args(analysis_args())
+        .subcommand(
+            Command::new("stats")
+                .about("Show waste score and size distributions")
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("wrapped")
+                .about("Summarize the last year of scan history")
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("List stored scans, or show the items of one")
+                .arg(
+                    Arg::new("scan")
+                        .help("Number of the stored scan to show")
+                        .value_name("SCAN"),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("note")
+                .about("Attach a note to an item, show one, or list them all")
+                .arg(
+                    Arg::new("item")
+                        .help("Sonarr/Radarr ID or title of the item")
+                        .value_name("ITEM"),
+                )
+                .arg(
+                    Arg::new("text")
+                        .help("Note to attach; empty to remove it")
+                        .value_name("TEXT"),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspect the rating and library cache")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("stats")
+                        .about("Show cache size, entries, age and the last run's hit rate")
+                        .args(analysis_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Keep running and rescan on an interval")
+                .arg(
+                    Arg::new("interval")
+                        .help("Time between scans, e.g. 6h")
+                        .value_name("DURATION")
+                        .long("interval")
+                        .value_parser(parse_interval),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Serve a web dashboard of the report, rescanning on an interval")
+                .arg(
+                    Arg::new("listen")
+                        .help("Address to serve the dashboard on")
+                        .long("listen")
+                        .value_name("ADDR"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .help("Time between scans, e.g. 6h")
+                        .value_name("DURATION")
+                        .long("interval")
+                        .value_parser(parse_interval),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("tui")
+                .about("Explore the scored items in an interactive terminal UI")
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("state")
+                .about("Move scan history, notes and the cache between machines")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Write history, notes and the cache to a bundle")
+                        .arg(
+                            Arg::new("bundle")
+                                .help("Bundle file")
+                                .value_name("FILE")
+                                .required(true),
+                        )
+                        .args(analysis_args()),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Merge a bundle into this machine's history and cache")
+                        .arg(
+                            Arg::new("bundle")
+                                .help("Bundle file")
+                                .value_name("FILE")
+                                .required(true),
+                        )
+                        .args(analysis_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Diff two stored scans, or a stored scan and a fresh one")
+                .arg(
+                    Arg::new("from")
+                        .help("Stored scan to compare from: a scan number or latest")
+                        .value_name("FROM"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .help("Stored scan to compare to; a fresh scan if left out")
+                        .value_name("TO"),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .help("Shell to generate the script for")
+                        .value_name("SHELL")
+                        .value_parser(clap::value_parser!(Shell))
+                        .required(true),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("man")
+                .about("Print the man page")
+                .args(analysis_args()),
+        )
+}
+
+/// Write the roff man page to stdout: the options and commands come from
+/// the CLI definition, the settings from CONFIG_KEYS
+fn print_man() -> Result<()> {
+    let cli = build_cli();
+    let man = clap_mangen::Man::new(cli.clone());
+    let mut out = Vec::new();
+    man.render_title(&mut out)?;
+    man.render_name_section(&mut out)?;
+    man.render_synopsis_section(&mut out)?;
+    man.render_description_section(&mut out)?;
+    man.render_options_section(&mut out)?;
+
+    let shared: HashSet<String> = analysis_args()
+        .iter()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    let mut roff = Roff::new();
+    roff.control("SH", ["COMMANDS"]);
+    roff.text([roman(
+        "Every command also takes the options above, which filter and shape its report.",
+    )]);
+    render_man_commands(&mut roff, &cli, "wastearr", &shared);
+
+    roff.control("SH", ["CONFIGURATION"]);
+    roff.text([roman(
+        "Settings are read from the environment first, then from the first of these files \
+         that sets them: ",
+    )]);
+    roff.text([
+        italic("$XDG_CONFIG_HOME/wastearr/config.toml"),
+        roman(" with typed sections, then the KEY=value lines of "),
+        italic("$XDG_CONFIG_HOME/wastearr/config"),
+        roman(", "),
+        italic(".env"),
+        roman(" in the current directory, and "),
+        italic("/etc/wastearr/config"),
+        roman("."),
+    ]);
+    roff.text([roman(
+        "The timeout, retry, rate limit, TLS and auth settings can be set per instance by \
+         swapping WASTEARR for the instance prefix, e.g. RADARR_4K_RETRIES.",
+    )]);
+    roff.text([roman(
+        "In config.toml, instances are [[sonarr]] and [[radarr]] entries with url, api_key, \
+         an optional name and the per-instance settings; [network] holds the defaults. \
+         Notifiers are [notify.<backend>] tables with events, min_severity, dedupe, \
+         quiet_hours and digest, and [[protect]] rules (name, instance, type, genre, path, \
+         min_rating) keep the items matching all their conditions at a waste score of 0. \
+         Other keys go in sections named after them, e.g. [scoring] tv_factor.",
+    )]);
+    for (key, description) in CONFIG_KEYS {
+        roff.control("TP", []);
+        roff.text([bold(*key)]);
+        roff.text([roman(*description)]);
+    }
+
+    roff.control("SH", ["ENVIRONMENT"]);
+    roff.text([roman(
+        "Besides the settings above, which may all come from the environment:",
+    )]);
+    for (key, description) in [
+        ("RUST_LOG", "Log filter overriding -v, e.g. wastearr=debug"),
+        (
+            "PAGER",
+            "Pager for long reports on a terminal (default: less -R)",
+        ),
+        (
+            "XDG_CONFIG_HOME",
+            "Base of the config file and the scorer plugins directory",
+        ),
+        ("XDG_CACHE_HOME", "Base of the default cache directory"),
+        ("XDG_STATE_HOME", "Base of the default state directory"),
+        (
+            "NOTIFY_SOCKET / WATCHDOG_USEC",
+            "Set by systemd for watch and serve to report readiness and feed the watchdog",
+        ),
+    ] {
+        roff.control("TP", []);
+        roff.text([bold(key)]);
+        roff.text([roman(description)]);
+    }
+    roff.to_writer(&mut out)?;
+    man.render_version_section(&mut out)?;
+
+    io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// List each runnable command under `path` with the arguments of its own
+fn render_man_commands(roff: &mut Roff, command: &Command, path: &str, shared: &HashSet<String>) {
+    for sub in command.get_subcommands() {
+        let path = format!("{} {}", path, sub.get_name());
+        if sub.has_subcommands() {
+            render_man_commands(roff, sub, &path, shared);
+            continue;
+        }
+        let own: Vec<&Arg> = sub
+            .get_arguments()
+            .filter(|arg| !shared.contains(arg.get_id().as_str()))
+            .collect();
+        let mut usage = vec![bold(path)];
+        for arg in &own {
+            let name = arg
+                .get_value_names()
+                .and_then(|names| names.first())
+                .map_or_else(
+                    || arg.get_id().to_string().to_uppercase(),
+                    |name| name.to_string(),
+                );
+            let shown = match arg.get_long() {
+                Some(long) => format!("--{} {}", long, name),
+                None => name,
+            };
+            usage.push(roman(" "));
+            usage.push(italic(if arg.is_required_set() {
+                shown
+            } else {
+                format!("[{}]", shown)
+            }));
+        }
+        roff.control("TP", []);
+        roff.text(usage);
+        if let Some(about) = sub.get_about() {
+            roff.text([roman(about.to_string())]);
+        }
+        for arg in own {
+            if let Some(help) = arg.get_help() {
+                roff.text([line_break(), roman(format!("{}: {}", arg.get_id(), help))]);
+            }
+        }
+    }
+}
+
+/// Write the completion script for `shell` to stdout. Instance and scorer plugin
+/// names are looked up now and baked into the script, so it needs regenerating
+/// after adding one.
+fn print_completions(shell: Shell) {
+    let mut targets = vec![
+        "all".to_string(),
+        "sonarr".to_string(),
+        "radarr".to_string(),
+    ];
+    if let Ok(config) = load_config() {
+        for instance in config.instances {
+            if !targets.contains(&instance.name) {
+                targets.push(instance.name);
+            }
+        }
+    }
+    let values = [
+        ("clear-cache", targets),
+        ("scorer", ScorerPlugin::available()),
+    ];
+    let mut cli = with_completion_values(build_cli(), &values);
+    clap_complete::generate(shell, &mut cli, "wastearr", &mut io::stdout());
+}
+
+/// Offer `values` for the matching args of `command` and all its subcommands
+fn with_completion_values(mut command: Command, values: &[(&str, Vec<String>)]) -> Command {
+    for (id, names) in values {
+        if !names.is_empty() && command.get_arguments().any(|arg| arg.get_id() == *id) {
+            let names = names.clone();
+            command = command.mut_arg(*id, |arg| {
+                arg.value_parser(PossibleValuesParser::new(names))
+            });
+        }
+    }
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    for name in subcommands {
+        command = command.mut_subcommand(name, |sub| with_completion_values(sub, values));
+    }
+    command
+}
+
+fn parse_args() -> Args {
+    let matches = build_cli().get_matches();
+    let (command, matches) = match matches.subcommand() {
+        // Nested commands are named by their path, e.g. "cache stats"
+        Some((group @ ("cache" | "state"), group_matches)) => match group_matches.subcommand() {
+            Some((name, sub_matches)) => (Some(format!("{} {}", group, name)), sub_matches.clone()),
+            None => unreachable!("{} requires a subcommand", group),
+        },
+        Some((name, sub_matches)) => (Some(name.to_string()), sub_matches.clone()),
+        None => (None, matches),
+    };
+
+    // Only some subcommands define these
+    let scan_ref = |id: &str| matches.try_get_one::<String>(id).ok().flatten().cloned();
+    Args {
+        command,
+        compare_from: scan_ref("from"),
+        compare_to: scan_ref("to"),
+        scan: scan_ref("scan"),
+        note_item: scan_ref("item"),
+        note_text: scan_ref("text"),
+        bundle: scan_ref("bundle"),
+        shell: matches
+            .try_get_one::<Shell>("shell")
+            .ok()
+            .flatten()
+            .copied(),
+        interval: matches
+            .try_get_one::<Duration>("interval")
+            .ok()
+            .flatten()
+            .copied(),
+        listen: scan_ref("listen"),
+        item_type: matches.get_one::<String>("item_type").cloned(),
+        top_waste: matches.get_one::<usize>("top-waste").copied(),
+        waste_score: matches.get_one::<i32>("waste-score").copied(),
+        min_size: matches.get_one::<String>("min-size").cloned(),
+        ratings: matches.get_one::<f64>("ratings").copied(),
+        clear_cache: matches.get_one::<String>("clear-cache").cloned(),
+        // Fixture data must not end up in the real cache or scan history
+        no_cache: matches.get_flag("no-cache")
+            || matches.contains_id("mock")
+            || matches.contains_id("replay"),
+        table_style: matches
+            .get_one::<String>("table-style")
+            .cloned()
+            .unwrap_or_else(|| "rounded".to_string()),
+        plain: matches.get_flag("plain"),
+        no_pager: matches.get_flag("no-pager"),
+        quiet: matches.get_flag("quiet"),
+        full_names: matches.get_flag("full-names"),
+        group_by: matches.get_one::<String>("group-by").cloned(),
+        output: matches
+            .get_one::<String>("output")
+            .cloned()
+            .unwrap_or_else(|| "table".to_string()),
+        // Filtering on media details needs them fetched first
+        media_info: matches.get_flag("media-info")
+            || ["resolution", "video-codec", "audio"]
+                .iter()
+                .any(|id| matches.contains_id(id)),
+        episodes: matches.get_flag("episodes") || matches.get_flag("incomplete"),
+        incomplete: matches.get_flag("incomplete"),
+        specials: matches
+            .get_one::<String>("specials")
+            .cloned()
+            .unwrap_or_else(|| "include".to_string()),
+        resolution: matches.get_one::<String>("resolution").cloned(),
+        video_codec: matches.get_one::<String>("video-codec").cloned(),
+        audio: matches.get_one::<String>("audio").cloned(),
+        root_folders: matches.get_flag("root-folders"),
+        disk_space: matches.get_flag("disk-space"),
+        forecast: matches.get_flag("forecast"),
+        duplicates: matches.get_flag("duplicates"),
+        cross_instance: matches.get_flag("cross-instance"),
+        trend: matches.get_flag("trend"),
+        new_since_last: matches.get_flag("new-since-last"),
+        no_history: matches.get_flag("no-history")
+            || matches.contains_id("mock")
+            || matches.contains_id("replay")
+            || matches.get_flag("offline"),
+        no_notify: matches.get_flag("no-notify")
+            || matches.contains_id("mock")
+            || matches.contains_id("replay")
+            || matches.get_flag("offline"),
+        concurrency: matches.get_one::<usize>("concurrency").copied(),
+        timeout: matches.get_one::<f64>("timeout").copied(),
+        scorer: matches.get_one::<String>("scorer").cloned(),
+        stream: matches.get_flag("stream"),
+        timing: matches.get_flag("timing"),
+        verbose: matches.get_count("verbose"),
+        wait: matches.get_flag("wait"),
+        skip_unreachable: matches.get_flag("skip-unreachable"),
+        fail_above_score: matches.get_one::<i32>("fail-above-score").copied(),
+        fail_above_size: matches.get_one::<u64>("fail-above-size").copied(),
+        warning: matches.get_one::<u64>("warning").copied(),
+        critical: matches.get_one::<u64>("critical").copied(),
+        skip_sonarr: matches.get_flag("skip-sonarr"),
+        skip_radarr: matches.get_flag("skip-radarr"),
+        mock: matches.get_one::<PathBuf>("mock").cloned(),
+        record: matches.get_one::<PathBuf>("record").cloned(),
+        replay: matches.get_one::<PathBuf>("replay").cloned(),
+        offline: matches.get_flag("offline"),
+        refresh_ratings: matches.get_flag("refresh-ratings"),
+    }
+}
+
+fn item_matches(item: &Item, args: &Args, min_size_bytes: Option<u64>) -> bool {
+    let media_matches = |filter: &Option<String>, field: fn(&MediaInfo) -> &str| {
+        filter.as_ref().is_none_or(|wanted| {
+            item.media_info
+                .as_ref()
+                .is_some_and(|media| field(media).to_lowercase().contains(&wanted.to_lowercase()))
+        })
+    };
+
+    (!args.new_since_last || matches!(item.trend, Some(Trend::New)))
+        && (!args.incomplete
+            || item
+                .episodes
+                .as_ref()
+                .is_some_and(EpisodeCounts::is_incomplete))
+        && media_matches(&args.resolution, |m| &m.resolution)
+        && media_matches(&args.video_codec, |m| &m.video_codec)
+        && media_matches(&args.audio, |m| &m.audio)
+        && args.waste_score.is_none_or(|min| item.waste_score >= min)
+        && min_size_bytes.is_none_or(|min| item.size_bytes >= min)
+        && args.ratings.is_none_or(|max| {
+            item.rating == "N/A" || item.rating.parse::<f64>().unwrap_or(0.0) <= max
+        })
+}
+
+fn filter_items(items: &mut Vec<Item>, args: &Args, min_size_bytes: Option<u64>) -> Vec<String> {
+    items.retain(|item| item_matches(item, args, min_size_bytes));
+
+    items.sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+
+    let mut filters = Vec::new();
+    if let Some(score) = args.waste_score {
+        filters.push(format!("Waste Score >= {}", score));
+    }
+    if let Some(size) = min_size_bytes {
+        filters.push(format!("Size >= {}", format_file_size(size)));
+    }
+    if let Some(rating) = args.ratings {
+        filters.push(format!("Rating <= {}", rating));
+    }
+    if args.new_since_last {
+        filters.push("New Since Last Scan".to_string());
+    }
+    if args.incomplete {
+        filters.push("Incomplete Series".to_string());
+    }
+    if let Some(resolution) = &args.resolution {
+        filters.push(format!("Resolution ~ {}", resolution));
+    }
+    if let Some(codec) = &args.video_codec {
+        filters.push(format!("Video Codec ~ {}", codec));
+    }
+    if let Some(audio) = &args.audio {
+        filters.push(format!("Audio ~ {}", audio));
+    }
+
+    if let Some(top_n) = args.top_waste {
+        items.truncate(top_n);
+        if filters.is_empty() {
+            filters.push(format!("Top {} Highest Waste Scores", top_n));
+        }
+    }
+
+    filters
+}
+
+/// Print the report of the items that pass the filters; `library` itself is
+/// left whole for the threshold checks and notifications that follow
+fn print_results(
+    library: &[Item],
+    requested_types: &[String],
+    args: &Args,
+    min_size_bytes: Option<u64>,
+    extras: &ReportExtras,
+) {
+    let library_by_root = args.root_folders.then(|| root_folder_totals(library));
+    let mut items = library.to_vec();
+    let filters = filter_items(&mut items, args, min_size_bytes);
+    let items = &items;
+
+    let groups = args
+        .group_by
+        .as_deref()
+        .map(|group_by| group_items(items, group_by));
+
+    if args.output == "json" {
+        let report = serde_json::json!({
+            "scan": extras.metrics,
+            "filters": filters,
+            "items": items,
+            "groups": groups.as_ref().map(|groups| groups
+                .iter()
+                .map(|group| serde_json::json!({
+                    "key": group.key,
+                    "count": group.count,
+                    "size_bytes": group.size_bytes,
+                    "average_rating": group.average_rating(),
+                    "average_waste": group.average_waste(),
+                }))
+                .collect::<Vec<_>>()),
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).unwrap_or_default()
+        );
+        return;
+    }
+
+    if args.plain {
+        let rows = match &groups {
+            Some(groups) => format_plain_groups(groups),
+            None => format_plain_rows(items),
+        };
+        if !rows.is_empty() {
+            println!("{}", rows);
+        }
+        return;
+    }
+
+    let mut output = offline_banner(&extras.metrics);
+    if !filters.is_empty() {
+        let prefix = if requested_types.len() == 1 {
+            match requested_types[0].as_str() {
+                "sonarr" => "Series",
+                "radarr" => "Movies",
+                _ => "Items",
+            }
+        } else {
+            "Items"
+        };
+        let _ = writeln!(output, "{} with {}", prefix, filters.join(", "));
+        let _ = writeln!(output, "{}", "=".repeat(60));
+    }
+
+    let table = match (&groups, args.group_by.as_deref()) {
+        (Some(groups), Some(group_by)) => format_group_table(groups, group_by, &args.table_style),
+        _ => format_unified_table(
+            items,
+            &TableOptions {
+                style: &args.table_style,
+                show_trend_column: args.trend,
+                show_type_column: requested_types.len() > 1,
+                show_media_columns: args.media_info,
+                show_episode_columns: args.episodes,
+                show_note_column: items.iter().any(|item| item.note.is_some()),
+                max_width: if args.full_names {
+                    None
+                } else {
+                    terminal_width()
+                },
+                links: io::stdout().is_terminal(),
+            },
+        ),
+    };
+    let _ = writeln!(output, "{}", table);
+
+    if requested_types.len() > 1 {
+        let (tv, movies) = items.iter().fold((0, 0), |acc, item| {
+            if item.item_type == "show" {
+                (acc.0 + 1, acc.1)
+            } else {
+                (acc.0, acc.1 + 1)
+            }
+        });
+        let _ = writeln!(
+            output,
+            "\nTotal items: {} ({} series, {} movies)",
+            items.len(),
+            tv,
+            movies
+        );
+        if tv > 0 && movies > 0 {
+            let _ = writeln!(
+                output,
+                "{}",
+                format_type_breakdown(items, &args.table_style)
+            );
+        }
+    } else {
+        let item_type = match requested_types[0].as_str() {
+            "sonarr" => "series",
+            "radarr" => "movies",
+            _ => &requested_types[0],
+        };
+        let _ = writeln!(output, "\nTotal {} shown: {}", item_type, items.len());
+    }
+    let _ = writeln!(output, "{}", format_severity_summary(items));
+    if !args.quiet {
+        let _ = writeln!(output, "{}", format_scan_metrics(&extras.metrics));
+    }
+
+    if !extras.extra_files.is_empty() {
+        let _ = writeln!(
+            output,
+            "\nMovies with extra files\n{}",
+            format_extra_files_table(&extras.extra_files, &args.table_style)
+        );
+    } else if args.duplicates {
+        let _ = writeln!(output, "\nNo movies with extra files found");
+    }
+
+    if !extras.cross_instance.is_empty() {
+        let _ = writeln!(
+            output,
+            "\nItems on multiple instances\n{}",
+            format_cross_instance_table(&extras.cross_instance, &args.table_style)
+        );
+    } else if args.cross_instance {
+        let _ = writeln!(output, "\nNo items found on more than one instance");
+    }
+
+    let disks = &extras.disks;
+    if args.disk_space && !disks.is_empty() {
+        let _ = writeln!(
+            output,
+            "\nDisk space\n{}",
+            format_disk_space_table(disks, library, &args.table_style)
+        );
+        if let Some(projection) = disk_space_projection(disks, library) {
+            let _ = writeln!(output, "{}", projection);
+        }
+    }
+
+    if let Some(forecast) = &extras.forecast {
+        let _ = writeln!(
+            output,
+            "\n{}",
+            format_growth_forecast(forecast, disks, library, &args.table_style)
+        );
+    }
+
+    if let Some(library_by_root) = &library_by_root {
+        let _ = writeln!(
+            output,
+            "\nDisk usage by root folder\n{}",
+            format_root_folder_table(
+                library_by_root,
+                &root_folder_totals(
+                    library
+                        .iter()
+                        .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+                ),
+                &args.table_style
+            )
+        );
+    }
+
+    page_output(&output, args.no_pager);
+}
+
+fn render_histogram(buckets: &[(String, usize)]) -> String {
+    const PARTIAL_BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+    let max_count = buckets.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let label_width = buckets
+        .iter()
+        .map(|(label, _)| display_width(label))
+        .max()
+        .unwrap_or(0);
+
+    buckets
+        .iter()
+        .map(|(label, count)| {
+            // Bar length in eighths of a cell so small buckets stay visible
+            let eighths = (count * HISTOGRAM_WIDTH * 8)
+                .checked_div(max_count)
+                .unwrap_or(0);
+            let mut bar = "█".repeat(eighths / 8);
+            if eighths % 8 > 0 {
+                bar.push(PARTIAL_BLOCKS[eighths % 8]);
+            }
+            format!(
+                "{}{} │{} {}",
+                label,
+                " ".repeat(label_width - display_width(label)),
+                bar,
+                count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn waste_histogram(items: &[Item]) -> Vec<(String, usize)> {
+    (0..10)
+        .map(|bucket| {
+            let low = bucket * 10;
+            let high = if bucket == 9 { 100 } else { low + 9 };
+            let count = items
+                .iter()
+                .filter(|item| item.waste_score >= low && item.waste_score <= high)
+                .count();
+            (format!("{}-{}", low, high), count)
+        })
+        .collect()
+}
+
+fn size_histogram(items: &[Item]) -> Vec<(String, usize)> {
+    let gb = 1024_u64.pow(3);
+    let bounds = [0, 1, 5, 10, 25, 50, 100];
+    bounds
+        .iter()
+        .enumerate()
+        .map(|(i, &low)| {
+            let high = bounds.get(i + 1).copied();
+            let count = items
+                .iter()
+                .filter(|item| {
+                    item.size_bytes >= low * gb && high.is_none_or(|h| item.size_bytes < h * gb)
+                })
+                .count();
+            let label = match high {
+                Some(h) => format!("{}-{} GB", low, h),
+                None => format!("{}+ GB", low),
+            };
+            (label, count)
+        })
+        .collect()
+}
+
+fn print_stats(
+    library: &[Item],
+    requested_types: &[String],
+    args: &Args,
+    min_size_bytes: Option<u64>,
+    metrics: &ScanMetrics,
+) {
+    let mut items = library.to_vec();
+    let filters = filter_items(&mut items, args, min_size_bytes);
+    let items = &items;
+
+    let mut output = offline_banner(metrics);
+    if !filters.is_empty() {
+        let _ = writeln!(output, "Statistics for items with {}", filters.join(", "));
+        let _ = writeln!(output, "{}", "=".repeat(60));
+    }
+
+    let waste_scores: Vec<f64> = items.iter().map(|i| i.waste_score as f64).collect();
+    let sizes: Vec<f64> = items.iter().map(|i| i.size_bytes as f64).collect();
+    let total_size: u64 = items.iter().map(|i| i.size_bytes).sum();
+    let scope = match requested_types {
+        [single] if single == "sonarr" => "series",
+        [single] if single == "radarr" => "movies",
+        _ => "items",
+    };
+
+    let _ = writeln!(output, "Total {}: {}", scope, items.len());
+    let _ = writeln!(output, "Total size: {}", format_file_size(total_size));
+    if !items.is_empty() {
+        let _ = writeln!(
+            output,
+            "Median size: {}",
+            format_file_size(median(sizes) as u64)
+        );
+        let _ = writeln!(
+            output,
+            "Waste score: {:.1} mean, {:.1} median",
+            waste_scores.iter().sum::<f64>() / waste_scores.len() as f64,
+            median(waste_scores)
+        );
+    }
+
+    let _ = writeln!(output, "{}", format_severity_summary(items));
+
+    let _ = writeln!(
+        output,
+        "\nWaste score distribution\n{}",
+        render_histogram(&waste_histogram(items))
+    );
+    let _ = writeln!(
+        output,
+        "\nSize distribution\n{}",
+        render_histogram(&size_histogram(items))
+    );
+    if !args.quiet {
+        let _ = writeln!(output, "{}", format_scan_metrics(metrics));
+    }
+
+    page_output(&output, args.no_pager);
+}
+
+fn page_output(output: &str, no_pager: bool) {
+    let fits_screen = terminal_size()
+        .filter(|(_, Height(rows))| *rows > 0)
+        .is_none_or(|(_, Height(rows))| output.lines().count() < rows as usize);
+    if no_pager || fits_screen || !io::stdout().is_terminal() {
+        print!("{}", output);
+        return;
+    }
+
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut parts = pager.split_whitespace();
+    let Some(program) = parts.next() else {
+        print!("{}", output);
+        return;
+    };
+
+    match process::Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The user may quit the pager early, closing the pipe
+                let _ = stdin.write_all(output.as_bytes());
+            }
+            let _ = child.wait();
+        }
+        Err(_) => print!("{}", output),
+    }
+}
+
+/// Log lines as the CLI has always printed them: info as plain text and
+/// warnings prefixed, with time, level and module only on debug and trace
+struct LogFormat;
+
+impl<S, N> tracing_subscriber::fmt::FormatEvent<S, N> for LogFormat
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    N: for<'a> tracing_subscriber::fmt::FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &tracing_subscriber::fmt::FmtContext<'_, S, N>,
+        mut writer: tracing_subscriber::fmt::format::Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        match *metadata.level() {
+            tracing::Level::ERROR => write!(writer, "Error: ")?,
+            tracing::Level::WARN => write!(writer, "Warning: ")?,
+            tracing::Level::INFO if metadata.target().starts_with("wastearr") => {}
+            level => write!(
+                writer,
+                "{} {:>5} {}: ",
+                chrono::Local::now().format("%H:%M:%S%.3f"),
+                level,
+                metadata.target()
+            )?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Log to stderr at info, or debug/trace with -v/-vv; RUST_LOG overrides both
+fn init_logging(verbose: u8, quiet: bool) {
+    let default = match verbose {
+        _ if quiet => "error",
+        0 => "warn,wastearr=info",
+        1 => "warn,wastearr=debug",
+        _ => "info,wastearr=trace",
+    };
+    let (filter, invalid) = match env::var("RUST_LOG") {
+        Ok(directives) if !directives.trim().is_empty() => {
+            match tracing_subscriber::EnvFilter::try_new(&directives) {
+                Ok(filter) => (filter, None),
+                Err(e) => (tracing_subscriber::EnvFilter::new(default), Some(e)),
+            }
+        }
+        _ => (tracing_subscriber::EnvFilter::new(default), None),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        .event_format(LogFormat)
+        .with_writer(|| {
+            if LOG_MUTED.load(Ordering::Relaxed) {
+                EitherWriter::B(io::sink())
+            } else {
+                EitherWriter::A(LogStderr)
+            }
+        })
+        .init();
+    if let Some(e) = invalid {
+        warn!("ignoring invalid RUST_LOG: {}", e);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = parse_args();
+    init_logging(args.verbose, args.quiet);
+    BARS_ENABLED.store(!args.quiet && io::stderr().is_terminal(), Ordering::Relaxed);
+    if args.quiet {
+        args.no_pager = true;
+    }
+    if args.stream && args.command.is_some() {
+        anyhow::bail!("--stream only applies to the default report");
+    }
+    if args.stream && args.output == "nagios" {
+        anyhow::bail!("--stream can't be combined with --output nagios");
+    }
+    if args.command.as_deref() == Some("man") {
+        return print_man();
+    }
+    if let (Some("completions"), Some(shell)) = (args.command.as_deref(), args.shell) {
+        print_completions(shell);
+        return Ok(());
+    }
+    check_toml_config()?;
+    if args.command.as_deref() == Some("wrapped") {
+        print_wrapped(&args);
+        return Ok(());
+    }
+    if args.command.as_deref() == Some("history") {
+        return print_history(&args);
+    }
+    if args.command.as_deref() == Some("note") {
+        return run_note(&args);
+    }
+    match (args.command.as_deref(), &args.bundle) {
+        (Some("state export"), Some(bundle)) => return export_state(Path::new(bundle)),
+        (Some("state import"), Some(bundle)) => return import_state(Path::new(bundle)),
+        _ => {}
+    }
+    if args.command.as_deref() == Some("cache stats") {
+        return print_cache_stats(&args);
+    }
+    if args.command.as_deref() == Some("watch") {
+        // Nobody is there to page through the reports
+        args.no_pager = true;
+        return run_watch(&args).await;
+    }
+    if args.command.as_deref() == Some("serve") {
+        return run_serve(&args).await;
+    }
+    if args.command.as_deref() == Some("tui") {
+        return run_tui(&args).await;
+    }
+    let run = match run_monitored_scan(&args).await {
+        Ok(run) => run,
+        Err(e) if args.output == "nagios" => {
+            println!("WASTEARR UNKNOWN - {:#}", e);
+            process::exit(3);
+        }
+        Err(e) => return Err(e),
+    };
+    if args.output == "nagios" {
+        let (state, line) = nagios_check(&run, &args);
+        println!("{}", line);
+        process::exit(state as i32);
+    }
+    let breaches = threshold_breaches(&run.items, &args);
+    for breach in breaches.iter().filter(|_| !args.quiet) {
+        eprintln!("Threshold exceeded: {}", breach);
+    }
+    if !breaches.is_empty() {
+        process::exit(THRESHOLD_EXIT_CODE);
+    }
+    if run.partial {
+        process::exit(PARTIAL_SCAN_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// Nagios/Icinga plugin output: the state from the flagged size against
+/// --warning/--critical, and perfdata for the totals
+fn nagios_check(run: &ScanRun, args: &Args) -> (usize, String) {
+    let totals = summarize_instance(
+        "",
+        run.items
+            .iter()
+            .map(|item| (item.size_bytes, item.waste_score)),
+    );
+    let flagged_bytes: u64 = run
+        .items
+        .iter()
+        .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+        .map(|item| item.size_bytes)
+        .sum();
+    let mut summary = format!(
+        "{} flagged items take {}",
+        totals.flagged,
+        format_file_size(flagged_bytes)
+    );
+    let state = match (args.critical, args.warning) {
+        (Some(critical), _) if flagged_bytes > critical => {
+            let _ = write!(summary, " (critical above {})", format_file_size(critical));
+            2
+        }
+        (_, Some(warning)) if flagged_bytes > warning => {
+            let _ = write!(summary, " (warning above {})", format_file_size(warning));
+            1
+        }
+        _ => 0,
+    };
+    // A partial scan can't vouch for the instances it left out
+    let state = if run.partial {
+        let skipped: Vec<&str> = run
+            .metrics
+            .skipped
+            .iter()
+            .map(|skipped| skipped.instance.as_str())
+            .collect();
+        let _ = write!(summary, ", skipped {}", skipped.join(", "));
+        state.max(1)
+    } else {
+        state
+    };
+    let threshold = |limit: Option<u64>| limit.map(|l| l.to_string()).unwrap_or_default();
+    let line = format!(
+        "WASTEARR {} - {} | waste={}B;{};{};0 total={}B;;;0 items={};;;0 flagged={};;;0 average_waste={:.1};;;0;100",
+        NAGIOS_STATES[state],
+        summary,
+        flagged_bytes,
+        threshold(args.warning),
+        threshold(args.critical),
+        totals.total_bytes,
+        totals.items,
+        totals.flagged,
+        totals.average_waste
+    );
+    (state, line)
+}
+
+/// How the scan breaks the --fail-above-score and --fail-above-size thresholds
+fn threshold_breaches(items: &[Item], args: &Args) -> Vec<String> {
+    let mut breaches = Vec::new();
+    if let Some(limit) = args.fail_above_score {
+        let above = items.iter().filter(|item| item.waste_score > limit).count();
+        if above > 0 {
+            breaches.push(format!("{} items score above {}", above, limit));
+        }
+    }
+    if let Some(limit) = args.fail_above_size {
+        let flagged: u64 = items
+            .iter()
+            .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+            .map(|item| item.size_bytes)
+            .sum();
+        if flagged > limit {
+            breaches.push(format!(
+                "flagged items take {}, above {}",
+                format_file_size(flagged),
+                format_file_size(limit)
+            ));
+        }
+    }
+    breaches
+}
+
+/// Rescan every `--interval`, timed from the start of each scan, and run the
+/// cron-scheduled actions from the config. A failed run is reported and
+/// retried on its next turn. Stops cleanly on SIGTERM or Ctrl-C.
+async fn run_watch(args: &Args) -> Result<()> {
+    let shutdown = shutdown_token();
+    tokio::select! {
+        result = watch_loop(args) => result,
+        _ = shutdown.cancelled() => Ok(()),
+    }
+}
+
+async fn watch_loop(args: &Args) -> Result<()> {
+    let (mut jobs, mut interval) = load_watch_schedules(args)?;
+    let mut next_scan = interval.map(|_| current_timestamp());
+    let fixture = args.mock.is_some() || args.replay.is_some() || args.offline;
+    let mut low_space = if fixture {
+        None
+    } else {
+        load_low_space_watch()?
+    };
+    let mut next_space_check = low_space.as_ref().map(|_| current_timestamp());
+    // Attached to low-space alerts, so there's something to delete right away
+    let mut latest_items: Vec<Item> = Vec::new();
+    let mut next_runs = next_job_runs(&jobs);
+    let mut config_watch = ConfigWatch::new();
+    let mut next_config_check = current_timestamp();
+    let mut next_flush = current_timestamp() + HELD_NOTICE_POLL_INTERVAL.as_secs_f64();
+    notify_ready();
+
+    loop {
+        let wake = next_runs
+            .iter()
+            .flatten()
+            .chain(&next_scan)
+            .chain(&next_space_check)
+            .copied()
+            .min_by(f64::total_cmp)
+            .context("No scheduled runs left")?
+            .min(next_config_check)
+            .min(next_flush);
+        tokio::time::sleep(Duration::from_secs_f64(
+            (wake - current_timestamp()).max(0.0),
+        ))
+        .await;
+
+        if next_config_check <= current_timestamp() {
+            next_config_check = current_timestamp() + CONFIG_POLL_INTERVAL.as_secs_f64();
+            let changes = config_watch.changes();
+            if !changes.is_empty() {
+                progress!("Config changed: {}", changes.join(", "));
+                // A broken edit keeps the old setup running until it's fixed
+                if config_changed(&changes, "WASTEARR_SCHEDULE_") {
+                    match load_watch_schedules(args) {
+                        Ok((new_jobs, new_interval)) => {
+                            if new_interval.is_none() {
+                                next_scan = None;
+                            } else if interval != new_interval {
+                                next_scan = Some(current_timestamp());
+                            }
+                            (jobs, interval) = (new_jobs, new_interval);
+                            next_runs = next_job_runs(&jobs);
+                        }
+                        Err(e) => warn!("keeping the old schedules: {:#}", e),
+                    }
+                }
+                if !fixture && config_changed(&changes, "WASTEARR_LOW_SPACE") {
+                    match load_low_space_watch() {
+                        Ok(Some(mut watch)) => {
+                            if let Some(old) = low_space.take() {
+                                watch.low = old.low;
+                            }
+                            next_space_check = Some(current_timestamp());
+                            low_space = Some(watch);
+                        }
+                        Ok(None) => {
+                            low_space = None;
+                            next_space_check = None;
+                        }
+                        Err(e) => {
+                            warn!("keeping the old disk space alerts: {:#}", e)
+                        }
+                    }
+                }
+            }
+        }
+
+        if let (Some(interval), Some(at)) = (interval, next_scan) {
+            if at <= current_timestamp() {
+                let started = current_timestamp();
+                if let Some(items) = run_watch_action("scan", args).await {
+                    latest_items = items;
+                }
+                let next = started + interval.as_secs_f64();
+                progress!("Next scan at {}", format_local_time(next));
+                sd_notify(&format!("STATUS=Next scan at {}", format_local_time(next)));
+                next_scan = Some(next);
+            }
+        }
+        for (job, next) in jobs.iter().zip(&mut next_runs) {
+            if next.is_some_and(|at| at <= current_timestamp()) {
+                if let Some(items) = run_watch_action(job.action, args).await {
+                    latest_items = items;
+                }
+                // Runs missed while another one was busy are skipped, not queued
+                *next = next_run(&job.schedule, current_timestamp());
+                if let Some(next) = next {
+                    progress!("Next {} at {}", job.action, format_local_time(*next));
+                }
+            }
+        }
+        if let (Some(watch), Some(at)) = (&mut low_space, next_space_check) {
+            if at <= current_timestamp() {
+                check_low_space(watch, &latest_items, args).await;
+                next_space_check = Some(current_timestamp() + watch.interval.as_secs_f64());
+            }
+        }
+        if next_flush <= current_timestamp() {
+            if !args.no_notify {
+                flush_held_notifications().await;
+            }
+            next_flush = current_timestamp() + HELD_NOTICE_POLL_INTERVAL.as_secs_f64();
+        }
+    }
+}
+
+/// The cron jobs for `watch`, and the scan interval unless a scan schedule
+/// replaces it
+fn load_watch_schedules(args: &Args) -> Result<(Vec<ScheduledJob>, Option<Duration>)> {
+    let mut jobs = load_schedules()?;
+    // --interval beats a scan schedule from the config
+    let interval = if args.interval.is_some() || !jobs.iter().any(|job| job.action == "scan") {
+        jobs.retain(|job| job.action != "scan");
+        Some(args.interval.unwrap_or(DEFAULT_WATCH_INTERVAL))
+    } else {
+        None
+    };
+    Ok((jobs, interval))
+}
+
+fn next_job_runs(jobs: &[ScheduledJob]) -> Vec<Option<f64>> {
+    let next_runs: Vec<Option<f64>> = jobs
+        .iter()
+        .map(|job| next_run(&job.schedule, current_timestamp()))
+        .collect();
+    for (job, next) in jobs.iter().zip(&next_runs) {
+        if let Some(next) = next {
+            progress!("Next {} at {}", job.action, format_local_time(*next));
+        }
+    }
+    next_runs
+}
+
+/// Cancelled on SIGTERM or Ctrl-C, so `watch` and `serve` can stop between
+/// steps instead of being killed halfway through one
+fn shutdown_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            let mut terminate =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                    .expect("Failed to listen for SIGTERM");
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        progress!("Shutting down");
+        sd_notify("STOPPING=1");
+        cancel.cancel();
+    });
+    token
+}
+
+/// Tell systemd the service is up, when it runs as a Type=notify unit, and
+/// keep its watchdog fed when WatchdogSec= is set
+fn notify_ready() {
+    sd_notify("READY=1");
+    let Some(usec) = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|&usec| usec > 0)
+    else {
+        return;
+    };
+    // The watchdog may be meant for another process of the unit
+    if env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid != process::id())
+    {
+        return;
+    }
+    // Half the timeout, as sd_watchdog_enabled(3) recommends
+    let period = Duration::from_micros(usec / 2);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(period);
+        loop {
+            ticks.tick().await;
+            sd_notify("WATCHDOG=1");
+        }
+    });
+}
+
+/// Send a state change to systemd over $NOTIFY_SOCKET; a no-op when not
+/// started by systemd
+fn sd_notify(state: &str) {
+    trace!("sd_notify {}", state);
+    #[cfg(unix)]
+    if let Some(socket) = env::var_os("NOTIFY_SOCKET") {
+        use std::os::unix::net::UnixDatagram;
+        let send = || -> io::Result<usize> {
+            let datagram = UnixDatagram::unbound()?;
+            // A leading '@' names a socket in the abstract namespace
+            #[cfg(target_os = "linux")]
+            if let Some(name) = socket.as_encoded_bytes().strip_prefix(b"@") {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                return datagram.send_to_addr(state.as_bytes(), &address);
+            }
+            datagram.send_to(state.as_bytes(), &socket)
+        };
+        if let Err(e) = send() {
+            warn!("failed to notify systemd: {}", e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// Items of the latest scan a watch action ran, if it scanned
+async fn run_watch_action(action: &str, args: &Args) -> Option<Vec<Item>> {
+    let started = current_timestamp();
+    progress!("Running {} at {}", action, format_local_time(started));
+    let result = match action {
+        "wrapped" => {
+            print_wrapped(args);
+            return None;
+        }
+        "stats" => {
+            let args = Args {
+                command: Some("stats".to_string()),
+                ..args.clone()
+            };
+            run_monitored_scan(&args).await
+        }
+        _ => run_monitored_scan(args).await,
+    };
+    match result {
+        Ok(run) => {
+            if let Err(e) = write_report_files(&run, started) {
+                warn!("failed to write the report files: {:#}", e);
+            }
+            Some(run.items)
+        }
+        Err(e) => {
+            warn!("{} failed: {:#}", action, e);
+            None
+        }
+    }
+}
+
+const DEFAULT_REPORT_KEEP: usize = 30;
+const REPORT_FORMATS: [&str; 2] = ["html", "json"];
+
+/// Save a `watch` report to WASTEARR_REPORT_DIR as report-DATE.html and
+/// report-DATE.json, point latest.html and latest.json at them, and delete
+/// all but the newest WASTEARR_REPORT_KEEP of each
+fn write_report_files(run: &ScanRun, scanned_at: f64) -> Result<()> {
+    let Some(dir) = get_config_value("WASTEARR_REPORT_DIR") else {
+        return Ok(());
+    };
+    let dir = PathBuf::from(dir);
+    let keep = match get_config_value("WASTEARR_REPORT_KEEP") {
+        Some(keep) => keep
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&keep| keep > 0)
+            .context("Invalid WASTEARR_REPORT_KEEP, expected a positive number")?,
+        None => DEFAULT_REPORT_KEEP,
+    };
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let date = chrono::DateTime::from_timestamp(scanned_at as i64, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d")
+                .to_string()
+        })
+        .unwrap_or_default();
+    for format in REPORT_FORMATS {
+        let contents = if format == "html" {
+            format_report_html(&run.items, &run.metrics, scanned_at)
+        } else {
+            serde_json::to_string_pretty(&serde_json::json!({
+                "scanned_at": scanned_at,
+                "scan": run.metrics,
+                "items": run.items,
+            }))?
+        };
+        // Written aside and renamed, so a web server never serves half a report
+        let name = format!("report-{}.{}", date, format);
+        let partial = dir.join(format!(".{}.tmp", name));
+        fs::write(&partial, contents)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, dir.join(&name))?;
+        link_latest_report(&dir, &name, format)?;
+
+        let mut reports: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| {
+                        name.starts_with("report-") && name.ends_with(&format!(".{}", format))
+                    })
+            })
+            .collect();
+        // Dated names sort oldest first
+        reports.sort();
+        for old in &reports[..reports.len().saturating_sub(keep)] {
+            fs::remove_file(old).with_context(|| format!("Failed to remove {}", old.display()))?;
+        }
+    }
+    progress!("Saved report-{}.html and .json to {}", date, dir.display());
+    Ok(())
+}
+
+/// Point latest.html (or .json) at the newest report: a relative symlink,
+/// swapped in atomically, or a copy where there are no symlinks
+fn link_latest_report(dir: &Path, name: &str, format: &str) -> Result<()> {
+    let latest = dir.join(format!("latest.{}", format));
+    #[cfg(unix)]
+    {
+        let partial = dir.join(format!(".latest.{}.tmp", format));
+        let _ = fs::remove_file(&partial);
+        std::os::unix::fs::symlink(name, &partial)
+            .with_context(|| format!("Failed to link {}", latest.display()))?;
+        fs::rename(&partial, &latest)?;
+    }
+    #[cfg(not(unix))]
+    fs::copy(dir.join(name), &latest)
+        .with_context(|| format!("Failed to write {}", latest.display()))?;
+    Ok(())
+}
+
+/// A standalone HTML page of a report: the totals, the severity bands and
+/// the items by waste score
+fn format_report_html(items: &[Item], metrics: &ScanMetrics, scanned_at: f64) -> String {
+    let totals = summarize_instance(
+        "",
+        items.iter().map(|item| (item.size_bytes, item.waste_score)),
+    );
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>wastearr report {0}</title></head>\n<body style=\"font-family: sans-serif\">\n<h2>wastearr report {0}</h2>\n<ul>\n",
+        xml_escape(&format_local_time(scanned_at))
+    );
+    let _ = writeln!(
+        html,
+        "<li>Library: {} items, {}</li>\n<li>Average waste: {:.1}</li>",
+        totals.items,
+        format_file_size(totals.total_bytes),
+        totals.average_waste
+    );
+    for (i, (label, low)) in SEVERITY_BANDS.iter().enumerate().rev() {
+        let high = SEVERITY_BANDS.get(i + 1).map_or(100, |(_, next)| *next);
+        let (count, size) = items
+            .iter()
+            .filter(|item| severity_of(item.waste_score) == *label)
+            .fold((0, 0u64), |acc, item| (acc.0 + 1, acc.1 + item.size_bytes));
+        let _ = writeln!(
+            html,
+            "<li>{} ({}-{}): {} items, {}</li>",
+            label,
+            low,
+            high,
+            count,
+            format_file_size(size)
+        );
+    }
+    if !metrics.skipped.is_empty() {
+        let skipped: Vec<&str> = metrics
+            .skipped
+            .iter()
+            .map(|skipped| skipped.instance.as_str())
+            .collect();
+        let _ = writeln!(
+            html,
+            "<li>Skipped: {}</li>",
+            xml_escape(&skipped.join(", "))
+        );
+    }
+    html.push_str("</ul>\n<table cellpadding=\"4\" style=\"border-collapse: collapse\">\n<tr><th>#</th><th align=\"left\">Item</th><th>Type</th><th>Instance</th><th>Size</th><th>Score</th><th>Severity</th><th align=\"left\">Note</th></tr>\n");
+    let mut sorted: Vec<&Item> = items.iter().collect();
+    sorted.sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+    for (rank, item) in sorted.iter().enumerate() {
+        let name = xml_escape(&format!("{} ({})", item.name, item.year));
+        let name = match &item.url {
+            Some(url) => format!("<a href=\"{}\">{}</a>", xml_escape(url), name),
+            None => name,
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td align=\"right\">{}</td><td align=\"right\">{}</td><td>{}</td><td>{}</td></tr>",
+            rank + 1,
+            name,
+            item.item_type,
+            xml_escape(&item.instance),
+            format_file_size(item.size_bytes),
+            item.waste_score,
+            severity_of(item.waste_score),
+            xml_escape(item.note.as_deref().unwrap_or_default())
+        );
+    }
+    html.push_str("</table>\n</body></html>\n");
+    html
+}
+
+const DEFAULT_LOW_SPACE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Free space below which a disk counts as low: an absolute size or a share
+/// of the disk
+#[derive(Debug, Clone, Copy)]
+enum SpaceThreshold {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl SpaceThreshold {
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Some(percent) = value.strip_suffix('%') {
+            match percent.trim().parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(Self::Percent(p)),
+                _ => anyhow::bail!("'{}' is not a percentage between 0% and 100%", value),
+            }
+        } else {
+            Ok(Self::Bytes(parse_size_string(value)?))
+        }
+    }
+
+    fn is_low(&self, disk: &DiskSpace) -> bool {
+        match *self {
+            Self::Bytes(bytes) => disk.free_bytes < bytes,
+            Self::Percent(percent) => percent_free(disk.free_bytes, disk.total_bytes) < percent,
+        }
+    }
+}
+
+impl std::fmt::Display for SpaceThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Bytes(bytes) => write!(f, "{}", format_file_size(*bytes)),
+            Self::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Disk space polling for `watch`, from WASTEARR_LOW_SPACE
+#[derive(Debug)]
+struct LowSpaceWatch {
+    threshold: SpaceThreshold,
+    // Paths from WASTEARR_LOW_SPACE_DISKS; every disk the instances report otherwise
+    disks: Option<Vec<String>>,
+    interval: Duration,
+    // (host, path) of the disks already alerted on, so each drop alerts once
+    low: HashSet<(String, String)>,
+}
+
+fn load_low_space_watch() -> Result<Option<LowSpaceWatch>> {
+    let Some(threshold) = get_config_value("WASTEARR_LOW_SPACE") else {
+        return Ok(None);
+    };
+    let threshold = SpaceThreshold::parse(&threshold).context("Invalid WASTEARR_LOW_SPACE")?;
+    let disks = get_config_value("WASTEARR_LOW_SPACE_DISKS").map(|list| {
+        list.split(',')
+            .map(|path| path.trim().trim_end_matches('/').to_string())
+            .filter(|path| !path.is_empty())
+            .collect()
+    });
+    let interval = match get_config_value("WASTEARR_LOW_SPACE_INTERVAL") {
+        Some(value) => parse_interval(&value)
+            .map_err(anyhow::Error::msg)
+            .context("Invalid WASTEARR_LOW_SPACE_INTERVAL")?,
+        None => DEFAULT_LOW_SPACE_INTERVAL,
+    };
+    progress!(
+        "Alerting when free disk space drops below {}, checked every {}",
+        threshold,
+        format_age(interval.as_secs_f64())
+    );
+    Ok(Some(LowSpaceWatch {
+        threshold,
+        disks,
+        interval,
+        low: HashSet::new(),
+    }))
+}
+
+/// Poll the instances' disk space and alert about disks that dropped below
+/// the threshold since the last check, with the top waste of the latest scan
+async fn check_low_space(watch: &mut LowSpaceWatch, items: &[Item], args: &Args) {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("disk space check failed: {:#}", e);
+            return;
+        }
+    };
+    let scan_types = ["sonarr".to_string(), "radarr".to_string()];
+    let disks: Vec<DiskSpace> = fetch_disk_space(&config, &scan_types)
+        .await
+        .into_iter()
+        .filter(|disk| {
+            watch.disks.as_ref().is_none_or(|paths| {
+                paths
+                    .iter()
+                    .any(|path| disk.path.trim_end_matches('/') == path)
+            })
+        })
+        .collect();
+    let mut newly_low = Vec::new();
+    for disk in disks {
+        let key = (disk.host.clone(), disk.path.clone());
+        if watch.threshold.is_low(&disk) {
+            if watch.low.insert(key) {
+                warn!(
+                    "low disk space on {}: {} free of {}",
+                    disk_name(&disk),
+                    format_file_size(disk.free_bytes),
+                    format_file_size(disk.total_bytes)
+                );
+                newly_low.push(disk);
+            }
+        } else if watch.low.remove(&key) {
+            progress!(
+                "Disk space on {} recovered: {} free",
+                disk_name(&disk),
+                format_file_size(disk.free_bytes)
+            );
+        }
+    }
+    if newly_low.is_empty() || args.no_notify {
+        return;
+    }
+    match load_notifiers() {
+        Ok(notifiers) => {
+            send_notifications(&notifiers, &build_low_space_notice(items, newly_low)).await
+        }
+        Err(e) => warn!("failed to send low disk space alert: {:#}", e),
+    }
+}
+
+/// Run a scan with the monitoring around it: pings to WASTEARR_HEALTHCHECK_URL
+/// as it starts and when it succeeds or fails, so a scheduled job that breaks
+/// or stops running gets noticed, and the OTLP export of its spans and metrics
+async fn run_monitored_scan(args: &Args) -> Result<ScanRun> {
+    let fixture = args.mock.is_some() || args.replay.is_some();
+    let healthcheck = get_config_value("WASTEARR_HEALTHCHECK_URL")
+        .filter(|_| !fixture && !args.offline)
+        .map(|url| url.trim_end_matches('/').to_string());
+    if let Some(url) = &healthcheck {
+        ping_healthcheck(&format!("{}/start", url), String::new()).await;
+    }
+    let started_at = current_timestamp();
+    let result = run_scan(args).await;
+    if let Some(url) = &healthcheck {
+        report_healthcheck(url, &result).await;
+    }
+    if let Some(otlp) = load_otlp_settings().filter(|_| !fixture) {
+        export_otlp(&otlp, started_at, &result).await;
+    }
+    result
+}
+
+async fn report_healthcheck(url: &str, result: &Result<ScanRun>) {
+    match result {
+        Ok(run) if !run.partial => {
+            ping_healthcheck(url, format!("Scanned {} items", run.items.len())).await
+        }
+        Ok(run) => {
+            let skipped: Vec<&str> = run
+                .metrics
+                .skipped
+                .iter()
+                .map(|skipped| skipped.instance.as_str())
+                .collect();
+            ping_healthcheck(
+                &format!("{}/fail", url),
+                format!("Partial scan, skipped: {}", skipped.join(", ")),
+            )
+            .await
+        }
+        Err(e) => ping_healthcheck(&format!("{}/fail", url), format!("{:#}", e)).await,
+    }
+}
+
+// Large libraries make one request per item; past this, requests aren't traced
+const OTLP_MAX_REQUEST_SPANS: usize = 1000;
+
+/// Where to send scan telemetry, from the standard OTEL_* settings
+#[derive(Debug)]
+struct OtlpSettings {
+    traces_url: String,
+    metrics_url: String,
+    headers: HeaderMap,
+    service_name: String,
+}
+
+fn load_otlp_settings() -> Option<OtlpSettings> {
+    let endpoint = get_config_value("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .map(|url| url.trim_end_matches('/').to_string());
+    let signal_url = |signal: &str| {
+        get_config_value(&format!(
+            "OTEL_EXPORTER_OTLP_{}_ENDPOINT",
+            signal.to_uppercase()
+        ))
+        .or_else(|| {
+            endpoint
+                .as_ref()
+                .map(|url| format!("{}/v1/{}", url, signal))
+        })
+    };
+    let (traces_url, metrics_url) = (signal_url("traces"), signal_url("metrics"));
+    if traces_url.is_none() && metrics_url.is_none() {
+        return None;
+    }
+    let mut headers = HeaderMap::new();
+    for pair in get_config_value("OTEL_EXPORTER_OTLP_HEADERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+    {
+        let parsed = pair.split_once('=').and_then(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                HeaderValue::from_str(value.trim()).ok()?,
+            ))
+        });
+        match parsed {
+            Some((name, value)) => {
+                headers.insert(name, value);
+            }
+            None => warn!(
+                "ignoring invalid OTEL_EXPORTER_OTLP_HEADERS entry '{}'",
+                pair.trim()
+            ),
+        }
+    }
+    Some(OtlpSettings {
+        // An empty URL turns that signal off
+        traces_url: traces_url.unwrap_or_default(),
+        metrics_url: metrics_url.unwrap_or_default(),
+        headers,
+        service_name: get_config_value("OTEL_SERVICE_NAME")
+            .unwrap_or_else(|| "wastearr".to_string()),
+    })
+}
+
+/// Send the scan's spans and metrics to the collector as OTLP/HTTP JSON
+async fn export_otlp(otlp: &OtlpSettings, started_at: f64, result: &Result<ScanRun>) {
+    let resource = serde_json::json!({
+        "attributes": [otlp_attribute("service.name", otlp.service_name.as_str())],
+    });
+    let scope = serde_json::json!({ "name": "wastearr", "version": env!("CARGO_PKG_VERSION") });
+    let ended_at = current_timestamp();
+    let mut exports = Vec::new();
+    if !otlp.traces_url.is_empty() {
+        let spans = otlp_spans(started_at, ended_at, result);
+        exports.push((
+            "traces",
+            &otlp.traces_url,
+            serde_json::json!({ "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{ "scope": scope, "spans": spans }],
+            }]}),
+        ));
+    }
+    if !otlp.metrics_url.is_empty() {
+        let metrics = otlp_metrics(started_at, ended_at, result);
+        exports.push((
+            "metrics",
+            &otlp.metrics_url,
+            serde_json::json!({ "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{ "scope": scope, "metrics": metrics }],
+            }]}),
+        ));
+    }
+    let client = match Client::builder().timeout(NOTIFY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("failed to set up OTLP export: {:#}", e);
+            return;
+        }
+    };
+    for (signal, url, payload) in exports {
+        let result = client
+            .post(url.as_str())
+            .headers(otlp.headers.clone())
+            .json(&payload)
+            .send()
+            .await
+            .and_then(Response::error_for_status);
+        if let Err(e) = result {
+            warn!("OTLP {} export failed: {:#}", signal, e);
+        }
+    }
+}
+
+fn otlp_spans(started_at: f64, ended_at: f64, result: &Result<ScanRun>) -> Vec<serde_json::Value> {
+    let trace_id = format!("{:016x}{:016x}", otlp_id(), otlp_id());
+    let root_id = format!("{:016x}", otlp_id());
+    let span = |id: &str, parent: &str, name: &str, start: f64, end: f64| {
+        serde_json::json!({
+            "traceId": trace_id,
+            "spanId": id,
+            "parentSpanId": parent,
+            "name": name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": otlp_nanos(start),
+            "endTimeUnixNano": otlp_nanos(end),
+        })
+    };
+    let mut root = span(&root_id, "", "scan", started_at, ended_at);
+    match result {
+        Ok(run) => {
+            root["attributes"] = serde_json::json!([
+                otlp_attribute("wastearr.items", run.items.len()),
+                otlp_attribute("wastearr.partial", run.partial),
+            ]);
+            // STATUS_CODE_OK
+            root["status"] = serde_json::json!({ "code": 1 });
+        }
+        Err(e) => {
+            // STATUS_CODE_ERROR
+            root["status"] = serde_json::json!({ "code": 2, "message": format!("{:#}", e) });
+        }
+    }
+    let mut spans = vec![root];
+    if let Ok(run) = result {
+        let mut start = started_at;
+        for (phase, duration) in &run.phases {
+            let end = start + duration.as_secs_f64();
+            spans.push(span(
+                &format!("{:016x}", otlp_id()),
+                &root_id,
+                phase,
+                start,
+                end,
+            ));
+            start = end;
+        }
+    }
+    let requests = REQUEST_LOG.lock().unwrap();
+    for sample in requests.iter().take(OTLP_MAX_REQUEST_SPANS) {
+        let mut request = span(
+            &format!("{:016x}", otlp_id()),
+            &root_id,
+            &format!("GET {}", sample.endpoint),
+            sample.started_at,
+            sample.started_at + sample.duration.as_secs_f64(),
+        );
+        // SPAN_KIND_CLIENT
+        request["kind"] = 3.into();
+        request["attributes"] = serde_json::json!([
+            otlp_attribute("wastearr.instance", sample.instance.as_str()),
+            otlp_attribute("url.path", sample.endpoint.as_str()),
+        ]);
+        if !sample.ok {
+            request["status"] = serde_json::json!({ "code": 2 });
+        }
+        spans.push(request);
+    }
+    spans
+}
+
+fn otlp_metrics(
+    started_at: f64,
+    ended_at: f64,
+    result: &Result<ScanRun>,
+) -> Vec<serde_json::Value> {
+    let time = otlp_nanos(ended_at);
+    let point = |value: serde_json::Value, instance: Option<&str>| {
+        let mut point = serde_json::json!({ "timeUnixNano": time });
+        match value {
+            serde_json::Value::Number(n) if n.is_f64() => point["asDouble"] = n.into(),
+            value => point["asInt"] = value.to_string().into(),
+        }
+        if let Some(instance) = instance {
+            point["attributes"] =
+                serde_json::json!([otlp_attribute("wastearr.instance", instance)]);
+        }
+        point
+    };
+    let gauge = |name: &str, unit: &str, points: Vec<serde_json::Value>| serde_json::json!({ "name": name, "unit": unit, "gauge": { "dataPoints": points } });
+    let mut metrics = vec![
+        gauge(
+            "wastearr.scan.success",
+            "1",
+            vec![point((result.is_ok() as u64).into(), None)],
+        ),
+        gauge(
+            "wastearr.scan.duration",
+            "s",
+            vec![point((ended_at - started_at).into(), None)],
+        ),
+    ];
+    let Ok(run) = result else {
+        return metrics;
+    };
+    #[derive(Default)]
+    struct Totals {
+        items: u64,
+        bytes: u64,
+        flagged: u64,
+        flagged_bytes: u64,
+        waste: f64,
+    }
+    let mut per_instance: BTreeMap<&str, Totals> = BTreeMap::new();
+    for item in &run.items {
+        let totals = per_instance.entry(item.instance.as_str()).or_default();
+        totals.items += 1;
+        totals.bytes += item.size_bytes;
+        if item.waste_score >= FLAGGED_MIN_SCORE {
+            totals.flagged += 1;
+            totals.flagged_bytes += item.size_bytes;
+        }
+        totals.waste += item.waste_score as f64;
+    }
+    let per_instance_gauge =
+        |name: &str, unit: &str, value: &dyn Fn(&Totals) -> serde_json::Value| {
+            gauge(
+                name,
+                unit,
+                per_instance
+                    .iter()
+                    .map(|(instance, totals)| point(value(totals), Some(instance)))
+                    .collect(),
+            )
+        };
+    metrics.extend([
+        per_instance_gauge("wastearr.items", "{item}", &|t| t.items.into()),
+        per_instance_gauge("wastearr.library.size", "By", &|t| t.bytes.into()),
+        per_instance_gauge("wastearr.flagged.items", "{item}", &|t| t.flagged.into()),
+        per_instance_gauge("wastearr.flagged.size", "By", &|t| t.flagged_bytes.into()),
+        per_instance_gauge("wastearr.waste_score.average", "1", &|t| {
+            (t.waste / t.items.max(1) as f64).into()
+        }),
+        gauge(
+            "wastearr.api.requests",
+            "{request}",
+            vec![point(run.metrics.requests.into(), None)],
+        ),
+        gauge(
+            "wastearr.api.requests.failed",
+            "{request}",
+            vec![point(run.metrics.failed_requests.into(), None)],
+        ),
+        gauge(
+            "wastearr.cache.hits",
+            "{item}",
+            vec![point(run.metrics.cache_hits.into(), None)],
+        ),
+        gauge(
+            "wastearr.cache.misses",
+            "{item}",
+            vec![point(run.metrics.cache_misses.into(), None)],
+        ),
+    ]);
+    metrics
+}
+
+fn otlp_attribute(key: &str, value: impl Into<serde_json::Value>) -> serde_json::Value {
+    let value = match value.into() {
+        serde_json::Value::Bool(b) => serde_json::json!({ "boolValue": b }),
+        serde_json::Value::Number(n) if n.is_f64() => serde_json::json!({ "doubleValue": n }),
+        serde_json::Value::Number(n) => serde_json::json!({ "intValue": n.to_string() }),
+        value => serde_json::json!({ "stringValue": value.as_str().unwrap_or_default() }),
+    };
+    serde_json::json!({ "key": key, "value": value })
+}
+
+fn otlp_nanos(timestamp: f64) -> String {
+    ((timestamp * 1e9) as u64).to_string()
+}
+
+/// Random span and trace ID halves, from std's randomly keyed hasher
+fn otlp_id() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+async fn ping_healthcheck(url: &str, body: String) {
+    let result = match Client::builder().timeout(NOTIFY_TIMEOUT).build() {
+        Ok(client) => client
+            .post(url)
+            .body(body)
+            .send()
+            .await
+            .and_then(Response::error_for_status),
+        Err(e) => Err(e),
+    };
+    // The ping URL is the secret, so keep it out of the warning
+    if let Err(e) = result {
+        warn!("healthcheck ping failed: {:#}", e.without_url());
+    }
+}
+
+// Notification events: every finished scan, only scans that flag new items,
+// or disks dropping below WASTEARR_LOW_SPACE in `watch`
+const NOTIFY_EVENTS: [&str; 3] = ["scan", "flagged", "low_space"];
+const NOTIFY_TOP_ITEMS: usize = 5;
+const LOW_SPACE_TOP_ITEMS: usize = 10;
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a finished scan reports to the notification backends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScanNotice {
+    // "flagged" when the scan flagged new items, "low_space" for disk space
+    // alerts, "scan" otherwise
+    event: String,
+    scanned_at: f64,
+    instances: Vec<String>,
+    items: usize,
+    total_bytes: u64,
+    average_waste: f64,
+    flagged: usize,
+    flagged_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    newly_flagged: Option<usize>,
+    // Flagged items gone since the previous scan, and the lifetime total
+    reclaimed: Reclaimed,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reclaimed_total: Option<Reclaimed>,
+    skipped: Vec<String>,
+    top_offenders: Vec<NoticeItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    low_space: Vec<NoticeDisk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoticeItem {
+    name: String,
+    year: i32,
+    instance: String,
+    waste_score: i32,
+    size_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoticeDisk {
+    host: String,
+    path: String,
+    label: String,
+    free_bytes: u64,
+    total_bytes: u64,
+}
+
+fn notice_item(item: &Item) -> NoticeItem {
+    NoticeItem {
+        name: item.name.clone(),
+        year: item.year,
+        instance: item.instance.clone(),
+        waste_score: item.waste_score,
+        size_bytes: item.size_bytes,
+        url: item.url.clone(),
+    }
+}
+
+fn build_scan_notice(
+    items: &[Item],
+    instances: &[String],
+    reclaimed: Reclaimed,
+    metrics: &ScanMetrics,
+) -> ScanNotice {
+    let totals = summarize_instance(
+        "",
+        items.iter().map(|item| (item.size_bytes, item.waste_score)),
+    );
+    let newly_flagged = metrics
+        .since_last_scan
+        .as_ref()
+        .and_then(|trend| trend.newly_flagged);
+    let mut offenders: Vec<&Item> = items
+        .iter()
+        .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+        .collect();
+    offenders.sort_by_key(|item| {
+        (
+            std::cmp::Reverse(item.waste_score),
+            std::cmp::Reverse(item.size_bytes),
+        )
+    });
+    ScanNotice {
+        event: if newly_flagged.unwrap_or(0) > 0 {
+            "flagged"
+        } else {
+            "scan"
+        }
+        .to_string(),
+        scanned_at: current_timestamp(),
+        instances: instances.to_vec(),
+        items: totals.items,
+        total_bytes: totals.total_bytes,
+        average_waste: totals.average_waste,
+        flagged: totals.flagged,
+        flagged_bytes: offenders.iter().map(|item| item.size_bytes).sum(),
+        newly_flagged,
+        reclaimed,
+        reclaimed_total: metrics.reclaimed,
+        skipped: metrics
+            .skipped
+            .iter()
+            .map(|skipped| skipped.instance.clone())
+            .collect(),
+        top_offenders: offenders
+            .into_iter()
+            .take(NOTIFY_TOP_ITEMS)
+            .map(notice_item)
+            .collect(),
+        low_space: Vec::new(),
+    }
+}
+
+/// A low disk space alert, with the library totals and the biggest waste of
+/// the latest scan as the candidates for freeing space
+fn build_low_space_notice(items: &[Item], disks: Vec<DiskSpace>) -> ScanNotice {
+    let totals = summarize_instance(
+        "",
+        items.iter().map(|item| (item.size_bytes, item.waste_score)),
+    );
+    let mut top: Vec<&Item> = items.iter().collect();
+    top.sort_by_key(|item| {
+        (
+            std::cmp::Reverse(item.waste_score),
+            std::cmp::Reverse(item.size_bytes),
+        )
+    });
+    let mut instances: Vec<String> = Vec::new();
+    for disk in &disks {
+        for instance in &disk.instances {
+            if !instances.contains(instance) {
+                instances.push(instance.clone());
+            }
+        }
+    }
+    ScanNotice {
+        event: "low_space".to_string(),
+        scanned_at: current_timestamp(),
+        instances,
+        items: totals.items,
+        total_bytes: totals.total_bytes,
+        average_waste: totals.average_waste,
+        flagged: totals.flagged,
+        flagged_bytes: items
+            .iter()
+            .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+            .map(|item| item.size_bytes)
+            .sum(),
+        newly_flagged: None,
+        reclaimed: Reclaimed::default(),
+        reclaimed_total: None,
+        skipped: Vec::new(),
+        top_offenders: top
+            .into_iter()
+            .take(LOW_SPACE_TOP_ITEMS)
+            .map(notice_item)
+            .collect(),
+        low_space: disks
+            .into_iter()
+            .map(|disk| NoticeDisk {
+                host: disk.host,
+                path: disk.path,
+                label: disk.label,
+                free_bytes: disk.free_bytes,
+                total_bytes: disk.total_bytes,
+            })
+            .collect(),
+    }
+}
+
+fn format_notice_title(notice: &ScanNotice) -> String {
+    if let Some(disk) = notice.low_space.first() {
+        return match notice.low_space.len() {
+            1 => format!("wastearr: low disk space on {}", disk.path),
+            n => format!("wastearr: low disk space on {} disks", n),
+        };
+    }
+    match notice.newly_flagged {
+        Some(1) => "wastearr: 1 newly flagged item".to_string(),
+        Some(n) if n > 1 => format!("wastearr: {} newly flagged items", n),
+        _ => format!("wastearr: scanned {} items", notice.items),
+    }
+}
+
+/// One line per disk of a low disk space alert
+fn format_low_space_lines(notice: &ScanNotice) -> Vec<String> {
+    notice
+        .low_space
+        .iter()
+        .map(|disk| {
+            format!(
+                "Low space on {}{}: {} free of {} ({:.1}%)",
+                disk.path,
+                if disk.label.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", disk.label)
+                },
+                format_file_size(disk.free_bytes),
+                format_file_size(disk.total_bytes),
+                percent_free(disk.free_bytes, disk.total_bytes)
+            )
+        })
+        .collect()
+}
+
+/// Plain-text summary lines shared by the text-based backends
+fn format_notice_lines(notice: &ScanNotice) -> Vec<String> {
+    let mut lines = format_low_space_lines(notice);
+    lines.extend([
+        format!(
+            "Library: {} items, {}",
+            notice.items,
+            format_file_size(notice.total_bytes)
+        ),
+        format!(
+            "Flagged: {} items, {} (average waste {:.1})",
+            notice.flagged,
+            format_file_size(notice.flagged_bytes),
+            notice.average_waste
+        ),
+    ]);
+    if notice.reclaimed.items > 0 {
+        lines.push(format!(
+            "Freed since the last scan: {} across {} items",
+            format_file_size(notice.reclaimed.bytes),
+            notice.reclaimed.items
+        ));
+    }
+    if !notice.skipped.is_empty() {
+        lines.push(format!("Skipped: {}", notice.skipped.join(", ")));
+    }
+    lines
+}
+
+/// Summary lines followed by the numbered top offenders
+fn format_notice_text(notice: &ScanNotice) -> String {
+    let mut text = format_notice_lines(notice).join("\n");
+    if !notice.top_offenders.is_empty() {
+        text.push_str("\n\nTop offenders:");
+        for (rank, item) in notice.top_offenders.iter().enumerate() {
+            let _ = write!(text, "\n{}. {}", rank + 1, format_notice_item(item));
+        }
+    }
+    text
+}
+
+/// Fill `{placeholder}`s in a user-supplied notification template, passing
+/// each value through `escape` for the format the template is written in.
+/// `{json}` takes `json` as-is. Filled-in values are never expanded again,
+/// so an item or instance name with braces in it comes out untouched.
+fn render_notice_template(
+    template: &str,
+    notice: &ScanNotice,
+    json: Option<&str>,
+    escape: impl Fn(&str) -> String,
+) -> String {
+    let value = |name: &str| {
+        let value = match name {
+            "json" => return json.map(str::to_string),
+            "title" => format_notice_title(notice),
+            "text" => format_notice_text(notice),
+            "event" => notice.event.clone(),
+            "items" => notice.items.to_string(),
+            "total_size" => format_file_size(notice.total_bytes),
+            "flagged" => notice.flagged.to_string(),
+            "flagged_size" => format_file_size(notice.flagged_bytes),
+            "newly_flagged" => notice.newly_flagged.unwrap_or_default().to_string(),
+            "average_waste" => format!("{:.1}", notice.average_waste),
+            "freed" => format_file_size(notice.reclaimed.bytes),
+            "instances" => notice.instances.join(", "),
+            _ => return None,
+        };
+        Some(escape(&value))
+    };
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = rest[1..].find('}').map(|end| &rest[1..=end]);
+        match name.and_then(|name| Some((name, value(name)?))) {
+            Some((name, value)) => {
+                text.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            // Not a placeholder, so keep the brace as written
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn format_notice_item(item: &NoticeItem) -> String {
+    format!(
+        "{} ({}) - score {}, {} on {}",
+        item.name,
+        item.year,
+        item.waste_score,
+        format_file_size(item.size_bytes),
+        item.instance
+    )
+}
+
+#[derive(Debug)]
+enum NotifyTarget {
+    Discord {
+        webhook: String,
+    },
+    Slack {
+        webhook: String,
+    },
+    Telegram {
+        api_url: String,
+        token: String,
+        chat_id: String,
+    },
+    Ntfy {
+        server: String,
+        topic: String,
+        token: Option<String>,
+        priority: u8,
+        title: Option<String>,
+    },
+    Gotify {
+        url: String,
+        token: String,
+        priority: u8,
+    },
+    Webhook {
+        url: String,
+        headers: HeaderMap,
+        template: Option<String>,
+    },
+    Apprise {
+        url: String,
+        // Service URLs for a stateless call; None when `url` names a stored config key
+        urls: Option<String>,
+        tag: Option<String>,
+    },
+    Email {
+        smtp: SmtpSettings,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+        markdown: bool,
+    },
+}
+
+#[derive(Debug)]
+struct SmtpSettings {
+    host: String,
+    port: Option<u16>,
+    // "starttls", "tls" (implicit TLS) or "none"
+    tls: String,
+    credentials: Option<(String, String)>,
+}
+
+#[derive(Debug)]
+struct Notifier {
+    name: &'static str,
+    route: NotifyRoute,
+    target: NotifyTarget,
+}
+
+/// Which notices a backend gets, from its WASTEARR_<NAME>_EVENTS and
+/// WASTEARR_<NAME>_MIN_SEVERITY, and when, from the settings that hold them back
+#[derive(Debug, Clone)]
+struct NotifyRoute {
+    events: Vec<String>,
+    min_score: Option<i32>,
+    // Skip notices whose candidates are the same as the last one sent
+    dedupe: bool,
+    // (start, end) in minutes past local midnight; may wrap past midnight
+    quiet_hours: Option<(u32, u32)>,
+    // Hold notices and send them batched on this schedule
+    digest: Option<cron::Schedule>,
+}
+
+impl NotifyRoute {
+    /// Whether notices for this backend go through the state in history.db
+    fn is_stateful(&self) -> bool {
+        self.dedupe || self.quiet_hours.is_some() || self.digest.is_some()
+    }
+
+    /// When a notice held at `now` goes out: the next digest time, if any,
+    /// and then the end of quiet hours if that falls inside them
+    fn release_time(&self, now: f64) -> f64 {
+        let at = self
+            .digest
+            .as_ref()
+            .and_then(|digest| next_run(digest, now))
+            .unwrap_or(now);
+        self.quiet_hours
+            .and_then(|window| quiet_until(window, at))
+            .unwrap_or(at)
+    }
+}
+
+impl Notifier {
+    /// Identifies the backend's held notices; webhooks can have several URLs
+    fn key(&self) -> String {
+        match &self.target {
+            NotifyTarget::Webhook { url, .. } => format!("webhook {}", url),
+            _ => self.name.to_string(),
+        }
+    }
+
+    /// The notice as this backend gets it: None when it doesn't take the
+    /// event, or when no top offender reaches its minimum severity. Low disk
+    /// space alerts keep all their items, since any of them frees space
+    fn route(&self, notice: &ScanNotice) -> Option<ScanNotice> {
+        let wanted =
+            self.route.events.iter().any(|event| {
+                *event == notice.event || (event == "scan" && notice.event == "flagged")
+            });
+        if !wanted {
+            return None;
+        }
+        let mut notice = notice.clone();
+        if let Some(min_score) = self.route.min_score {
+            if notice.low_space.is_empty() {
+                notice
+                    .top_offenders
+                    .retain(|item| item.waste_score >= min_score);
+                if notice.top_offenders.is_empty() {
+                    return None;
+                }
+            }
+        }
+        Some(notice)
+    }
+}
+
+/// Notification backends from the config, each with its WASTEARR_<NAME>_EVENTS
+fn load_notifiers() -> Result<Vec<Notifier>> {
+    let mut notifiers = Vec::new();
+    if let Some(webhook) = get_config_value("WASTEARR_DISCORD_WEBHOOK") {
+        notifiers.push(Notifier {
+            name: "discord",
+            route: notify_route("DISCORD")?,
+            target: NotifyTarget::Discord { webhook },
+        });
+    }
+    if let Some(webhook) = get_config_value("WASTEARR_SLACK_WEBHOOK") {
+        notifiers.push(Notifier {
+            name: "slack",
+            route: notify_route("SLACK")?,
+            target: NotifyTarget::Slack { webhook },
+        });
+    }
+    match (
+        get_config_value("WASTEARR_TELEGRAM_TOKEN"),
+        get_config_value("WASTEARR_TELEGRAM_CHAT_ID"),
+    ) {
+        (Some(token), Some(chat_id)) => notifiers.push(Notifier {
+            name: "telegram",
+            route: notify_route("TELEGRAM")?,
+            target: NotifyTarget::Telegram {
+                // A self-hosted Bot API server can stand in for api.telegram.org
+                api_url: get_config_value("WASTEARR_TELEGRAM_API_URL")
+                    .map(|url| url.trim_end_matches('/').to_string())
+                    .unwrap_or_else(|| "https://api.telegram.org".to_string()),
+                token,
+                chat_id,
+            },
+        }),
+        (None, None) => {}
+        _ => anyhow::bail!(
+            "WASTEARR_TELEGRAM_TOKEN and WASTEARR_TELEGRAM_CHAT_ID must be set together"
+        ),
+    }
+    if let Some(url) = get_config_value("WASTEARR_NTFY_URL") {
+        let url = url.trim_end_matches('/');
+        let Some((server, topic)) = url.rsplit_once('/').filter(|(server, topic)| {
+            server.contains("://") && !server.ends_with('/') && !topic.is_empty()
+        }) else {
+            anyhow::bail!("WASTEARR_NTFY_URL must be a topic URL such as https://ntfy.sh/my-topic");
+        };
+        notifiers.push(Notifier {
+            name: "ntfy",
+            route: notify_route("NTFY")?,
+            target: NotifyTarget::Ntfy {
+                server: server.to_string(),
+                topic: topic.to_string(),
+                token: get_config_value("WASTEARR_NTFY_TOKEN"),
+                priority: get_config_value("WASTEARR_NTFY_PRIORITY")
+                    .map(|priority| parse_ntfy_priority(&priority))
+                    .transpose()?
+                    .unwrap_or(3),
+                title: get_config_value("WASTEARR_NTFY_TITLE"),
+            },
+        });
+    }
+    match (
+        get_config_value("WASTEARR_GOTIFY_URL"),
+        get_config_value("WASTEARR_GOTIFY_TOKEN"),
+    ) {
+        (Some(url), Some(token)) => notifiers.push(Notifier {
+            name: "gotify",
+            route: notify_route("GOTIFY")?,
+            target: NotifyTarget::Gotify {
+                url: format!("{}/message", url.trim_end_matches('/')),
+                token,
+                priority: get_config_value("WASTEARR_GOTIFY_PRIORITY")
+                    .map(|priority| {
+                        priority
+                            .trim()
+                            .parse::<u8>()
+                            .ok()
+                            .filter(|priority| *priority <= 10)
+                            .with_context(|| {
+                                format!(
+                                    "Invalid WASTEARR_GOTIFY_PRIORITY '{}', expected 0-10",
+                                    priority
+                                )
+                            })
+                    })
+                    .transpose()?
+                    .unwrap_or(5),
+            },
+        }),
+        (None, None) => {}
+        _ => anyhow::bail!("WASTEARR_GOTIFY_URL and WASTEARR_GOTIFY_TOKEN must be set together"),
+    }
+    if let Some(host) = get_config_value("WASTEARR_SMTP_HOST") {
+        let parse_mailbox = |key: &str, address: &str| {
+            address
+                .trim()
+                .parse::<Mailbox>()
+                .with_context(|| format!("Invalid address '{}' in {}", address.trim(), key))
+        };
+        let from = get_config_value("WASTEARR_EMAIL_FROM")
+            .context("WASTEARR_EMAIL_FROM is required for email notifications")?;
+        let to = get_config_value("WASTEARR_EMAIL_TO")
+            .context("WASTEARR_EMAIL_TO is required for email notifications")?;
+        let tls = get_config_value("WASTEARR_SMTP_TLS")
+            .map(|tls| tls.trim().to_lowercase())
+            .unwrap_or_else(|| "starttls".to_string());
+        if !matches!(tls.as_str(), "starttls" | "tls" | "none") {
+            anyhow::bail!(
+                "Invalid WASTEARR_SMTP_TLS '{}', expected starttls, tls or none",
+                tls
+            );
+        }
+        let credentials = match (
+            get_config_value("WASTEARR_SMTP_USERNAME"),
+            get_config_value("WASTEARR_SMTP_PASSWORD"),
+        ) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => anyhow::bail!(
+                "WASTEARR_SMTP_USERNAME and WASTEARR_SMTP_PASSWORD must be set together"
+            ),
+        };
+        let format = get_config_value("WASTEARR_EMAIL_FORMAT").map(|f| f.trim().to_lowercase());
+        let markdown = match format.as_deref() {
+            None | Some("html") => false,
+            Some("markdown") => true,
+            Some(other) => anyhow::bail!(
+                "Invalid WASTEARR_EMAIL_FORMAT '{}', expected html or markdown",
+                other
+            ),
+        };
+        notifiers.push(Notifier {
+            name: "email",
+            route: notify_route("EMAIL")?,
+            target: NotifyTarget::Email {
+                smtp: SmtpSettings {
+                    host,
+                    port: get_config_value("WASTEARR_SMTP_PORT")
+                        .map(|port| {
+                            port.trim()
+                                .parse()
+                                .with_context(|| format!("Invalid WASTEARR_SMTP_PORT '{}'", port))
+                        })
+                        .transpose()?,
+                    tls,
+                    credentials,
+                },
+                from: parse_mailbox("WASTEARR_EMAIL_FROM", &from)?,
+                to: to
+                    .split(',')
+                    .filter(|address| !address.trim().is_empty())
+                    .map(|address| parse_mailbox("WASTEARR_EMAIL_TO", address))
+                    .collect::<Result<_>>()?,
+                markdown,
+            },
+        });
+    }
+    if let Some(url) = get_config_value("WASTEARR_APPRISE_URL") {
+        notifiers.push(Notifier {
+            name: "apprise",
+            route: notify_route("APPRISE")?,
+            target: NotifyTarget::Apprise {
+                url,
+                urls: get_config_value("WASTEARR_APPRISE_URLS"),
+                tag: get_config_value("WASTEARR_APPRISE_TAG"),
+            },
+        });
+    }
+    if let Some(urls) = get_config_value("WASTEARR_WEBHOOK_URL") {
+        let headers = get_config_value("WASTEARR_WEBHOOK_HEADERS")
+            .map(|spec| parse_extra_headers(&spec))
+            .transpose()
+            .context("Invalid WASTEARR_WEBHOOK_HEADERS")?
+            .unwrap_or_default();
+        let template = get_config_value("WASTEARR_WEBHOOK_TEMPLATE");
+        let route = notify_route("WEBHOOK")?;
+        for url in urls.split(',').map(str::trim).filter(|url| !url.is_empty()) {
+            notifiers.push(Notifier {
+                name: "webhook",
+                route: route.clone(),
+                target: NotifyTarget::Webhook {
+                    url: url.to_string(),
+                    headers: headers.clone(),
+                    template: template.clone(),
+                },
+            });
+        }
+    }
+    Ok(notifiers)
+}
+
+fn notify_route(prefix: &str) -> Result<NotifyRoute> {
+    let key = format!("WASTEARR_{}_MIN_SEVERITY", prefix);
+    let min_score = get_config_value(&key)
+        .map(|severity| {
+            SEVERITY_BANDS
+                .iter()
+                .find(|(label, _)| label.eq_ignore_ascii_case(severity.trim()))
+                .map(|&(_, min_score)| min_score)
+                .with_context(|| {
+                    format!(
+                        "Unknown severity '{}' in {}, expected one of: {}",
+                        severity,
+                        key,
+                        SEVERITY_BANDS
+                            .iter()
+                            .map(|(label, _)| label.to_lowercase())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+        })
+        .transpose()?;
+    let setting = |key: &str| {
+        get_config_value(&format!("WASTEARR_{}_{}", prefix, key))
+            .or_else(|| get_config_value(&format!("WASTEARR_NOTIFY_{}", key)))
+    };
+    let quiet_hours = setting("QUIET_HOURS")
+        .map(|hours| parse_quiet_hours(&hours))
+        .transpose()
+        .with_context(|| format!("Invalid WASTEARR_{}_QUIET_HOURS", prefix))?;
+    let digest = setting("DIGEST")
+        .map(|expr| parse_schedule(&expr))
+        .transpose()
+        .with_context(|| format!("Invalid WASTEARR_{}_DIGEST", prefix))?;
+    Ok(NotifyRoute {
+        events: notify_events(prefix)?,
+        min_score,
+        dedupe: setting("DEDUPE")
+            .is_some_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes")),
+        quiet_hours,
+        digest,
+    })
+}
+
+/// A local time window such as "22:00-07:00" or "22-7", as minutes past midnight
+fn parse_quiet_hours(value: &str) -> Result<(u32, u32)> {
+    let minutes = |time: &str| -> Option<u32> {
+        let (hour, minute) = time.trim().split_once(':').unwrap_or((time.trim(), "0"));
+        let (hour, minute) = (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?);
+        (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+    };
+    value
+        .split_once('-')
+        .and_then(|(start, end)| Some((minutes(start)?, minutes(end)?)))
+        .with_context(|| format!("'{}' is not a time range like 22:00-07:00", value))
+}
+
+/// The end of the quiet hours `at` falls in, or None outside them
+fn quiet_until((start, end): (u32, u32), at: f64) -> Option<f64> {
+    use chrono::Timelike;
+    let local = chrono::DateTime::from_timestamp(at as i64, 0)?.with_timezone(&chrono::Local);
+    let minute = local.hour() * 60 + local.minute();
+    let quiet = if start <= end {
+        (start..end).contains(&minute)
+    } else {
+        minute >= start || minute < end
+    };
+    if !quiet {
+        return None;
+    }
+    let wait = (end + 24 * 60 - minute) % (24 * 60);
+    Some((at as i64 - i64::from(local.second())) as f64 + f64::from(wait) * 60.0)
+}
+
+fn notify_events(prefix: &str) -> Result<Vec<String>> {
+    let key = format!("WASTEARR_{}_EVENTS", prefix);
+    let Some(list) = get_config_value(&key) else {
+        return Ok(NOTIFY_EVENTS
+            .iter()
+            .map(|event| event.to_string())
+            .collect());
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|event| !event.is_empty())
+        .map(|event| {
+            if NOTIFY_EVENTS.contains(&event) {
+                Ok(event.to_string())
+            } else {
+                anyhow::bail!(
+                    "Unknown event '{}' in {}, expected one of: {}",
+                    event,
+                    key,
+                    NOTIFY_EVENTS.join(", ")
+                )
+            }
+        })
+        .collect()
+}
+
+/// ntfy priorities by number (1-5) or name
+fn parse_ntfy_priority(priority: &str) -> Result<u8> {
+    Ok(match priority.trim().to_lowercase().as_str() {
+        "1" | "min" => 1,
+        "2" | "low" => 2,
+        "3" | "default" => 3,
+        "4" | "high" => 4,
+        "5" | "max" | "urgent" => 5,
+        other => anyhow::bail!(
+            "Invalid WASTEARR_NTFY_PRIORITY '{}', expected 1-5 or min, low, default, high, max",
+            other
+        ),
+    })
+}
+
+/// Send the notice to every backend subscribed to its event, or hold it for
+/// later under quiet hours or a digest schedule; failures only warn
+async fn send_notifications(notifiers: &[Notifier], notice: &ScanNotice) {
+    dispatch_notifications(notifiers, Some(notice)).await;
+}
+
+/// Send the held notices that are due, for `watch` to deliver digests and
+/// what quiet hours held back without waiting for the next scan
+async fn flush_held_notifications() {
+    match load_notifiers() {
+        Ok(notifiers) => {
+            if notifiers
+                .iter()
+                .any(|notifier| notifier.route.is_stateful())
+            {
+                dispatch_notifications(&notifiers, None).await;
+            }
+        }
+        Err(e) => warn!("failed to send held notifications: {:#}", e),
+    }
+}
+
+async fn dispatch_notifications(notifiers: &[Notifier], notice: Option<&ScanNotice>) {
+    let now = current_timestamp();
+    let store = if notifiers
+        .iter()
+        .any(|notifier| notifier.route.is_stateful())
+    {
+        // Without the store nothing can be held or compared, so send as usual
+        open_history_store().unwrap_or_else(|e| {
+            warn!("failed to open the notification state: {:#}", e);
+            None
+        })
+    } else {
+        None
+    };
+    let mut routed: Vec<(&Notifier, ScanNotice, Option<String>)> = Vec::new();
+    for notifier in notifiers {
+        let notice = notice.and_then(|notice| notifier.route(notice));
+        match store.as_ref().filter(|_| notifier.route.is_stateful()) {
+            Some(conn) => match release_notice(conn, notifier, notice, now) {
+                Ok(Some((notice, fingerprint))) => routed.push((notifier, notice, fingerprint)),
+                Ok(None) => {}
+                Err(e) => warn!("{} notification failed: {:#}", notifier.name, e),
+            },
+            None => routed.extend(notice.map(|notice| (notifier, notice, None))),
+        }
+    }
+    if routed.is_empty() {
+        return;
+    }
+    let client = match Client::builder().timeout(NOTIFY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("failed to set up notifications: {:#}", e);
+            return;
+        }
+    };
+    let results = join_all(
+        routed
+            .iter()
+            .map(|(notifier, notice, _)| send_notice(&client, notifier, notice)),
+    )
+    .await;
+    for ((notifier, _, fingerprint), result) in routed.iter().zip(results) {
+        match (result, store.as_ref(), fingerprint) {
+            (Err(e), _, _) => {
+                warn!("{} notification failed: {:#}", notifier.name, e)
+            }
+            (Ok(()), Some(conn), Some(fingerprint)) => {
+                if let Err(e) = conn.execute(
+                    "UPDATE notifications SET fingerprint = ?2 WHERE backend = ?1",
+                    params![notifier.key(), fingerprint],
+                ) {
+                    warn!("failed to save the notification state: {:#}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Add the notice to what the backend holds, and return it all as one notice
+/// when it's due, with the fingerprint to record once it's sent. Notices whose
+/// candidates match the last one sent are dropped when deduplicating
+fn release_notice(
+    conn: &Connection,
+    notifier: &Notifier,
+    notice: Option<ScanNotice>,
+    now: f64,
+) -> Result<Option<(ScanNotice, Option<String>)>> {
+    let key = notifier.key();
+    let (fingerprint, held, release_at) = conn
+        .query_row(
+            "SELECT fingerprint, pending, release_at FROM notifications WHERE backend = ?1",
+            [&key],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<f64>>(2)?,
+                ))
+            },
+        )
+        .optional()?
+        .unwrap_or_default();
+    let held = held
+        .map(|json| serde_json::from_str::<ScanNotice>(&json))
+        .transpose()
+        .context("Unreadable held notice")?;
+    let notice = match (held, notice) {
+        (Some(held), Some(notice)) => merge_notices(held, notice),
+        (held, notice) => match held.or(notice) {
+            Some(notice) => notice,
+            None => return Ok(None),
+        },
+    };
+    // Earlier when the settings changed since the notice was held
+    let release_at = notifier
+        .route
+        .release_time(now)
+        .min(release_at.unwrap_or(f64::INFINITY));
+    if release_at > now {
+        conn.execute(
+            "INSERT INTO notifications (backend, fingerprint, pending, release_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (backend) DO UPDATE SET pending = ?3, release_at = ?4",
+            params![
+                key,
+                fingerprint,
+                serde_json::to_string(&notice)?,
+                release_at
+            ],
+        )?;
+        return Ok(None);
+    }
+    conn.execute(
+        "INSERT INTO notifications (backend, fingerprint, pending, release_at)
+         VALUES (?1, ?2, NULL, NULL)
+         ON CONFLICT (backend) DO UPDATE SET pending = NULL, release_at = NULL",
+        params![key, fingerprint],
+    )?;
+    let new_fingerprint = notifier
+        .route
+        .dedupe
+        .then(|| notice_fingerprint(&notice))
+        .flatten();
+    if new_fingerprint.is_some() && new_fingerprint == fingerprint {
+        return Ok(None);
+    }
+    Ok(Some((notice, new_fingerprint)))
+}
+
+/// Fold a held notice into a newer one: the newer totals and top offenders,
+/// with the newly flagged counts, space freed and low disks of both
+fn merge_notices(held: ScanNotice, newer: ScanNotice) -> ScanNotice {
+    let rank = |event: &str| NOTIFY_EVENTS.iter().position(|e| *e == event);
+    let mut merged = newer;
+    if rank(&held.event) > rank(&merged.event) {
+        merged.event = held.event;
+    }
+    merged.newly_flagged = match (held.newly_flagged, merged.newly_flagged) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    };
+    merged.reclaimed += held.reclaimed;
+    for disk in held.low_space {
+        if !merged
+            .low_space
+            .iter()
+            .any(|d| d.host == disk.host && d.path == disk.path)
+        {
+            merged.low_space.push(disk);
+        }
+    }
+    merged
+}
+
+/// What makes two notices the same news: the flagged totals and the top
+/// offenders, not when they were scanned. Low disk space alerts always go out
+fn notice_fingerprint(notice: &ScanNotice) -> Option<String> {
+    if !notice.low_space.is_empty() {
+        return None;
+    }
+    let candidates: Vec<(&str, &str, i32, i32, u64)> = notice
+        .top_offenders
+        .iter()
+        .map(|item| {
+            (
+                item.instance.as_str(),
+                item.name.as_str(),
+                item.year,
+                item.waste_score,
+                item.size_bytes,
+            )
+        })
+        .collect();
+    serde_json::to_string(&(notice.flagged, notice.flagged_bytes, candidates)).ok()
+}
+
+async fn send_notice(client: &Client, notifier: &Notifier, notice: &ScanNotice) -> Result<()> {
+    debug!("Sending the {} notice to {}", notice.event, notifier.name);
+    let request = match &notifier.target {
+        NotifyTarget::Email {
+            smtp,
+            from,
+            to,
+            markdown,
+        } => return send_email(smtp, from, to, *markdown, notice).await,
+        NotifyTarget::Discord { webhook } => client.post(webhook).json(&discord_payload(notice)),
+        NotifyTarget::Slack { webhook } => client.post(webhook).json(&slack_payload(notice)),
+        NotifyTarget::Telegram {
+            api_url,
+            token,
+            chat_id,
+        } => client
+            .post(format!("{}/bot{}/sendMessage", api_url, token))
+            .json(&serde_json::json!({
+                "chat_id": chat_id,
+                "text": format!("{}\n\n{}", format_notice_title(notice), format_notice_text(notice)),
+                "disable_web_page_preview": true,
+            })),
+        NotifyTarget::Ntfy {
+            server,
+            topic,
+            token,
+            priority,
+            title,
+        } => {
+            // JSON publishing keeps non-ASCII titles out of HTTP headers
+            let request = client.post(server).json(&serde_json::json!({
+                "topic": topic,
+                "title": title
+                    .as_deref()
+                    .map_or_else(|| format_notice_title(notice), |t| render_notice_template(t, notice, None, str::to_string)),
+                "message": format_notice_text(notice),
+                "priority": priority,
+                "tags": ["wastebasket"],
+            }));
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        }
+        NotifyTarget::Gotify {
+            url,
+            token,
+            priority,
+        } => client
+            .post(url)
+            .header("X-Gotify-Key", token)
+            .json(&serde_json::json!({
+                "title": format_notice_title(notice),
+                "message": format_notice_text(notice),
+                "priority": priority,
+            })),
+        NotifyTarget::Apprise { url, urls, tag } => {
+            let mut payload = serde_json::json!({
+                "title": format_notice_title(notice),
+                "body": format_notice_text(notice),
+                "type": if notice.event == "scan" { "info" } else { "warning" },
+                "format": "text",
+            });
+            if let Some(urls) = urls {
+                payload["urls"] = urls.as_str().into();
+            }
+            if let Some(tag) = tag {
+                payload["tag"] = tag.as_str().into();
+            }
+            client.post(url).json(&payload)
+        }
+        NotifyTarget::Webhook {
+            url,
+            headers,
+            template,
+        } => {
+            let request = client.post(url);
+            let request = match template {
+                Some(template) => {
+                    let content_type = headers
+                        .get(reqwest::header::CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or("application/json")
+                        .to_string();
+                    let json = serde_json::to_string(notice).unwrap_or_default();
+                    let body = if content_type.contains("json") {
+                        // Escaped so placeholders can sit inside JSON strings
+                        render_notice_template(template, notice, Some(&json), |value| {
+                            let quoted = serde_json::Value::from(value).to_string();
+                            quoted[1..quoted.len() - 1].to_string()
+                        })
+                    } else {
+                        render_notice_template(template, notice, Some(&json), str::to_string)
+                    };
+                    request
+                        .header(reqwest::header::CONTENT_TYPE, content_type)
+                        .body(body)
+                }
+                None => request.json(notice),
+            };
+            request.headers(headers.clone())
+        }
+    };
+    // Webhook and bot URLs carry secrets, so keep them out of error messages
+    request
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .map_err(reqwest::Error::without_url)?;
+    Ok(())
+}
+
+fn discord_payload(notice: &ScanNotice) -> serde_json::Value {
+    let offenders: Vec<String> = notice
+        .top_offenders
+        .iter()
+        .enumerate()
+        .map(|(rank, item)| {
+            let title = format!("{} ({})", item.name, item.year);
+            let title = match &item.url {
+                Some(url) => format!("[{}]({})", title, url),
+                None => title,
+            };
+            format!(
+                "{}. **{}** · score {} · {} · {}",
+                rank + 1,
+                title,
+                item.waste_score,
+                format_file_size(item.size_bytes),
+                item.instance
+            )
+        })
+        .collect();
+    let mut fields = vec![
+        serde_json::json!({
+            "name": "Library",
+            "value": format!("{} items · {}", notice.items, format_file_size(notice.total_bytes)),
+            "inline": true,
+        }),
+        serde_json::json!({
+            "name": "Total waste",
+            "value": format!("{} flagged · {}", notice.flagged, format_file_size(notice.flagged_bytes)),
+            "inline": true,
+        }),
+        serde_json::json!({
+            "name": "Average waste",
+            "value": format!("{:.1}", notice.average_waste),
+            "inline": true,
+        }),
+    ];
+    if let Some(total) = notice.reclaimed_total {
+        fields.push(serde_json::json!({
+            "name": "Space freed",
+            "value": format!(
+                "{} this scan · {} so far",
+                format_file_size(notice.reclaimed.bytes),
+                format_file_size(total.bytes)
+            ),
+            "inline": true,
+        }));
+    }
+    if !notice.skipped.is_empty() {
+        fields.push(serde_json::json!({
+            "name": "Skipped",
+            "value": notice.skipped.join(", "),
+        }));
+    }
+    let mut description = format_low_space_lines(notice);
+    if offenders.is_empty() {
+        description.push("Nothing flagged".to_string());
+    } else {
+        if !description.is_empty() {
+            description.push(String::new());
+        }
+        description.push(format!("**Top offenders**\n{}", offenders.join("\n")));
+    }
+    serde_json::json!({
+        "username": "wastearr",
+        "embeds": [{
+            "title": format_notice_title(notice),
+            "description": description.join("\n"),
+            // Red for low disk space, orange when something is flagged, green otherwise
+            "color": if !notice.low_space.is_empty() {
+                0xE74C3C
+            } else if notice.flagged > 0 {
+                0xE67E22
+            } else {
+                0x2ECC71
+            },
+            "fields": fields,
+            "timestamp": chrono::DateTime::from_timestamp(notice.scanned_at as i64, 0)
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+        }],
+    })
+}
+
+async fn send_email(
+    smtp: &SmtpSettings,
+    from: &Mailbox,
+    to: &[Mailbox],
+    markdown: bool,
+    notice: &ScanNotice,
+) -> Result<()> {
+    let mut message = lettre::Message::builder()
+        .from(from.clone())
+        .subject(format_notice_title(notice));
+    for recipient in to {
+        message = message.to(recipient.clone());
+    }
+    let message = if markdown {
+        message.singlepart(SinglePart::plain(format_notice_markdown(notice)))?
+    } else {
+        message.multipart(MultiPart::alternative_plain_html(
+            format_notice_text(notice),
+            format_notice_html(notice),
+        ))?
+    };
+    let mut transport = match smtp.tls.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?,
+    }
+    .timeout(Some(NOTIFY_TIMEOUT));
+    if let Some(port) = smtp.port {
+        transport = transport.port(port);
+    }
+    if let Some((username, password)) = &smtp.credentials {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
+
+fn format_notice_markdown(notice: &ScanNotice) -> String {
+    let mut text = format!("# {}\n\n", format_notice_title(notice));
+    for line in format_notice_lines(notice) {
+        let _ = writeln!(text, "- {}", line);
+    }
+    if !notice.top_offenders.is_empty() {
+        text.push_str(
+            "\n## Top offenders\n\n| # | Item | Score | Size | Instance |\n|---|---|---|---|---|\n",
+        );
+        for (rank, item) in notice.top_offenders.iter().enumerate() {
+            let name = format!("{} ({})", item.name, item.year).replace('|', "\\|");
+            let name = match &item.url {
+                Some(url) => format!("[{}]({})", name, url),
+                None => name,
+            };
+            let _ = writeln!(
+                text,
+                "| {} | {} | {} | {} | {} |",
+                rank + 1,
+                name,
+                item.waste_score,
+                format_file_size(item.size_bytes),
+                item.instance
+            );
+        }
+    }
+    text
+}
+
+fn format_notice_html(notice: &ScanNotice) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><body style=\"font-family: sans-serif\">\n<h2>{}</h2>\n<ul>\n",
+        xml_escape(&format_notice_title(notice))
+    );
+    for line in format_notice_lines(notice) {
+        let _ = writeln!(html, "<li>{}</li>", xml_escape(&line));
+    }
+    html.push_str("</ul>\n");
+    if !notice.top_offenders.is_empty() {
+        html.push_str("<h3>Top offenders</h3>\n<table cellpadding=\"4\" style=\"border-collapse: collapse\">\n<tr><th>#</th><th align=\"left\">Item</th><th>Score</th><th>Size</th><th>Instance</th></tr>\n");
+        for (rank, item) in notice.top_offenders.iter().enumerate() {
+            let name = xml_escape(&format!("{} ({})", item.name, item.year));
+            let name = match &item.url {
+                Some(url) => format!("<a href=\"{}\">{}</a>", xml_escape(url), name),
+                None => name,
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td align=\"right\">{}</td><td align=\"right\">{}</td><td>{}</td></tr>",
+                rank + 1,
+                name,
+                item.waste_score,
+                format_file_size(item.size_bytes),
+                xml_escape(&item.instance)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// Block Kit message for a Slack incoming webhook
+fn slack_payload(notice: &ScanNotice) -> serde_json::Value {
+    let title = format_notice_title(notice);
+    let mut fields = vec![
+        format!(
+            "*Library*\n{} items · {}",
+            notice.items,
+            format_file_size(notice.total_bytes)
+        ),
+        format!(
+            "*Total waste*\n{} flagged · {}",
+            notice.flagged,
+            format_file_size(notice.flagged_bytes)
+        ),
+        format!("*Average waste*\n{:.1}", notice.average_waste),
+    ];
+    if let Some(total) = notice.reclaimed_total {
+        fields.push(format!(
+            "*Space freed*\n{} this scan · {} so far",
+            format_file_size(notice.reclaimed.bytes),
+            format_file_size(total.bytes)
+        ));
+    }
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": title },
+        }),
+        serde_json::json!({
+            "type": "section",
+            "fields": fields
+                .iter()
+                .map(|field| serde_json::json!({ "type": "mrkdwn", "text": field }))
+                .collect::<Vec<_>>(),
+        }),
+    ];
+    let low_space = format_low_space_lines(notice);
+    if !low_space.is_empty() {
+        blocks.insert(
+            1,
+            serde_json::json!({
+                "type": "section",
+                "text": { "type": "mrkdwn", "text": slack_escape(&low_space.join("\n")) },
+            }),
+        );
+    }
+    if !notice.top_offenders.is_empty() {
+        let offenders: Vec<String> = notice
+            .top_offenders
+            .iter()
+            .enumerate()
+            .map(|(rank, item)| {
+                let title = slack_escape(&format!("{} ({})", item.name, item.year));
+                let title = match &item.url {
+                    Some(url) => format!("<{}|{}>", url, title),
+                    None => title,
+                };
+                format!(
+                    "{}. *{}* · score {} · {} · {}",
+                    rank + 1,
+                    title,
+                    item.waste_score,
+                    format_file_size(item.size_bytes),
+                    slack_escape(&item.instance)
+                )
+            })
+            .collect();
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Top offenders*\n{}", offenders.join("\n")),
+            },
+        }));
+    }
+    if !notice.skipped.is_empty() {
+        blocks.push(serde_json::json!({
+            "type": "context",
+            "elements": [{
+                "type": "mrkdwn",
+                "text": format!("Skipped: {}", slack_escape(&notice.skipped.join(", "))),
+            }],
+        }));
+    }
+    // `text` is the fallback shown in notifications and by clients without blocks
+    serde_json::json!({ "text": title, "blocks": blocks })
+}
+
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// The latest scan shown by `serve`, replaced after every rescan
+#[derive(Debug, Default)]
+struct DashboardData {
+    scanned_at: Option<f64>,
+    items: Vec<Item>,
+    scan: ScanMetrics,
+}
+
+/// State shared between the `serve` scan loop and its request handlers
+#[derive(Debug, Default)]
+struct ServeState {
+    data: RwLock<DashboardData>,
+    scanning: AtomicBool,
+    // Wakes the scan loop early for POST /api/scan
+    rescan: tokio::sync::Notify,
+    // Ends the open event streams so graceful shutdown isn't held up by them
+    shutdown: CancellationToken,
+    // Item actions need a configured login, not just a passed check
+    auth: Arc<RwLock<ServeAuth>>,
+    read_only: Option<&'static str>,
+}
+
+type SharedDashboard = Arc<ServeState>;
+
+/// Serve the dashboard and rescan every `--interval` in the background
+async fn run_serve(args: &Args) -> Result<()> {
+    let listen = args.listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    let auth = load_serve_auth()?;
+    let public = !listener.local_addr()?.ip().is_loopback();
+    warn_open_dashboard(&auth, public, listen);
+    let auth = Arc::new(RwLock::new(auth));
+    let shutdown = shutdown_token();
+    let state = SharedDashboard::new(ServeState {
+        shutdown: shutdown.clone(),
+        auth: auth.clone(),
+        read_only: read_only_flag(args),
+        ..ServeState::default()
+    });
+    let app = axum::Router::new()
+        .route("/", get(|| async { Html(DASHBOARD_HTML) }))
+        .route("/api/items", get(api_items))
+        .route("/api/items/action", post(api_item_action))
+        .route("/api/stats", get(api_stats))
+        .route("/api/history", get(api_history))
+        .route("/api/stats/timeseries", get(api_timeseries))
+        .route("/api/stats/severity", get(api_severity_history))
+        .route("/api/stats/reclaimed", get(api_reclaimed_history))
+        .route("/api/scan", post(api_scan))
+        .route("/api/scan/events", get(api_scan_events))
+        .route("/feed.xml", get(flagged_feed))
+        .with_state(state.clone())
+        .layer(axum::middleware::from_fn_with_state(
+            auth.clone(),
+            require_auth,
+        ))
+        // Outside the dashboard auth: the arrs authenticate with the webhook secret
+        .merge(
+            axum::Router::new()
+                .route("/api/webhook", post(api_webhook))
+                .layer(axum::extract::DefaultBodyLimit::max(WEBHOOK_MAX_BODY))
+                .with_state(state.clone()),
+        );
+    progress!("Serving the dashboard on http://{}", listener.local_addr()?);
+    let mut server = tokio::spawn({
+        // Finish the requests in flight before stopping
+        let stopped = shutdown.clone().cancelled_owned();
+        async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(stopped)
+            .await
+        }
+    });
+    notify_ready();
+
+    let interval = args.interval.unwrap_or(DEFAULT_WATCH_INTERVAL);
+    let mut config_watch = ConfigWatch::new();
+    loop {
+        let started = current_timestamp();
+        state.scanning.store(true, Ordering::Relaxed);
+        sd_notify("STATUS=Scanning");
+        publish_scan_event(ScanEvent::Started);
+        let scan = tokio::select! {
+            scan = run_monitored_scan(args) => scan,
+            _ = shutdown.cancelled() => return stop_server(server).await,
+        };
+        state.scanning.store(false, Ordering::Relaxed);
+        publish_scan_event(ScanEvent::Finished {
+            ok: scan.is_ok(),
+            items: scan.as_ref().map_or(0, |run| run.items.len()),
+        });
+        match scan {
+            Ok(mut run) => {
+                run.items
+                    .sort_by_key(|item| std::cmp::Reverse(item.waste_score));
+                *state.data.write().unwrap_or_else(PoisonError::into_inner) = DashboardData {
+                    scanned_at: Some(started),
+                    items: run.items,
+                    scan: run.metrics,
+                };
+            }
+            Err(e) => warn!("scan failed: {:#}", e),
+        }
+        let next = started + interval.as_secs_f64();
+        progress!("Next scan at {}", format_local_time(next));
+        sd_notify(&format!("STATUS=Next scan at {}", format_local_time(next)));
+        let wait = tokio::time::sleep(Duration::from_secs_f64(
+            (next - current_timestamp()).max(0.0),
+        ));
+        tokio::pin!(wait);
+        loop {
+            tokio::select! {
+                result = &mut server => {
+                    result?.context("Dashboard server stopped")?;
+                    anyhow::bail!("Dashboard server stopped");
+                }
+                _ = &mut wait => break,
+                _ = shutdown.cancelled() => return stop_server(server).await,
+                _ = state.rescan.notified() => {
+                    progress!("Rescan requested");
+                    break;
+                }
+                _ = tokio::time::sleep(CONFIG_POLL_INTERVAL) => {
+                    let changes = config_watch.changes();
+                    if changes.is_empty() {
+                        continue;
+                    }
+                    progress!("Config changed: {}", changes.join(", "));
+                    if !config_changed(&changes, "WASTEARR_SERVE_") {
+                        continue;
+                    }
+                    match load_serve_auth() {
+                        Ok(new_auth) => {
+                            warn_open_dashboard(&new_auth, public, listen);
+                            *auth.write().unwrap_or_else(PoisonError::into_inner) = new_auth;
+                        }
+                        Err(e) => warn!("keeping the old dashboard authentication: {:#}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the dashboard server to wind down after shutdown was requested
+async fn stop_server(server: tokio::task::JoinHandle<io::Result<()>>) -> Result<()> {
+    server.await?.context("Dashboard server stopped")
+}
+
+fn warn_open_dashboard(auth: &ServeAuth, public: bool, listen: &str) {
+    if auth.is_open() && public {
+        warn!(
+            "serving on {} without authentication; set WASTEARR_SERVE_TOKEN, WASTEARR_SERVE_USERNAME/WASTEARR_SERVE_PASSWORD or WASTEARR_SERVE_AUTH_HEADER",
+            listen
+        );
+    }
+}
+
+/// Who may use `serve`, from the WASTEARR_SERVE_* settings. Any one configured
+/// method is enough; with none configured, everyone is let in
+#[derive(Debug, Default)]
+struct ServeAuth {
+    token: Option<String>,
+    // "username:password"
+    basic: Option<String>,
+    // Set by a forward-auth proxy once it has authenticated the user
+    trusted_header: Option<HeaderName>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl ServeAuth {
+    fn is_open(&self) -> bool {
+        self.token.is_none() && self.basic.is_none() && self.trusted_header.is_none()
+    }
+
+    fn allows(&self, headers: &HeaderMap, peer: IpAddr) -> bool {
+        if self.is_open() {
+            return true;
+        }
+        if let Some(name) = &self.trusted_header {
+            let authenticated = headers
+                .get(name)
+                .is_some_and(|value| !value.as_bytes().trim_ascii().is_empty());
+            if authenticated && self.trusted_proxies.contains(&peer.to_canonical()) {
+                return true;
+            }
+        }
+        let Some(authorization) = headers
+            .get(reqwest::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let (scheme, credentials) = authorization
+            .trim()
+            .split_once(' ')
+            .unwrap_or((authorization, ""));
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            if let Some(token) = &self.token {
+                return secure_eq(credentials.as_bytes(), token.as_bytes());
+            }
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            if let (Some(basic), Ok(decoded)) = (&self.basic, BASE64.decode(credentials)) {
+                return secure_eq(&decoded, basic.as_bytes());
+            }
+        }
+        false
+    }
+}
+
+fn load_serve_auth() -> Result<ServeAuth> {
+    let basic = match (
+        get_config_value("WASTEARR_SERVE_USERNAME"),
+        get_config_value("WASTEARR_SERVE_PASSWORD"),
+    ) {
+        (Some(username), Some(password)) => Some(format!("{}:{}", username, password)),
+        (None, None) => None,
+        _ => anyhow::bail!(
+            "WASTEARR_SERVE_USERNAME and WASTEARR_SERVE_PASSWORD must be set together"
+        ),
+    };
+    let trusted_header = get_config_value("WASTEARR_SERVE_AUTH_HEADER")
+        .map(|name| HeaderName::from_str(name.trim()))
+        .transpose()
+        .context("Invalid WASTEARR_SERVE_AUTH_HEADER")?;
+    let trusted_proxies = match get_config_value("WASTEARR_SERVE_TRUSTED_PROXIES") {
+        Some(list) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| {
+                ip.parse::<IpAddr>()
+                    .map(|ip| ip.to_canonical())
+                    .with_context(|| {
+                        format!("Invalid WASTEARR_SERVE_TRUSTED_PROXIES entry '{}'", ip)
+                    })
+            })
+            .collect::<Result<_>>()?,
+        None => vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+    };
+    Ok(ServeAuth {
+        token: get_config_value("WASTEARR_SERVE_TOKEN").filter(|token| !token.is_empty()),
+        basic,
+        trusted_header,
+        trusted_proxies,
+    })
+}
+
+/// Compare secrets without returning early on the first differing byte
+fn secure_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn require_auth(
+    State(auth): State<Arc<RwLock<ServeAuth>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> axum::response::Response {
+    // Settled before awaiting, so the lock isn't held across the request
+    let (allowed, basic) = {
+        let auth = auth.read().unwrap_or_else(PoisonError::into_inner);
+        (
+            auth.allows(request.headers(), peer.ip()),
+            auth.basic.is_some(),
+        )
+    };
+    if allowed {
+        return next.run(request).await;
+    }
+    let mut response = (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+    if basic {
+        response.headers_mut().insert(
+            reqwest::header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"wastearr\""),
+        );
+    }
+    response
+}
+
+type ApiError = (StatusCode, String);
+
+/// Filters for GET /api/items, matching the report options of the same name
+#[derive(Debug, Deserialize)]
+struct ItemsQuery {
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    instance: Option<String>,
+    min_score: Option<i32>,
+    min_size: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Items of the latest scan, highest waste score first
+async fn api_items(
+    State(state): State<SharedDashboard>,
+    Query(query): Query<ItemsQuery>,
+) -> Result<Json<Vec<Item>>, ApiError> {
+    let item_type = match query.item_type.as_deref() {
+        None => None,
+        Some("show" | "sonarr") => Some("show"),
+        Some("movie" | "radarr") => Some("movie"),
+        Some(other) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown type '{}', expected movie or show", other),
+            ));
+        }
+    };
+    let min_size = match query.min_size.as_deref() {
+        Some(size) => Some(
+            parse_size_string(size).map_err(|e| (StatusCode::BAD_REQUEST, format!("{:#}", e)))?,
+        ),
+        None => None,
+    };
+    let data = state.data.read().unwrap_or_else(PoisonError::into_inner);
+    let items = data
+        .items
+        .iter()
+        .filter(|item| item_type.is_none_or(|t| item.item_type == t))
+        .filter(|item| query.instance.as_ref().is_none_or(|i| &item.instance == i))
+        .filter(|item| query.min_score.is_none_or(|s| item.waste_score >= s))
+        .filter(|item| min_size.is_none_or(|s| item.size_bytes >= s))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    Ok(Json(items))
+}
+
+#[derive(Debug, Serialize)]
+struct SeverityCount {
+    severity: &'static str,
+    min_score: i32,
+    items: usize,
+}
+
+/// Totals of the latest scan, shaped for dashboard widgets
+#[derive(Debug, Serialize)]
+struct ApiStats<'a> {
+    scanned_at: Option<f64>,
+    scanning: bool,
+    items: usize,
+    total_bytes: u64,
+    average_waste: f64,
+    flagged: usize,
+    flagged_bytes: u64,
+    severity: Vec<SeverityCount>,
+    instances: Vec<InstanceSummary>,
+    scan: &'a ScanMetrics,
+}
+
+async fn api_stats(State(state): State<SharedDashboard>) -> Json<serde_json::Value> {
+    let data = state.data.read().unwrap_or_else(PoisonError::into_inner);
+    let totals = summarize_instance(
+        "",
+        data.items
+            .iter()
+            .map(|item| (item.size_bytes, item.waste_score)),
+    );
+    let instances: Vec<String> = data.scan.items_per_instance.keys().cloned().collect();
+    let stats = ApiStats {
+        scanned_at: data.scanned_at,
+        scanning: state.scanning.load(Ordering::Relaxed),
+        items: totals.items,
+        total_bytes: totals.total_bytes,
+        average_waste: totals.average_waste,
+        flagged: totals.flagged,
+        flagged_bytes: data
+            .items
+            .iter()
+            .filter(|item| item.waste_score >= FLAGGED_MIN_SCORE)
+            .map(|item| item.size_bytes)
+            .sum(),
+        severity: SEVERITY_BANDS
+            .iter()
+            .map(|&(severity, min_score)| SeverityCount {
+                severity,
+                min_score,
+                items: data
+                    .items
+                    .iter()
+                    .filter(|item| severity_of(item.waste_score) == severity)
+                    .count(),
+            })
+            .collect(),
+        instances: summarize_scan(&data.items, &instances),
+        scan: &data.scan,
+    };
+    Json(serde_json::to_value(&stats).unwrap_or_default())
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedQuery {
+    min_score: Option<i32>,
+}
+
+/// RSS feed of the flagged items of the latest scan, so removals can be vetoed
+/// from a feed reader
+async fn flagged_feed(
+    State(state): State<SharedDashboard>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let link = headers
+        .get(reqwest::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("http://{}/", host))
+        .unwrap_or_default();
+    let data = state.data.read().unwrap_or_else(PoisonError::into_inner);
+    let feed = format_flagged_feed(
+        &data.items,
+        query.min_score.unwrap_or(FLAGGED_MIN_SCORE),
+        data.scanned_at,
+        &link,
+    );
+    (
+        [(
+            reqwest::header::CONTENT_TYPE,
+            "application/rss+xml; charset=utf-8",
+        )],
+        feed,
+    )
+}
+
+fn format_flagged_feed(
+    items: &[Item],
+    min_score: i32,
+    scanned_at: Option<f64>,
+    link: &str,
+) -> String {
+    let published = scanned_at
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp as i64, 0))
+        .map(|time| time.to_rfc2822())
+        .unwrap_or_default();
+    let mut feed = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n",
+    );
+    let _ = writeln!(feed, "<title>wastearr: flagged items</title>");
+    let _ = writeln!(feed, "<link>{}</link>", xml_escape(link));
+    let _ = writeln!(
+        feed,
+        "<description>Items with a waste score of {} or more in the latest scan</description>",
+        min_score
+    );
+    if !published.is_empty() {
+        let _ = writeln!(feed, "<lastBuildDate>{}</lastBuildDate>", published);
+    }
+    for item in items.iter().filter(|item| item.waste_score >= min_score) {
+        let mut description = format!(
+            "Waste score {} ({}), {}, on {}",
+            item.waste_score,
+            severity_of(item.waste_score),
+            format_file_size(item.size_bytes),
+            item.instance
+        );
+        if let Some(note) = &item.note {
+            let _ = write!(description, ". Note: {}", note);
+        }
+        let _ = writeln!(feed, "<item>");
+        let _ = writeln!(
+            feed,
+            "<title>{}</title>",
+            xml_escape(&format!(
+                "{} ({}) - score {}",
+                item.name, item.year, item.waste_score
+            ))
+        );
+        if let Some(url) = &item.url {
+            let _ = writeln!(feed, "<link>{}</link>", xml_escape(url));
+        }
+        let _ = writeln!(
+            feed,
+            "<description>{}</description>",
+            xml_escape(&description)
+        );
+        let _ = writeln!(
+            feed,
+            "<guid isPermaLink=\"false\">{}:{}</guid>",
+            xml_escape(&item.instance),
+            item.id
+        );
+        if !published.is_empty() {
+            let _ = writeln!(feed, "<pubDate>{}</pubDate>", published);
+        }
+        let _ = writeln!(feed, "</item>");
+    }
+    feed.push_str("</channel>\n</rss>\n");
+    feed
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+/// Recorded scans, newest first
+async fn api_history(
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<ScanListing>>, ApiError> {
+    let mut scans = tokio::task::spawn_blocking(list_scans)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|scans| scans)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    scans.truncate(query.limit.unwrap_or(usize::MAX));
+    Ok(Json(scans))
+}
+
+/// Filters for GET /api/stats/timeseries; `from`/`to` take Grafana's
+/// `${__from}`/`${__to}` epoch milliseconds
+#[derive(Debug, Deserialize)]
+struct TimeseriesQuery {
+    by: Option<String>,
+    instance: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+async fn api_timeseries(
+    Query(query): Query<TimeseriesQuery>,
+) -> Result<Json<Vec<TimeseriesPoint>>, ApiError> {
+    let per_instance = match query.by.as_deref() {
+        None | Some("scan") => false,
+        Some("instance") => true,
+        Some(other) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown by '{}', expected scan or instance", other),
+            ));
+        }
+    };
+    tokio::task::spawn_blocking(move || {
+        read_timeseries(
+            per_instance,
+            query.instance.as_deref(),
+            query.from,
+            query.to,
+        )
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|points| points)
+    .map(Json)
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+async fn api_severity_history() -> Result<Json<Vec<SeverityPoint>>, ApiError> {
+    tokio::task::spawn_blocking(read_severity_history)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|points| points)
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+async fn api_reclaimed_history() -> Result<Json<Vec<ReclaimedMonth>>, ApiError> {
+    tokio::task::spawn_blocking(read_reclaimed_by_month)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|months| months)
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+/// Queue a rescan; the scan loop picks it up as soon as it is idle
+async fn api_scan(State(state): State<SharedDashboard>) -> (StatusCode, Json<serde_json::Value>) {
+    let status = if state.scanning.load(Ordering::Relaxed) {
+        "running"
+    } else {
+        state.rescan.notify_one();
+        "queued"
+    };
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "status": status })),
+    )
+}
+
+/// Body of POST /api/items/action
+#[derive(Debug, Deserialize)]
+struct ItemActionRequest {
+    action: String,
+    // The label for "tag"
+    tag: Option<String>,
+    items: Vec<ItemRef>,
+    // Without it, nothing changes: the reply says what would
+    #[serde(default)]
+    confirm: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ItemRef {
+    instance: String,
+    id: i32,
+}
+
+/// Delete, unmonitor, tag or exclude items of the latest scan. A request
+/// without `confirm` is a dry run that returns the question to confirm
+async fn api_item_action(
+    State(state): State<SharedDashboard>,
+    Json(request): Json<ItemActionRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if state
+        .auth
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_open()
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Item actions need dashboard authentication: set WASTEARR_SERVE_TOKEN, WASTEARR_SERVE_USERNAME/WASTEARR_SERVE_PASSWORD or WASTEARR_SERVE_AUTH_HEADER".to_string(),
+        ));
+    }
+    if let Some(flag) = state.read_only {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Actions are unavailable in a {} run", flag),
+        ));
+    }
+    let action = match (
+        request.action.as_str(),
+        request.tag.as_deref().map(str::trim),
+    ) {
+        ("delete", _) => ItemAction::Delete,
+        ("unmonitor", _) => ItemAction::Unmonitor,
+        ("exclude", _) => ItemAction::Exclude,
+        ("tag", Some(label)) if !label.is_empty() => ItemAction::Tag(label.to_string()),
+        ("tag", _) => return Err((StatusCode::BAD_REQUEST, "tag needs a label".to_string())),
+        (other, _) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unknown action '{}', expected delete, unmonitor, tag or exclude",
+                    other
+                ),
+            ));
+        }
+    };
+    if request.items.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No items selected".to_string()));
+    }
+    // Acted on as the latest scan saw them, so the confirmed sizes hold
+    let items = {
+        let data = state.data.read().unwrap_or_else(PoisonError::into_inner);
+        request
+            .items
+            .iter()
+            .map(|wanted| {
+                data.items
+                    .iter()
+                    .find(|item| item.instance == wanted.instance && item.id == wanted.id)
+                    .cloned()
+                    .ok_or_else(|| {
+                        (
+                            StatusCode::NOT_FOUND,
+                            format!(
+                                "{} item {} isn't in the latest scan",
+                                wanted.instance, wanted.id
+                            ),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<Item>, ApiError>>()?
+    };
+    let targets: Vec<&Item> = items.iter().collect();
+    let size = targets.iter().map(|item| item.size_bytes).sum();
+    if !request.confirm {
+        return Ok(Json(serde_json::json!({
+            "dry_run": true,
+            "question": action.question(targets.len(), size),
+            "size_bytes": size,
+            "items": targets
+                .iter()
+                .map(|item| serde_json::json!({
+                    "instance": item.instance,
+                    "id": item.id,
+                    "name": item.name,
+                    "size_bytes": item.size_bytes,
+                }))
+                .collect::<Vec<_>>(),
+        })));
+    }
+
+    let config = tokio::task::spawn_blocking(load_config)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|config| config)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    let (applied, errors) = apply_action_by_instance(&config, &action, &targets).await;
+    let done: Vec<&Item> = targets
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| applied.contains(i))
+        .map(|(_, item)| *item)
+        .collect();
+    info!("Dashboard: {}", action.summary(&done, targets.len()));
+    if matches!(action, ItemAction::Delete) {
+        state
+            .data
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .items
+            .retain(|item| {
+                !done
+                    .iter()
+                    .any(|gone| gone.instance == item.instance && gone.id == item.id)
+            });
+    }
+    if done.is_empty() && !errors.is_empty() {
+        return Err((StatusCode::BAD_GATEWAY, errors.join("; ")));
+    }
+    Ok(Json(serde_json::json!({
+        "dry_run": false,
+        "message": action.summary(&done, targets.len()),
+        "applied": done
+            .iter()
+            .map(|item| ItemRef {
+                instance: item.instance.clone(),
+                id: item.id,
+            })
+            .collect::<Vec<_>>(),
+        "errors": errors,
+    })))
+}
+
+/// Stream scan progress as server-sent events, opening with `started` when
+/// a scan is already underway
+async fn api_scan_events(
+    State(state): State<SharedDashboard>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = SCAN_EVENTS.subscribe();
+    let running = state
+        .scanning
+        .load(Ordering::Relaxed)
+        .then_some(ScanEvent::Started);
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let stream = stream::iter(running)
+        .chain(events)
+        .map(|event| Event::default().json_data(event))
+        .take_until(state.shutdown.clone().cancelled_owned());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Arr webhook payloads are a few KB; anything much bigger isn't one
+const WEBHOOK_MAX_BODY: usize = 256 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct WebhookQuery {
+    // Instance whose cached library the event invalidates
+    instance: Option<String>,
+    token: Option<String>,
+}
+
+/// The parts of a Sonarr/Radarr webhook payload wastearr acts on
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArrWebhook {
+    event_type: String,
+    series: Option<WebhookMedia>,
+    movie: Option<WebhookMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookMedia {
+    id: i32,
+}
+
+/// POST /api/webhook, for a "Webhook" connection in Sonarr or Radarr: events
+/// that change the library drop the instance's cached library and queue a
+/// rescan. Off unless WASTEARR_SERVE_WEBHOOK_SECRET is set
+async fn api_webhook(
+    State(state): State<SharedDashboard>,
+    Query(query): Query<WebhookQuery>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let Some(secret) =
+        get_config_value("WASTEARR_SERVE_WEBHOOK_SECRET").filter(|secret| !secret.is_empty())
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Webhooks are not enabled".to_string(),
+        ));
+    };
+    if !webhook_authorized(&secret, &headers, query.token.as_deref(), &body) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Invalid webhook secret or signature".to_string(),
+        ));
+    }
+    let event: ArrWebhook = serde_json::from_slice(&body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid webhook payload: {}", e),
+        )
+    })?;
+    let changes_library = match event.event_type.as_str() {
+        "Download" | "Rename" | "ImportComplete" | "SeriesAdd" | "SeriesDelete"
+        | "EpisodeFileDelete" | "MovieAdded" | "MovieDelete" | "MovieFileDelete" => true,
+        "Test"
+        | "Grab"
+        | "Health"
+        | "HealthRestored"
+        | "ApplicationUpdate"
+        | "ManualInteractionRequired" => false,
+        other => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown webhook event type '{}'", other),
+            ));
+        }
+    };
+    if !changes_library {
+        return Ok((
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "ignored" })),
+        ));
+    }
+    let subject = match (&event.series, &event.movie) {
+        (Some(series), _) => format!("series {}", series.id),
+        (None, Some(movie)) => format!("movie {}", movie.id),
+        (None, None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("{} event without a series or movie", event.event_type),
+            ));
+        }
+    };
+    if let Some(instance) = query.instance.clone() {
+        let known = tokio::task::spawn_blocking(move || -> Result<bool> {
+            let config = load_config()?;
+            if !config.instances.iter().any(|i| i.name == instance) {
+                return Ok(false);
+            }
+            if let Err(e) = drop_cached_library(&instance) {
+                warn!("failed to drop the cached library of {}: {:#}", instance, e);
+            }
+            Ok(true)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|known| known)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+        if !known {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unknown instance '{}'",
+                    query.instance.as_deref().unwrap_or_default()
+                ),
+            ));
+        }
+    }
+    progress!(
+        "Webhook: {} of {}{}",
+        event.event_type,
+        subject,
+        query
+            .instance
+            .as_deref()
+            .map(|instance| format!(" on {}", instance))
+            .unwrap_or_default()
+    );
+    let status = if state.scanning.load(Ordering::Relaxed) {
+        "running"
+    } else {
+        state.rescan.notify_one();
+        "queued"
+    };
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "status": status })),
+    ))
+}
+
+/// The webhook secret as `?token=`, an X-Wastearr-Token header or the
+/// password of basic auth (what the arrs' webhook settings can send), or an
+/// X-Wastearr-Signature header of `sha256=` and the body's HMAC-SHA256 under it
+fn webhook_authorized(secret: &str, headers: &HeaderMap, token: Option<&str>, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(signature) = header("x-wastearr-signature") {
+        let expected: String = hmac_sha256(secret.as_bytes(), body)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        return signature
+            .trim()
+            .strip_prefix("sha256=")
+            .is_some_and(|signature| {
+                secure_eq(
+                    signature.to_ascii_lowercase().as_bytes(),
+                    expected.as_bytes(),
+                )
+            });
+    }
+    let password = header("authorization")
+        .and_then(|authorization| authorization.trim().split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+        .and_then(|(_, credentials)| BASE64.decode(credentials.trim()).ok())
+        .and_then(|decoded| {
+            let decoded = String::from_utf8(decoded).ok()?;
+            decoded
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+    let given = token
+        .map(str::to_string)
+        .or_else(|| header("x-wastearr-token").map(str::to_string))
+        .or(password);
+    given.is_some_and(|given| secure_eq(given.as_bytes(), secret.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+struct ScheduledJob {
+    action: &'static str,
+    schedule: cron::Schedule,
+}
+
+/// Cron schedules for `watch` from WASTEARR_SCHEDULE_SCAN, _STATS and _WRAPPED
+fn load_schedules() -> Result<Vec<ScheduledJob>> {
+    SCHEDULED_ACTIONS
+        .iter()
+        .filter_map(|&action| {
+            let key = format!("WASTEARR_SCHEDULE_{}", action.to_uppercase());
+            get_config_value(&key).map(|expr| {
+                let schedule = parse_schedule(&expr).with_context(|| format!("Invalid {}", key))?;
+                Ok(ScheduledJob { action, schedule })
+            })
+        })
+        .collect()
+}
+
+/// Standard five-field cron expressions, or the six- and seven-field form with
+/// seconds (and years)
+fn parse_schedule(expr: &str) -> Result<cron::Schedule> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+    Ok(cron::Schedule::from_str(&expr)?)
+}
+
+fn next_run(schedule: &cron::Schedule, after: f64) -> Option<f64> {
+    let after = chrono::DateTime::from_timestamp(after as i64, 0)?.with_timezone(&chrono::Local);
+    schedule
+        .after(&after)
+        .next()
+        .map(|time| time.timestamp() as f64)
+}
+
+/// Columns of the `tui` item list, in display order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TuiColumn {
+    Name,
+    Type,
+    Instance,
+    Year,
+    Rating,
+    Size,
+    Quality,
+    Resolution,
+    VideoCodec,
+    Audio,
+    Note,
+    Score,
+}
+
+const TUI_COLUMNS: [TuiColumn; 12] = [
+    TuiColumn::Name,
+    TuiColumn::Type,
+    TuiColumn::Instance,
+    TuiColumn::Year,
+    TuiColumn::Rating,
+    TuiColumn::Size,
+    TuiColumn::Quality,
+    TuiColumn::Resolution,
+    TuiColumn::VideoCodec,
+    TuiColumn::Audio,
+    TuiColumn::Note,
+    TuiColumn::Score,
+];
+
+impl TuiColumn {
+    fn title(self) -> &'static str {
+        match self {
+            TuiColumn::Name => "Name",
+            TuiColumn::Type => "Type",
+            TuiColumn::Instance => "Instance",
+            TuiColumn::Year => "Year",
+            TuiColumn::Rating => "Rating",
+            TuiColumn::Size => "Size",
+            TuiColumn::Quality => "Quality",
+            TuiColumn::Resolution => "Resolution",
+            TuiColumn::VideoCodec => "Video Codec",
+            TuiColumn::Audio => "Audio",
+            TuiColumn::Note => "Note",
+            TuiColumn::Score => "Score",
+        }
+    }
+
+    fn cell(self, item: &Item) -> String {
+        let media = item.media_info.as_ref();
+        match self {
+            TuiColumn::Name => item.name.clone(),
+            TuiColumn::Type => if item.item_type == "show" {
+                "Show"
+            } else {
+                "Movie"
+            }
+            .to_string(),
+            TuiColumn::Instance => item.instance.clone(),
+            TuiColumn::Year => item.year.to_string(),
+            TuiColumn::Rating => item.rating.clone(),
+            TuiColumn::Size => format_file_size(item.size_bytes),
+            TuiColumn::Quality => item.quality.clone(),
+            TuiColumn::Resolution => media.map_or("-", |m| &m.resolution).to_string(),
+            TuiColumn::VideoCodec => media.map_or("-", |m| &m.video_codec).to_string(),
+            TuiColumn::Audio => media.map_or("-", |m| &m.audio).to_string(),
+            TuiColumn::Note => item.note.clone().unwrap_or_default(),
+            TuiColumn::Score => item.waste_score.to_string(),
+        }
+    }
+
+    fn numeric(self) -> bool {
+        matches!(
+            self,
+            TuiColumn::Year | TuiColumn::Rating | TuiColumn::Size | TuiColumn::Score
+        )
+    }
+
+    fn compare(self, a: &Item, b: &Item) -> std::cmp::Ordering {
+        // Unrated items sort below every rating
+        let rating = |item: &Item| item.rating.parse::<f64>().unwrap_or(-1.0);
+        match self {
+            TuiColumn::Year => a.year.cmp(&b.year),
+            TuiColumn::Rating => rating(a).total_cmp(&rating(b)),
+            TuiColumn::Size => a.size_bytes.cmp(&b.size_bytes),
+            TuiColumn::Score => a.waste_score.cmp(&b.waste_score),
+            _ => self
+                .cell(a)
+                .to_lowercase()
+                .cmp(&self.cell(b).to_lowercase()),
+        }
+    }
+}
+
+/// Filter being typed into the `tui` prompt line
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TuiField {
+    Search,
+    MinScore,
+    MinSize,
+    // Not a filter: the tag for ItemAction::Tag
+    Tag,
+}
+
+impl TuiField {
+    fn prompt(self) -> &'static str {
+        match self {
+            TuiField::Search => "Search: ",
+            TuiField::MinScore => "Min score: ",
+            TuiField::MinSize => "Min size: ",
+            TuiField::Tag => "Tag: ",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct TuiFilter {
+    search: String,
+    // "show" or "movie"
+    item_type: Option<&'static str>,
+    min_score: Option<i32>,
+    min_size: Option<u64>,
+}
+
+impl TuiFilter {
+    /// Everything but the search, which TuiState::refresh matches fuzzily
+    fn matches(&self, item: &Item) -> bool {
+        self.item_type.is_none_or(|wanted| item.item_type == wanted)
+            && self.min_score.is_none_or(|min| item.waste_score >= min)
+            && self.min_size.is_none_or(|min| item.size_bytes >= min)
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        if !self.search.is_empty() {
+            filters.push(format!("\"{}\"", self.search));
+        }
+        if let Some(item_type) = self.item_type {
+            filters.push(
+                if item_type == "show" {
+                    "Shows"
+                } else {
+                    "Movies"
+                }
+                .to_string(),
+            );
+        }
+        if let Some(score) = self.min_score {
+            filters.push(format!("Score >= {}", score));
+        }
+        if let Some(size) = self.min_size {
+            filters.push(format!("Size >= {}", format_file_size(size)));
+        }
+        filters
+    }
+}
+
+/// Everything the `tui` screen shows; `visible` indexes `items` after
+/// filtering and sorting
+struct TuiState {
+    items: Vec<Item>,
+    visible: Vec<usize>,
+    table: ratatui::widgets::TableState,
+    shown: Vec<bool>,
+    sort: TuiColumn,
+    descending: bool,
+    filter: TuiFilter,
+    // Prompt being edited, with the filter as it was before, for Esc
+    input: Option<(TuiField, String, TuiFilter)>,
+    // Column picker cursor while it is open
+    picker: Option<usize>,
+    message: Option<String>,
+    // Indexes into `items`, kept across filter changes
+    marked: HashSet<usize>,
+    // Action waiting for y/n, with the items it applies to
+    confirm: Option<(ItemAction, Vec<usize>)>,
+    // Why actions are off, for runs that never talked to the servers
+    read_only: Option<&'static str>,
+    // Detail pane of the item under the cursor, while it is open
+    detail: Option<TuiDetail>,
+    // Scorer plugin file, when it replaced the built-in score
+    scorer: Option<String>,
+    // Fuzzy name search, parsed from `filter.search` on every refresh
+    pattern: Option<Pattern>,
+    matcher: nucleo_matcher::Matcher,
+    // For the score breakdown of the detail pane
+    scoring: Scoring,
+    protect: Vec<ProtectRule>,
+}
+
+/// The item shown in the detail pane and its scan history, read once per item
+struct TuiDetail {
+    index: usize,
+    history: Result<Vec<(f64, u64, i32)>, String>,
+}
+
+impl TuiState {
+    fn new(items: Vec<Item>, args: &Args, min_size: Option<u64>, config: &Config) -> Self {
+        let types: HashSet<&str> = items.iter().map(|item| item.item_type.as_str()).collect();
+        let instances: HashSet<&str> = items.iter().map(|item| item.instance.as_str()).collect();
+        let shown = TUI_COLUMNS
+            .iter()
+            .map(|column| match column {
+                TuiColumn::Type => types.len() > 1,
+                TuiColumn::Instance => instances.len() > 1,
+                TuiColumn::Quality => false,
+                TuiColumn::Resolution | TuiColumn::VideoCodec | TuiColumn::Audio => args.media_info,
+                TuiColumn::Note => items.iter().any(|item| item.note.is_some()),
+                _ => true,
+            })
+            .collect();
+        let mut state = TuiState {
+            items,
+            visible: Vec::new(),
+            table: ratatui::widgets::TableState::default(),
+            shown,
+            sort: TuiColumn::Score,
+            descending: true,
+            filter: TuiFilter {
+                min_score: args.waste_score,
+                min_size,
+                ..TuiFilter::default()
+            },
+            input: None,
+            picker: None,
+            message: None,
+            marked: HashSet::new(),
+            confirm: None,
+            detail: None,
+            scorer: args.scorer.clone(),
+            pattern: None,
+            matcher: nucleo_matcher::Matcher::new(nucleo_matcher::Config::DEFAULT),
+            scoring: config.scoring,
+            protect: config.protect.clone(),
+            read_only: read_only_flag(args),
+        };
+        state.refresh();
+        state
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(&index) = self.table.selected().and_then(|row| self.visible.get(row)) {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+        }
+    }
+
+    /// Shift+arrow selection: mark the row left and the row landed on
+    fn extend_marks(&mut self, delta: isize) {
+        let row = self.table.selected();
+        if let Some(&index) = row.and_then(|row| self.visible.get(row)) {
+            self.marked.insert(index);
+        }
+        self.move_cursor(delta);
+        if let Some(&index) = self.table.selected().and_then(|row| self.visible.get(row)) {
+            self.marked.insert(index);
+        }
+    }
+
+    /// Items an action applies to: the one in the detail pane, the marked ones,
+    /// or else the one under the cursor
+    fn action_targets(&self) -> Vec<usize> {
+        if let Some(detail) = &self.detail {
+            vec![detail.index]
+        } else if self.marked.is_empty() {
+            self.table
+                .selected()
+                .and_then(|row| self.visible.get(row).copied())
+                .into_iter()
+                .collect()
+        } else {
+            // In list order, then whatever the filters hide
+            let mut targets: Vec<usize> = self
+                .visible
+                .iter()
+                .copied()
+                .filter(|index| self.marked.contains(index))
+                .collect();
+            let mut hidden: Vec<usize> = self
+                .marked
+                .iter()
+                .copied()
+                .filter(|index| !targets.contains(index))
+                .collect();
+            hidden.sort_unstable();
+            targets.extend(hidden);
+            targets
+        }
+    }
+
+    fn ask(&mut self, action: ItemAction) {
+        if let Some(flag) = self.read_only {
+            self.message = Some(format!("Actions are unavailable in a {} run", flag));
+            return;
+        }
+        let targets = self.action_targets();
+        if !targets.is_empty() {
+            self.confirm = Some((action, targets));
+        }
+    }
+
+    /// Point the open detail pane at the item under the cursor, closing it when there is none
+    fn sync_detail(&mut self) {
+        let Some(detail) = &self.detail else {
+            return;
+        };
+        let selected = self
+            .table
+            .selected()
+            .and_then(|row| self.visible.get(row).copied());
+        match selected {
+            Some(index) if index == detail.index => {}
+            Some(index) => self.detail = Some(TuiDetail::load(&self.items[index], index)),
+            None => self.detail = None,
+        }
+    }
+
+    /// Drop deleted items, keeping the cursor on the same row
+    fn remove_items(&mut self, removed: &HashSet<usize>) {
+        let row = self.table.selected().unwrap_or(0);
+        let mut index = 0;
+        self.items.retain(|_| {
+            index += 1;
+            !removed.contains(&(index - 1))
+        });
+        self.marked.clear();
+        self.table.select(None);
+        self.refresh();
+        if !self.visible.is_empty() {
+            self.table.select(Some(row.min(self.visible.len() - 1)));
+        }
+        // Indexes have shifted, so reload the pane for whatever is now under the cursor
+        if self.detail.take().is_some() {
+            self.detail = self
+                .table
+                .selected()
+                .map(|row| self.visible[row])
+                .map(|index| TuiDetail::load(&self.items[index], index));
+        }
+    }
+
+    fn selected_item(&self) -> Option<&Item> {
+        self.table
+            .selected()
+            .and_then(|row| self.visible.get(row))
+            .map(|&index| &self.items[index])
+    }
+
+    /// Re-filter and re-sort, keeping the cursor on the same item when it is still listed
+    fn refresh(&mut self) {
+        let selected = self
+            .table
+            .selected()
+            .and_then(|row| self.visible.get(row).copied());
+        let (sort, descending) = (self.sort, self.descending);
+        self.pattern = (!self.filter.search.is_empty()).then(|| {
+            Pattern::parse(
+                &self.filter.search,
+                CaseMatching::Ignore,
+                Normalization::Smart,
+            )
+        });
+        let mut scores = HashMap::new();
+        let mut buffer = Vec::new();
+        self.visible = (0..self.items.len())
+            .filter(|&index| {
+                let item = &self.items[index];
+                self.filter.matches(item)
+                    && self.pattern.as_ref().is_none_or(|pattern| {
+                        let score = pattern
+                            .score(Utf32Str::new(&item.name, &mut buffer), &mut self.matcher);
+                        score.map(|score| scores.insert(index, score)).is_some()
+                    })
+            })
+            .collect();
+        let items = &self.items;
+        // While searching, the best matches come first and the sort breaks ties
+        self.visible.sort_by(|&a, &b| {
+            let order = sort
+                .compare(&items[a], &items[b])
+                .then_with(|| items[a].waste_score.cmp(&items[b].waste_score));
+            let order = if descending { order.reverse() } else { order };
+            scores.get(&b).cmp(&scores.get(&a)).then(order)
+        });
+        let row = selected
+            .and_then(|index| self.visible.iter().position(|&i| i == index))
+            .unwrap_or(0);
+        self.table.select((!self.visible.is_empty()).then_some(row));
+    }
+
+    fn shown_columns(&self) -> Vec<TuiColumn> {
+        TUI_COLUMNS
+            .iter()
+            .zip(&self.shown)
+            .filter(|(_, shown)| **shown)
+            .map(|(column, _)| *column)
+            .collect()
+    }
+
+    /// Move the sort to the next or previous shown column
+    fn cycle_sort(&mut self, forward: bool) {
+        let columns = self.shown_columns();
+        let current = columns.iter().position(|&c| c == self.sort).unwrap_or(0);
+        let next = if forward {
+            (current + 1) % columns.len()
+        } else {
+            (current + columns.len() - 1) % columns.len()
+        };
+        self.sort = columns[next];
+        self.descending = self.sort.numeric();
+        self.refresh();
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let row = self.table.selected().unwrap_or(0) as isize + delta;
+        self.table
+            .select(Some(row.clamp(0, self.visible.len() as isize - 1) as usize));
+    }
+
+    /// Apply the prompt text to its filter; Search applies as it is typed
+    fn apply_input(&mut self, field: TuiField, text: &str) -> Result<()> {
+        let text = text.trim();
+        match field {
+            TuiField::Search => self.filter.search = text.to_string(),
+            TuiField::MinScore if text.is_empty() => self.filter.min_score = None,
+            TuiField::MinScore => {
+                self.filter.min_score = Some(
+                    text.parse()
+                        .with_context(|| format!("Invalid score: {}", text))?,
+                )
+            }
+            TuiField::MinSize if text.is_empty() => self.filter.min_size = None,
+            TuiField::MinSize => self.filter.min_size = Some(parse_size_string(text)?),
+            TuiField::Tag => unreachable!("tags aren't a filter"),
+        }
+        self.refresh();
+        Ok(())
+    }
+}
+
+impl TuiDetail {
+    fn load(item: &Item, index: usize) -> Self {
+        let history = open_history_store()
+            .and_then(|conn| match conn {
+                Some(conn) => read_item_history(&conn, &item.instance, item.id),
+                None => Ok(Vec::new()),
+            })
+            .map_err(|e| format!("{:#}", e));
+        TuiDetail { index, history }
+    }
+}
+
+/// Changes the `tui` and the `serve` dashboard can make to the selected items
+/// through the arr APIs
+#[derive(Debug, Clone)]
+enum ItemAction {
+    Delete,
+    Unmonitor,
+    Tag(String),
+    Exclude,
+}
+
+impl ItemAction {
+    fn question(&self, count: usize, size: u64) -> String {
+        let items = format!(
+            "{} item{} ({})",
+            count,
+            if count == 1 { "" } else { "s" },
+            format_file_size(size)
+        );
+        match self {
+            ItemAction::Delete => format!("Delete {} and their files?", items),
+            ItemAction::Unmonitor => format!("Unmonitor {}?", items),
+            ItemAction::Tag(label) => format!("Tag {} with \"{}\"?", items, label),
+            ItemAction::Exclude => format!("Add {} to the import list exclusions?", items),
+        }
+    }
+
+    fn done(&self) -> &'static str {
+        match self {
+            ItemAction::Delete => "Deleted",
+            ItemAction::Unmonitor => "Unmonitored",
+            ItemAction::Tag(_) => "Tagged",
+            ItemAction::Exclude => "Excluded",
+        }
+    }
+
+    fn summary(&self, applied: &[&Item], targets: usize) -> String {
+        format!(
+            "{} {} of {} items ({})",
+            self.done(),
+            applied.len(),
+            targets,
+            format_file_size(applied.iter().map(|item| item.size_bytes).sum())
+        )
+    }
+}
+
+/// Why actions are off, for runs that never talked to the servers
+fn read_only_flag(args: &Args) -> Option<&'static str> {
+    if args.mock.is_some() {
+        Some("--mock")
+    } else if args.replay.is_some() {
+        Some("--replay")
+    } else if args.offline {
+        Some("--offline")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TagRecord {
+    id: i64,
+    label: String,
+}
+
+/// Id of the tag with this label, created if the instance doesn't have it yet
+async fn ensure_tag(instance: &Instance, label: &str) -> Result<i64> {
+    let tags = fetch_api_data::<TagRecord>(instance, "tag").await?;
+    if let Some(tag) = tags
+        .iter()
+        .find(|tag| tag.label.eq_ignore_ascii_case(label))
+    {
+        return Ok(tag.id);
+    }
+    let created = send_api_change(
+        instance,
+        reqwest::Method::POST,
+        "tag",
+        &serde_json::json!({ "label": label }),
+    )
+    .await?;
+    created["id"].as_i64().with_context(|| {
+        format!(
+            "{} API returned no id for the new tag",
+            instance.service_name()
+        )
+    })
+}
+
+/// Apply an action to items of one instance, through the bulk editor where there is one
+async fn apply_item_action(
+    instance: &Instance,
+    action: &ItemAction,
+    items: &[&Item],
+) -> Result<()> {
+    let ids: Vec<i32> = items.iter().map(|item| item.id).collect();
+    let (editor, ids_key) = if instance.kind == "sonarr" {
+        ("series/editor", "seriesIds")
+    } else {
+        ("movie/editor", "movieIds")
+    };
+    let body = match action {
+        ItemAction::Delete if instance.kind == "radarr" => serde_json::json!({
+            ids_key: ids,
+            "deleteFiles": true,
+            "addImportExclusion": false,
+        }),
+        ItemAction::Delete => serde_json::json!({ ids_key: ids, "deleteFiles": true }),
+        ItemAction::Unmonitor => serde_json::json!({ ids_key: ids, "monitored": false }),
+        ItemAction::Tag(label) => {
+            let tag = ensure_tag(instance, label).await?;
+            serde_json::json!({ ids_key: ids, "tags": [tag], "applyTags": "add" })
+        }
+        ItemAction::Exclude => {
+            for item in items {
+                let (endpoint, exclusion) = if instance.kind == "sonarr" {
+                    let tvdb_id = item
+                        .tvdb_id
+                        .with_context(|| format!("{} has no TVDB id", item.name))?;
+                    (
+                        "importlistexclusion",
+                        serde_json::json!({ "tvdbId": tvdb_id, "title": item.name }),
+                    )
+                } else {
+                    let tmdb_id = item
+                        .tmdb_id
+                        .with_context(|| format!("{} has no TMDB id", item.name))?;
+                    (
+                        "exclusions",
+                        serde_json::json!({
+                            "tmdbId": tmdb_id,
+                            "movieTitle": item.name,
+                            "movieYear": item.year,
+                        }),
+                    )
+                };
+                send_api_change(instance, reqwest::Method::POST, endpoint, &exclusion).await?;
+            }
+            return Ok(());
+        }
+    };
+    let method = if matches!(action, ItemAction::Delete) {
+        reqwest::Method::DELETE
+    } else {
+        reqwest::Method::PUT
+    };
+    send_api_change(instance, method, editor, &body).await?;
+    Ok(())
+}
+
+/// Apply a confirmed action instance by instance. Returns the indexes into
+/// `items` that went through, and what failed
+async fn apply_action_by_instance(
+    config: &Config,
+    action: &ItemAction,
+    items: &[&Item],
+) -> (HashSet<usize>, Vec<String>) {
+    let mut by_instance: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, item) in items.iter().enumerate() {
+        by_instance
+            .entry(item.instance.as_str())
+            .or_default()
+            .push(index);
+    }
+    let mut applied = HashSet::new();
+    let mut errors = Vec::new();
+    for (name, indexes) in &by_instance {
+        let batch: Vec<&Item> = indexes.iter().map(|&i| items[i]).collect();
+        let result = match config.require_instance(name) {
+            Ok(instance) => apply_item_action(instance, action, &batch).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => applied.extend(indexes.iter().copied()),
+            Err(e) => errors.push(format!("{:#}", e)),
+        }
+    }
+    if matches!(action, ItemAction::Delete) {
+        let deleted: Vec<Item> = applied.iter().map(|&i| items[i].clone()).collect();
+        let instances: Vec<String> = by_instance.keys().map(|name| name.to_string()).collect();
+        let stored = tokio::task::spawn_blocking(move || {
+            let mut errors = Vec::new();
+            if let Err(e) = record_deletions(&deleted, current_timestamp()) {
+                errors.push(format!("failed to log the deletions: {:#}", e));
+            }
+            // The cached libraries would bring the deleted items back until they expire
+            for name in &instances {
+                if let Err(e) = drop_cached_library(name) {
+                    errors.push(format!(
+                        "failed to drop the cached {} library: {:#}",
+                        name, e
+                    ));
+                }
+            }
+            errors
+        })
+        .await;
+        match stored {
+            Ok(stored) => errors.extend(stored),
+            Err(e) => errors.push(format!("failed to log the deletions: {}", e)),
+        }
+    }
+    (applied, errors)
+}
+
+/// Run a confirmed action instance by instance, reporting what went through
+async fn run_tui_action(
+    state: &mut TuiState,
+    config: &Config,
+    action: &ItemAction,
+    targets: &[usize],
+) {
+    let items: Vec<&Item> = targets.iter().map(|&i| &state.items[i]).collect();
+    let (applied, errors) = apply_action_by_instance(config, action, &items).await;
+    let done: Vec<&Item> = applied.iter().map(|&i| items[i]).collect();
+    let mut message = action.summary(&done, targets.len());
+    if let Some(first) = errors.first() {
+        let _ = write!(message, "; {}", first);
+    }
+    let applied: HashSet<usize> = applied.iter().map(|&i| targets[i]).collect();
+    if matches!(action, ItemAction::Delete) {
+        state.remove_items(&applied);
+    } else {
+        state.marked.retain(|index| !applied.contains(index));
+    }
+    state.message = Some(message);
+}
+
+/// Scan once, then explore the items in a full-screen terminal UI
+async fn run_tui(args: &Args) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        anyhow::bail!("tui needs an interactive terminal");
+    }
+    let run = run_monitored_scan(args).await?;
+    let min_size = args
+        .min_size
+        .as_deref()
+        .map(parse_size_string)
+        .transpose()?;
+    let config = load_config()?;
+    let mut state = TuiState::new(run.items, args, min_size, &config);
+    let mut terminal = ratatui::try_init().context("Failed to start the terminal UI")?;
+    LOG_MUTED.store(true, Ordering::Relaxed);
+    let result = tui_loop(&mut terminal, &mut state, &config).await;
+    LOG_MUTED.store(false, Ordering::Relaxed);
+    ratatui::restore();
+    result
+}
+
+async fn tui_loop(
+    terminal: &mut ratatui::DefaultTerminal,
+    state: &mut TuiState,
+    config: &Config,
+) -> Result<()> {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+
+    loop {
+        state.sync_detail();
+        terminal.draw(|frame| draw_tui(frame, state))?;
+        let Event::Key(key) = tokio::task::block_in_place(event::read)? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        state.message = None;
+
+        if let Some((action, targets)) = state.confirm.take() {
+            if key.code == KeyCode::Char('y') {
+                state.message = Some(format!("{}…", action.done()));
+                terminal.draw(|frame| draw_tui(frame, state))?;
+                run_tui_action(state, config, &action, &targets).await;
+            }
+            continue;
+        }
+
+        if let Some((field, mut text, before)) = state.input.take() {
+            match key.code {
+                KeyCode::Esc => {
+                    state.filter = before;
+                    state.refresh();
+                }
+                KeyCode::Enter if field == TuiField::Tag => {
+                    if !text.trim().is_empty() {
+                        state.ask(ItemAction::Tag(text.trim().to_string()));
+                    }
+                }
+                KeyCode::Enter => {
+                    if let Err(e) = state.apply_input(field, &text) {
+                        state.message = Some(format!("{:#}", e));
+                        state.input = Some((field, text, before));
+                    }
+                }
+                code => {
+                    match code {
+                        KeyCode::Backspace => {
+                            text.pop();
+                        }
+                        KeyCode::Char(c) => text.push(c),
+                        _ => {}
+                    }
+                    if field == TuiField::Search {
+                        state.apply_input(field, &text)?;
+                    }
+                    state.input = Some((field, text, before));
+                }
+            }
+            continue;
+        }
+
+        if let Some(cursor) = state.picker {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('c' | 'q') | KeyCode::Enter => state.picker = None,
+                KeyCode::Up | KeyCode::Char('k') => state.picker = Some(cursor.saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    state.picker = Some((cursor + 1).min(TUI_COLUMNS.len() - 1))
+                }
+                // The name column always stays, or rows would be anonymous
+                KeyCode::Char(' ') if TUI_COLUMNS[cursor] != TuiColumn::Name => {
+                    state.shown[cursor] = !state.shown[cursor];
+                    if !state.shown_columns().contains(&state.sort) {
+                        state.sort = TuiColumn::Score;
+                        state.descending = true;
+                        state.refresh();
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        if state.detail.is_some()
+            && matches!(key.code, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q'))
+        {
+            state.detail = None;
+            continue;
+        }
+
+        let page = terminal.size()?.height.saturating_sub(4).max(1) as isize;
+        let prompt =
+            |field: TuiField, text: String, filter: &TuiFilter| Some((field, text, filter.clone()));
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Enter => {
+                if let Some(&index) = state
+                    .table
+                    .selected()
+                    .and_then(|row| state.visible.get(row))
+                {
+                    state.detail = Some(TuiDetail::load(&state.items[index], index));
+                }
+            }
+            KeyCode::Up if key.modifiers.contains(KeyModifiers::SHIFT) => state.extend_marks(-1),
+            KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => state.extend_marks(1),
+            KeyCode::Up | KeyCode::Char('k') => state.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => state.move_cursor(1),
+            KeyCode::Char(' ') => {
+                state.toggle_mark();
+                state.move_cursor(1);
+            }
+            KeyCode::Char('u') => state.marked.clear(),
+            KeyCode::Char('D') => state.ask(ItemAction::Delete),
+            KeyCode::Char('U') => state.ask(ItemAction::Unmonitor),
+            KeyCode::Char('E') => state.ask(ItemAction::Exclude),
+            KeyCode::Char('T') if state.read_only.is_none() => {
+                state.input = prompt(TuiField::Tag, String::new(), &state.filter)
+            }
+            KeyCode::Char('T') => state.ask(ItemAction::Tag(String::new())),
+            KeyCode::PageUp => state.move_cursor(-page),
+            KeyCode::PageDown => state.move_cursor(page),
+            KeyCode::Home | KeyCode::Char('g') => state.move_cursor(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => state.move_cursor(isize::MAX / 2),
+            KeyCode::Char('>') => state.cycle_sort(true),
+            KeyCode::Char('<') => state.cycle_sort(false),
+            KeyCode::Char('r') => {
+                state.descending = !state.descending;
+                state.refresh();
+            }
+            KeyCode::Char('c') => state.picker = Some(0),
+            KeyCode::Char('/') => {
+                state.input = prompt(TuiField::Search, state.filter.search.clone(), &state.filter)
+            }
+            KeyCode::Char('m') => {
+                let text = state
+                    .filter
+                    .min_score
+                    .map(|s| s.to_string())
+                    .unwrap_or_default();
+                state.input = prompt(TuiField::MinScore, text, &state.filter);
+            }
+            KeyCode::Char('z') => {
+                let text = state
+                    .filter
+                    .min_size
+                    .map(format_file_size)
+                    .unwrap_or_default();
+                state.input = prompt(TuiField::MinSize, text, &state.filter);
+            }
+            KeyCode::Char('t') => {
+                state.filter.item_type = match state.filter.item_type {
+                    None => Some("show"),
+                    Some("show") => Some("movie"),
+                    _ => None,
+                };
+                state.refresh();
+            }
+            KeyCode::Char('x') => {
+                state.filter = TuiFilter::default();
+                state.refresh();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw_tui(frame: &mut ratatui::Frame, state: &mut TuiState) {
+    use ratatui::layout::{Alignment, Constraint, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Cell, Paragraph, Row, Table, Wrap};
+
+    let [header_area, table_area, status_area, help_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+
+    let visible: Vec<&Item> = state.visible.iter().map(|&i| &state.items[i]).collect();
+    let size: u64 = visible.iter().map(|item| item.size_bytes).sum();
+    let mut summary = format!(
+        "wastearr · {} of {} items · {}",
+        visible.len(),
+        state.items.len(),
+        format_file_size(size)
+    );
+    let filters = state.filter.describe();
+    if !filters.is_empty() {
+        let _ = write!(summary, " · {}", filters.join(", "));
+    }
+    if state.pattern.is_some() {
+        summary.push_str(" · best matches first");
+    }
+    if !state.marked.is_empty() {
+        let marked: u64 = state
+            .marked
+            .iter()
+            .map(|&i| state.items[i].size_bytes)
+            .sum();
+        let _ = write!(
+            summary,
+            " · {} marked ({})",
+            state.marked.len(),
+            format_file_size(marked)
+        );
+    }
+    frame.render_widget(
+        Paragraph::new(summary).style(Style::new().add_modifier(Modifier::BOLD)),
+        header_area,
+    );
+
+    let columns = state.shown_columns();
+    // A one-cell gutter carries the marks
+    let widths: Vec<Constraint> = std::iter::once(Constraint::Length(1))
+        .chain(columns.iter().map(|&column| {
+            match column {
+                TuiColumn::Name => Constraint::Fill(1),
+                TuiColumn::Note => Constraint::Length(NOTE_COLUMN_WIDTH as u16),
+                _ => Constraint::Length(
+                    visible
+                        .iter()
+                        .map(|item| display_width(&column.cell(item)))
+                        .chain([display_width(column.title()) + 2])
+                        .max()
+                        .unwrap_or(0) as u16,
+                ),
+            }
+        }))
+        .collect();
+    let header = Row::new(
+        std::iter::once(Cell::from("")).chain(columns.iter().map(|&column| {
+            let mut title = column.title().to_string();
+            if column == state.sort {
+                title.push_str(if state.descending { " ▾" } else { " ▴" });
+            }
+            Cell::from(title)
+        })),
+    )
+    .style(Style::new().add_modifier(Modifier::BOLD | Modifier::UNDERLINED));
+    let rows = state.visible.iter().map(|index| {
+        let item = &state.items[*index];
+        let marked = state.marked.contains(index);
+        let mark = Cell::from(if marked { "●" } else { " " });
+        let cells = columns.iter().map(|&column| {
+            let line = match &state.pattern {
+                Some(pattern) if column == TuiColumn::Name => {
+                    highlight_matches(&item.name, pattern, &mut state.matcher)
+                }
+                _ => Line::from(column.cell(item)),
+            };
+            let line = if column.numeric() {
+                line.alignment(Alignment::Right)
+            } else {
+                line
+            };
+            let cell = Cell::from(line);
+            if column == TuiColumn::Score {
+                cell.style(Style::new().fg(severity_color(item.waste_score)))
+            } else {
+                cell
+            }
+        });
+        let row = Row::new(std::iter::once(mark).chain(cells));
+        if marked {
+            row.style(Style::new().fg(Color::Cyan))
+        } else {
+            row
+        }
+    });
+    let table = Table::new(rows, widths)
+        .header(header)
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, table_area, &mut state.table);
+
+    let status = match (&state.input, &state.message) {
+        (_, Some(message)) => Line::styled(message.clone(), Style::new().fg(Color::Red)),
+        (Some((field, text, _)), None) => Line::from(format!("{}{}█", field.prompt(), text)),
+        (None, None) => Line::from(
+            state
+                .selected_item()
+                .map(|item| item.path.clone())
+                .unwrap_or_default(),
+        )
+        .style(Style::new().fg(Color::DarkGray)),
+    };
+    frame.render_widget(Paragraph::new(status), status_area);
+    let help = if state.input.is_some() {
+        "enter apply · esc cancel"
+    } else {
+        "q quit · ↑↓ move · </> sort · r reverse · c columns · / search · t type · m min score · z min size · x clear · space mark · u unmark · D delete · U unmonitor · T tag · E exclude"
+    };
+    frame.render_widget(
+        Paragraph::new(help)
+            .wrap(Wrap { trim: true })
+            .style(Style::new().fg(Color::DarkGray)),
+        help_area,
+    );
+
+    if let Some(cursor) = state.picker {
+        draw_column_picker(frame, state, cursor);
+    }
+    if let Some(detail) = &state.detail {
+        draw_detail(frame, state, detail);
+    }
+    if let Some((action, targets)) = &state.confirm {
+        draw_confirm(frame, state, action, targets);
+    }
+}
+
+/// Scans listed in the detail pane, the most recent ones
+const TUI_DETAIL_SCANS: usize = 8;
+
+fn draw_detail(frame: &mut ratatui::Frame, state: &TuiState, detail: &TuiDetail) {
+    use ratatui::layout::Margin;
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::{Line, Span};
+    use ratatui::widgets::{Block, Clear, Paragraph, Wrap};
+
+    let item = &state.items[detail.index];
+    let label = Style::new().fg(Color::DarkGray);
+    let heading = Style::new().add_modifier(Modifier::BOLD);
+    let field = |name: &str, value: String| {
+        Line::from(vec![
+            Span::styled(format!("{:<16}", name), label),
+            Span::raw(value),
+        ])
+    };
+
+    let factors = ScoreFactors::of(item, &state.scoring);
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("Waste score     ", label),
+            Span::styled(
+                format!("{} ({})", item.waste_score, severity_of(item.waste_score)),
+                Style::new()
+                    .fg(severity_color(item.waste_score))
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ]),
+        field(
+            "  Size",
+            format!(
+                "{:.1} for {}{}",
+                factors.size_score,
+                format_file_size(item.size_bytes),
+                if factors.size_score >= 80.0 {
+                    " (capped)"
+                } else {
+                    ""
+                }
+            ),
+        ),
+    ];
+    if factors.tv_factor != 1.0 {
+        lines.push(field("  Show discount", format!("×{}", factors.tv_factor)));
+    }
+    lines.push(field(
+        "  Rating",
+        match factors.rating {
+            Some(rating) => format!("×{} for {:.1}", factors.rating_multiplier, rating),
+            None => format!(
+                "×{} (unrated, taken as {:.1})",
+                factors.rating_multiplier, state.scoring.unrated_rating
+            ),
+        },
+    ));
+    let protected_by = state.protect.iter().position(|rule| rule.matches(item));
+    match &state.scorer {
+        _ if protected_by.is_some() => lines.push(field(
+            "  Protected",
+            format!(
+                "by [[protect]] rule {} of config.toml, scoring 0",
+                protected_by.unwrap_or_default() + 1
+            ),
+        )),
+        Some(plugin) if factors.score() != item.waste_score => lines.push(field(
+            "  Plugin",
+            format!(
+                "{} adjusted {} to {}",
+                plugin,
+                factors.score(),
+                item.waste_score
+            ),
+        )),
+        _ => {}
+    }
+
+    lines.extend([Line::from(""), Line::styled("Item", heading)]);
+    lines.push(field(
+        "Type",
+        format!(
+            "{} in {}",
+            if item.item_type == "show" {
+                "Show"
+            } else {
+                "Movie"
+            },
+            item.instance
+        ),
+    ));
+    lines.push(field("Quality profile", item.quality.clone()));
+    if !item.genres.is_empty() {
+        lines.push(field("Genres", item.genres.join(", ")));
+    }
+    if let Some(collection) = &item.collection {
+        lines.push(field("Collection", collection.clone()));
+    }
+    if let Some(episodes) = &item.episodes {
+        lines.push(field(
+            "Episodes",
+            format!(
+                "{} of {} in {} seasons ({:.0}%)",
+                episodes.file_count,
+                episodes.total_count,
+                episodes.season_count,
+                episodes.percent_complete
+            ),
+        ));
+    }
+    if item.specials_bytes > 0 {
+        lines.push(field("Specials", format_file_size(item.specials_bytes)));
+    }
+    if let Some(media) = &item.media_info {
+        lines.push(field(
+            "Media",
+            format!(
+                "{}, {}, {}",
+                media.resolution, media.video_codec, media.audio
+            ),
+        ));
+    }
+    lines.push(field("Path", item.path.clone()));
+    lines.push(field("Root folder", item.root_folder.clone()));
+    if let Some(primary) = item.primary_file_bytes {
+        lines.push(field(
+            "Main file",
+            format!(
+                "{} of {} on disk",
+                format_file_size(primary),
+                format_file_size(item.size_bytes)
+            ),
+        ));
+    }
+    if let Some(note) = &item.note {
+        lines.push(field("Note", note.clone()));
     }
 
-    if let Some(top_n) = args.top_waste {
-        items.truncate(top_n);
-        if filters.is_empty() {
-            filters.push(format!("Top {} Highest Waste Scores", top_n));
+    lines.extend([Line::from(""), Line::styled("Scan history", heading)]);
+    match &detail.history {
+        Ok(history) if history.is_empty() => lines.push(Line::styled("No stored scans", label)),
+        Ok(history) => {
+            lines.push(field("First seen", format_local_time(history[0].0)));
+            for (timestamp, size, score) in history.iter().rev().take(TUI_DETAIL_SCANS) {
+                lines.push(field(
+                    &format!("  {}", format_local_time(*timestamp)),
+                    format!("{:>10}  score {}", format_file_size(*size), score),
+                ));
+            }
         }
+        Err(e) => lines.push(Line::styled(e.clone(), Style::new().fg(Color::Red))),
     }
 
-    if !filters.is_empty() {
-        let prefix = if requested_types.len() == 1 {
-            match requested_types[0].as_str() {
-                "sonarr" => "Series",
-                "radarr" => "Movies",
-                _ => "Items",
-            }
+    lines.extend([Line::from(""), Line::styled("Links", heading)]);
+    if let Some(url) = &item.url {
+        lines.push(field("Open", url.clone()));
+    }
+    if let Some(tmdb_id) = item.tmdb_id {
+        let kind = if item.item_type == "show" {
+            "tv"
         } else {
-            "Items"
+            "movie"
         };
-        println!("{} with {}", prefix, filters.join(", "));
-        println!("{}", "=".repeat(60));
+        lines.push(field(
+            "TMDB",
+            format!("https://www.themoviedb.org/{}/{}", kind, tmdb_id),
+        ));
+    }
+    if let Some(tvdb_id) = item.tvdb_id {
+        lines.push(field(
+            "TVDB",
+            format!("https://thetvdb.com/?tab=series&id={}", tvdb_id),
+        ));
     }
 
-    println!("{}", format_unified_table(items, requested_types.len() > 1));
+    let area = frame.area().inner(Margin::new(4, 1));
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::bordered()
+                .title(format!(" {} ({}) ", item.name, item.year))
+                .title_bottom(" ↑↓ previous/next · D U T E act on it · esc close "),
+        ),
+        area,
+    );
+}
 
-    if requested_types.len() > 1 {
-        let (tv, movies) = items.iter().fold((0, 0), |acc, item| {
-            if item.item_type == "show" {
-                (acc.0 + 1, acc.1)
+/// Items shown by name in the confirmation before the rest are counted
+const TUI_CONFIRM_NAMES: usize = 8;
+
+fn draw_confirm(
+    frame: &mut ratatui::Frame,
+    state: &TuiState,
+    action: &ItemAction,
+    targets: &[usize],
+) {
+    use ratatui::layout::Rect;
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Block, Clear, Paragraph, Wrap};
+
+    let size: u64 = targets.iter().map(|&i| state.items[i].size_bytes).sum();
+    let mut lines = vec![
+        Line::styled(
+            action.question(targets.len(), size),
+            Style::new().add_modifier(Modifier::BOLD),
+        ),
+        Line::from(""),
+    ];
+    lines.extend(targets.iter().take(TUI_CONFIRM_NAMES).map(|&i| {
+        let item = &state.items[i];
+        Line::from(format!(
+            "  {} ({}, {}, {})",
+            item.name,
+            item.year,
+            item.instance,
+            format_file_size(item.size_bytes)
+        ))
+    }));
+    if targets.len() > TUI_CONFIRM_NAMES {
+        lines.push(Line::from(format!(
+            "  …and {} more",
+            targets.len() - TUI_CONFIRM_NAMES
+        )));
+    }
+    lines.extend([
+        Line::from(""),
+        Line::from("y confirm · any other key cancels"),
+    ]);
+
+    let area = frame.area();
+    let width = 70.min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let color = if matches!(action, ItemAction::Delete) {
+        Color::Red
+    } else {
+        Color::Yellow
+    };
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().border_style(Style::new().fg(color))),
+        popup,
+    );
+}
+
+fn draw_column_picker(frame: &mut ratatui::Frame, state: &TuiState, cursor: usize) {
+    use ratatui::layout::Rect;
+    use ratatui::style::{Modifier, Style};
+    use ratatui::widgets::{Block, Clear, List, ListState};
+
+    let area = frame.area();
+    let height = (TUI_COLUMNS.len() as u16 + 2).min(area.height);
+    let width = 30.min(area.width);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let entries = TUI_COLUMNS
+        .iter()
+        .zip(&state.shown)
+        .map(|(column, shown)| format!("[{}] {}", if *shown { 'x' } else { ' ' }, column.title()));
+    let list = List::new(entries)
+        .block(Block::bordered().title(" Columns · space toggles "))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_widget(Clear, popup);
+    frame.render_stateful_widget(
+        list,
+        popup,
+        &mut ListState::default().with_selected(Some(cursor)),
+    );
+}
+
+/// A name with the characters the fuzzy search matched in bold
+fn highlight_matches(
+    name: &str,
+    pattern: &Pattern,
+    matcher: &mut nucleo_matcher::Matcher,
+) -> ratatui::text::Line<'static> {
+    use ratatui::style::{Modifier, Style};
+    use ratatui::text::{Line, Span};
+
+    let mut buffer = Vec::new();
+    let mut indices = Vec::new();
+    pattern.indices(Utf32Str::new(name, &mut buffer), matcher, &mut indices);
+    indices.sort_unstable();
+    indices.dedup();
+
+    let matched = Style::new().add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+    let mut spans: Vec<Span> = Vec::new();
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (position, c) in name.chars().enumerate() {
+        let is_match = indices.binary_search(&(position as u32)).is_ok();
+        if is_match != run_matched && !run.is_empty() {
+            let text = std::mem::take(&mut run);
+            spans.push(if run_matched {
+                Span::styled(text, matched)
             } else {
-                (acc.0, acc.1 + 1)
-            }
-        });
-        println!(
-            "\nTotal items: {} ({} series, {} movies)",
-            items.len(),
-            tv,
-            movies
-        );
+                Span::raw(text)
+            });
+        }
+        run_matched = is_match;
+        run.push(c);
+    }
+    spans.push(if run_matched {
+        Span::styled(run, matched)
     } else {
-        let item_type = match requested_types[0].as_str() {
-            "sonarr" => "series",
-            "radarr" => "movies",
-            _ => &requested_types[0],
-        };
-        println!("\nTotal {} shown: {}", item_type, items.len());
+        Span::raw(run)
+    });
+    Line::from(spans)
+}
+
+fn severity_color(waste_score: i32) -> ratatui::style::Color {
+    use ratatui::style::Color;
+    match severity_of(waste_score) {
+        "Critical" => Color::Red,
+        "High" => Color::LightRed,
+        "Moderate" => Color::Yellow,
+        _ => Color::Green,
     }
 }
 
-fn main() -> Result<()> {
-    let args = parse_args();
-    let config = Config {
-        sonarr_url: get_config_value("SONARR_URL")
-            .unwrap_or_else(|| "http://localhost:8989".to_string()),
-        sonarr_api_key: get_config_value("SONARR_API_KEY"),
-        radarr_url: get_config_value("RADARR_URL")
-            .unwrap_or_else(|| "http://localhost:7878".to_string()),
-        radarr_api_key: get_config_value("RADARR_API_KEY"),
-    };
+/// Items and metrics of a finished scan, unfiltered
+#[derive(Debug, Default)]
+struct ScanRun {
+    items: Vec<Item>,
+    metrics: ScanMetrics,
+    // Unreachable instances were skipped
+    partial: bool,
+    phases: Vec<(&'static str, Duration)>,
+}
 
-    if args.clear_cache {
-        if let Some(cache_path) = cache_dir().map(|d| d.join("wastearr/cache.json")) {
-            if cache_path.exists() {
-                println!("Clearing cache: {}", cache_path.display());
-                fs::remove_file(&cache_path)?;
-            } else {
-                println!("No cache file to clear");
-            }
+/// Scan and print the report, except for `serve`, which renders it itself
+async fn run_scan(args: &Args) -> Result<ScanRun> {
+    let mut timer = PhaseTimer::new();
+    // watch and serve scan over and over in one process
+    REQUEST_LOG.lock().unwrap().clear();
+    // Read before scanning, so `latest` isn't the scan this run is about to record
+    let baseline = if args.command.as_deref() == Some("compare") {
+        let baseline = load_snapshot(args.compare_from.as_deref().unwrap_or("latest"))?;
+        if let Some(to) = &args.compare_to {
+            let (to_id, to) = load_snapshot(to)?;
+            print_comparison(&baseline, &to, Some(to_id), args);
+            return Ok(ScanRun::default());
+        }
+        Some(baseline)
+    } else {
+        None
+    };
+    // Fixture, replay and offline runs don't touch the APIs or write the cache
+    let _run_lock = if args.mock.is_some() || args.replay.is_some() || args.offline {
+        None
+    } else {
+        acquire_run_lock(args.wait)?
+    };
+    let mut config = load_config()?;
+    let recorder = set_up_response_source(&mut config, args)?;
+    if let Some(concurrency) = args.concurrency {
+        config.concurrency = concurrency;
+    }
+    if let Some(timeout) = args.timeout {
+        for instance in &mut config.instances {
+            instance.request_timeout = Some(Duration::from_secs_f64(timeout));
         }
     }
 
+    if let Some(target) = &args.clear_cache {
+        clear_cache(target, &config)?;
+    }
+
     // Parse min-size if provided
     let min_size_bytes = if let Some(size_str) = &args.min_size {
         Some(parse_size_string(size_str)?)
@@ -660,86 +13482,944 @@ fn main() -> Result<()> {
     };
 
     // Determine what to scan
-    let scan_types = if let Some(item_type) = &args.item_type {
+    let mut scan_types = if let Some(item_type) = &args.item_type {
         vec![item_type.clone()]
     } else {
         vec!["sonarr".to_string(), "radarr".to_string()]
     };
+    scan_types.retain(|kind| {
+        !(args.skip_sonarr && kind == "sonarr" || args.skip_radarr && kind == "radarr")
+    });
+    if scan_types.is_empty() {
+        anyhow::bail!(
+            "Nothing to scan: {} is skipped",
+            args.item_type.as_deref().unwrap_or_default()
+        );
+    }
+
+    // Load a custom scorer up front so a broken plugin fails before the scan
+    let mut scorer = args.scorer.as_deref().map(ScorerPlugin::load).transpose()?;
+    let notifiers = if args.no_notify || baseline.is_some() {
+        Vec::new()
+    } else {
+        load_notifiers()?
+    };
+    timer.lap("Setup");
 
     // Validate API connectivity
-    validate_api_connectivity(&config, &scan_types)?;
+    let mut skipped = if args.offline {
+        Vec::new()
+    } else {
+        validate_api_connectivity(&mut config, &scan_types, args.skip_unreachable).await?
+    };
+    timer.lap("Connectivity check");
 
     // Load cache once at the beginning (unless bypassing cache)
-    let (mut sonarr_cache, mut radarr_cache) = if args.no_cache {
-        println!("Bypassing cache - fetching fresh ratings");
-        (HashMap::new(), HashMap::new())
+    let mut cache = if args.no_cache {
+        progress!("Bypassing cache - fetching fresh ratings");
+        CacheData::default()
     } else {
-        load_cache()
+        check_cache_sources(&config);
+        load_cache(args.offline, args.refresh_ratings)
     };
 
     // Process all requested types
     let mut all_items = Vec::new();
     let mut cache_stats = (0usize, 0usize); // (hits, misses)
 
-    for scan_type in &scan_types {
-        println!("Fetching {} data from API", scan_type);
+    if args.stream {
+        let emitted = run_stream_pipeline(
+            &config,
+            &scan_types,
+            args,
+            min_size_bytes,
+            scorer.as_mut(),
+            &mut cache.sonarr_ratings,
+            &mut cache.radarr_ratings,
+            &mut cache_stats,
+        )
+        .await?;
+        timer.lap("Stream");
+        if !args.no_cache {
+            cache.run_stats = cache_stats;
+            save_cache(&cache);
+        }
+        progress!("Wrote {} matching items", emitted);
+        progress!(
+            "Cache stats: {} hits, {} misses",
+            cache_stats.0,
+            cache_stats.1
+        );
+        if let (Some(path), Some(recorder)) = (&args.record, &recorder) {
+            save_session(path, &config.instances, recorder)?;
+        }
+        if args.timing {
+            eprint!("{}", format_timing_report(&timer));
+        }
+        return Ok(ScanRun {
+            partial: !skipped.is_empty(),
+            ..ScanRun::default()
+        });
+    }
 
-        let items = match scan_type.as_str() {
-            "sonarr" => {
-                let mut cache_ref = if args.no_cache {
-                    None
-                } else {
-                    Some(&mut sonarr_cache)
-                };
-                scan_api_data(
-                    &config.sonarr_url,
-                    config.sonarr_api_key.as_ref(),
-                    "series",
-                    "Sonarr",
-                    "show",
-                    &mut cache_stats,
-                    &mut cache_ref,
-                )?
-            }
-            "radarr" => {
-                let mut cache_ref = if args.no_cache {
-                    None
-                } else {
-                    Some(&mut radarr_cache)
-                };
-                scan_api_data(
-                    &config.radarr_url,
-                    config.radarr_api_key.as_ref(),
-                    "movie",
-                    "Radarr",
-                    "movie",
-                    &mut cache_stats,
-                    &mut cache_ref,
-                )?
+    // Libraries fetched within the TTL (or at all, offline) come straight from the cache
+    let library_ttl = library_cache_ttl();
+    let now = current_timestamp();
+    let (cached, mut instances): (Vec<&Instance>, Vec<&Instance>) =
+        config.instances_for(&scan_types).partition(|instance| {
+            cache
+                .libraries
+                .get(&instance.name)
+                .is_some_and(|library| args.offline || now - library.fetched_at < library_ttl)
+        });
+    let mut data_as_of = None;
+    if args.offline {
+        for instance in instances.drain(..) {
+            warn!("no cached library for {}, leaving it out", instance.name);
+        }
+        data_as_of = cached
+            .iter()
+            .map(|instance| cache.libraries[&instance.name].fetched_at)
+            .min_by(f64::total_cmp);
+        if data_as_of.is_none() {
+            anyhow::bail!("No cached libraries to work with offline; run wastearr online first");
+        }
+    }
+    for instance in cached {
+        let library = &cache.libraries[&instance.name];
+        progress!(
+            "Using {} library cached {} ago",
+            instance.name,
+            format_age(now - library.fetched_at)
+        );
+        cache_stats.0 += library.items.len();
+        all_items.extend(library.items.iter().cloned());
+    }
+
+    let fetched = join_all(instances.iter().map(|instance| {
+        let previous = cache
+            .libraries
+            .get(&instance.name)
+            .map(|library| library.validators.clone())
+            .unwrap_or_default();
+        fetch_instance_data(instance, previous)
+    }))
+    .await;
+    for (instance, data) in instances.into_iter().zip(fetched) {
+        let data = match data {
+            Ok(data) => data,
+            Err(e) if args.skip_unreachable => {
+                skip_instance(&mut skipped, &instance.name, format!("{:#}", e));
+                continue;
             }
-            _ => Vec::new(),
+            Err(e) => return Err(e),
         };
+        if let (Library::Unchanged, Some(library)) =
+            (&data.library, cache.libraries.get_mut(&instance.name))
+        {
+            library.fetched_at = now;
+            cache_stats.0 += library.items.len();
+            all_items.extend(library.items.iter().cloned());
+            continue;
+        }
+
+        let mut cache_ref = match (args.no_cache, instance.kind.as_str()) {
+            (true, _) => None,
+            (false, "sonarr") => Some(&mut cache.sonarr_ratings),
+            (false, _) => Some(&mut cache.radarr_ratings),
+        };
+        let items = scan_api_data(instance, &data, &mut cache_stats, &mut cache_ref);
 
+        if !args.no_cache {
+            cache.libraries.insert(
+                instance.name.clone(),
+                CachedLibrary {
+                    fetched_at: now,
+                    items: items.clone(),
+                    validators: data.validators,
+                },
+            );
+        }
         all_items.extend(items);
     }
+    // Skipped instances must not count as scanned, or their items would look deleted in history
+    config
+        .instances
+        .retain(|instance| !skipped.iter().any(|s| s.instance == instance.name));
 
-    if !args.no_cache {
-        save_cache(&sonarr_cache, &radarr_cache);
+    if !args.no_cache && !args.offline {
+        cache.run_stats = cache_stats;
+        save_cache(&cache);
     }
+    timer.lap("Fetch libraries");
 
-    println!("Processing {} items", all_items.len());
-    all_items
-        .iter_mut()
-        .for_each(calculate_normalized_waste_score);
+    if args.media_info {
+        enrich_series_media_info(&mut all_items, &config).await;
+        timer.lap("Media info");
+    }
+    apply_specials_mode(&mut all_items, &args.specials);
+
+    progress!("Processing {} items", all_items.len());
+    if let Some(plugin) = &scorer {
+        progress!("Scoring with plugin {}", plugin.name);
+    }
+    let bar = phase_bar(all_items.len(), "Scoring");
+    for item in &mut all_items {
+        calculate_normalized_waste_score(item, &config.scoring);
+        if let Some(plugin) = &mut scorer {
+            item.waste_score = plugin.score(item)?;
+        }
+        if config.is_protected(item) {
+            item.waste_score = 0;
+        }
+        bar.inc(1);
+    }
+    drop(bar);
+    timer.lap("Scoring");
+
+    let scanned_instances: Vec<String> = config
+        .instances_for(&scan_types)
+        .map(|instance| instance.name.clone())
+        .collect();
+    let previous = previous_scan(&scanned_instances);
+    if args.trend || args.new_since_last {
+        match previous.timestamp {
+            Some(_) if !previous.items_recorded => {
+                progress!("Previous scan was recorded without items, trends unavailable")
+            }
+            Some(timestamp) => {
+                progress!(
+                    "Comparing against scan from {} ago",
+                    format_age(current_timestamp() - timestamp)
+                );
+                apply_trends(&mut all_items, &previous.items);
+            }
+            None => progress!("No previous scan recorded, trends unavailable"),
+        }
+    }
+    apply_notes(&mut all_items);
+    let summaries = summarize_scan(&all_items, &scanned_instances);
+    let since_last_scan = scan_trend(&summaries, &previous, &all_items);
+    let (reclaimed, this_scan) = reclaimed_totals();
+    if !args.no_history {
+        // Summaries alone keep the footer trends; item lists also drive --trend and wrapped
+        let keep_items = get_config_value("WASTEARR_HISTORY_ITEMS")
+            .is_none_or(|value| !matches!(value.to_lowercase().as_str(), "0" | "false" | "no"));
+        append_history(&ScanSnapshot {
+            timestamp: current_timestamp(),
+            instances: scanned_instances.clone(),
+            items: if keep_items {
+                snapshot_items(&all_items)
+            } else {
+                Vec::new()
+            },
+            summaries,
+            folders: summarize_folders(&all_items),
+            reclaimed: this_scan,
+        });
+    }
+    timer.lap("History");
+
+    let disks = if args.disk_space || args.forecast {
+        fetch_disk_space(&config, &scan_types).await
+    } else {
+        Vec::new()
+    };
+    let extras = ReportExtras {
+        forecast: args
+            .forecast
+            .then(|| growth_forecast(&scanned_instances, &disks)),
+        disks,
+        extra_files: if args.duplicates {
+            find_extra_movie_files(&all_items, &config).await
+        } else {
+            Vec::new()
+        },
+        cross_instance: if args.cross_instance {
+            find_cross_instance_duplicates(&all_items)
+        } else {
+            Vec::new()
+        },
+        metrics: ScanMetrics {
+            data_as_of: data_as_of.map(format_local_time),
+            since_last_scan,
+            reclaimed: (reclaimed.items > 0).then_some(reclaimed),
+            ..scan_metrics(&all_items, timer.started.elapsed(), cache_stats, &skipped)
+        },
+    };
+    if args.disk_space || args.forecast || args.duplicates || args.cross_instance {
+        timer.lap("Extra reports");
+    }
 
-    print_results(&mut all_items, &scan_types, &args, min_size_bytes);
+    match (args.command.as_deref(), &baseline) {
+        (Some("serve" | "tui"), _) => {}
+        (Some("compare"), Some(baseline)) => {
+            let current = ScanSnapshot {
+                timestamp: current_timestamp(),
+                instances: scanned_instances.clone(),
+                items: snapshot_items(&all_items),
+                summaries: Vec::new(),
+                folders: Vec::new(),
+                reclaimed: Reclaimed::default(),
+            };
+            print_comparison(baseline, &current, None, args)
+        }
+        // main prints the check result from the returned items
+        _ if args.output == "nagios" => {}
+        (Some("stats"), _) => print_stats(
+            &all_items,
+            &scan_types,
+            args,
+            min_size_bytes,
+            &extras.metrics,
+        ),
+        _ => print_results(&all_items, &scan_types, args, min_size_bytes, &extras),
+    }
+    timer.lap("Rendering");
 
     if cache_stats.0 > 0 || cache_stats.1 > 0 {
-        println!(
+        progress!(
             "Cache stats: {} hits, {} misses",
-            cache_stats.0, cache_stats.1
+            cache_stats.0,
+            cache_stats.1
         );
     }
+    if let (Some(path), Some(recorder)) = (&args.record, &recorder) {
+        save_session(path, &config.instances, recorder)?;
+    }
+    if !notifiers.is_empty() {
+        let notice = build_scan_notice(&all_items, &scanned_instances, this_scan, &extras.metrics);
+        send_notifications(&notifiers, &notice).await;
+    }
+    if args.timing {
+        eprint!("{}", format_timing_report(&timer));
+    }
+    Ok(ScanRun {
+        items: all_items,
+        metrics: extras.metrics,
+        partial: !skipped.is_empty(),
+        phases: timer.phases,
+    })
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn notifier(route: NotifyRoute) -> Notifier {
+        Notifier {
+            name: "discord",
+            route,
+            target: NotifyTarget::Discord {
+                webhook: "https://discord.invalid/hook".to_string(),
+            },
+        }
+    }
+
+    fn route(events: &[&str]) -> NotifyRoute {
+        NotifyRoute {
+            events: events.iter().map(|event| event.to_string()).collect(),
+            min_score: None,
+            dedupe: false,
+            quiet_hours: None,
+            digest: None,
+        }
+    }
+
+    fn notice(event: &str, scores: &[i32]) -> ScanNotice {
+        ScanNotice {
+            event: event.to_string(),
+            scanned_at: 0.0,
+            instances: vec!["sonarr".to_string()],
+            items: scores.len(),
+            total_bytes: 0,
+            average_waste: 0.0,
+            flagged: scores.len(),
+            flagged_bytes: 0,
+            newly_flagged: None,
+            reclaimed: Reclaimed::default(),
+            reclaimed_total: None,
+            skipped: Vec::new(),
+            top_offenders: scores
+                .iter()
+                .map(|&waste_score| NoticeItem {
+                    name: format!("Item {}", waste_score),
+                    year: 2020,
+                    instance: "sonarr".to_string(),
+                    waste_score,
+                    size_bytes: 1,
+                    url: None,
+                })
+                .collect(),
+            low_space: Vec::new(),
+        }
+    }
+
+    fn item(instance: &str, path: &str, size_bytes: u64, waste_score: i32) -> Item {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": path,
+            "year": 2020,
+            "size_bytes": size_bytes,
+            "rating": "5.0",
+            "item_type": "movie",
+            "waste_score": waste_score,
+            "instance": instance,
+            "genres": [],
+            "path": path,
+            "root_folder": "",
+            "quality": "",
+            "specials_bytes": 0,
+        }))
+        .unwrap()
+    }
+
+    fn disk(host: &str, instance: &str, path: &str) -> DiskSpace {
+        DiskSpace {
+            host: host.to_string(),
+            instances: vec![instance.to_string()],
+            path: path.to_string(),
+            label: String::new(),
+            free_bytes: 100,
+            total_bytes: 1000,
+        }
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval(" 30m "), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_interval("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(86400)));
+        for invalid in ["", "0", "-5m", "6w", "m", "inf"] {
+            assert!(parse_interval(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_quiet_hours() {
+        assert_eq!(parse_quiet_hours("22:00-07:00").unwrap(), (1320, 420));
+        assert_eq!(parse_quiet_hours("22-7").unwrap(), (1320, 420));
+        assert_eq!(parse_quiet_hours(" 9:30 - 17:15 ").unwrap(), (570, 1035));
+        for invalid in ["22:00", "24-7", "22:60-07:00", "late-early", ""] {
+            assert!(parse_quiet_hours(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size_string("500MB").unwrap(), 500 * 1024 * 1024);
+        assert_eq!(parse_size_string("1.5gb").unwrap(), 1536 * 1024 * 1024);
+        assert_eq!(parse_size_string("2 T").unwrap(), 2 * 1024_u64.pow(4));
+        assert_eq!(parse_size_string("100B").unwrap(), 100);
+        assert!(parse_size_string("5PB").is_err());
+        assert!(parse_size_string("GB").is_err());
+    }
+
+    #[test]
+    fn parses_space_thresholds() {
+        assert!(matches!(
+            SpaceThreshold::parse("10%").unwrap(),
+            SpaceThreshold::Percent(p) if p == 10.0
+        ));
+        assert!(matches!(
+            SpaceThreshold::parse(" 50GB ").unwrap(),
+            SpaceThreshold::Bytes(bytes) if bytes == 50 * 1024_u64.pow(3)
+        ));
+        for invalid in ["101%", "-1%", "lots%", "lots"] {
+            assert!(SpaceThreshold::parse(invalid).is_err(), "{:?}", invalid);
+        }
+
+        let disk = DiskSpace {
+            host: "sonarr".to_string(),
+            instances: vec!["sonarr".to_string()],
+            path: "/data".to_string(),
+            label: "/data".to_string(),
+            free_bytes: 5 * 1024_u64.pow(3),
+            total_bytes: 100 * 1024_u64.pow(3),
+        };
+        assert!(SpaceThreshold::parse("10%").unwrap().is_low(&disk));
+        assert!(!SpaceThreshold::parse("5%").unwrap().is_low(&disk));
+        assert!(SpaceThreshold::parse("6GB").unwrap().is_low(&disk));
+        assert!(!SpaceThreshold::parse("5GB").unwrap().is_low(&disk));
+    }
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Keys longer than a block are hashed first
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn authorizes_webhooks_by_token_basic_auth_or_signature() {
+        let secret = "s3cret";
+        let body = br#"{"eventType":"Download"}"#;
+        let headers = |pairs: &[(&'static str, String)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+        let signature = format!("sha256={}", hex(&hmac_sha256(secret.as_bytes(), body)));
+
+        assert!(webhook_authorized(
+            secret,
+            &HeaderMap::new(),
+            Some("s3cret"),
+            body
+        ));
+        assert!(!webhook_authorized(
+            secret,
+            &HeaderMap::new(),
+            Some("wrong"),
+            body
+        ));
+        assert!(!webhook_authorized(secret, &HeaderMap::new(), None, body));
+        assert!(webhook_authorized(
+            secret,
+            &headers(&[("x-wastearr-token", secret.to_string())]),
+            None,
+            body
+        ));
+        assert!(webhook_authorized(
+            secret,
+            &headers(&[(
+                "authorization",
+                format!("Basic {}", BASE64.encode("sonarr:s3cret"))
+            )]),
+            None,
+            body
+        ));
+        assert!(!webhook_authorized(
+            secret,
+            &headers(&[(
+                "authorization",
+                format!("Bearer {}", BASE64.encode("sonarr:s3cret"))
+            )]),
+            None,
+            body
+        ));
+        assert!(webhook_authorized(
+            secret,
+            &headers(&[(
+                "x-wastearr-signature",
+                signature.to_uppercase().replace("SHA256=", "sha256=")
+            )]),
+            None,
+            body
+        ));
+        assert!(!webhook_authorized(
+            secret,
+            &headers(&[("x-wastearr-signature", signature.clone())]),
+            None,
+            b"tampered"
+        ));
+        // A bad signature isn't rescued by a good token
+        assert!(!webhook_authorized(
+            secret,
+            &headers(&[("x-wastearr-signature", "sha256=00".to_string())]),
+            Some(secret),
+            body
+        ));
+    }
+
+    #[test]
+    fn merges_imported_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("imported.db");
+        let imported = Connection::open(&path).unwrap();
+        imported.execute_batch(HISTORY_SCHEMA).unwrap();
+        imported
+            .execute_batch(
+                "INSERT INTO scans (id, timestamp) VALUES (1, 100.0), (2, 300.0);
+                 INSERT INTO scan_instances VALUES (1, 'sonarr'), (2, 'sonarr');
+                 INSERT INTO scan_items VALUES (1, 'sonarr', 7, 'Old', 'show', 10, 40);
+                 INSERT INTO notes VALUES ('sonarr', 7, 'newer note', 50.0);
+                 INSERT INTO notes VALUES ('sonarr', 8, 'older note', 5.0);
+                 INSERT INTO deletions VALUES ('sonarr', 9, 'Gone', 'show', 30, 150.0);",
+            )
+            .unwrap();
+        drop(imported);
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO scans (id, timestamp) VALUES (1, 200.0), (2, 300.0);
+             INSERT INTO scan_instances VALUES (1, 'radarr'), (2, 'radarr');
+             INSERT INTO notes VALUES ('sonarr', 7, 'older note', 10.0);
+             INSERT INTO notes VALUES ('sonarr', 8, 'newer note', 20.0);",
+        )
+        .unwrap();
+
+        // Only the scan at 100.0 is new, and only note 7 is newer there
+        assert_eq!(attached(&mut conn, &path, merge_history).unwrap(), (1, 1));
+        // Again, nothing new comes in
+        assert_eq!(attached(&mut conn, &path, merge_history).unwrap().0, 0);
+        let deletions: i64 = conn
+            .query_row("SELECT COUNT(*) FROM deletions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(deletions, 1);
+
+        let scans: Vec<(i64, f64)> = conn
+            .prepare("SELECT id, timestamp FROM scans ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        // The older imported scan was renumbered ahead of the local ones
+        assert_eq!(scans, [(1, 100.0), (2, 200.0), (3, 300.0)]);
+        let imported_item: (i64, String) = conn
+            .query_row(
+                "SELECT scan_id, name FROM scan_items WHERE id = 7",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(imported_item, (1, "Old".to_string()));
+        let instances: Vec<(i64, String)> = conn
+            .prepare("SELECT scan_id, instance FROM scan_instances ORDER BY scan_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            instances,
+            [
+                (1, "sonarr".to_string()),
+                (2, "radarr".to_string()),
+                (3, "radarr".to_string())
+            ]
+        );
+        // The newer side of each note wins
+        let notes: Vec<String> = conn
+            .prepare("SELECT note FROM notes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(notes, ["newer note", "newer note"]);
+    }
+
+    #[test]
+    fn merges_cached_history_into_a_started_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("wastearr.db");
+        let cache = Connection::open(&old).unwrap();
+        cache.execute_batch(CACHE_SCHEMA).unwrap();
+        cache
+            .execute_batch(
+                "CREATE TABLE scans (id INTEGER PRIMARY KEY, timestamp REAL NOT NULL);
+                 CREATE TABLE scan_instances (scan_id INTEGER NOT NULL, instance TEXT NOT NULL);
+                 INSERT INTO scans VALUES (1, 100.0), (2, 300.0);
+                 INSERT INTO scan_instances VALUES (1, 'sonarr'), (2, 'sonarr');",
+            )
+            .unwrap();
+        drop(cache);
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO scans VALUES (1, 200.0);
+             INSERT INTO scan_instances VALUES (1, 'radarr');",
+        )
+        .unwrap();
+        migrate_cached_history(&mut conn, &old).unwrap();
+
+        let scans: Vec<(f64, String)> = conn
+            .prepare(
+                "SELECT timestamp, instance FROM scans
+                 JOIN scan_instances ON scan_id = id ORDER BY id",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            scans,
+            [
+                (100.0, "sonarr".to_string()),
+                (200.0, "radarr".to_string()),
+                (300.0, "sonarr".to_string())
+            ]
+        );
+        // Moved, so the next start has nothing left to merge
+        let left: i64 = Connection::open(&old)
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name LIKE 'scan%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn counts_reclaimed_space_from_the_deletion_log() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO scans (id, timestamp) VALUES (1, 100.0), (2, 200.0);
+             -- Guessed by older versions from items going missing
+             INSERT INTO scan_reclaimed VALUES (2, 5, 500);
+             INSERT INTO deletions VALUES ('radarr', 1, 'A', 'movie', 10, 50.0);
+             INSERT INTO deletions VALUES ('radarr', 2, 'B', 'movie', 20, 150.0);
+             INSERT INTO deletions VALUES ('sonarr', 3, 'C', 'show', 30, 160.0);
+             INSERT INTO deletions VALUES ('sonarr', 4, 'D', 'show', 40, 250.0);
+             PRAGMA user_version = 1;",
+        )
+        .unwrap();
+        rebuild_reclaimed(&mut conn).unwrap();
+        let per_scan: Vec<(i64, i64, i64)> = conn
+            .prepare("SELECT scan_id, items, bytes FROM scan_reclaimed ORDER BY scan_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        // The deletion after the last scan waits for the next one
+        assert_eq!(per_scan, [(1, 1, 10), (2, 2, 50)]);
+
+        let deletions: Vec<Deletion> = [("movie", 10, 50.0), ("show", 30, 160.0)]
+            .into_iter()
+            .map(|(item_type, size_bytes, deleted_at)| Deletion {
+                item_type: item_type.to_string(),
+                size_bytes,
+                deleted_at,
+            })
+            .collect();
+        let since = sum_deletions(&deletions, Some(100.0));
+        assert_eq!((since.items, since.bytes), (1, 30));
+        let all = sum_deletions(&deletions, None);
+        assert_eq!((all.items, all.bytes), (2, 40));
+    }
+
+    #[test]
+    fn routes_notices_by_event_and_severity() {
+        let scan = notice("scan", &[80, 60]);
+        let flagged = notice("flagged", &[80, 60]);
+
+        assert!(notifier(route(&["flagged"])).route(&scan).is_none());
+        assert!(notifier(route(&["flagged"])).route(&flagged).is_some());
+        // Flagged scans are still scans
+        assert!(notifier(route(&["scan"])).route(&flagged).is_some());
+        assert!(notifier(route(&["low_space"])).route(&scan).is_none());
+
+        let strict = notifier(NotifyRoute {
+            min_score: Some(75),
+            ..route(&["scan"])
+        });
+        let routed = strict.route(&scan).unwrap();
+        assert_eq!(routed.top_offenders.len(), 1);
+        assert_eq!(routed.top_offenders[0].waste_score, 80);
+        assert!(strict.route(&notice("scan", &[60])).is_none());
+
+        // Low space alerts keep every candidate
+        let mut low_space = notice("low_space", &[10]);
+        low_space.low_space.push(NoticeDisk {
+            host: "sonarr".to_string(),
+            path: "/data".to_string(),
+            label: "/data".to_string(),
+            free_bytes: 1,
+            total_bytes: 100,
+        });
+        let alert = notifier(NotifyRoute {
+            min_score: Some(75),
+            ..route(&["low_space"])
+        });
+        assert_eq!(alert.route(&low_space).unwrap().top_offenders.len(), 1);
+    }
+
+    #[test]
+    fn dedupes_notices_with_the_same_candidates() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        let deduping = notifier(NotifyRoute {
+            dedupe: true,
+            ..route(&["scan"])
+        });
+        let send = |notice: ScanNotice| {
+            let released = release_notice(&conn, &deduping, Some(notice), 1000.0).unwrap();
+            if let Some((_, Some(fingerprint))) = &released {
+                conn.execute(
+                    "UPDATE notifications SET fingerprint = ?2 WHERE backend = ?1",
+                    params![deduping.key(), fingerprint],
+                )
+                .unwrap();
+            }
+            released.is_some()
+        };
+
+        assert!(send(notice("scan", &[80, 60])));
+        // Rescanned later, same news
+        let mut again = notice("scan", &[80, 60]);
+        again.scanned_at = 500.0;
+        assert!(!send(again));
+        assert!(send(notice("scan", &[80, 61])));
+
+        // Without dedupe every notice goes out
+        let plain = notifier(route(&["scan"]));
+        for _ in 0..2 {
+            assert!(
+                release_notice(&conn, &plain, Some(notice("scan", &[80])), 1000.0)
+                    .unwrap()
+                    .is_some()
+            );
+        }
+    }
+
+    #[test]
+    fn merges_held_notices() {
+        let mut held = notice("flagged", &[90]);
+        held.newly_flagged = Some(2);
+        held.reclaimed = Reclaimed {
+            items: 1,
+            bytes: 10,
+        };
+        let mut newer = notice("scan", &[70]);
+        newer.newly_flagged = Some(1);
+        newer.reclaimed = Reclaimed { items: 2, bytes: 5 };
+
+        let merged = merge_notices(held, newer);
+        assert_eq!(merged.event, "flagged");
+        assert_eq!(merged.newly_flagged, Some(3));
+        assert_eq!((merged.reclaimed.items, merged.reclaimed.bytes), (3, 15));
+        assert_eq!(merged.top_offenders[0].waste_score, 70);
+    }
+
+    #[test]
+    fn renders_templates_without_expanding_values() {
+        let mut notice = notice("scan", &[]);
+        notice.instances = vec!["{event}".to_string(), "{json}".to_string()];
+        let render =
+            |template| render_notice_template(template, &notice, Some("{items}"), str::to_string);
+        assert_eq!(
+            render("{event}: {instances} {json} {unknown} {{items}} {"),
+            "scan: {event}, {json} {items} {unknown} {0} {"
+        );
+
+        let quoted = render_notice_template(r#"{"text": "{instances}"}"#, &notice, None, |value| {
+            value.replace('{', "<")
+        });
+        assert_eq!(quoted, r#"{"text": "<event}, <json}"}"#);
+    }
+
+    #[test]
+    fn groups_items_under_every_genre() {
+        let mut both = item("radarr", "/movies/A", 10, 80);
+        both.genres = vec!["Drama".to_string(), "Comedy".to_string()];
+        let mut drama = item("radarr", "/movies/B", 5, 20);
+        drama.genres = vec!["Drama".to_string()];
+        let none = item("radarr", "/movies/C", 1, 0);
+
+        let groups = group_items(&[both, drama, none], "genre");
+        let totals: Vec<(&str, usize, u64, i64)> = groups
+            .iter()
+            .map(|group| {
+                (
+                    group.key.as_str(),
+                    group.count,
+                    group.size_bytes,
+                    group.average_waste(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("Drama", 2, 15, 50),
+                ("Comedy", 1, 10, 80),
+                ("Unknown", 1, 1, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn fits_growth_per_root_folder() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        let now = current_timestamp();
+        for day in 0..8 {
+            let scan = ScanSnapshot {
+                timestamp: now - (7 - day) as f64 * 86400.0,
+                instances: vec!["radarr".to_string()],
+                items: Vec::new(),
+                summaries: Vec::new(),
+                folders: [("/data/fast", 1000 + day * 100), ("/data/static", 5000)]
+                    .into_iter()
+                    .map(|(root_folder, total_bytes)| FolderSummary {
+                        instance: "radarr".to_string(),
+                        root_folder: root_folder.to_string(),
+                        total_bytes,
+                    })
+                    .collect(),
+                reclaimed: Reclaimed::default(),
+            };
+            insert_snapshot(&conn, &scan).unwrap();
+        }
+
+        let folders = read_folder_growth(&conn, &["radarr".to_string()]).unwrap();
+        let rates: Vec<(&str, i64)> = folders
+            .iter()
+            .map(|(_, folder, growth)| (folder.as_str(), growth.round() as i64))
+            .collect();
+        assert_eq!(rates, [("/data/fast", 700), ("/data/static", 0)]);
+        assert!(
+            read_folder_growth(&conn, &["sonarr".to_string()])
+                .unwrap()
+                .is_empty()
+        );
+
+        // The fast folder's growth lands on its own disk only
+        let disks = [
+            disk("nas", "radarr", "/data/fast"),
+            disk("nas", "radarr", "/data/static"),
+        ];
+        let by_disk = growth_by_disk(&folders, &disks);
+        let growth = |path: &str| by_disk[&("nas".to_string(), path.to_string())].round();
+        assert_eq!((growth("/data/fast"), growth("/data/static")), (700.0, 0.0));
+    }
+
+    #[test]
+    fn counts_flagged_bytes_per_host_and_disk() {
+        let disks = [
+            disk("nas-a:7878", "radarr", "/data"),
+            disk("nas-b:7878", "radarr-4k", "/data"),
+            disk("nas-a:7878", "radarr", "/data/archive"),
+        ];
+        let items = [
+            item("radarr", "/data/movies/A", 10, 80),
+            item("radarr", "/data/archive/B", 20, FLAGGED_MIN_SCORE),
+            item("radarr-4k", "/data/movies/A", 30, 60),
+            // Not flagged, so not counted
+            item("radarr-4k", "/data/movies/C", 40, FLAGGED_MIN_SCORE - 1),
+            // On no known disk
+            item("radarr", "/elsewhere/D", 50, 90),
+        ];
+        let totals = flagged_bytes_by_disk(&disks, &items);
+        assert_eq!(totals.get(&("nas-a:7878", "/data")), Some(&10));
+        assert_eq!(totals.get(&("nas-a:7878", "/data/archive")), Some(&20));
+        assert_eq!(totals.get(&("nas-b:7878", "/data")), Some(&30));
+        assert_eq!(totals.len(), 3);
+    }
 }